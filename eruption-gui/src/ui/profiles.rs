@@ -15,7 +15,8 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use crate::{constants, manifest, profiles::Profile};
+use crate::profiles::{GradientStop, Profile};
+use crate::{constants, manifest};
 use crate::{manifest::Manifest, util};
 use gdk::RGBA;
 use glib::clone;
@@ -24,18 +25,100 @@ use gtk::{prelude::*, Orientation};
 use gtk::{ShadowType, StackExt};
 use sourceview::prelude::*;
 use sourceview::BufferBuilder;
+use std::cell::RefCell;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 type Result<T> = std::result::Result<T, eyre::Error>;
 
+/// Time to wait for further edits of a config value, before the profile is saved
+const SAVE_DELAY_MILLIS: u32 = 500;
+
+/// Convert a color of a color chooser to a color value of a profile
+fn rgba_to_color(rgba: &RGBA) -> u32 {
+    let c = |v: f64| (v * 255.0).round() as u32 & 0xff;
+
+    (c(rgba.alpha) << 24) | (c(rgba.red) << 16) | (c(rgba.green) << 8) | c(rgba.blue)
+}
+
+/// Convert a color value of a profile to a color of a color chooser
+fn color_to_rgba(color: u32) -> RGBA {
+    let colors = util::color_to_rgba(color);
+
+    RGBA {
+        red: colors.0 as f64 / 255.0,
+        green: colors.1 as f64 / 255.0,
+        blue: colors.2 as f64 / 255.0,
+        alpha: colors.3 as f64 / 255.0,
+    }
+}
+
+/// Returns the range of a slider, for a parameter that doesn't declare its `min` and `max`
+fn fallback_range(default: f64, value: f64) -> (f64, f64) {
+    (
+        0.0_f64.min(default).min(value),
+        (default * 4.0).max(value).max(1.0),
+    )
+}
+
+/// Modify the profile `profile_file` with `set_value`, and save it
+fn save_config_value<F>(profile_file: &Path, set_value: F)
+where
+    F: FnOnce(&mut Profile) -> Result<()>,
+{
+    let result = Profile::from(profile_file).and_then(|mut profile| {
+        set_value(&mut profile)?;
+        profile.save()
+    });
+
+    result.unwrap_or_else(|e| log::error!("Could not save a config value: {}", e));
+}
+
+/// Returns a change handler, that saves the most recent value to the profile `profile_file` with
+/// `set_value`, once no further edits happened for `SAVE_DELAY_MILLIS`. This avoids rewriting the
+/// profile on each keystroke, or while a slider is still being dragged
+fn save_delayed<T, F>(profile_file: PathBuf, set_value: F) -> impl Fn(T)
+where
+    T: 'static,
+    F: Fn(&mut Profile, &T) -> Result<()> + 'static,
+{
+    let set_value = Rc::new(set_value);
+    let pending: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+
+    move |value: T| {
+        if let Some(source_id) = pending.borrow_mut().take() {
+            glib::source_remove(source_id);
+        }
+
+        let mut value = Some(value);
+
+        let source_id = glib::timeout_add_local(
+            SAVE_DELAY_MILLIS,
+            clone!(@strong profile_file, @strong set_value, @strong pending => move || {
+                pending.borrow_mut().take();
+
+                if let Some(value) = value.take() {
+                    save_config_value(&profile_file, |profile| set_value(profile, &value));
+                }
+
+                Continue(false)
+            }),
+        );
+
+        pending.borrow_mut().replace(source_id);
+    }
+}
+
 fn build_int_config(
     name: &str,
     description: &str,
     _default: i64,
     min: i64,
     max: i64,
+    step: i64,
     value: i64,
+    on_change: impl Fn(i64) + 'static,
 ) -> Result<gtk::Grid> {
     let container = gtk::GridBuilder::new()
         .border_width(10)
@@ -58,11 +141,16 @@ fn build_int_config(
     let adjustment = gtk::AdjustmentBuilder::new()
         .lower(min as f64)
         .upper(max as f64)
+        .step_increment(step as f64)
+        .value(value as f64)
         .build();
 
+    adjustment.connect_value_changed(move |a| on_change(a.get_value().round() as i64));
+
     let child = gtk::ScaleBuilder::new()
         .adjustment(&adjustment)
-        .fill_level(value as f64)
+        .digits(0)
+        .draw_value(true)
         .build();
 
     container.attach(&child, 0, 1, 2, 1);
@@ -76,7 +164,9 @@ fn build_float_config(
     _default: f64,
     min: f64,
     max: f64,
+    step: f64,
     value: f64,
+    on_change: impl Fn(f64) + 'static,
 ) -> Result<gtk::Grid> {
     let container = gtk::GridBuilder::new()
         .border_width(10)
@@ -99,11 +189,16 @@ fn build_float_config(
     let adjustment = gtk::AdjustmentBuilder::new()
         .lower(min as f64)
         .upper(max as f64)
+        .step_increment(step)
+        .value(value)
         .build();
 
+    adjustment.connect_value_changed(move |a| on_change(a.get_value()));
+
     let child = gtk::ScaleBuilder::new()
         .adjustment(&adjustment)
-        .fill_level(value as f64)
+        .digits(2)
+        .draw_value(true)
         .build();
 
     container.attach(&child, 0, 1, 2, 1);
@@ -116,6 +211,7 @@ fn build_bool_config(
     description: &str,
     _default: bool,
     value: bool,
+    on_change: impl Fn(bool) + 'static,
 ) -> Result<gtk::Grid> {
     let container = gtk::GridBuilder::new()
         .border_width(10)
@@ -136,6 +232,13 @@ fn build_bool_config(
     container.attach(&child, 1, 0, 1, 1);
 
     let child = gtk::SwitchBuilder::new().expand(false).state(value).build();
+
+    child.connect_state_set(move |_sw, state| {
+        on_change(state);
+
+        gtk::Inhibit(false)
+    });
+
     container.attach(&child, 0, 1, 1, 1);
 
     Ok(container)
@@ -146,6 +249,7 @@ fn build_string_config(
     description: &str,
     _default: &str,
     value: &str,
+    on_change: impl Fn(String) + 'static,
 ) -> Result<gtk::Grid> {
     let container = gtk::GridBuilder::new()
         .border_width(10)
@@ -166,6 +270,8 @@ fn build_string_config(
     container.attach(&child, 1, 0, 1, 1);
 
     let child = gtk::EntryBuilder::new().text(&value).build();
+    child.connect_changed(move |entry| on_change(entry.get_text().to_string()));
+
     container.attach(&child, 0, 1, 2, 1);
 
     Ok(container)
@@ -176,6 +282,7 @@ fn build_color_config(
     description: &str,
     _default: u32,
     value: u32,
+    on_change: impl Fn(u32) + 'static,
 ) -> Result<gtk::Grid> {
     let container = gtk::GridBuilder::new()
         .border_width(10)
@@ -195,30 +302,226 @@ fn build_color_config(
     let child = gtk::LabelBuilder::new().label(&description).build();
     container.attach(&child, 1, 0, 1, 1);
 
-    let colors = util::color_to_rgba(value);
-    let rgba = RGBA {
-        red: colors.0 as f64 / 255.0,
-        green: colors.1 as f64 / 255.0,
-        blue: colors.2 as f64 / 255.0,
-        alpha: colors.3 as f64 / 255.0,
-    };
+    let child = gtk::ColorChooserWidgetBuilder::new()
+        .rgba(&color_to_rgba(value))
+        .build();
+
+    child
+        .connect_property_rgba_notify(move |chooser| on_change(rgba_to_color(&chooser.get_rgba())));
 
-    let child = gtk::ColorChooserWidgetBuilder::new().rgba(&rgba).build();
     container.attach(&child, 0, 1, 2, 1);
 
     Ok(container)
 }
 
-fn create_config_editor(param: &manifest::ConfigParam) -> Result<gtk::Frame> {
+fn build_choice_config(
+    name: &str,
+    description: &str,
+    _default: &str,
+    options: &[manifest::ChoiceOption],
+    value: &str,
+    on_change: impl Fn(String) + 'static,
+) -> Result<gtk::Grid> {
+    let container = gtk::GridBuilder::new()
+        .border_width(10)
+        .column_spacing(10)
+        .row_spacing(10)
+        .build();
+
+    let child = gtk::LabelBuilder::new()
+        .use_markup(true)
+        .label(&format!("<b>{}</b>", name))
+        .build();
+    container.attach(&child, 0, 0, 1, 1);
+
+    let child = gtk::LabelBuilder::new().label(&description).build();
+    container.attach(&child, 1, 0, 1, 1);
+
+    let child = gtk::ComboBoxTextBuilder::new().build();
+
+    for option in options.iter() {
+        child.append(Some(&option.value), &option.label);
+    }
+
+    child.set_active_id(Some(value));
+
+    child.connect_changed(move |cb| {
+        if let Some(id) = cb.get_active_id() {
+            on_change(id.to_string());
+        }
+    });
+
+    container.attach(&child, 0, 1, 2, 1);
+
+    Ok(container)
+}
+
+fn build_gradient_config(
+    name: &str,
+    description: &str,
+    _default: &[GradientStop],
+    value: &[GradientStop],
+    on_change: impl Fn(Vec<GradientStop>) + 'static,
+) -> Result<gtk::Grid> {
+    let container = gtk::GridBuilder::new()
+        .border_width(10)
+        .column_spacing(10)
+        .row_spacing(10)
+        .build();
+
+    let child = gtk::LabelBuilder::new()
+        .use_markup(true)
+        .label(&format!("<b>{}</b>", name))
+        .build();
+    container.attach(&child, 0, 0, 1, 1);
+
+    let child = gtk::LabelBuilder::new().label(&description).build();
+    container.attach(&child, 1, 0, 1, 1);
+
+    // one color button per stop, labelled with the position of the stop
+    let stops = gtk::BoxBuilder::new()
+        .orientation(Orientation::Horizontal)
+        .spacing(10)
+        .build();
+
+    let on_change = Rc::new(on_change);
+    let gradient = Rc::new(RefCell::new(value.to_vec()));
+
+    for (index, stop) in value.iter().enumerate() {
+        let stop_container = gtk::BoxBuilder::new()
+            .orientation(Orientation::Vertical)
+            .build();
+
+        let button = gtk::ColorButtonBuilder::new()
+            .rgba(&color_to_rgba(stop.color))
+            .build();
+
+        button.connect_color_set(
+            clone!(@strong gradient, @strong on_change => move |button| {
+                gradient.borrow_mut()[index].color = rgba_to_color(&button.get_rgba());
+                on_change(gradient.borrow().clone());
+            }),
+        );
+
+        stop_container.pack_start(&button, false, false, 0);
+
+        let label = gtk::LabelBuilder::new()
+            .label(&format!("{:.0}%", stop.position * 100.0))
+            .build();
+        stop_container.pack_start(&label, false, false, 0);
+
+        stops.pack_start(&stop_container, false, false, 0);
+    }
+
+    container.attach(&stops, 0, 1, 2, 1);
+
+    Ok(container)
+}
+
+fn build_key_set_config(
+    name: &str,
+    description: &str,
+    _default: &[u8],
+    value: &[u8],
+    on_change: impl Fn(Vec<u8>) + 'static,
+) -> Result<gtk::Grid> {
+    let container = gtk::GridBuilder::new()
+        .border_width(10)
+        .column_spacing(10)
+        .row_spacing(10)
+        .build();
+
+    let child = gtk::LabelBuilder::new()
+        .use_markup(true)
+        .label(&format!("<b>{}</b>", name))
+        .build();
+    container.attach(&child, 0, 0, 1, 1);
+
+    let child = gtk::LabelBuilder::new().label(&description).build();
+    container.attach(&child, 1, 0, 1, 1);
+
+    // key picker: one toggle button per key index
+    let keys = gtk::FlowBoxBuilder::new()
+        .max_children_per_line(24)
+        .selection_mode(gtk::SelectionMode::None)
+        .homogeneous(true)
+        .build();
+
+    let on_change = Rc::new(on_change);
+    let key_set = Rc::new(RefCell::new(value.to_vec()));
+
+    for index in 1..constants::MAX_KEYS {
+        let index = index as u8;
+
+        let button = gtk::ToggleButtonBuilder::new()
+            .label(&format!("{}", index))
+            .active(value.contains(&index))
+            .build();
+
+        button.connect_toggled(clone!(@strong key_set, @strong on_change => move |button| {
+            let mut key_set = key_set.borrow_mut();
+
+            key_set.retain(|k| *k != index);
+
+            if button.get_active() {
+                key_set.push(index);
+                key_set.sort_unstable();
+            }
+
+            on_change(key_set.clone());
+        }));
+
+        keys.add(&button);
+    }
+
+    container.attach(&keys, 0, 1, 2, 1);
+
+    Ok(container)
+}
+
+/// Create the editor of the parameter `param` of the script `script`, changes are saved to `profile`
+fn create_config_editor(
+    profile: &Profile,
+    script: &str,
+    param: &manifest::ConfigParam,
+) -> Result<gtk::Frame> {
     let outer = gtk::FrameBuilder::new().border_width(10).build();
 
+    // returns a handler, that saves the changed value of type `$t` with the setter `$set` of the profile
+    macro_rules! save_with {
+        ($set:ident, $name:expr, $t:ty) => {{
+            let script = script.to_string();
+            let name = $name.to_string();
+
+            save_delayed(
+                profile.profile_file.clone(),
+                move |profile: &mut Profile, value: &$t| profile.$set(&script, &name, value),
+            )
+        }};
+    }
+
     match param {
         manifest::ConfigParam::Int {
             name,
             description,
             default,
+            min,
+            max,
+            step,
         } => {
-            let widget = build_int_config(&name, description, *default, 0, 200, 123)?;
+            let value = *profile.get_int_value(script, name).unwrap_or(default);
+            let range = fallback_range(*default as f64, value as f64);
+
+            let widget = build_int_config(
+                &name,
+                description,
+                *default,
+                min.unwrap_or(range.0 as i64),
+                max.unwrap_or(range.1.ceil() as i64),
+                step.unwrap_or(1),
+                value,
+                save_with!(set_int_value, name, i64),
+            )?;
             outer.add(&widget);
         }
 
@@ -226,8 +529,23 @@ fn create_config_editor(param: &manifest::ConfigParam) -> Result<gtk::Frame> {
             name,
             description,
             default,
+            min,
+            max,
+            step,
         } => {
-            let widget = build_float_config(&name, description, *default, 0.0, 200.0, 123.59)?;
+            let value = *profile.get_float_value(script, name).unwrap_or(default);
+            let range = fallback_range(*default, value);
+
+            let widget = build_float_config(
+                &name,
+                description,
+                *default,
+                min.unwrap_or(range.0),
+                max.unwrap_or(range.1),
+                step.unwrap_or(0.01),
+                value,
+                save_with!(set_float_value, name, f64),
+            )?;
             outer.add(&widget);
         }
 
@@ -236,7 +554,15 @@ fn create_config_editor(param: &manifest::ConfigParam) -> Result<gtk::Frame> {
             description,
             default,
         } => {
-            let widget = build_bool_config(&name, description, *default, true)?;
+            let value = *profile.get_bool_value(script, name).unwrap_or(default);
+
+            let widget = build_bool_config(
+                &name,
+                description,
+                *default,
+                value,
+                save_with!(set_bool_value, name, bool),
+            )?;
             outer.add(&widget);
         }
 
@@ -245,7 +571,15 @@ fn create_config_editor(param: &manifest::ConfigParam) -> Result<gtk::Frame> {
             description,
             default,
         } => {
-            let widget = build_string_config(&name, description, default, "123")?;
+            let value = profile.get_string_value(script, name).unwrap_or(default);
+
+            let widget = build_string_config(
+                &name,
+                description,
+                default,
+                value,
+                save_with!(set_string_value, name, String),
+            )?;
             outer.add(&widget);
         }
 
@@ -254,7 +588,68 @@ fn create_config_editor(param: &manifest::ConfigParam) -> Result<gtk::Frame> {
             description,
             default,
         } => {
-            let widget = build_color_config(&name, description, *default, 123)?;
+            let value = *profile.get_color_value(script, name).unwrap_or(default);
+
+            let widget = build_color_config(
+                &name,
+                description,
+                *default,
+                value,
+                save_with!(set_color_value, name, u32),
+            )?;
+            outer.add(&widget);
+        }
+
+        manifest::ConfigParam::Choice {
+            name,
+            description,
+            default,
+            options,
+        } => {
+            let value = profile.get_choice_value(script, name).unwrap_or(default);
+
+            let widget = build_choice_config(
+                &name,
+                description,
+                default,
+                options,
+                value,
+                save_with!(set_choice_value, name, String),
+            )?;
+            outer.add(&widget);
+        }
+
+        manifest::ConfigParam::Gradient {
+            name,
+            description,
+            default,
+        } => {
+            let value = profile.get_gradient_value(script, name).unwrap_or(default);
+
+            let widget = build_gradient_config(
+                &name,
+                description,
+                default,
+                value,
+                save_with!(set_gradient_value, name, Vec<GradientStop>),
+            )?;
+            outer.add(&widget);
+        }
+
+        manifest::ConfigParam::KeySet {
+            name,
+            description,
+            default,
+        } => {
+            let value = profile.get_key_set_value(script, name).unwrap_or(default);
+
+            let widget = build_key_set_config(
+                &name,
+                description,
+                default,
+                value,
+                save_with!(set_key_set_value, name, Vec<u8>),
+            )?;
             outer.add(&widget);
         }
     }
@@ -276,16 +671,21 @@ fn populate_visual_config_editor<P: AsRef<Path>>(builder: &gtk::Builder, profile
         .orientation(Orientation::Vertical)
         .build();
 
-    let profile = Profile::new(profile.as_ref())?;
+    let profile = Profile::from(profile.as_ref())?;
 
     let script_path = PathBuf::from(constants::DEFAULT_SCRIPT_DIR);
 
     for f in profile.active_scripts.iter() {
+        // native effects, like animations, do not have a manifest
+        if f.extension().map_or(true, |ext| ext != "lua") {
+            continue;
+        }
+
         let manifest = Manifest::from(&script_path.join(&f))?;
 
-        if let Some(params) = manifest.config {
+        if let Some(params) = &manifest.config {
             for param in params {
-                let child = create_config_editor(&param)?;
+                let child = create_config_editor(&profile, &manifest.name, param)?;
                 container.pack_start(&child, false, true, 10);
             }
         }
@@ -366,6 +766,14 @@ fn populate_stack_widget<P: AsRef<Path>>(builder: &gtk::Builder, profile: P) ->
     for p in util::enumerate_profiles(&path)? {
         if p.profile_file == profile.as_ref() {
             for f in p.active_scripts {
+                // binary files, like images, can not be shown in an editor
+                if f
+                    .extension()
+                    .map_or(true, |ext| ext != "lua" && ext != "animation")
+                {
+                    continue;
+                }
+
                 // TODO: use configuration values from eruption.conf
                 let script_path = PathBuf::from(constants::DEFAULT_SCRIPT_DIR);
 
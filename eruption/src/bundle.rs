@@ -0,0 +1,635 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::constants;
+use crate::plugins::audio;
+use crate::profiles::Profile;
+use crate::scripting::manifest::Manifest;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("Invalid file name: {path}")]
+    InvalidPath { path: String },
+
+    #[error("Could not read the file '{path}': {description}")]
+    ReadError { path: String, description: String },
+
+    #[error("Invalid bundle: {description}")]
+    InvalidBundle { description: String },
+
+    #[error("Unsupported bundle format version: {version}")]
+    UnsupportedVersion { version: u32 },
+
+    #[error("Checksum mismatch of the file: {path}")]
+    ChecksumMismatch { path: String },
+
+    #[error("The bundle conflicts with installed files: {paths}")]
+    Conflict { paths: String },
+}
+
+/// Version of the bundle format that is written by this version of Eruption
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// File name of the checksum manifest inside of a bundle
+const BUNDLE_MANIFEST: &str = "bundle.toml";
+
+/// Directory of the Lua libraries, relative to the script directory
+const LIB_DIR: &str = "lib";
+
+/// The directory that a file of a bundle is installed into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    Profiles,
+    Scripts,
+    Sfx,
+}
+
+impl Location {
+    /// Name of the directory inside of a bundle
+    fn archive_dir(&self) -> &'static str {
+        match self {
+            Location::Profiles => "profiles",
+            Location::Scripts => "scripts",
+            Location::Sfx => "sfx",
+        }
+    }
+
+    /// The directory of the system that files are installed into
+    fn install_dir(&self) -> PathBuf {
+        match self {
+            Location::Profiles => get_profile_dir(),
+            Location::Scripts => get_script_dir(),
+            Location::Sfx => audio::get_sfx_dir(),
+        }
+    }
+}
+
+/// The checksum manifest of a bundle
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleManifest {
+    format_version: u32,
+
+    /// Version of Eruption that exported the bundle
+    eruption_version: String,
+
+    /// File name of the profile, relative to the profile directory
+    profile: String,
+
+    files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleFile {
+    location: Location,
+
+    /// Path of the file, relative to the directory of `location`
+    path: String,
+
+    sha256: String,
+}
+
+fn get_profile_dir() -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("global.profile_dir").ok())
+            .unwrap_or_else(|| constants::DEFAULT_PROFILE_DIR.to_string()),
+    )
+}
+
+fn get_script_dir() -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("global.script_dir").ok())
+            .unwrap_or_else(|| constants::DEFAULT_SCRIPT_DIR.to_string()),
+    )
+}
+
+/// Returns `path`, if it is a relative path that stays inside of the directory it is relative to
+fn check_relative_path(path: &Path) -> Result<&Path> {
+    if path.as_os_str().is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        Err(BundleError::InvalidPath {
+            path: format!("{}", path.display()),
+        }
+        .into())
+    } else {
+        Ok(path)
+    }
+}
+
+fn checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns the names of the modules that are loaded via `require "name"` by the Lua source `text`
+fn find_required_modules(text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+
+    for (index, _) in text.match_indices("require") {
+        let rest = text[index + "require".len()..].trim_start();
+        let rest = rest.strip_prefix('(').unwrap_or(rest).trim_start();
+
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            if let Some(end) = rest[1..].find(quote) {
+                result.push(rest[1..=end].to_string());
+            }
+        }
+    }
+
+    result
+}
+
+/// Add the library `name` and the libraries it requires, if they are found in the lib directory
+fn add_library(name: &str, script_dir: &Path, files: &mut BTreeSet<(Location, PathBuf)>) {
+    let name = name.replace('.', "/");
+    let lib_dir = Path::new(LIB_DIR);
+
+    let path = [format!("{}.lua", name), name]
+        .iter()
+        .map(|f| lib_dir.join(f))
+        .find(|p| script_dir.join(p).is_file());
+
+    // libraries that are not part of the script directory, like C modules, are not bundled
+    if let Some(path) = path {
+        if check_relative_path(&path).is_err() || !files.insert((Location::Scripts, path.clone())) {
+            return;
+        }
+
+        if let Ok(text) = fs::read_to_string(script_dir.join(&path)) {
+            for module in find_required_modules(&text) {
+                add_library(&module, script_dir, files);
+            }
+        }
+    }
+}
+
+/// Add the profile `profile_file` and its chain of base profiles
+fn add_profile_chain(
+    profile_file: &Path,
+    profile_dir: &Path,
+    files: &mut BTreeSet<(Location, PathBuf)>,
+) -> Result<()> {
+    let mut profile_file = profile_file.to_path_buf();
+
+    loop {
+        check_relative_path(&profile_file)?;
+
+        // cyclic inheritance has already been rejected while loading the profile
+        if !files.insert((Location::Profiles, profile_file.clone())) {
+            return Ok(());
+        }
+
+        let path = profile_dir.join(&profile_file);
+        let toml = fs::read_to_string(&path).map_err(|e| BundleError::ReadError {
+            path: format!("{}", path.display()),
+            description: format!("{}", e),
+        })?;
+
+        let table = toml::de::from_str::<toml::value::Table>(&toml)?;
+
+        match table.get("base").and_then(|v| v.as_str()) {
+            Some(base) => {
+                profile_file = profile_file
+                    .parent()
+                    .map(|dir| dir.join(base))
+                    .unwrap_or_else(|| PathBuf::from(base));
+            }
+
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Export the profile `profile_file` into a bundle, along with the scripts and manifests that it
+/// references, the Lua libraries they require, and the sound effects pack that it selects
+pub fn export_bundle(profile_file: &Path) -> Result<Vec<u8>> {
+    let profile_dir = get_profile_dir();
+    let script_dir = get_script_dir();
+
+    let profile = Profile::from(&profile_dir.join(check_relative_path(profile_file)?))?;
+
+    let mut files = BTreeSet::new();
+
+    add_profile_chain(profile_file, &profile_dir, &mut files)?;
+
+    for script_file in profile.active_scripts.iter() {
+        check_relative_path(script_file)?;
+
+        files.insert((Location::Scripts, script_file.clone()));
+
+        // native effects, like animations, do not have a manifest
+        if script_file.extension().map_or(true, |ext| ext != "lua") {
+            continue;
+        }
+
+        let manifest_file = util::get_manifest_for(script_file);
+        files.insert((Location::Scripts, manifest_file));
+
+        let script_path = script_dir.join(script_file);
+
+        if let Some(requires) = Manifest::from(&script_path)?.requires {
+            for lib in requires.libs.iter() {
+                add_library(lib, &script_dir, &mut files);
+            }
+        }
+
+        let text = fs::read_to_string(&script_path).map_err(|e| BundleError::ReadError {
+            path: format!("{}", script_path.display()),
+            description: format!("{}", e),
+        })?;
+
+        for module in find_required_modules(&text) {
+            add_library(&module, &script_dir, &mut files);
+        }
+    }
+
+    if let Some(pack) = profile.audio.as_ref().and_then(|a| a.sfx_pack.as_ref()) {
+        for file in audio::get_sfx_pack_files(pack)? {
+            files.insert((Location::Sfx, check_relative_path(&file)?.to_path_buf()));
+        }
+    }
+
+    // read all files first, the checksum manifest is the first entry of the archive
+    let mut contents = Vec::new();
+
+    for (location, file) in files.iter() {
+        let path = location.install_dir().join(file);
+
+        let data = fs::read(&path).map_err(|e| BundleError::ReadError {
+            path: format!("{}", path.display()),
+            description: format!("{}", e),
+        })?;
+
+        contents.push((*location, file, data));
+    }
+
+    let manifest = BundleManifest {
+        format_version: BUNDLE_FORMAT_VERSION,
+        eruption_version: env!("CARGO_PKG_VERSION").to_string(),
+        profile: format!("{}", profile_file.display()),
+        files: contents
+            .iter()
+            .map(|(location, file, data)| BundleFile {
+                location: *location,
+                path: format!("{}", file.display()),
+                sha256: checksum(data),
+            })
+            .collect(),
+    };
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut builder = tar::Builder::new(Vec::new());
+
+    let mut append = |path: &Path, data: &[u8]| -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+
+        builder.append_data(&mut header, path, data)?;
+
+        Ok(())
+    };
+
+    append(
+        Path::new(BUNDLE_MANIFEST),
+        toml::ser::to_string_pretty(&manifest)?.as_bytes(),
+    )?;
+
+    for (location, file, data) in contents.iter() {
+        append(&Path::new(location.archive_dir()).join(file), data)?;
+    }
+
+    info!(
+        "Exported the profile '{}' with {} files",
+        profile_file.display(),
+        contents.len()
+    );
+
+    Ok(builder.into_inner()?)
+}
+
+/// Read all files of the archive `data`, keyed by their paths
+fn read_archive(data: &[u8]) -> Result<HashMap<PathBuf, Vec<u8>>> {
+    let mut result = HashMap::new();
+    let mut archive = tar::Archive::new(data);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+
+        if !entry.header().entry_type().is_file() {
+            return Err(BundleError::InvalidBundle {
+                description: format!("{} is not a regular file", path.display()),
+            }
+            .into());
+        }
+
+        check_relative_path(&path)?;
+
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+
+        result.insert(path, contents);
+    }
+
+    Ok(result)
+}
+
+/// Validate the bundle `data` and install its files into the profile, script and sfx directories.
+/// Installed files that differ from the files of the bundle are only replaced if `overwrite` is
+/// set, otherwise the conflicting files are reported as an error. Returns the installed files
+pub fn import_bundle(data: &[u8], overwrite: bool) -> Result<Vec<PathBuf>> {
+    let mut archive = read_archive(data)?;
+
+    let manifest =
+        archive
+            .remove(Path::new(BUNDLE_MANIFEST))
+            .ok_or_else(|| BundleError::InvalidBundle {
+                description: format!("{} is missing", BUNDLE_MANIFEST),
+            })?;
+
+    let manifest = toml::de::from_str::<BundleManifest>(&String::from_utf8_lossy(&manifest))
+        .map_err(|e| BundleError::InvalidBundle {
+            description: format!("{}: {}", BUNDLE_MANIFEST, e),
+        })?;
+
+    if manifest.format_version > BUNDLE_FORMAT_VERSION {
+        return Err(BundleError::UnsupportedVersion {
+            version: manifest.format_version,
+        }
+        .into());
+    }
+
+    if !manifest
+        .files
+        .iter()
+        .any(|f| f.location == Location::Profiles && f.path == manifest.profile)
+    {
+        return Err(BundleError::InvalidBundle {
+            description: format!("The profile {} is missing", manifest.profile),
+        }
+        .into());
+    }
+
+    // verify the checksums, and that the bundle contains exactly the listed files
+    let mut files = BTreeMap::new();
+
+    for file in manifest.files.iter() {
+        let path = check_relative_path(Path::new(&file.path))?;
+        let archive_path = Path::new(file.location.archive_dir()).join(path);
+
+        let data = archive
+            .remove(&archive_path)
+            .ok_or_else(|| BundleError::InvalidBundle {
+                description: format!("{} is missing", archive_path.display()),
+            })?;
+
+        if checksum(&data) != file.sha256 {
+            return Err(BundleError::ChecksumMismatch {
+                path: format!("{}", archive_path.display()),
+            }
+            .into());
+        }
+
+        // profiles, manifests and sound effects packs have to be valid TOML
+        if path.extension().map_or(false, |ext| {
+            ext == "profile" || ext == "manifest" || ext == "sfxpack"
+        }) {
+            toml::de::from_str::<toml::value::Table>(&String::from_utf8_lossy(&data)).map_err(
+                |e| BundleError::InvalidBundle {
+                    description: format!("{}: {}", archive_path.display(), e),
+                },
+            )?;
+        }
+
+        files.insert(file.location.install_dir().join(path), data);
+    }
+
+    if let Some(path) = archive.keys().next() {
+        return Err(BundleError::InvalidBundle {
+            description: format!("{} is not listed in {}", path.display(), BUNDLE_MANIFEST),
+        }
+        .into());
+    }
+
+    // files that are already installed with identical contents are skipped
+    let mut conflicts = Vec::new();
+    let mut pending = Vec::new();
+
+    for (path, data) in files {
+        match fs::read(&path) {
+            Ok(installed) if installed == data => continue,
+
+            Ok(_) => conflicts.push(format!("{}", path.display())),
+
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+
+            Err(e) => {
+                return Err(BundleError::ReadError {
+                    path: format!("{}", path.display()),
+                    description: format!("{}", e),
+                }
+                .into())
+            }
+        }
+
+        pending.push((path, data));
+    }
+
+    if !conflicts.is_empty() && !overwrite {
+        return Err(BundleError::Conflict {
+            paths: conflicts.join(", "),
+        }
+        .into());
+    }
+
+    for (path, data) in pending.iter() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // replace files atomically, so that a running profile never sees a partial file
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)?;
+    }
+
+    info!(
+        "Imported the profile '{}', installed {} files",
+        manifest.profile,
+        pending.len()
+    );
+
+    Ok(pending.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a bundle from `files`. The paths are written verbatim, so that
+    /// the archive may contain paths that `tar::Builder` would reject
+    fn bundle(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder.append(&header, *data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn manifest(files: &[(Location, &str, &[u8])]) -> String {
+        let manifest = BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            eruption_version: "0.0.0".to_string(),
+            profile: "test.profile".to_string(),
+            files: files
+                .iter()
+                .map(|(location, path, data)| BundleFile {
+                    location: *location,
+                    path: path.to_string(),
+                    sha256: checksum(data),
+                })
+                .collect(),
+        };
+
+        toml::ser::to_string(&manifest).unwrap()
+    }
+
+    fn is_invalid_path(result: Result<Vec<PathBuf>>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<BundleError>()),
+            Err(Ok(BundleError::InvalidPath { .. }))
+        )
+    }
+
+    #[test]
+    fn test_check_relative_path() {
+        assert!(check_relative_path(Path::new("test.profile")).is_ok());
+        assert!(check_relative_path(Path::new("lib/macros/user-macros.lua")).is_ok());
+
+        assert!(check_relative_path(Path::new("")).is_err());
+        assert!(check_relative_path(Path::new("/etc/passwd")).is_err());
+        assert!(check_relative_path(Path::new("..")).is_err());
+        assert!(check_relative_path(Path::new("../test.profile")).is_err());
+        assert!(check_relative_path(Path::new("lib/../../test.profile")).is_err());
+        assert!(check_relative_path(Path::new("./test.profile")).is_err());
+    }
+
+    #[test]
+    fn test_import_rejects_path_traversal_in_archive() {
+        let profile: &[u8] = b"id = 'test'";
+
+        for path in ["../profiles/test.profile", "/profiles/test.profile"].iter() {
+            let manifest = manifest(&[(Location::Profiles, "test.profile", profile)]);
+
+            let data = bundle(&[(BUNDLE_MANIFEST, manifest.as_bytes()), (path, profile)]);
+
+            assert!(is_invalid_path(import_bundle(&data, false)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_path_traversal_in_manifest() {
+        let profile: &[u8] = b"id = 'test'";
+        let script: &[u8] = b"-- script";
+
+        for path in ["../../etc/evil.lua", "/etc/evil.lua", "lib/../../evil.lua"].iter() {
+            let manifest = manifest(&[
+                (Location::Profiles, "test.profile", profile),
+                (Location::Scripts, path, script),
+            ]);
+
+            let data = bundle(&[
+                (BUNDLE_MANIFEST, manifest.as_bytes()),
+                ("profiles/test.profile", profile),
+                ("scripts/evil.lua", script),
+            ]);
+
+            assert!(is_invalid_path(import_bundle(&data, false)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_bundles() {
+        let profile: &[u8] = b"id = 'test'";
+        let manifest = manifest(&[(Location::Profiles, "test.profile", profile)]);
+
+        // missing manifest
+        assert!(import_bundle(&bundle(&[("profiles/test.profile", profile)]), false).is_err());
+
+        // checksum mismatch
+        let data = bundle(&[
+            (BUNDLE_MANIFEST, manifest.as_bytes()),
+            ("profiles/test.profile", b"id = 'other'"),
+        ]);
+
+        assert!(matches!(
+            import_bundle(&data, false).map_err(|e| e.downcast::<BundleError>()),
+            Err(Ok(BundleError::ChecksumMismatch { .. }))
+        ));
+
+        // files that are not listed in the manifest
+        let data = bundle(&[
+            (BUNDLE_MANIFEST, manifest.as_bytes()),
+            ("profiles/test.profile", profile),
+            ("scripts/unlisted.lua", b"-- script"),
+        ]);
+
+        assert!(matches!(
+            import_bundle(&data, false).map_err(|e| e.downcast::<BundleError>()),
+            Err(Ok(BundleError::InvalidBundle { .. }))
+        ));
+    }
+}
@@ -26,6 +26,18 @@ pub const DEFAULT_PROFILE_DIR: &str = "/var/lib/eruption/profiles/";
 /// Default script directory
 pub const DEFAULT_SCRIPT_DIR: &str = "/usr/share/eruption/scripts/";
 
+/// Default directory for recorded macros
+pub const DEFAULT_MACRO_DIR: &str = "/var/lib/eruption/macros/";
+
+/// Default path of the event stream socket
+pub const DEFAULT_EVENT_SOCKET: &str = "/run/eruption/events.sock";
+
+/// Max. number of events that may be queued for a single client of the event stream
+pub const EVENT_STREAM_QUEUE_SIZE: usize = 4096;
+
+/// Max. number of concurrent connections to the event stream socket
+pub const MAX_EVENT_STREAM_CLIENTS: usize = 16;
+
 /// State directory
 pub const STATE_DIR: &str = "/var/lib/eruption/";
 
@@ -79,6 +91,9 @@ pub const DBUS_TIMEOUT_MILLIS: u32 = 250;
 /// PolicyKit authentication
 pub const DBUS_TIMEOUT_MILLIS_INTERACTIVE: u32 = 30000;
 
+/// Emit the D-Bus signal for changes of the persistent store at most every n milliseconds
+pub const DBUS_PERSISTENCE_SIGNAL_MILLIS: u64 = 1000;
+
 // Wait n seconds before sending the LED "off pattern" on shutdown
 pub const SHUTDOWN_TIMEOUT_MILLIS: u32 = 150;
 
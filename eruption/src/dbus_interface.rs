@@ -21,11 +21,15 @@ use dbus_tree::{
     Access, MethodErr, Signal, {EmitsChangedSignal, Factory},
 };
 use log::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use crate::bundle;
 use crate::plugins::audio;
+use crate::plugins::introspection::IntrospectionPlugin;
+use crate::plugins::macros::MacrosPlugin;
+use crate::plugins::persistence::{self, PersistencePlugin};
 use crate::profiles;
 use crate::script;
 use crate::CONFIG;
@@ -40,6 +44,10 @@ pub enum Message {
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
+/// The status of a Lua VM: index, script file, name and version of the script,
+/// event handlers, used memory, queue depth and whether the VM has failed
+type VmStatusTuple = (u32, String, String, String, Vec<String>, u64, u64, bool);
+
 #[derive(Debug, thiserror::Error)]
 pub enum DbusApiError {
     #[error("D-Bus not connected")]
@@ -54,6 +62,7 @@ pub struct DbusApi {
     active_profile_changed: Arc<Signal<()>>,
     profiles_changed: Arc<Signal<()>>,
     brightness_changed: Arc<Signal<()>>,
+    persistence_keys_changed: Arc<Signal<()>>,
 }
 
 #[allow(dead_code)]
@@ -96,6 +105,13 @@ impl DbusApi {
         );
         let brightness_changed_signal_clone = brightness_changed_signal.clone();
 
+        let persistence_keys_changed_signal =
+            Arc::new(
+                f.signal("KeysChanged", ())
+                    .sarg::<Vec<(String, String)>, _>("namespaces and keys"),
+            );
+        let persistence_keys_changed_signal_clone = persistence_keys_changed_signal.clone();
+
         let active_slot_property = f
             .property::<u64, _>("ActiveSlot", ())
             .emits_changed(EmitsChangedSignal::Const)
@@ -253,6 +269,41 @@ impl DbusApi {
                                 })
                                 .outarg::<Vec<(u8, u8, u8, u8)>, _>("values"),
                             )
+                            .add_m(
+                                f.method("GetVmStatus", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let s = IntrospectionPlugin::get_vm_status()
+                                            .into_iter()
+                                            .map(|vm| {
+                                                let (name, version) = vm
+                                                    .manifest
+                                                    .map(|m| (m.name, m.version))
+                                                    .unwrap_or_default();
+
+                                                (
+                                                    vm.index as u32,
+                                                    vm.script_file.to_string_lossy().to_string(),
+                                                    name,
+                                                    version,
+                                                    vm.handlers,
+                                                    vm.memory_used as u64,
+                                                    vm.queue_depth as u64,
+                                                    vm.failed,
+                                                )
+                                            })
+                                            .collect::<Vec<_>>();
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<Vec<VmStatusTuple>, _>("status"),
+                            )
                             // .add_m(
                             //     f.method("SetLedColors", (), move |m| {
                             //         *crate::LAST_DBUS_EVENT_TIME.lock() = Instant::now();
@@ -501,6 +552,327 @@ impl DbusApi {
                                     }
                                 })
                                 .outarg::<Vec<(String, String)>, _>("profiles"),
+                            )
+                            .add_m(
+                                f.method("ExportBundle", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let n: &str = m.msg.read1()?;
+
+                                        let s = bundle::export_bundle(Path::new(n))
+                                            .map_err(|e| MethodErr::failed(&e))?;
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("filename")
+                                .outarg::<Vec<u8>, _>("bundle"),
+                            )
+                            .add_m(
+                                f.method("ImportBundle", (), move |m| {
+                                    if perms::has_manage_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (data, overwrite): (Vec<u8>, bool) = m.msg.read2()?;
+
+                                        let s = bundle::import_bundle(&data, overwrite)
+                                            .map_err(|e| MethodErr::failed(&e))?
+                                            .iter()
+                                            .map(|p| p.to_string_lossy().to_string())
+                                            .collect::<Vec<String>>();
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<Vec<u8>, _>("bundle")
+                                .inarg::<bool, _>("overwrite")
+                                .outarg::<Vec<String>, _>("files"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/persistence", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Persistence", ())
+                            .add_s(persistence_keys_changed_signal_clone)
+                            .add_m(
+                                f.method("ListKeys", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let namespace: &str = m.msg.read1()?;
+
+                                        let namespace = if namespace.is_empty() {
+                                            None
+                                        } else {
+                                            Some(namespace)
+                                        };
+
+                                        let s = PersistencePlugin::list_keys(namespace);
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("namespace")
+                                .outarg::<Vec<(String, String)>, _>("keys"),
+                            )
+                            .add_m(
+                                f.method("GetValue", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (namespace, key): (&str, &str) = m.msg.read2()?;
+
+                                        match PersistencePlugin::load_value(namespace, key) {
+                                            Some(value) => {
+                                                let s =
+                                                    persistence::to_json_value(&value).to_string();
+
+                                                Ok(vec![m.msg.method_return().append1(s)])
+                                            }
+
+                                            None => Err(MethodErr::failed("Non existent key")),
+                                        }
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("namespace")
+                                .inarg::<&str, _>("key")
+                                .outarg::<String, _>("value"),
+                            )
+                            .add_m(
+                                f.method("SetValue", (), move |m| {
+                                    if perms::has_settings_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (namespace, key, value): (&str, &str, &str) =
+                                            m.msg.read3()?;
+
+                                        let value =
+                                            serde_json::from_str::<serde_json::Value>(value)
+                                                .map_err(|e| MethodErr::invalid_arg(&e))
+                                                .and_then(|v| {
+                                                    persistence::from_json_value(&v, 0)
+                                                        .map_err(|e| MethodErr::invalid_arg(&e))
+                                                })?;
+
+                                        PersistencePlugin::store_value(
+                                            namespace,
+                                            key.to_owned(),
+                                            value,
+                                        )
+                                        .map_err(|e| MethodErr::failed(&e))?;
+
+                                        let s = true;
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("namespace")
+                                .inarg::<&str, _>("key")
+                                .inarg::<&str, _>("value")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("DeleteKey", (), move |m| {
+                                    if perms::has_settings_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (namespace, key): (&str, &str) = m.msg.read2()?;
+
+                                        let s = PersistencePlugin::delete_key(namespace, key);
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("namespace")
+                                .inarg::<&str, _>("key")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("Reset", (), move |m| {
+                                    if perms::has_settings_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (namespace, prefix): (&str, &str) = m.msg.read2()?;
+
+                                        let namespace = if namespace.is_empty() {
+                                            None
+                                        } else {
+                                            Some(namespace)
+                                        };
+
+                                        let s = PersistencePlugin::reset(namespace, prefix) as u64;
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("namespace")
+                                .inarg::<&str, _>("prefix")
+                                .outarg::<u64, _>("count"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/macros", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Macros", ())
+                            .add_m(
+                                f.method("ListMacros", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let s = MacrosPlugin::list_macros()
+                                            .map_err(|e| MethodErr::failed(&e))?;
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<Vec<String>, _>("macros"),
+                            )
+                            .add_m(
+                                f.method("IsRecording", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let s = MacrosPlugin::is_recording();
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StartRecording", (), move |m| {
+                                    if perms::has_manage_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let name: &str = m.msg.read1()?;
+
+                                        MacrosPlugin::start_recording(name)
+                                            .map_err(|e| MethodErr::failed(&e))?;
+
+                                        let s = true;
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("name")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StopRecording", (), move |m| {
+                                    if perms::has_manage_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let s = MacrosPlugin::stop_recording()
+                                            .map_err(|e| MethodErr::failed(&e))?;
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<String, _>("name"),
+                            )
+                            .add_m(
+                                f.method("PlayMacro", (), move |m| {
+                                    if perms::has_settings_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (name, speed, repeat): (&str, f64, u32) =
+                                            m.msg.read3()?;
+
+                                        MacrosPlugin::play_macro(name, speed, repeat)
+                                            .map_err(|e| MethodErr::failed(&e))?;
+
+                                        let s = true;
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("name")
+                                .inarg::<f64, _>("speed")
+                                .inarg::<u32, _>("repeat")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StopPlayback", (), move |m| {
+                                    if perms::has_settings_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        MacrosPlugin::stop_playback();
+
+                                        let s = true;
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetQueueDepth", (), move |m| {
+                                    if perms::has_monitor_permission(
+                                        &m.msg.sender().unwrap().to_string(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let s = MacrosPlugin::get_queue_depth() as u32;
+
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<u32, _>("depth"),
                             ),
                     ),
             );
@@ -515,6 +887,7 @@ impl DbusApi {
             active_profile_changed: active_profile_changed_signal,
             profiles_changed: profiles_changed_signal,
             brightness_changed: brightness_changed_signal,
+            persistence_keys_changed: persistence_keys_changed_signal,
         })
     }
 
@@ -580,6 +953,18 @@ impl DbusApi {
             .unwrap();
     }
 
+    pub fn notify_persistence_keys_changed(&self, keys: Vec<(String, String)>) {
+        self.connection
+            .as_ref()
+            .unwrap()
+            .send(self.persistence_keys_changed.emit(
+                &"/org/eruption/persistence".into(),
+                &"org.eruption.Persistence".into(),
+                &[keys],
+            ))
+            .unwrap();
+    }
+
     /// Returns true if an event is pending on the D-Bus connection
     pub fn has_pending_event(&self) -> Result<bool> {
         match self.connection {
@@ -640,7 +1025,7 @@ pub fn initialize(dbus_tx: Sender<Message>) -> Result<DbusApi> {
     DbusApi::new(dbus_tx)
 }
 
-mod perms {
+pub mod perms {
     use dbus::{arg::RefArg, arg::Variant, blocking::Connection};
     use std::{collections::HashMap, time::Duration};
 
@@ -650,7 +1035,6 @@ mod perms {
 
     pub fn has_monitor_permission(sender: &str) -> Result<bool> {
         use bus::OrgFreedesktopDBus;
-        use polkit::OrgFreedesktopPolicyKit1Authority;
 
         let conn = Connection::new_system().unwrap();
 
@@ -663,6 +1047,15 @@ mod perms {
         let pid: u32 = dbus_proxy.get_connection_unix_process_id(sender)?;
         let uid: u32 = dbus_proxy.get_connection_unix_user(sender)?;
 
+        has_monitor_permission_for_process(pid, uid)
+    }
+
+    /// Check the monitor permission of a local process, e.g. a client of the event stream socket
+    pub fn has_monitor_permission_for_process(pid: u32, uid: u32) -> Result<bool> {
+        use polkit::OrgFreedesktopPolicyKit1Authority;
+
+        let conn = Connection::new_system().unwrap();
+
         let polkit_proxy = conn.with_proxy(
             "org.freedesktop.PolicyKit1",
             "/org/freedesktop/PolicyKit1/Authority",
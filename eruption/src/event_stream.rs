@@ -0,0 +1,592 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crossbeam::channel::{bounded, Sender, TrySendError};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::constants;
+use crate::dbus_interface::perms;
+use crate::events::{self, Event};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventStreamError {
+    #[error("Could not get the credentials of the peer: {description}")]
+    PeerCredentialsError { description: String },
+
+    #[error("Invalid subscription: {description}")]
+    InvalidSubscription { description: String },
+
+    #[error("Unknown event type: {name}")]
+    UnknownEventType { name: String },
+}
+
+/// Names of the event types, that clients may subscribe to
+const EVENT_TYPES: &[&str] = &[
+    "daemon_startup",
+    "daemon_shutdown",
+    "file_system_event",
+    "keyboard_hid_event",
+    "mouse_hid_event",
+    "raw_keyboard_event",
+    "raw_mouse_event",
+    "key_down",
+    "key_up",
+    "mouse_button_down",
+    "mouse_button_up",
+    "mouse_move",
+    "mouse_wheel_event",
+];
+
+/// Time that a client has to send its subscription, after it has been authorized
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The first line that a client sends after connecting, e.g. `{"events": ["key_down", "key_up"]}`.
+/// Subscribes to all events if `events` is omitted
+#[derive(Debug, Deserialize)]
+struct Subscription {
+    #[serde(default)]
+    events: Option<Vec<String>>,
+}
+
+/// A connected and authorized client of the event stream
+struct Client {
+    /// The subscribed event types, or `None` for all event types
+    filter: Option<HashSet<String>>,
+
+    tx: Sender<Arc<String>>,
+}
+
+lazy_static! {
+    static ref CLIENTS: Arc<Mutex<Vec<Client>>> = Arc::new(Mutex::new(Vec::new()));
+
+    /// The path of the socket, if the event stream has been enabled
+    static ref SOCKET_PATH: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
+}
+
+/// Number of open connections, including clients that have not been authorized yet
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Reserve a slot for a new connection. Returns false if the max. number of clients is reached
+fn acquire_connection() -> bool {
+    if CONNECTIONS.fetch_add(1, Ordering::SeqCst) >= constants::MAX_EVENT_STREAM_CLIENTS {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+
+        false
+    } else {
+        true
+    }
+}
+
+/// Release the slot of a closed connection
+fn release_connection() {
+    CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Returns the name of the type of `event`, as used by subscriptions
+fn event_type(event: &Event) -> &'static str {
+    match event {
+        Event::DaemonStartup => "daemon_startup",
+        Event::DaemonShutdown => "daemon_shutdown",
+        Event::FileSystemEvent(_) => "file_system_event",
+        Event::KeyboardHidEvent(_) => "keyboard_hid_event",
+        Event::MouseHidEvent(_) => "mouse_hid_event",
+        Event::RawKeyboardEvent(_) => "raw_keyboard_event",
+        Event::RawMouseEvent(_) => "raw_mouse_event",
+        Event::KeyDown(_) => "key_down",
+        Event::KeyUp(_) => "key_up",
+        Event::MouseButtonDown(_) => "mouse_button_down",
+        Event::MouseButtonUp(_) => "mouse_button_up",
+        Event::MouseMove(_, _) => "mouse_move",
+        Event::MouseWheelEvent(_) => "mouse_wheel_event",
+    }
+}
+
+/// Serialize `event` to a single line of JSON
+fn to_json_line(event: &Event) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut value = json!({
+        "event": event_type(event),
+        "timestamp": timestamp,
+    });
+
+    let data = match event {
+        Event::DaemonStartup | Event::DaemonShutdown => json!({}),
+
+        Event::FileSystemEvent(e) => json!({ "kind": format!("{:?}", e) }),
+
+        Event::KeyboardHidEvent(e) => json!({ "data": format!("{:?}", e) }),
+        Event::MouseHidEvent(e) => json!({ "data": format!("{:?}", e) }),
+
+        Event::RawKeyboardEvent(e) | Event::RawMouseEvent(e) => json!({
+            "type": format!("{:?}", e.event_type),
+            "code": format!("{:?}", e.event_code),
+            "value": e.value,
+        }),
+
+        Event::KeyDown(index) | Event::KeyUp(index) => json!({ "key_index": index }),
+
+        Event::MouseButtonDown(index) | Event::MouseButtonUp(index) => {
+            json!({ "button_index": index })
+        }
+
+        Event::MouseMove(direction, delta) => json!({
+            "direction": direction,
+            "delta": delta,
+        }),
+
+        Event::MouseWheelEvent(direction) => json!({ "direction": direction }),
+    };
+
+    if let (Some(value), Some(data)) = (value.as_object_mut(), data.as_object()) {
+        value.extend(data.clone());
+    }
+
+    let mut line = value.to_string();
+    line.push('\n');
+
+    line
+}
+
+/// Send `event` to all subscribed clients. Events are dropped for clients that don't keep up,
+/// so that a slow client won't ever stall the input handling of the daemon
+fn dispatch_event(event: &Event) {
+    let mut clients = CLIENTS.lock();
+
+    if clients.is_empty() {
+        return;
+    }
+
+    let name = event_type(event);
+    let mut line = None;
+
+    clients.retain(|client| {
+        if client.filter.as_ref().map_or(true, |f| f.contains(name)) {
+            let line = line
+                .get_or_insert_with(|| Arc::new(to_json_line(event)))
+                .clone();
+
+            match client.tx.try_send(line) {
+                Ok(()) => true,
+
+                Err(TrySendError::Full(_)) => {
+                    trace!("Dropped an event for a slow client of the event stream");
+                    true
+                }
+
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        } else {
+            true
+        }
+    });
+}
+
+/// Returns the process id and user id of the peer of `stream`
+fn peer_credentials(stream: &UnixStream) -> Result<(u32, u32)> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if result == 0 {
+        Ok((credentials.pid as u32, credentials.uid))
+    } else {
+        Err(EventStreamError::PeerCredentialsError {
+            description: format!("{}", std::io::Error::last_os_error()),
+        }
+        .into())
+    }
+}
+
+/// Parse the subscription of a client
+fn parse_subscription(line: &str) -> Result<Option<HashSet<String>>> {
+    let subscription: Subscription =
+        serde_json::from_str(line).map_err(|e| EventStreamError::InvalidSubscription {
+            description: format!("{}", e),
+        })?;
+
+    match subscription.events {
+        Some(events) => {
+            for name in events.iter() {
+                if !EVENT_TYPES.contains(&name.as_str()) {
+                    return Err(EventStreamError::UnknownEventType { name: name.clone() }.into());
+                }
+            }
+
+            Ok(Some(events.into_iter().collect()))
+        }
+
+        None => Ok(None),
+    }
+}
+
+/// Authorize a newly connected client, read its subscription, and stream events to it
+fn handle_client(mut stream: UnixStream) -> Result<()> {
+    let (pid, uid) = peer_credentials(&stream)?;
+
+    if !perms::has_monitor_permission_for_process(pid, uid).unwrap_or(false) {
+        warn!(
+            "Event stream: Denied access to process {} (uid: {}), authentication failed",
+            pid, uid
+        );
+
+        writeln!(stream, "{}", json!({ "error": "Authentication failed" }))?;
+
+        return Ok(());
+    }
+
+    stream.set_read_timeout(Some(SUBSCRIPTION_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+
+    let filter = match parse_subscription(&line) {
+        Ok(filter) => filter,
+
+        Err(e) => {
+            writeln!(stream, "{}", json!({ "error": format!("{}", e) }))?;
+
+            return Err(e);
+        }
+    };
+
+    info!(
+        "Event stream: Process {} (uid: {}) subscribed to: {:?}",
+        pid, uid, filter
+    );
+
+    let (tx, rx) = bounded(constants::EVENT_STREAM_QUEUE_SIZE);
+
+    CLIENTS.lock().push(Client { filter, tx });
+
+    // the client is removed from the list of clients when the receiver is dropped
+    for line in rx.iter() {
+        if let Err(e) = stream.write_all(line.as_bytes()) {
+            debug!("Event stream: Process {} disconnected: {}", pid, e);
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the configured path of the event stream socket, if the event stream has been enabled
+fn get_socket_path() -> Option<PathBuf> {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref()?;
+
+    if config.get_bool("event_stream.enabled").unwrap_or(false) {
+        Some(PathBuf::from(
+            config
+                .get_str("event_stream.socket")
+                .unwrap_or_else(|_| constants::DEFAULT_EVENT_SOCKET.to_string()),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Create the socket at `path`, replacing a stale socket of a previous run
+fn bind_socket(path: &Path) -> Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    // access is controlled by the monitor permission of PolicyKit
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+
+    Ok(listener)
+}
+
+/// Start streaming events to the clients of the event stream socket, if enabled in `eruption.conf`
+pub fn initialize() -> Result<()> {
+    let path = match get_socket_path() {
+        Some(path) => path,
+
+        None => return Ok(()),
+    };
+
+    let listener = bind_socket(&path)?;
+
+    info!("Streaming events to the socket: {}", path.display());
+
+    *SOCKET_PATH.lock() = Some(path);
+
+    events::register_observer(|event: &Event| {
+        dispatch_event(event);
+
+        if let Event::DaemonShutdown = event {
+            shutdown();
+        }
+
+        Ok(true) // event has been processed
+    });
+
+    thread::Builder::new()
+        .name("event-stream".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if !acquire_connection() {
+                            warn!("Event stream: Too many clients, dropped a connection");
+
+                            continue;
+                        }
+
+                        let result = thread::Builder::new()
+                            .name("event-stream/client".into())
+                            .spawn(move || {
+                                handle_client(stream).unwrap_or_else(|e| {
+                                    warn!("Event stream: Client failed: {}", e)
+                                });
+
+                                release_connection();
+                            });
+
+                        if let Err(e) = result {
+                            release_connection();
+
+                            error!("Event stream: Could not spawn a thread: {}", e);
+                        }
+                    }
+
+                    Err(e) => error!("Event stream: Could not accept a connection: {}", e),
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Disconnect all clients and remove the socket
+fn shutdown() {
+    CLIENTS.lock().clear();
+
+    if let Some(path) = SOCKET_PATH.lock().take() {
+        fs::remove_file(&path).unwrap_or_else(|e| {
+            warn!(
+                "Could not remove the event stream socket {}: {}",
+                path.display(),
+                e
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::Receiver;
+
+    fn client(filter: Option<&[&str]>, capacity: usize) -> (Client, Receiver<Arc<String>>) {
+        let (tx, rx) = bounded(capacity);
+
+        let filter = filter.map(|f| f.iter().map(|s| s.to_string()).collect());
+
+        (Client { filter, tx }, rx)
+    }
+
+    fn received(rx: &Receiver<Arc<String>>) -> Vec<serde_json::Value> {
+        rx.try_iter()
+            .map(|line| serde_json::from_str(&line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_event_type() {
+        let events = [
+            Event::DaemonStartup,
+            Event::DaemonShutdown,
+            Event::KeyDown(1),
+            Event::KeyUp(1),
+            Event::MouseButtonDown(1),
+            Event::MouseButtonUp(1),
+            Event::MouseMove(1, -3),
+            Event::MouseWheelEvent(1),
+        ];
+
+        for event in events.iter() {
+            assert!(EVENT_TYPES.contains(&event_type(event)));
+        }
+    }
+
+    #[test]
+    fn test_to_json_line() {
+        let line = to_json_line(&Event::MouseMove(2, -15));
+
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.lines().count(), 1);
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["event"], "mouse_move");
+        assert_eq!(value["direction"], 2);
+        assert_eq!(value["delta"], -15);
+        assert!(value["timestamp"].as_u64().unwrap() > 0);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&to_json_line(&Event::KeyDown(42))).unwrap();
+
+        assert_eq!(value["event"], "key_down");
+        assert_eq!(value["key_index"], 42);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&to_json_line(&Event::DaemonStartup)).unwrap();
+
+        assert_eq!(value.as_object().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_subscription() {
+        assert_eq!(parse_subscription("{}\n").unwrap(), None);
+
+        let filter = parse_subscription(r#"{"events": ["key_down", "key_up"]}"#)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(filter.len(), 2);
+        assert!(filter.contains("key_down") && filter.contains("key_up"));
+
+        assert!(parse_subscription(r#"{"events": ["key_down", "unknown"]}"#).is_err());
+        assert!(parse_subscription(r#"{"events": "key_down"}"#).is_err());
+        assert!(parse_subscription("").is_err());
+    }
+
+    #[test]
+    fn test_dispatch_event() {
+        let (all, all_rx) = client(None, 16);
+        let (keys, keys_rx) = client(Some(&["key_down"]), 16);
+        let (slow, slow_rx) = client(None, 1);
+        let (gone, gone_rx) = client(None, 16);
+
+        drop(gone_rx);
+
+        CLIENTS.lock().extend(vec![all, keys, slow, gone]);
+
+        dispatch_event(&Event::KeyDown(1));
+        dispatch_event(&Event::KeyUp(1));
+
+        // disconnected clients are removed, slow clients are kept
+        assert_eq!(CLIENTS.lock().len(), 3);
+
+        let events = |values: Vec<serde_json::Value>| -> Vec<String> {
+            values
+                .iter()
+                .map(|v| v["event"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(events(received(&all_rx)), vec!["key_down", "key_up"]);
+        assert_eq!(events(received(&keys_rx)), vec!["key_down"]);
+        assert_eq!(events(received(&slow_rx)), vec!["key_down"]);
+
+        CLIENTS.lock().clear();
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        for _ in 0..constants::MAX_EVENT_STREAM_CLIENTS {
+            assert!(acquire_connection());
+        }
+
+        assert!(!acquire_connection());
+        assert_eq!(
+            CONNECTIONS.load(Ordering::SeqCst),
+            constants::MAX_EVENT_STREAM_CLIENTS
+        );
+
+        release_connection();
+        assert!(acquire_connection());
+
+        for _ in 0..constants::MAX_EVENT_STREAM_CLIENTS {
+            release_connection();
+        }
+
+        assert_eq!(CONNECTIONS.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_peer_credentials() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+
+        let (pid, uid) = peer_credentials(&stream).unwrap();
+
+        assert_eq!(pid, std::process::id());
+        assert_eq!(uid, unsafe { libc::getuid() });
+    }
+
+    #[test]
+    fn test_bind_socket() {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-{}-event-stream", std::process::id()));
+        let path = dir.join("run").join("events.sock");
+
+        let listener = bind_socket(&path).unwrap();
+        drop(listener);
+
+        // a stale socket of a previous run is replaced
+        let _listener = bind_socket(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+
+        UnixStream::connect(&path).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
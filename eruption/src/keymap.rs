@@ -0,0 +1,481 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use evdev_rs::enums::{int_to_ev_key, EventCode, EventType};
+use evdev_rs::InputEvent;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+use crate::plugins::macros::{self, MacrosPlugin};
+use crate::profiles::{Keymap, LayerMode};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeymapError {
+    #[error("Unknown key: {name}")]
+    UnknownKey { name: String },
+
+    #[error("Invalid remap target: {target}")]
+    InvalidTarget { target: String },
+
+    #[error("The key {name} activates more than one layer")]
+    DuplicateLayerKey { name: String },
+}
+
+/// Highest evdev key code that is looked up by name
+const MAX_KEY_CODE: u32 = 0x2ff;
+
+/// Prefix of remap targets that play a recorded macro
+const MACRO_PREFIX: &str = "macro:";
+
+/// Range of evdev key codes that are sent to the virtual mouse, instead of the virtual keyboard
+const BUTTON_CODES: std::ops::Range<u32> = 0x100..0x160;
+
+/// The action that a key or button has been mapped to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Action {
+    /// Press the keys or buttons in order, and release them in reverse order
+    Keys(Vec<u32>),
+
+    /// Play a recorded macro when the key is pressed
+    Macro(String),
+
+    /// Drop the key
+    Disabled,
+}
+
+#[derive(Debug)]
+struct Layer {
+    name: String,
+    key: u32,
+    mode: LayerMode,
+    remap: HashMap<u32, Action>,
+}
+
+/// A keymap of a profile, with all key names resolved to evdev key codes
+#[derive(Debug)]
+pub struct CompiledKeymap {
+    base: HashMap<u32, Action>,
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Default)]
+struct KeymapState {
+    keymap: Option<CompiledKeymap>,
+
+    /// Indices of the active layers, in the order of their activation
+    active_layers: Vec<usize>,
+
+    /// Actions of the keys that are currently held down, so that they are
+    /// released correctly, even if the active layers change in the meantime
+    pressed: HashMap<u32, Action>,
+}
+
+lazy_static! {
+    /// Maps evdev key names like "KEY_A" to key codes
+    static ref KEY_NAMES: HashMap<String, u32> = (0..=MAX_KEY_CODE)
+        .filter_map(|code| int_to_ev_key(code).map(|key| (format!("{:?}", key), code)))
+        .collect();
+
+    static ref KEYMAP: Mutex<KeymapState> = Mutex::new(KeymapState::default());
+}
+
+/// Parse a key, specified either by its evdev name or by its numeric key code
+pub(crate) fn parse_key(name: &str) -> Result<u32> {
+    let name = name.trim();
+
+    let code = match name.parse::<u32>() {
+        Ok(code) if int_to_ev_key(code).is_some() => Some(code),
+
+        Ok(_) => None,
+
+        Err(_) => KEY_NAMES.get(&name.to_uppercase()).cloned(),
+    };
+
+    code.ok_or_else(|| {
+        KeymapError::UnknownKey {
+            name: name.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Parse a remap target: a key or button, a combination like "KEY_LEFTCTRL+KEY_C",
+/// a recorded macro like "macro:name", or "none"
+pub(crate) fn parse_action(target: &str) -> Result<Action> {
+    let target = target.trim();
+
+    if target.eq_ignore_ascii_case("none") {
+        Ok(Action::Disabled)
+    } else if let Some(name) = target.strip_prefix(MACRO_PREFIX) {
+        if name.is_empty() {
+            Err(KeymapError::InvalidTarget {
+                target: target.to_string(),
+            }
+            .into())
+        } else {
+            Ok(Action::Macro(name.to_string()))
+        }
+    } else {
+        let keys = target
+            .split('+')
+            .map(parse_key)
+            .collect::<Result<Vec<u32>>>()?;
+
+        Ok(Action::Keys(keys))
+    }
+}
+
+pub(crate) fn compile_remap(remap: &HashMap<String, String>) -> Result<HashMap<u32, Action>> {
+    remap
+        .iter()
+        .map(|(key, target)| Ok((parse_key(key)?, parse_action(target)?)))
+        .collect()
+}
+
+/// Resolve all key names of `keymap`, so that errors are detected before a profile is activated
+pub fn compile(keymap: &Keymap) -> Result<CompiledKeymap> {
+    let mut layers: Vec<Layer> = Vec::new();
+
+    for layer in keymap.layers.iter() {
+        let key = parse_key(&layer.key)?;
+
+        if layers.iter().any(|l| l.key == key) {
+            return Err(KeymapError::DuplicateLayerKey {
+                name: layer.key.clone(),
+            }
+            .into());
+        }
+
+        layers.push(Layer {
+            name: layer.name.clone(),
+            key,
+            mode: layer.mode,
+            remap: compile_remap(&layer.remap)?,
+        });
+    }
+
+    Ok(CompiledKeymap {
+        base: compile_remap(&keymap.remap)?,
+        layers,
+    })
+}
+
+/// Activate `keymap`, or disable remapping if it is `None`
+pub fn apply_keymap(keymap: Option<CompiledKeymap>) {
+    let mut state = KEYMAP.lock();
+
+    // don't leave remapped keys stuck in the pressed state
+    for action in state.pressed.values() {
+        release(action);
+    }
+
+    *state = KeymapState {
+        keymap,
+        ..Default::default()
+    };
+}
+
+/// Activate or deactivate the layer `name` of the active keymap, e.g. while
+/// the shift button of the mouse is held down
+pub fn set_layer_active(name: &str, active: bool) {
+    let mut state = KEYMAP.lock();
+    let KeymapState {
+        keymap,
+        active_layers,
+        ..
+    } = &mut *state;
+
+    if let Some(index) = keymap
+        .as_ref()
+        .and_then(|k| k.layers.iter().position(|l| l.name == name))
+    {
+        active_layers.retain(|i| *i != index);
+
+        if active {
+            active_layers.push(index);
+        }
+    }
+}
+
+/// Inject a key on the virtual keyboard, or a button on the virtual mouse
+fn inject_key(key: u32, down: bool) {
+    let message = if BUTTON_CODES.contains(&key) {
+        macros::Message::MirrorMouseEventImmediate(InputEvent {
+            time: MacrosPlugin::current_time(),
+            event_type: EventType::EV_KEY,
+            event_code: EventCode::EV_KEY(int_to_ev_key(key).unwrap()),
+            value: if down { 1 } else { 0 },
+        })
+    } else {
+        macros::Message::InjectKey { key, down }
+    };
+
+    macros::UINPUT_TX
+        .lock()
+        .as_ref()
+        .unwrap()
+        .send(message)
+        .unwrap_or_else(|e| error!("Could not send a pending input event: {}", e));
+}
+
+pub(crate) fn press(action: &Action) {
+    match action {
+        Action::Keys(keys) => {
+            for key in keys.iter() {
+                inject_key(*key, true);
+            }
+        }
+
+        Action::Macro(name) => MacrosPlugin::play_macro(name, 1.0, 1)
+            .unwrap_or_else(|e| error!("Could not play the macro '{}': {}", name, e)),
+
+        Action::Disabled => {}
+    }
+}
+
+pub(crate) fn release(action: &Action) {
+    if let Action::Keys(keys) = action {
+        for key in keys.iter().rev() {
+            inject_key(*key, false);
+        }
+    }
+}
+
+/// Apply the active keymap to a raw event of the hardware keyboard. Returns true if
+/// the event has been handled, in which case it must not be mirrored to the virtual keyboard
+pub fn process_key_event(raw_event: &evdev_rs::InputEvent) -> bool {
+    let code = match &raw_event.event_code {
+        EventCode::EV_KEY(key) => key.clone() as u32,
+
+        _ => return false,
+    };
+
+    let mut state = KEYMAP.lock();
+    let KeymapState {
+        keymap,
+        active_layers,
+        pressed,
+    } = &mut *state;
+
+    let keymap = match keymap.as_ref() {
+        Some(keymap) => keymap,
+
+        None => return false,
+    };
+
+    let layer_index = keymap.layers.iter().position(|l| l.key == code);
+
+    match raw_event.value {
+        // key down
+        1 => {
+            if let Some(index) = layer_index {
+                let is_active = active_layers.contains(&index);
+
+                active_layers.retain(|i| *i != index);
+
+                if keymap.layers[index].mode == LayerMode::Momentary || !is_active {
+                    active_layers.push(index);
+                }
+
+                debug!(
+                    "Active keymap layers: {:?}",
+                    active_layers
+                        .iter()
+                        .map(|i| &keymap.layers[*i].name)
+                        .collect::<Vec<_>>()
+                );
+
+                return true;
+            }
+
+            let action = active_layers
+                .iter()
+                .rev()
+                .find_map(|index| keymap.layers[*index].remap.get(&code))
+                .or_else(|| keymap.base.get(&code))
+                .cloned();
+
+            match action {
+                Some(action) => {
+                    press(&action);
+                    pressed.insert(code, action);
+
+                    true
+                }
+
+                None => false,
+            }
+        }
+
+        // key up
+        0 => {
+            if let Some(index) = layer_index {
+                if keymap.layers[index].mode == LayerMode::Momentary {
+                    active_layers.retain(|i| *i != index);
+                }
+
+                return true;
+            }
+
+            match pressed.remove(&code) {
+                Some(action) => {
+                    release(&action);
+
+                    true
+                }
+
+                None => false,
+            }
+        }
+
+        // key repeat, the virtual keyboard only receives repeats of keys that are not remapped
+        _ => layer_index.is_some() || pressed.contains_key(&code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::KeymapLayer;
+    use evdev_rs::enums::EV_KEY;
+    use evdev_rs::TimeVal;
+
+    fn key_event(key: EV_KEY, value: i32) -> InputEvent {
+        InputEvent {
+            time: TimeVal::new(0, 0),
+            event_type: EventType::EV_KEY,
+            event_code: EventCode::EV_KEY(key),
+            value,
+        }
+    }
+
+    fn active_layer_names() -> Vec<String> {
+        let state = KEYMAP.lock();
+        let keymap = state.keymap.as_ref().unwrap();
+
+        state
+            .active_layers
+            .iter()
+            .map(|i| keymap.layers[*i].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("KEY_A").unwrap(), Action::Keys(vec![30]));
+        assert_eq!(parse_action(" key_a ").unwrap(), Action::Keys(vec![30]));
+        assert_eq!(parse_action("30").unwrap(), Action::Keys(vec![30]));
+        assert_eq!(parse_action("BTN_LEFT").unwrap(), Action::Keys(vec![0x110]));
+
+        assert_eq!(
+            parse_action("KEY_LEFTCTRL+KEY_C").unwrap(),
+            Action::Keys(vec![29, 46])
+        );
+
+        assert_eq!(
+            parse_action("macro:build-order").unwrap(),
+            Action::Macro("build-order".to_string())
+        );
+
+        assert_eq!(parse_action("none").unwrap(), Action::Disabled);
+        assert_eq!(parse_action("None").unwrap(), Action::Disabled);
+
+        assert!(parse_action("macro:").is_err());
+        assert!(parse_action("KEY_DOES_NOT_EXIST").is_err());
+        assert!(parse_action("KEY_LEFTCTRL+").is_err());
+        assert!(parse_action("").is_err());
+    }
+
+    #[test]
+    fn test_compile_duplicate_layer_key() {
+        let layer = |name: &str| KeymapLayer {
+            name: name.to_string(),
+            key: "KEY_CAPSLOCK".to_string(),
+            mode: LayerMode::Momentary,
+            remap: HashMap::new(),
+        };
+
+        let keymap = Keymap {
+            remap: HashMap::new(),
+            layers: vec![layer("first"), layer("second")],
+        };
+
+        assert!(compile(&keymap).is_err());
+    }
+
+    #[test]
+    fn test_layer_toggling() {
+        let keymap = Keymap {
+            remap: HashMap::new(),
+            layers: vec![
+                KeymapLayer {
+                    name: "momentary".to_string(),
+                    key: "KEY_CAPSLOCK".to_string(),
+                    mode: LayerMode::Momentary,
+                    remap: HashMap::new(),
+                },
+                KeymapLayer {
+                    name: "toggle".to_string(),
+                    key: "KEY_RIGHTALT".to_string(),
+                    mode: LayerMode::Toggle,
+                    remap: HashMap::new(),
+                },
+            ],
+        };
+
+        apply_keymap(Some(compile(&keymap).unwrap()));
+        assert!(active_layer_names().is_empty());
+
+        // a momentary layer is active while its key is held down
+        assert!(process_key_event(&key_event(EV_KEY::KEY_CAPSLOCK, 1)));
+        assert_eq!(active_layer_names(), vec!["momentary"]);
+
+        assert!(process_key_event(&key_event(EV_KEY::KEY_CAPSLOCK, 2)));
+        assert!(process_key_event(&key_event(EV_KEY::KEY_CAPSLOCK, 0)));
+        assert!(active_layer_names().is_empty());
+
+        // a toggle layer is switched on and off by presses of its key
+        assert!(process_key_event(&key_event(EV_KEY::KEY_RIGHTALT, 1)));
+        assert!(process_key_event(&key_event(EV_KEY::KEY_RIGHTALT, 0)));
+        assert_eq!(active_layer_names(), vec!["toggle"]);
+
+        // the most recently activated layer takes precedence
+        assert!(process_key_event(&key_event(EV_KEY::KEY_CAPSLOCK, 1)));
+        assert_eq!(active_layer_names(), vec!["toggle", "momentary"]);
+        assert!(process_key_event(&key_event(EV_KEY::KEY_CAPSLOCK, 0)));
+
+        assert!(process_key_event(&key_event(EV_KEY::KEY_RIGHTALT, 1)));
+        assert!(process_key_event(&key_event(EV_KEY::KEY_RIGHTALT, 0)));
+        assert!(active_layer_names().is_empty());
+
+        // layers may also be switched externally, e.g. by the mouse
+        set_layer_active("toggle", true);
+        assert_eq!(active_layer_names(), vec!["toggle"]);
+        set_layer_active("toggle", false);
+        assert!(active_layer_names().is_empty());
+
+        // keys without a remap are not handled by the keymap
+        assert!(!process_key_event(&key_event(EV_KEY::KEY_A, 1)));
+        assert!(!process_key_event(&key_event(EV_KEY::KEY_A, 0)));
+
+        apply_keymap(None);
+    }
+}
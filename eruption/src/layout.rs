@@ -0,0 +1,323 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Arc;
+
+use crate::keymap;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error("Unknown keyboard layout: {name}")]
+    UnknownLayout { name: String },
+
+    #[error("Could not parse the keyboard layout '{name}': {description}")]
+    ParseError { name: String, description: String },
+
+    #[error("The character {c:?} can not be typed with the keyboard layout '{layout}'")]
+    UnmappedCharacter { c: char, layout: String },
+}
+
+/// The keyboard layout that is used if none has been configured
+pub const DEFAULT_LAYOUT: &str = "us";
+
+/// Layouts that are compiled into the daemon
+const BUILTIN_LAYOUTS: &[(&str, &str)] = &[
+    ("us", include_str!("layouts/us.toml")),
+    ("de", include_str!("layouts/de.toml")),
+];
+
+/// Number of XKB shift levels that are supported: plain, Shift, AltGr and Shift + AltGr
+const NUM_LEVELS: usize = 4;
+
+/// Modifier keys that select the shift levels
+const KEY_LEFTSHIFT: u32 = 42;
+const KEY_RIGHTALT: u32 = 100;
+
+lazy_static! {
+    /// The keyboard layout of the system, loaded on first use
+    static ref LAYOUT: RwLock<Option<Arc<Layout>>> = RwLock::new(None);
+}
+
+/// A layout file, as stored on disk
+#[derive(Debug, Deserialize)]
+struct LayoutFile {
+    name: String,
+
+    /// Maps evdev key names to the characters of their shift levels
+    keys: BTreeMap<String, Vec<String>>,
+}
+
+/// A key together with the modifiers that are required to type a character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStroke {
+    pub key: u32,
+    pub level: usize,
+}
+
+impl KeyStroke {
+    /// The modifier keys that have to be held down while `key` is pressed
+    pub fn modifiers(&self) -> Vec<u32> {
+        let mut result = Vec::new();
+
+        if self.level & 1 != 0 {
+            result.push(KEY_LEFTSHIFT);
+        }
+
+        if self.level & 2 != 0 {
+            result.push(KEY_RIGHTALT);
+        }
+
+        result
+    }
+}
+
+/// Translates characters to key strokes and back, for a specific keyboard layout
+#[derive(Debug)]
+pub struct Layout {
+    pub name: String,
+    strokes: HashMap<char, KeyStroke>,
+    chars: HashMap<(u32, usize), char>,
+}
+
+impl Layout {
+    /// Parse a layout from the text of a layout file
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let file: LayoutFile = toml::de::from_str(text).map_err(|e| LayoutError::ParseError {
+            name: name.to_string(),
+            description: format!("{}", e),
+        })?;
+
+        let mut entries = Vec::new();
+
+        for (key_name, levels) in file.keys.iter() {
+            let key = keymap::parse_key(key_name)?;
+
+            if levels.len() > NUM_LEVELS {
+                return Err(LayoutError::ParseError {
+                    name: name.to_string(),
+                    description: format!(
+                        "The key {} has more than {} levels",
+                        key_name, NUM_LEVELS
+                    ),
+                }
+                .into());
+            }
+
+            for (level, s) in levels.iter().enumerate() {
+                let mut chars = s.chars();
+
+                match (chars.next(), chars.next()) {
+                    (None, _) => {}
+
+                    (Some(c), None) => entries.push((level, key, c)),
+
+                    _ => {
+                        return Err(LayoutError::ParseError {
+                            name: name.to_string(),
+                            description: format!(
+                                "The key {} produces more than one character: {:?}",
+                                key_name, s
+                            ),
+                        }
+                        .into())
+                    }
+                }
+            }
+        }
+
+        // prefer the lowest level and key code, if a character may be typed in more than one way
+        entries.sort();
+
+        let mut strokes = HashMap::new();
+        let mut chars = HashMap::new();
+
+        for (level, key, c) in entries {
+            strokes.entry(c).or_insert(KeyStroke { key, level });
+            chars.insert((key, level), c);
+        }
+
+        Ok(Self {
+            name: file.name,
+            strokes,
+            chars,
+        })
+    }
+
+    /// Load the layout `name`, either one of the built-in layouts or the path of a layout file
+    pub fn load(name: &str) -> Result<Self> {
+        if let Some((_, text)) = BUILTIN_LAYOUTS.iter().find(|(n, _)| *n == name) {
+            Self::parse(name, text)
+        } else if name.contains('/') {
+            let text = fs::read_to_string(name).map_err(|_| LayoutError::UnknownLayout {
+                name: name.to_string(),
+            })?;
+
+            Self::parse(name, &text)
+        } else {
+            Err(LayoutError::UnknownLayout {
+                name: name.to_string(),
+            }
+            .into())
+        }
+    }
+
+    /// Translate `text` to the key strokes that type it
+    pub fn key_strokes(&self, text: &str) -> Result<Vec<KeyStroke>> {
+        text.chars()
+            // treat Windows style line endings like Unix ones
+            .filter(|c| *c != '\r')
+            .map(|c| {
+                self.strokes.get(&c).cloned().ok_or_else(|| {
+                    LayoutError::UnmappedCharacter {
+                        c,
+                        layout: self.name.clone(),
+                    }
+                    .into()
+                })
+            })
+            .collect()
+    }
+
+    /// The character that is typed by `key`, while the modifiers are in the given state.
+    /// The state of CapsLock is not taken into account
+    pub fn to_char(&self, key: u32, shift: bool, altgr: bool) -> Option<char> {
+        let level = (shift as usize) | ((altgr as usize) << 1);
+
+        self.chars.get(&(key, level)).cloned()
+    }
+}
+
+/// Returns the keyboard layout of the system, as configured by `global.keyboard_layout`
+pub fn get_layout() -> Result<Arc<Layout>> {
+    if let Some(layout) = LAYOUT.read().as_ref() {
+        return Ok(layout.clone());
+    }
+
+    let name = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|c| c.get_str("global.keyboard_layout").ok())
+        .unwrap_or_else(|| DEFAULT_LAYOUT.to_string());
+
+    let layout = Arc::new(Layout::load(&name)?);
+
+    info!("Loaded the keyboard layout: {}", layout.name);
+
+    *LAYOUT.write() = Some(layout.clone());
+
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type `text` with the layout `name`, and translate the key strokes back to characters
+    fn round_trip(name: &str, text: &str) -> String {
+        let layout = Layout::load(name).unwrap();
+
+        layout
+            .key_strokes(text)
+            .unwrap()
+            .iter()
+            .map(|s| {
+                layout
+                    .to_char(s.key, s.level & 1 != 0, s.level & 2 != 0)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_layouts() {
+        for (name, _) in BUILTIN_LAYOUTS.iter() {
+            let layout = Layout::load(name).unwrap();
+
+            assert_eq!(layout.name, *name);
+
+            for (c, stroke) in layout.strokes.iter() {
+                assert_eq!(
+                    layout.to_char(stroke.key, stroke.level & 1 != 0, stroke.level & 2 != 0),
+                    Some(*c)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_us() {
+        let text = "Hello, World! 1 + 2 = 3; a_b-c@example.com (#42)\n\t~`'\"[]{}|\\<>?/";
+
+        assert_eq!(round_trip("us", text), text);
+        assert_eq!(round_trip("us", "one\r\ntwo"), "one\ntwo");
+    }
+
+    #[test]
+    fn test_round_trip_de() {
+        let text = "Größe: 12,5 m² über Äpfel & Öl; \"Zeile\" #1 {a|b} [c] \\ @ ~ ß?\n\t";
+
+        assert_eq!(round_trip("de", text), text);
+    }
+
+    #[test]
+    fn test_key_strokes() {
+        let us = Layout::load("us").unwrap();
+        let de = Layout::load("de").unwrap();
+
+        // KEY_Y and KEY_Z are swapped on German keyboards
+        assert_eq!(
+            us.key_strokes("y").unwrap(),
+            vec![KeyStroke { key: 21, level: 0 }]
+        );
+        assert_eq!(
+            de.key_strokes("y").unwrap(),
+            vec![KeyStroke { key: 44, level: 0 }]
+        );
+
+        // '@' requires Shift on US keyboards, and AltGr on German keyboards
+        let at = us.key_strokes("@").unwrap()[0];
+        assert_eq!(at, KeyStroke { key: 3, level: 1 });
+        assert_eq!(at.modifiers(), vec![KEY_LEFTSHIFT]);
+
+        let at = de.key_strokes("@").unwrap()[0];
+        assert_eq!(at, KeyStroke { key: 16, level: 2 });
+        assert_eq!(at.modifiers(), vec![KEY_RIGHTALT]);
+
+        assert!(us.key_strokes("ä").is_err());
+        assert!(Layout::load("xx").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Layout::parse("test", "name = \"test\"\n[keys]\nKEY_A = [\"ab\"]").is_err());
+        assert!(Layout::parse("test", "name = \"test\"\n[keys]\nKEY_FOO = [\"a\"]").is_err());
+
+        assert!(Layout::parse(
+            "test",
+            "name = \"test\"\n[keys]\nKEY_A = [\"a\", \"b\", \"c\", \"d\", \"e\"]"
+        )
+        .is_err());
+    }
+}
@@ -35,25 +35,35 @@ use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::u64;
-use std::{collections::HashSet, thread};
+use std::{
+    collections::{HashMap, HashSet},
+    thread,
+};
 
 mod util;
 
 mod hwdevices;
 use hwdevices::{KeyboardDevice, KeyboardHidEvent, MouseDevice, MouseHidEvent};
 
+mod bundle;
 mod constants;
 mod dbus_interface;
+mod event_stream;
 mod events;
+mod keymap;
+mod layout;
+mod mousemap;
 mod plugin_manager;
 mod plugins;
 mod profiles;
+mod script_test;
 mod scripting;
 mod state;
 
 use plugins::macros;
 use profiles::Profile;
-use scripting::manifest::Manifest;
+use scripting::manifest::{self, Manifest};
+use scripting::native;
 use scripting::script;
 
 lazy_static! {
@@ -252,6 +262,8 @@ fn spawn_dbus_api_thread(
         .spawn(move || -> Result<()> {
             let dbus = dbus_interface::initialize(dbus_tx)?;
 
+            let mut last_persistence_signal = Instant::now();
+
             loop {
                 // process events, destined for the dbus api
                 match dbus_api_rx.recv_timeout(Duration::from_millis(0)) {
@@ -269,6 +281,18 @@ fn spawn_dbus_api_thread(
                     Err(_e) => (),
                 }
 
+                // notify subscribers of modified keys of the persistent store, at a limited rate
+                if last_persistence_signal.elapsed()
+                    >= Duration::from_millis(constants::DBUS_PERSISTENCE_SIGNAL_MILLIS)
+                {
+                    last_persistence_signal = Instant::now();
+
+                    let changed_keys = plugins::persistence::take_changed_keys();
+                    if !changed_keys.is_empty() {
+                        dbus.notify_persistence_keys_changed(changed_keys);
+                    }
+                }
+
                 dbus.get_next_event_timeout(25)
                     .unwrap_or_else(|e| error!("Could not get the next D-Bus event: {}", e));
             }
@@ -385,6 +409,19 @@ fn spawn_keyboard_input_thread(
     Ok(())
 }
 
+/// Apply the mouse configuration of the active profile to a pointer motion or `SYN_REPORT`
+/// event, and directly mirror the resulting events to the virtual mouse
+fn mirror_motion_events(raw_event: &evdev_rs::InputEvent) {
+    for event in mousemap::process_motion_event(raw_event) {
+        macros::UINPUT_TX
+            .lock()
+            .as_ref()
+            .unwrap()
+            .send(macros::Message::MirrorMouseEventImmediate(event))
+            .unwrap_or_else(|e| error!("Could not send a pending mouse event: {}", e));
+    }
+}
+
 /// Spawns the mouse events thread and executes it's main loop
 fn spawn_mouse_input_thread(
     mouse_tx: Sender<Option<evdev_rs::InputEvent>>,
@@ -466,20 +503,16 @@ fn spawn_mouse_input_thread(
                                 && code != evdev_rs::enums::EV_REL::REL_HWHEEL_HI_RES
                             {
                                 // directly mirror pointer motion events to reduce input lag.
-                                // This currently prohibits further manipulation of pointer motion events
+                                // Only the mouse configuration of the profile may manipulate them
                                 if GRAB_MOUSE.load(Ordering::SeqCst) {
-                                    macros::UINPUT_TX
-                                        .lock()
-                                        .as_ref()
-                                        .unwrap()
-                                        .send(macros::Message::MirrorMouseEventImmediate(
-                                            k.1.clone(),
-                                        ))
-                                        .unwrap_or_else(|e| {
-                                            error!("Could not send a pending mouse event: {}", e)
-                                        });
+                                    mirror_motion_events(&k.1);
                                 }
                             }
+                        } else if let evdev_rs::enums::EventCode::EV_SYN(_) = k.1.event_code {
+                            // completes the pointer motion of the current report
+                            if GRAB_MOUSE.load(Ordering::SeqCst) {
+                                mirror_motion_events(&k.1);
+                            }
                         }
 
                         mouse_tx.send(Some(k.1)).unwrap_or_else(|e| {
@@ -584,7 +617,8 @@ fn spawn_mouse_input_thread_secondary(
                         // update our internal representation of the device state
                         if let evdev_rs::enums::EventCode::EV_KEY(code) = k.1.clone().event_code {
                             let is_pressed = k.1.value > 0;
-                            let index = mouse_device.read().ev_key_to_button_index(code).unwrap() as usize;
+                            let index =
+                                mouse_device.read().ev_key_to_button_index(code).unwrap() as usize;
 
                             BUTTON_STATES.lock()[index] = is_pressed;
                         } else if let evdev_rs::enums::EventCode::EV_REL(code) =
@@ -596,24 +630,23 @@ fn spawn_mouse_input_thread_secondary(
                                 && code != evdev_rs::enums::EV_REL::REL_HWHEEL_HI_RES
                             {
                                 // directly mirror pointer motion events to reduce input lag.
-                                // This currently prohibits further manipulation of pointer motion events
+                                // Only the mouse configuration of the profile may manipulate them
                                 if GRAB_MOUSE.load(Ordering::SeqCst) {
-                                    macros::UINPUT_TX
-                                        .lock()
-                                        .as_ref()
-                                        .unwrap()
-                                        .send(macros::Message::MirrorMouseEventImmediate(
-                                            k.1.clone(),
-                                        ))
-                                        .unwrap_or_else(|e| {
-                                            error!("Could not send a pending mouse sub-device event: {}", e)
-                                        });
+                                    mirror_motion_events(&k.1);
                                 }
                             }
+                        } else if let evdev_rs::enums::EventCode::EV_SYN(_) = k.1.event_code {
+                            // completes the pointer motion of the current report
+                            if GRAB_MOUSE.load(Ordering::SeqCst) {
+                                mirror_motion_events(&k.1);
+                            }
                         }
 
                         mouse_tx.send(Some(k.1)).unwrap_or_else(|e| {
-                            error!("Could not send a mouse sub-device event to the main thread: {}", e)
+                            error!(
+                                "Could not send a mouse sub-device event to the main thread: {}",
+                                e
+                            )
                         });
 
                         // update AFK timer
@@ -685,6 +718,7 @@ fn spawn_lua_thread(
         #[allow(clippy::never_loop)]
         loop {
             let result = script::run_script(
+                thread_idx,
                 script_path.clone(),
                 &lua_rx,
                 &keyboard_devices.clone(),
@@ -716,6 +750,35 @@ fn spawn_lua_thread(
     Ok(())
 }
 
+fn spawn_native_thread(
+    thread_idx: usize,
+    lua_rx: Receiver<script::Message>,
+    script_path: PathBuf,
+    effect: Box<dyn native::NativeEffect + Send>,
+) -> Result<()> {
+    let builder = thread::Builder::new().name(format!(
+        "{}:{}",
+        thread_idx,
+        script_path.file_name().unwrap().to_string_lossy(),
+    ));
+
+    builder.spawn(move || -> Result<()> {
+        match native::run_effect(effect, &lua_rx)? {
+            script::RunScriptResult::TerminatedGracefully => Ok(()),
+
+            script::RunScriptResult::TerminatedWithErrors => {
+                error!("Native effect execution failed");
+
+                // TODO: Try to get rid of this! We currently need it here since
+                //       otherwise, we may deadlock on error sometimes.
+                std::process::abort();
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
 /// Switches the currently active profile to the profile file `profile_path`
 fn switch_profile<P: AsRef<Path>>(
     profile_file: P,
@@ -748,9 +811,25 @@ fn switch_profile<P: AsRef<Path>>(
 
     // verify script files first; better fail early if we can
     let script_files = profile.active_scripts.clone();
-    for script_file in script_files.iter() {
+    let mut native_effects = HashMap::new();
+    let mut manifests = HashMap::new();
+
+    for (thread_idx, script_file) in script_files.iter().enumerate() {
         let script_path = script_dir.join(&script_file);
 
+        if native::is_native_effect(&script_path) {
+            // native effects have no manifest, load them right away
+            let effect = native::load_effect(&script_path, Some(&profile)).map_err(|e| {
+                error!("Could not load effect '{}': {}", script_path.display(), e);
+
+                MainError::SwitchProfileError {}
+            })?;
+
+            native_effects.insert(thread_idx, effect);
+
+            continue;
+        }
+
         if !util::is_script_file_accessible(&script_path)
             || !util::is_manifest_file_accessible(&script_path)
         {
@@ -760,8 +839,77 @@ fn switch_profile<P: AsRef<Path>>(
             );
             return Err(MainError::SwitchProfileError {}.into());
         }
+
+        let manifest = Manifest::from(&script_path).map_err(|e| {
+            error!(
+                "Could not parse manifest file for script '{}': {}",
+                script_path.display(),
+                e
+            );
+
+            MainError::SwitchProfileError {}
+        })?;
+
+        script::check_requirements(&manifest, keyboard_devices, mouse_devices).map_err(|e| {
+            error!(
+                "Script '{}' can not be executed: {}",
+                script_path.display(),
+                e
+            );
+
+            MainError::SwitchProfileError {}
+        })?;
+
+        manifests.insert(thread_idx, manifest);
+    }
+
+    // unknown sections and parameters are only reported, the scripts use their defaults instead
+    let mut profile_valid = true;
+
+    for e in manifest::validate_profile(&profile, &script_dir) {
+        if e.is_fatal() {
+            error!("Invalid profile '{}': {}", profile_path.display(), e);
+
+            profile_valid = false;
+        } else {
+            warn!("Profile '{}': {}", profile_path.display(), e);
+        }
+    }
+
+    if !profile_valid {
+        return Err(MainError::SwitchProfileError {}.into());
     }
 
+    let keymap = profile
+        .keymap
+        .as_ref()
+        .map(keymap::compile)
+        .transpose()
+        .map_err(|e| {
+            error!(
+                "Invalid keymap in profile '{}': {}",
+                profile_path.display(),
+                e
+            );
+
+            MainError::SwitchProfileError {}
+        })?;
+
+    let mousemap = profile
+        .mouse
+        .as_ref()
+        .map(|settings| mousemap::compile(settings, profile.keymap.as_ref()))
+        .transpose()
+        .map_err(|e| {
+            error!(
+                "Invalid mouse configuration in profile '{}': {}",
+                profile_path.display(),
+                e
+            );
+
+            MainError::SwitchProfileError {}
+        })?;
+
     // now request termination of all Lua VMs
     let mut lua_txs = LUA_TXS.lock();
 
@@ -773,29 +921,55 @@ fn switch_profile<P: AsRef<Path>>(
 
     // be safe and clear any leftover channels
     lua_txs.clear();
+    plugins::introspection::clear_vms();
+
+    // gestures are registered by the scripts of the new profile
+    plugins::keyboard::clear_gestures();
+    plugins::macros::clear_text_expansions();
+
+    plugins::audio::apply_settings(profile.audio.as_ref());
+
+    keymap::apply_keymap(keymap);
+    mousemap::apply_mousemap(mousemap);
+
+    // assign the globally active profile, the new Lua VMs apply its parameters on startup
+    *ACTIVE_PROFILE.lock() = Some(profile);
 
     // now spawn a new set of Lua VMs, with scripts from the new profile
     for (thread_idx, script_file) in script_files.iter().enumerate() {
         let script_path = script_dir.join(&script_file);
 
         let (lua_tx, lua_rx) = unbounded();
-        spawn_lua_thread(
+
+        // register the VM before it is started, the script reports its event handlers right away
+        plugins::introspection::register_vm(
             thread_idx,
-            lua_rx,
             script_path.clone(),
-            keyboard_devices.to_owned(),
-            mouse_devices.to_owned(),
-        )
-        .unwrap_or_else(|e| {
+            manifests.remove(&thread_idx),
+            lua_tx.clone(),
+        );
+
+        let result = if let Some(effect) = native_effects.remove(&thread_idx) {
+            spawn_native_thread(thread_idx, lua_rx, script_path.clone(), effect)
+        } else {
+            spawn_lua_thread(
+                thread_idx,
+                lua_rx,
+                script_path.clone(),
+                keyboard_devices.to_owned(),
+                mouse_devices.to_owned(),
+            )
+        };
+
+        if let Err(e) = result {
             error!("Could not spawn a thread: {}", e);
-        });
+
+            plugins::introspection::unregister_vm(thread_idx);
+        }
 
         lua_txs.push(lua_tx);
     }
 
-    // finally assign the globally active profile
-    *ACTIVE_PROFILE.lock() = Some(profile);
-
     dbus_api_tx
         .send(DbusApiEvent::ActiveProfileChanged)
         .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
@@ -1097,6 +1271,10 @@ async fn process_mouse_event(
     // notify all observers of raw events
     events::notify_observers(events::Event::RawMouseEvent(raw_event.clone())).ok();
 
+    // apply the mouse configuration of the active profile first, so that remapped
+    // buttons don't have to wait for the Lua VMs to complete their upcalls
+    let handled = mousemap::process_button_event(raw_event);
+
     if let evdev_rs::enums::EventCode::EV_REL(ref code) = raw_event.clone().event_code {
         match code {
             evdev_rs::enums::EV_REL::REL_X
@@ -1292,7 +1470,7 @@ async fn process_mouse_event(
         }
     }
 
-    if mirror_event {
+    if mirror_event && !handled {
         // mirror all events, except pointer motion events.
         // Pointer motion events currently can not be overridden,
         // they are mirrored to the virtual mouse directly after they are
@@ -1431,6 +1609,10 @@ async fn process_keyboard_event(
     // notify all observers of raw events
     events::notify_observers(events::Event::RawKeyboardEvent(raw_event.clone())).ok();
 
+    // apply the keymap of the active profile first, so that remapped
+    // keys don't have to wait for the Lua VMs to complete their upcalls
+    let handled = keymap::process_key_event(raw_event);
+
     if let evdev_rs::enums::EventCode::EV_KEY(ref code) = raw_event.event_code {
         let is_pressed = raw_event.value > 0;
         let index = keyboard_device.read().ev_key_to_key_index(code.clone());
@@ -1504,12 +1686,14 @@ async fn process_keyboard_event(
 
     // handler for Message::MirrorKey will drop the key if a Lua VM
     // called inject_key(..), so that the key won't be reported twice
-    macros::UINPUT_TX
-        .lock()
-        .as_ref()
-        .unwrap()
-        .send(macros::Message::MirrorKey(raw_event.clone()))
-        .unwrap_or_else(|e| error!("Could not send a pending keyboard event: {}", e));
+    if !handled {
+        macros::UINPUT_TX
+            .lock()
+            .as_ref()
+            .unwrap()
+            .send(macros::Message::MirrorKey(raw_event.clone()))
+            .unwrap_or_else(|e| error!("Could not send a pending keyboard event: {}", e));
+    }
 
     Ok(())
 }
@@ -1706,8 +1890,14 @@ async fn run_main_loop(
 
         join_all(futures).await;
 
+        // wake up early, if a key gesture may be recognized before the next tick
+        let mut timeout = Duration::from_millis(1000 / constants::TARGET_FPS);
+        if let Some(deadline) = plugins::keyboard::next_gesture_deadline() {
+            timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+        }
+
         // now, process events from all available sources...
-        match sel.select_timeout(Duration::from_millis(1000 / constants::TARGET_FPS)) {
+        match sel.select_timeout(timeout) {
             Ok(oper) => match oper.index() {
                 i if i == ctrl_c => {
                     // consume the event, so that we don't cause a panic
@@ -1796,6 +1986,21 @@ async fn run_main_loop(
             Err(_e) => { /* do nothing */ }
         };
 
+        // notify the Lua VMs about the key gestures they registered
+        for (index, name) in plugins::keyboard::take_pending_gestures() {
+            // if this tx failed previously, then skip it completely
+            if !failed_txs.contains(&index) {
+                if let Some(lua_tx) = LUA_TXS.lock().get(index) {
+                    lua_tx
+                        .send(script::Message::Gesture(name))
+                        .unwrap_or_else(|e| {
+                            error!("Send error during gesture event: {}", e);
+                            failed_txs.insert(index);
+                        });
+                }
+            }
+        }
+
         if delay_time.elapsed() >= Duration::from_millis(1000 / (constants::TARGET_FPS * 4)) {
             // poll HID events on all available devices
             for device in keyboard_devices.iter() {
@@ -1816,6 +2021,21 @@ async fn run_main_loop(
 
             delay_time = Instant::now();
 
+            // notify the Lua VMs about beats detected by the audio grabber
+            if let Some(strength) = plugins::audio::take_pending_beat() {
+                for (index, lua_tx) in LUA_TXS.lock().iter().enumerate() {
+                    // if this tx failed previously, then skip it completely
+                    if !failed_txs.contains(&index) {
+                        lua_tx
+                            .send(script::Message::Beat(strength))
+                            .unwrap_or_else(|e| {
+                                error!("Send error during beat event: {}", e);
+                                failed_txs.insert(index);
+                            });
+                    }
+                }
+            }
+
             // send timer tick events to the Lua VMs
             for (index, lua_tx) in LUA_TXS.lock().iter().enumerate() {
                 // if this tx failed previously, then skip it completely
@@ -1829,6 +2049,8 @@ async fn run_main_loop(
                 }
             }
 
+            plugins::introspection::set_failed_vms(&failed_txs);
+
             // finally, update the LEDs if necessary
             let current_frame_generation = script::FRAME_GENERATION_COUNTER.load(Ordering::SeqCst);
             if saved_frame_generation.load(Ordering::SeqCst) < current_frame_generation {
@@ -2148,6 +2370,11 @@ fn init_mouse_device(mouse_device: &MouseDevice, hidapi: &hidapi::HidApi) {
 pub async fn main() -> std::result::Result<(), eyre::Error> {
     color_eyre::install()?;
 
+    // when invoked as `eruption-script-test`, run the headless script test runner instead
+    if script_test::is_invoked_as_test_runner() {
+        return script_test::main();
+    }
+
     if unsafe { libc::isatty(0) != 0 } {
         print_header();
     }
@@ -2241,6 +2468,10 @@ pub async fn main() -> std::result::Result<(), eyre::Error> {
 
             info!("Plugins loaded and initialized successfully");
 
+            // stream events to external consumers, if enabled
+            event_stream::initialize()
+                .unwrap_or_else(|e| error!("Could not initialize the event stream: {}", e));
+
             // enumerate devices
             info!("Enumerating connected devices...");
 
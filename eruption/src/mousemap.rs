@@ -0,0 +1,751 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use evdev_rs::enums::{EventCode, EventType, EV_REL, EV_SYN};
+use evdev_rs::{InputEvent, TimeVal};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::keymap::{self, Action};
+use crate::profiles::{AccelerationCurve, Keymap, MouseSettings};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MouseMapError {
+    #[error("Invalid sniper factor: {factor}, it has to be in the range of 0.0..10.0")]
+    InvalidSniperFactor { factor: f64 },
+
+    #[error("The keymap of the profile has no layer named: {name}")]
+    UnknownLayer { name: String },
+
+    #[error("Invalid sensitivity: {value}, it has to be in the range of 0.0..100.0")]
+    InvalidSensitivity { value: f64 },
+
+    #[error("Invalid acceleration curve: {description}")]
+    InvalidAccelerationCurve { description: String },
+}
+
+/// Scale factor of the pointer motion while sniper mode is active, if not configured
+const DEFAULT_SNIPER_FACTOR: f64 = 0.3;
+
+/// Bounds of the interval between two reports of the mouse in milliseconds, used to
+/// calculate the speed of the pointer, e.g. after the mouse has been idle for a while
+const MIN_REPORT_INTERVAL: f64 = 0.125;
+const MAX_REPORT_INTERVAL: f64 = 10.0;
+
+/// The mouse configuration of a profile, with all button names resolved to evdev key codes
+#[derive(Debug)]
+pub struct CompiledMouseMap {
+    remap: HashMap<u32, Action>,
+    sniper_button: Option<u32>,
+    sniper_factor: f64,
+    shift_button: Option<u32>,
+    shift_layer: Option<String>,
+
+    /// Sensitivity multipliers of the X and Y axis
+    sensitivity: (f64, f64),
+    acceleration: Option<AccelerationCurve>,
+}
+
+impl CompiledMouseMap {
+    /// Returns true if pointer motion is shaped, even while sniper mode is inactive
+    fn shapes_motion(&self) -> bool {
+        self.sensitivity != (1.0, 1.0)
+            || !matches!(self.acceleration, None | Some(AccelerationCurve::Flat))
+    }
+
+    /// The gain of the acceleration curve at `speed`, in counts per millisecond
+    fn gain(&self, speed: f64) -> f64 {
+        match &self.acceleration {
+            None | Some(AccelerationCurve::Flat) => 1.0,
+
+            Some(AccelerationCurve::Linear {
+                acceleration,
+                offset,
+                cap,
+            }) => {
+                let gain = 1.0 + acceleration * (speed - offset).max(0.0);
+
+                cap.map_or(gain, |cap| gain.min(cap))
+            }
+
+            Some(AccelerationCurve::Power {
+                scale,
+                exponent,
+                offset,
+                cap,
+            }) => {
+                let gain = 1.0 + (scale * (speed - offset).max(0.0)).powf(*exponent);
+
+                cap.map_or(gain, |cap| gain.min(cap))
+            }
+
+            Some(AccelerationCurve::Custom { points }) => {
+                match points.iter().position(|(s, _)| *s > speed) {
+                    Some(0) => points[0].1,
+
+                    Some(index) => {
+                        let (s0, g0) = points[index - 1];
+                        let (s1, g1) = points[index];
+
+                        g0 + (g1 - g0) * (speed - s0) / (s1 - s0)
+                    }
+
+                    None => points[points.len() - 1].1,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MouseMapState {
+    mousemap: Option<CompiledMouseMap>,
+
+    /// Actions of the buttons that are currently held down
+    pressed: HashMap<u32, Action>,
+
+    /// Pointer motion of the current report of the mouse, that is
+    /// shaped as a whole when the report is complete
+    motion: (i32, i32),
+    motion_time: Option<TimeVal>,
+
+    /// Time of the previous report that contained pointer motion
+    last_motion_time: Option<TimeVal>,
+
+    /// Fractional parts of scaled pointer motion, that have not been sent yet
+    remainder: (f64, f64),
+}
+
+lazy_static! {
+    static ref MOUSEMAP: Mutex<MouseMapState> = Mutex::new(MouseMapState::default());
+
+    /// Set while the sniper button is held down
+    static ref SNIPER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+    /// Set while the shift button is held down
+    static ref SHIFT_ACTIVE: AtomicBool = AtomicBool::new(false);
+}
+
+/// Resolve all button names of `settings`, so that errors are detected before a profile is
+/// activated. The shift layer has to be defined by the profile's `keymap`
+pub fn compile(settings: &MouseSettings, keymap: Option<&Keymap>) -> Result<CompiledMouseMap> {
+    let sniper_factor = settings.sniper_factor.unwrap_or(DEFAULT_SNIPER_FACTOR);
+
+    if !(sniper_factor > 0.0 && sniper_factor <= 10.0) {
+        return Err(MouseMapError::InvalidSniperFactor {
+            factor: sniper_factor,
+        }
+        .into());
+    }
+
+    let sensitivity = settings.sensitivity.unwrap_or(1.0);
+    let sensitivity_x = settings.sensitivity_x.unwrap_or(1.0);
+    let sensitivity_y = settings.sensitivity_y.unwrap_or(1.0);
+
+    for value in [sensitivity, sensitivity_x, sensitivity_y].iter() {
+        if !(*value > 0.0 && *value <= 100.0) {
+            return Err(MouseMapError::InvalidSensitivity { value: *value }.into());
+        }
+    }
+
+    if let Some(curve) = settings.acceleration.as_ref() {
+        validate_curve(curve)?;
+    }
+
+    if let Some(name) = settings.shift_layer.as_ref() {
+        let exists = keymap
+            .map(|k| k.layers.iter().any(|l| &l.name == name))
+            .unwrap_or(false);
+
+        if !exists {
+            return Err(MouseMapError::UnknownLayer { name: name.clone() }.into());
+        }
+    }
+
+    Ok(CompiledMouseMap {
+        remap: keymap::compile_remap(&settings.remap)?,
+        sniper_button: settings
+            .sniper_button
+            .as_deref()
+            .map(keymap::parse_key)
+            .transpose()?,
+        sniper_factor,
+        shift_button: settings
+            .shift_button
+            .as_deref()
+            .map(keymap::parse_key)
+            .transpose()?,
+        shift_layer: settings.shift_layer.clone(),
+        sensitivity: (sensitivity * sensitivity_x, sensitivity * sensitivity_y),
+        acceleration: settings.acceleration.clone(),
+    })
+}
+
+fn validate_curve(curve: &AccelerationCurve) -> Result<()> {
+    let invalid = |description: &str| -> Result<()> {
+        Err(MouseMapError::InvalidAccelerationCurve {
+            description: description.to_string(),
+        }
+        .into())
+    };
+
+    match curve {
+        AccelerationCurve::Flat => Ok(()),
+
+        AccelerationCurve::Linear {
+            acceleration,
+            offset,
+            cap,
+        } => {
+            if !acceleration.is_finite() || *acceleration < 0.0 {
+                invalid("The acceleration must not be negative")
+            } else if !offset.is_finite() || *offset < 0.0 {
+                invalid("The offset must not be negative")
+            } else if cap.map_or(false, |cap| !(cap >= 1.0)) {
+                invalid("The cap must be at least 1.0")
+            } else {
+                Ok(())
+            }
+        }
+
+        AccelerationCurve::Power {
+            scale,
+            exponent,
+            offset,
+            cap,
+        } => {
+            if !(*scale > 0.0) || !scale.is_finite() {
+                invalid("The scale must be greater than 0.0")
+            } else if !(*exponent > 0.0 && *exponent <= 10.0) {
+                invalid("The exponent has to be in the range of 0.0..10.0")
+            } else if !offset.is_finite() || *offset < 0.0 {
+                invalid("The offset must not be negative")
+            } else if cap.map_or(false, |cap| !(cap >= 1.0)) {
+                invalid("The cap must be at least 1.0")
+            } else {
+                Ok(())
+            }
+        }
+
+        AccelerationCurve::Custom { points } => {
+            if points.is_empty() {
+                invalid("At least one point is required")
+            } else if points
+                .iter()
+                .any(|(speed, gain)| !(*speed >= 0.0) || !(*gain > 0.0 && *gain <= 100.0))
+            {
+                invalid("Speeds must not be negative, gains have to be in the range of 0.0..100.0")
+            } else if points.windows(2).any(|w| !(w[0].0 < w[1].0)) {
+                invalid("The speeds of the points must be strictly increasing")
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Activate `mousemap`, or disable the mouse configuration if it is `None`
+pub fn apply_mousemap(mousemap: Option<CompiledMouseMap>) {
+    let mut state = MOUSEMAP.lock();
+
+    // don't leave remapped buttons stuck in the pressed state
+    for action in state.pressed.values() {
+        keymap::release(action);
+    }
+
+    SNIPER_ACTIVE.store(false, Ordering::SeqCst);
+    SHIFT_ACTIVE.store(false, Ordering::SeqCst);
+
+    *state = MouseMapState {
+        mousemap,
+        ..Default::default()
+    };
+}
+
+/// Returns true while the sniper button is held down
+pub fn is_sniper_active() -> bool {
+    SNIPER_ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Returns true while the shift button is held down
+pub fn is_shift_active() -> bool {
+    SHIFT_ACTIVE.load(Ordering::SeqCst)
+}
+
+/// The interval between the previous and the current report of the mouse in milliseconds
+fn report_interval(last: Option<TimeVal>, time: &TimeVal) -> f64 {
+    match last {
+        Some(last) => {
+            let micros = (time.tv_sec - last.tv_sec) * 1_000_000 + (time.tv_usec - last.tv_usec);
+
+            (micros as f64 / 1000.0)
+                .max(MIN_REPORT_INTERVAL)
+                .min(MAX_REPORT_INTERVAL)
+        }
+
+        None => MAX_REPORT_INTERVAL,
+    }
+}
+
+/// Scale the motion `delta` of an axis, carrying the fractional part over to the next report
+fn scale_axis(delta: i32, scale: f64, remainder: &mut f64) -> i32 {
+    let value = delta as f64 * scale + *remainder;
+    let scaled = value.trunc();
+
+    *remainder = value - scaled;
+
+    scaled as i32
+}
+
+/// Apply the mouse configuration to a pointer motion or `SYN_REPORT` event of the hardware mouse.
+/// Motion is collected until the report is complete, so that the speed of the pointer is known.
+/// Returns the events that shall be mirrored to the virtual mouse
+pub fn process_motion_event(raw_event: &InputEvent) -> Vec<InputEvent> {
+    let mut state = MOUSEMAP.lock();
+    let MouseMapState {
+        mousemap,
+        motion,
+        motion_time,
+        last_motion_time,
+        remainder,
+        ..
+    } = &mut *state;
+
+    match raw_event.event_code {
+        EventCode::EV_REL(EV_REL::REL_X) | EventCode::EV_REL(EV_REL::REL_Y) => {
+            let shaped = mousemap.as_ref().map_or(false, |m| {
+                m.shapes_motion() || SNIPER_ACTIVE.load(Ordering::SeqCst)
+            });
+
+            if !shaped && motion_time.is_none() {
+                return vec![raw_event.clone()];
+            }
+
+            if raw_event.event_code == EventCode::EV_REL(EV_REL::REL_X) {
+                motion.0 += raw_event.value;
+            } else {
+                motion.1 += raw_event.value;
+            }
+
+            *motion_time = Some(raw_event.time.clone());
+
+            vec![]
+        }
+
+        EventCode::EV_SYN(EV_SYN::SYN_REPORT) => {
+            let time = match motion_time.take() {
+                Some(time) => time,
+
+                None => return vec![],
+            };
+
+            let (dx, dy) = std::mem::take(motion);
+
+            let interval = report_interval(last_motion_time.replace(time.clone()), &time);
+
+            let (scale_x, scale_y) = match mousemap.as_ref() {
+                Some(mousemap) => {
+                    let speed = (dx as f64).hypot(dy as f64) / interval;
+
+                    let mut gain = mousemap.gain(speed);
+
+                    if SNIPER_ACTIVE.load(Ordering::SeqCst) {
+                        gain *= mousemap.sniper_factor;
+                    }
+
+                    (mousemap.sensitivity.0 * gain, mousemap.sensitivity.1 * gain)
+                }
+
+                None => (1.0, 1.0),
+            };
+
+            let mut result = vec![];
+
+            for (code, value) in [
+                (EV_REL::REL_X, scale_axis(dx, scale_x, &mut remainder.0)),
+                (EV_REL::REL_Y, scale_axis(dy, scale_y, &mut remainder.1)),
+            ]
+            .iter()
+            {
+                if *value != 0 {
+                    result.push(InputEvent {
+                        time: time.clone(),
+                        event_type: EventType::EV_REL,
+                        event_code: EventCode::EV_REL(code.clone()),
+                        value: *value,
+                    });
+                }
+            }
+
+            result
+        }
+
+        _ => vec![raw_event.clone()],
+    }
+}
+
+/// Apply the mouse configuration to a raw button event of the hardware mouse. Returns true if
+/// the event has been handled, in which case it must not be mirrored to the virtual mouse
+pub fn process_button_event(raw_event: &InputEvent) -> bool {
+    let code = match &raw_event.event_code {
+        EventCode::EV_KEY(key) => key.clone() as u32,
+
+        _ => return false,
+    };
+
+    let mut state = MOUSEMAP.lock();
+    let MouseMapState {
+        mousemap, pressed, ..
+    } = &mut *state;
+
+    let mousemap = match mousemap.as_ref() {
+        Some(mousemap) => mousemap,
+
+        None => return false,
+    };
+
+    let down = raw_event.value > 0;
+
+    if mousemap.sniper_button == Some(code) {
+        SNIPER_ACTIVE.store(down, Ordering::SeqCst);
+
+        true
+    } else if mousemap.shift_button == Some(code) {
+        SHIFT_ACTIVE.store(down, Ordering::SeqCst);
+
+        if let Some(name) = mousemap.shift_layer.as_ref() {
+            keymap::set_layer_active(name, down);
+        }
+
+        true
+    } else if down {
+        match mousemap.remap.get(&code) {
+            Some(action) => {
+                keymap::press(action);
+                pressed.insert(code, action.clone());
+
+                true
+            }
+
+            None => false,
+        }
+    } else {
+        match pressed.remove(&code) {
+            Some(action) => {
+                keymap::release(&action);
+
+                true
+            }
+
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::{KeymapLayer, LayerMode};
+    use evdev_rs::enums::EV_KEY;
+
+    fn mousemap(curve: AccelerationCurve) -> CompiledMouseMap {
+        CompiledMouseMap {
+            remap: HashMap::new(),
+            sniper_button: None,
+            sniper_factor: DEFAULT_SNIPER_FACTOR,
+            shift_button: None,
+            shift_layer: None,
+            sensitivity: (1.0, 1.0),
+            acceleration: Some(curve),
+        }
+    }
+
+    fn assert_gain(curve: &CompiledMouseMap, speed: f64, expected: f64) {
+        let gain = curve.gain(speed);
+
+        assert!(
+            (gain - expected).abs() < 1e-9,
+            "speed: {}, gain: {}, expected: {}",
+            speed,
+            gain,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_flat_curve() {
+        let curve = mousemap(AccelerationCurve::Flat);
+
+        assert_gain(&curve, 0.0, 1.0);
+        assert_gain(&curve, 100.0, 1.0);
+        assert!(!curve.shapes_motion());
+    }
+
+    #[test]
+    fn test_linear_curve() {
+        let curve = mousemap(AccelerationCurve::Linear {
+            acceleration: 0.5,
+            offset: 2.0,
+            cap: Some(3.0),
+        });
+
+        // no acceleration below the offset
+        assert_gain(&curve, 0.0, 1.0);
+        assert_gain(&curve, 2.0, 1.0);
+
+        assert_gain(&curve, 4.0, 2.0);
+        assert_gain(&curve, 6.0, 3.0);
+        assert_gain(&curve, 100.0, 3.0);
+        assert!(curve.shapes_motion());
+    }
+
+    #[test]
+    fn test_power_curve() {
+        let curve = mousemap(AccelerationCurve::Power {
+            scale: 0.5,
+            exponent: 2.0,
+            offset: 1.0,
+            cap: None,
+        });
+
+        assert_gain(&curve, 1.0, 1.0);
+        assert_gain(&curve, 3.0, 2.0);
+        assert_gain(&curve, 5.0, 5.0);
+        assert_gain(&curve, 21.0, 101.0);
+    }
+
+    #[test]
+    fn test_custom_curve() {
+        let curve = mousemap(AccelerationCurve::Custom {
+            points: vec![(1.0, 0.5), (3.0, 1.5), (5.0, 2.0)],
+        });
+
+        // constant outside of the points, interpolated in between
+        assert_gain(&curve, 0.0, 0.5);
+        assert_gain(&curve, 1.0, 0.5);
+        assert_gain(&curve, 2.0, 1.0);
+        assert_gain(&curve, 3.0, 1.5);
+        assert_gain(&curve, 4.0, 1.75);
+        assert_gain(&curve, 5.0, 2.0);
+        assert_gain(&curve, 50.0, 2.0);
+
+        let curve = mousemap(AccelerationCurve::Custom {
+            points: vec![(2.0, 1.5)],
+        });
+
+        assert_gain(&curve, 0.0, 1.5);
+        assert_gain(&curve, 10.0, 1.5);
+    }
+
+    #[test]
+    fn test_validate_curve() {
+        let linear = |acceleration, offset, cap| AccelerationCurve::Linear {
+            acceleration,
+            offset,
+            cap,
+        };
+
+        let power = |scale, exponent| AccelerationCurve::Power {
+            scale,
+            exponent,
+            offset: 0.0,
+            cap: None,
+        };
+
+        let custom = |points: &[(f64, f64)]| AccelerationCurve::Custom {
+            points: points.to_vec(),
+        };
+
+        assert!(validate_curve(&AccelerationCurve::Flat).is_ok());
+        assert!(validate_curve(&linear(0.5, 2.0, Some(3.0))).is_ok());
+        assert!(validate_curve(&power(0.5, 2.0)).is_ok());
+        assert!(validate_curve(&custom(&[(0.0, 1.0), (5.0, 2.0)])).is_ok());
+
+        assert!(validate_curve(&linear(-0.5, 0.0, None)).is_err());
+        assert!(validate_curve(&linear(f64::NAN, 0.0, None)).is_err());
+        assert!(validate_curve(&linear(0.5, -1.0, None)).is_err());
+        assert!(validate_curve(&linear(0.5, 0.0, Some(0.5))).is_err());
+        assert!(validate_curve(&linear(0.5, 0.0, Some(f64::NAN))).is_err());
+
+        assert!(validate_curve(&power(0.0, 2.0)).is_err());
+        assert!(validate_curve(&power(f64::INFINITY, 2.0)).is_err());
+        assert!(validate_curve(&power(1.0, 0.0)).is_err());
+        assert!(validate_curve(&power(1.0, 10.5)).is_err());
+
+        assert!(validate_curve(&custom(&[])).is_err());
+        assert!(validate_curve(&custom(&[(-1.0, 1.0)])).is_err());
+        assert!(validate_curve(&custom(&[(0.0, 0.0)])).is_err());
+        assert!(validate_curve(&custom(&[(0.0, 101.0)])).is_err());
+        assert!(validate_curve(&custom(&[(1.0, 1.0), (1.0, 2.0)])).is_err());
+        assert!(validate_curve(&custom(&[(2.0, 1.0), (1.0, 2.0)])).is_err());
+    }
+
+    #[test]
+    fn test_report_interval() {
+        let time = |tv_sec, tv_usec| TimeVal::new(tv_sec, tv_usec);
+
+        assert_eq!(report_interval(None, &time(1, 0)), MAX_REPORT_INTERVAL);
+        assert_eq!(report_interval(Some(time(1, 0)), &time(1, 1000)), 1.0);
+        assert_eq!(report_interval(Some(time(1, 999_000)), &time(2, 1000)), 2.0);
+
+        // clamped, e.g. after the mouse has been idle, or for bogus timestamps
+        assert_eq!(
+            report_interval(Some(time(1, 0)), &time(5, 0)),
+            MAX_REPORT_INTERVAL
+        );
+        assert_eq!(
+            report_interval(Some(time(1, 0)), &time(1, 0)),
+            MIN_REPORT_INTERVAL
+        );
+        assert_eq!(
+            report_interval(Some(time(2, 0)), &time(1, 0)),
+            MIN_REPORT_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_scale_axis() {
+        let mut remainder = 0.0;
+
+        // fractional motion is carried over to the next report
+        let scaled: Vec<i32> = (0..4).map(|_| scale_axis(1, 0.5, &mut remainder)).collect();
+
+        assert_eq!(scaled, vec![0, 1, 0, 1]);
+        assert_eq!(remainder, 0.0);
+
+        let scaled: Vec<i32> = (0..4)
+            .map(|_| scale_axis(-3, 0.5, &mut remainder))
+            .collect();
+
+        assert_eq!(scaled, vec![-1, -2, -1, -2]);
+        assert_eq!(remainder, 0.0);
+    }
+
+    #[test]
+    fn test_compile() {
+        let mousemap = compile(&MouseSettings::default(), None).unwrap();
+
+        assert!(mousemap.remap.is_empty());
+        assert_eq!(mousemap.sniper_button, None);
+        assert_eq!(mousemap.sniper_factor, DEFAULT_SNIPER_FACTOR);
+        assert_eq!(mousemap.sensitivity, (1.0, 1.0));
+        assert!(!mousemap.shapes_motion());
+
+        let keymap = Keymap {
+            remap: HashMap::new(),
+            layers: vec![KeymapLayer {
+                name: "fn".to_string(),
+                key: "KEY_CAPSLOCK".to_string(),
+                mode: LayerMode::default(),
+                remap: HashMap::new(),
+            }],
+        };
+
+        let mut settings = MouseSettings {
+            sniper_button: Some("BTN_EXTRA".to_string()),
+            sniper_factor: Some(0.5),
+            shift_button: Some("btn_forward".to_string()),
+            shift_layer: Some("fn".to_string()),
+            sensitivity: Some(2.0),
+            sensitivity_x: Some(0.5),
+            ..MouseSettings::default()
+        };
+
+        settings
+            .remap
+            .insert("BTN_SIDE".to_string(), "KEY_LEFTCTRL+KEY_C".to_string());
+        settings
+            .remap
+            .insert("BTN_MIDDLE".to_string(), "none".to_string());
+
+        let mousemap = compile(&settings, Some(&keymap)).unwrap();
+
+        assert_eq!(
+            mousemap.remap.get(&(EV_KEY::BTN_SIDE as u32)),
+            Some(&Action::Keys(vec![
+                EV_KEY::KEY_LEFTCTRL as u32,
+                EV_KEY::KEY_C as u32
+            ]))
+        );
+        assert_eq!(
+            mousemap.remap.get(&(EV_KEY::BTN_MIDDLE as u32)),
+            Some(&Action::Disabled)
+        );
+        assert_eq!(mousemap.sniper_button, Some(EV_KEY::BTN_EXTRA as u32));
+        assert_eq!(mousemap.sniper_factor, 0.5);
+        assert_eq!(mousemap.shift_button, Some(EV_KEY::BTN_FORWARD as u32));
+        assert_eq!(mousemap.shift_layer.as_deref(), Some("fn"));
+        assert_eq!(mousemap.sensitivity, (1.0, 2.0));
+        assert!(mousemap.shapes_motion());
+
+        // the shift layer has to be defined by the keymap of the profile
+        assert!(compile(&settings, None).is_err());
+        assert!(compile(&settings, Some(&Keymap::default())).is_err());
+    }
+
+    #[test]
+    fn test_compile_invalid() {
+        let invalid = |settings: MouseSettings| compile(&settings, None).is_err();
+
+        for factor in [0.0, -0.5, 10.5, f64::NAN].iter() {
+            assert!(invalid(MouseSettings {
+                sniper_factor: Some(*factor),
+                ..MouseSettings::default()
+            }));
+        }
+
+        for value in [0.0, 100.5, f64::NAN].iter() {
+            assert!(invalid(MouseSettings {
+                sensitivity: Some(*value),
+                ..MouseSettings::default()
+            }));
+            assert!(invalid(MouseSettings {
+                sensitivity_x: Some(*value),
+                ..MouseSettings::default()
+            }));
+            assert!(invalid(MouseSettings {
+                sensitivity_y: Some(*value),
+                ..MouseSettings::default()
+            }));
+        }
+
+        assert!(invalid(MouseSettings {
+            acceleration: Some(AccelerationCurve::Custom { points: Vec::new() }),
+            ..MouseSettings::default()
+        }));
+
+        assert!(invalid(MouseSettings {
+            sniper_button: Some("BTN_UNKNOWN".to_string()),
+            ..MouseSettings::default()
+        }));
+        assert!(invalid(MouseSettings {
+            shift_button: Some("".to_string()),
+            ..MouseSettings::default()
+        }));
+
+        let mut settings = MouseSettings::default();
+        settings
+            .remap
+            .insert("BTN_SIDE".to_string(), "macro:".to_string());
+
+        assert!(invalid(settings));
+    }
+}
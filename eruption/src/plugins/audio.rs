@@ -19,6 +19,8 @@ use lazy_static::lazy_static;
 use log::*;
 use mlua::prelude::*;
 use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::{
@@ -28,6 +30,7 @@ use std::{
 
 use crate::events;
 use crate::plugins::{self, Plugin};
+use crate::profiles::AudioSettings;
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
@@ -46,15 +49,25 @@ pub enum AudioPluginError {
     GrabberError { description: String },
 }
 
-/// How many sound effects may be played back simultaneously
-pub const MAX_IN_FLIGHT_SFX: usize = 2;
+/// How many sound effects are mixed simultaneously, the oldest one is cut off if exceeded
+pub const MAX_SFX_VOICES: usize = 32;
 
 /// The allocated size of the audio grabber buffer
 pub const AUDIO_GRABBER_BUFFER_SIZE: usize = 44100 * 2 / 16;
 
-/// Number of FFT frequency buckets of the spectrum analyzer
+/// Size of the FFT of the beat detector, and the default size of the FFT of the spectrum analyzer
 pub const FFT_SIZE: usize = 512;
 
+/// Smallest and largest supported FFT size of the spectrum analyzer
+pub const MIN_FFT_SIZE: usize = 64;
+pub const MAX_FFT_SIZE: usize = 8192;
+
+/// Sample rate of the audio grabber
+pub const AUDIO_SAMPLE_RATE: usize = 44100;
+
+/// Frequency bands of the band energy analysis, if not configured otherwise: bass, mids and treble
+pub const DEFAULT_FREQUENCY_BANDS: &str = "20-250, 250-4000, 4000-16000";
+
 /// Thread termination request flag of the audio grabber thread
 pub static AUDIO_GRABBER_THREAD_SHALL_TERMINATE: AtomicBool = AtomicBool::new(false);
 
@@ -70,7 +83,7 @@ static CURRENT_RMS: AtomicIsize = AtomicIsize::new(0);
 static ERROR_RATE_LIMIT_MILLIS: u64 = 10000;
 
 lazy_static! {
-    /// Pluggable audio backend. Currently supported backends are "Null", ALSA and PulseAudio
+    /// Pluggable audio backend. Currently supported backends are "Null", PulseAudio and "File"
     pub static ref AUDIO_BACKEND: Arc<Mutex<Option<Box<dyn backends::AudioBackend + 'static + Sync + Send>>>> =
         // Arc::new(Mutex::new(backends::PulseAudioBackend::new().expect("Could not instantiate the audio backend!")));
         Arc::new(Mutex::new(None));
@@ -84,19 +97,74 @@ lazy_static! {
     /// Spectrum analyzer state
     static ref AUDIO_SPECTRUM: Arc<RwLock<Vec<f32>>> = Arc::new(RwLock::new(vec![0.0; FFT_SIZE / 2]));
 
+    /// Settings of the audio analysis pipeline, global settings merged with the settings of the active profile
+    static ref ANALYSIS_SETTINGS: Arc<RwLock<analysis::AnalysisSettings>> =
+        Arc::new(RwLock::new(analysis::AnalysisSettings::new(None)));
+
+    /// Frequency bands of the band energy analysis, in Hz
+    static ref FREQUENCY_BANDS: Vec<(f32, f32)> = analysis::get_frequency_bands();
+
+    /// Smoothed energies of the frequency bands
+    static ref BAND_ENERGIES: Arc<RwLock<Vec<f32>>> = Arc::new(RwLock::new(vec![0.0; FREQUENCY_BANDS.len()]));
+
+    /// Estimated tempo of the music in beats per minute
+    static ref CURRENT_BPM: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.0));
+
+    /// Strength of the last detected beat, if it has not yet been dispatched to the Lua VMs
+    static ref PENDING_BEAT: Arc<Mutex<Option<f32>>> = Arc::new(Mutex::new(None));
+
     /// Global "sound effects enabled" flag
     pub static ref ENABLE_SFX: AtomicBool = AtomicBool::new(false);
 
-    // Sound FX audio buffers
-    /// Key down SFX
-    pub static ref SFX_KEY_DOWN: Option<Vec<u8>> = util::load_sfx("key-down.wav").ok();
-    /// Key up SFX
-    pub static ref SFX_KEY_UP: Option<Vec<u8>> = util::load_sfx("key-up.wav").ok();
+    /// The active sound effects pack
+    static ref SFX_PACK: Arc<RwLock<Option<sfx::SoundPack>>> =
+        Arc::new(RwLock::new(sfx::SoundPack::load_from_settings(None)));
+
+    /// Sound effects that are played by file name, instead of by a name defined in the pack
+    static ref SFX_FILE_CACHE: Arc<Mutex<HashMap<String, Arc<Vec<i16>>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
 }
 
 // Enable computation of RMS and Spectrum Analyzer data?
 static AUDIO_GRABBER_PERFORM_RMS_COMPUTATION: AtomicBool = AtomicBool::new(false);
 static AUDIO_GRABBER_PERFORM_FFT_COMPUTATION: AtomicBool = AtomicBool::new(false);
+static AUDIO_GRABBER_PERFORM_BEAT_DETECTION: AtomicBool = AtomicBool::new(false);
+
+/// Incremented each time the analysis settings change, so the grabber thread can pick them up
+static ANALYSIS_SETTINGS_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Apply the audio settings of a profile, on top of the global settings.
+/// Restarts the audio grabber if the capture source has changed
+pub fn apply_settings(profile_settings: Option<&AudioSettings>) {
+    sfx::select_pack(profile_settings);
+
+    let settings = analysis::AnalysisSettings::new(profile_settings);
+
+    let mut current = ANALYSIS_SETTINGS.write();
+
+    if *current != settings {
+        debug!("Audio analysis settings changed: {:?}", settings);
+
+        let source_changed = current.source != settings.source;
+
+        *current = settings;
+        ANALYSIS_SETTINGS_GENERATION.fetch_add(1, Ordering::SeqCst);
+
+        if source_changed && AUDIO_GRABBER_THREAD_RUNNING.load(Ordering::SeqCst) {
+            AUDIO_GRABBER_THREAD_SHALL_TERMINATE.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Returns the directory that holds the sound effects and sound effect packs
+pub fn get_sfx_dir() -> PathBuf {
+    util::sfx_dir()
+}
+
+/// Returns the files of the sound effects pack `id`, relative to the sfx directory
+pub fn get_sfx_pack_files(id: &str) -> Result<Vec<PathBuf>> {
+    sfx::get_pack_files(id)
+}
 
 pub fn reset_audio_backend() {
     AUDIO_GRABBER_THREAD_SHALL_TERMINATE.store(true, Ordering::SeqCst);
@@ -104,30 +172,108 @@ pub fn reset_audio_backend() {
 
     AUDIO_GRABBER_PERFORM_RMS_COMPUTATION.store(false, Ordering::SeqCst);
     AUDIO_GRABBER_PERFORM_FFT_COMPUTATION.store(false, Ordering::SeqCst);
+    AUDIO_GRABBER_PERFORM_BEAT_DETECTION.store(false, Ordering::SeqCst);
 
     *RATE_LIMIT_TIME.write() = Instant::now()
         .checked_sub(Duration::from_millis(ERROR_RATE_LIMIT_MILLIS))
         .unwrap();
 }
 
+/// Read the `[audio]` section of `eruption.conf`
+fn get_global_settings() -> AudioSettings {
+    let config = crate::CONFIG.lock();
+
+    match config.as_ref() {
+        Some(config) => AudioSettings {
+            source: config.get_str("audio.source").ok(),
+            fft_size: config.get_int("audio.fft_size").ok().map(|v| v as usize),
+            window: config.get_str("audio.window").ok(),
+            bin_mapping: config.get_str("audio.bin_mapping").ok(),
+            bins: config.get_int("audio.bins").ok().map(|v| v as usize),
+            attack: config.get_float("audio.attack").ok(),
+            decay: config.get_float("audio.decay").ok(),
+            noise_floor: config.get_float("audio.noise_floor").ok(),
+            sfx_pack: config.get_str("audio.sfx_pack").ok(),
+            sfx_volume: config.get_float("audio.sfx_volume").ok(),
+        },
+
+        None => AudioSettings::default(),
+    }
+}
+
+/// Play back a sound effect, starts the audio backend if necessary
+fn play_sound(data: Arc<Vec<i16>>, volume: f32) -> Result<()> {
+    let start_backend = AUDIO_BACKEND.lock().is_none();
+    if start_backend {
+        if RATE_LIMIT_TIME.read().elapsed().as_millis() <= ERROR_RATE_LIMIT_MILLIS as u128 {
+            return Ok(());
+        }
+
+        try_start_audio_backend()?;
+    }
+
+    if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
+        backend.play_sfx(data, volume)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the strength of the last detected beat, if it has not been consumed yet
+pub fn take_pending_beat() -> Option<f32> {
+    PENDING_BEAT.lock().take()
+}
+
 fn try_start_audio_backend() -> Result<()> {
     // AUDIO_GRABBER_THREAD_SHALL_TERMINATE.store(false, Ordering::SeqCst);
     // AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
 
     AUDIO_BACKEND
         .lock()
-        .replace(Box::new(backends::PulseAudioBackend::new().map_err(
-            |e| {
-                *RATE_LIMIT_TIME.write() = Instant::now();
+        .replace(create_audio_backend().map_err(|e| {
+            *RATE_LIMIT_TIME.write() = Instant::now();
 
-                error!("Could not initialize the audio backend: {}", e);
-                e
-            },
-        )?));
+            error!("Could not initialize the audio backend: {}", e);
+            e
+        })?);
 
     Ok(())
 }
 
+/// Instantiate the audio backend that is selected in the `[audio]` section of `eruption.conf`
+fn create_audio_backend() -> Result<Box<dyn backends::AudioBackend + 'static + Sync + Send>> {
+    let (backend, file) = {
+        let config = crate::CONFIG.lock();
+
+        (
+            config
+                .as_ref()
+                .and_then(|c| c.get_str("audio.backend").ok())
+                .unwrap_or_else(|| "pulseaudio".to_string()),
+            config.as_ref().and_then(|c| c.get_str("audio.file").ok()),
+        )
+    };
+
+    match backend.as_str() {
+        "pulseaudio" => Ok(Box::new(backends::PulseAudioBackend::new()?)),
+
+        "file" => {
+            let file = file.ok_or_else(|| AudioPluginError::GrabberError {
+                description: "The file backend requires the setting 'audio.file'".into(),
+            })?;
+
+            Ok(Box::new(backends::FileBackend::new(PathBuf::from(file))?))
+        }
+
+        "null" => Ok(Box::new(backends::NullBackend {})),
+
+        _ => Err(AudioPluginError::GrabberError {
+            description: format!("Unknown audio backend: {}", backend),
+        }
+        .into()),
+    }
+}
+
 fn try_start_audio_grabber() -> Result<()> {
     let start_backend = AUDIO_BACKEND.lock().is_none();
     if start_backend {
@@ -191,6 +337,55 @@ impl AudioPlugin {
         AUDIO_GRABBER_BUFFER.read().to_vec()
     }
 
+    /// Enable onset and beat detection, even if no script queries the results.
+    /// Required by scripts that only implement the `on_beat` event handler
+    pub fn enable_beat_detection() {
+        AUDIO_GRABBER_PERFORM_BEAT_DETECTION.store(true, Ordering::Relaxed);
+
+        if !AUDIO_GRABBER_THREAD_RUNNING.load(Ordering::SeqCst) {
+            if RATE_LIMIT_TIME.read().elapsed().as_millis() > ERROR_RATE_LIMIT_MILLIS as u128 {
+                try_start_audio_grabber()
+                    .unwrap_or_else(|e| error!("Could not start the audio grabber: {}", e));
+            }
+        }
+    }
+
+    pub fn get_audio_band_energies() -> Vec<f32> {
+        Self::enable_beat_detection();
+
+        BAND_ENERGIES.read().clone()
+    }
+
+    pub fn get_audio_bpm() -> f32 {
+        Self::enable_beat_detection();
+
+        *CURRENT_BPM.read()
+    }
+
+    /// Play back a sound effect of the active pack, or a file from the sfx directory.
+    /// Returns false if the sound is unknown or sound effects are disabled
+    pub fn play_sfx(name: &str, volume: f32) -> bool {
+        if !ENABLE_SFX.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        match sfx::find_sound(name) {
+            Some((data, pack_volume)) => {
+                play_sound(data, pack_volume * volume.max(0.0)).unwrap_or_else(|e| {
+                    error!("Could not play sound effect '{}': {}", name, e);
+                });
+
+                true
+            }
+
+            None => {
+                warn!("Unknown sound effect: {}", name);
+
+                false
+            }
+        }
+    }
+
     pub fn get_audio_volume() -> isize {
         let start_backend = AUDIO_BACKEND.lock().is_none();
         if start_backend {
@@ -217,52 +412,32 @@ impl Plugin for AudioPlugin {
 
     fn initialize(&mut self) -> plugins::Result<()> {
         events::register_observer(|event: &events::Event| {
-            match event {
-                events::Event::KeyDown(_index) => {
-                    if ENABLE_SFX.load(Ordering::SeqCst)
-                        && SFX_KEY_DOWN.is_some()
-                        && ACTIVE_SFX.load(Ordering::SeqCst) <= MAX_IN_FLIGHT_SFX
-                    {
-                        let mut start_backend = false;
-
-                        if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
-                            backend
-                                .play_sfx(&SFX_KEY_DOWN.as_ref().unwrap())
-                                .unwrap_or_else(|e| error!("{}", e));
-                        } else {
-                            start_backend = true;
-                        }
+            if !ENABLE_SFX.load(Ordering::SeqCst) {
+                return Ok(true);
+            }
 
-                        if start_backend {
-                            try_start_audio_backend()?;
-                        }
-                    }
+            let sound = match event {
+                events::Event::KeyDown(index) => {
+                    sfx::sound_for_event(sfx::SfxEvent::KeyDown, *index)
                 }
 
-                events::Event::KeyUp(_index) => {
-                    if ENABLE_SFX.load(Ordering::SeqCst)
-                        && SFX_KEY_UP.is_some()
-                        && ACTIVE_SFX.load(Ordering::SeqCst) <= MAX_IN_FLIGHT_SFX
-                    {
-                        let mut start_backend = false;
-
-                        if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
-                            backend
-                                .play_sfx(&SFX_KEY_UP.as_ref().unwrap())
-                                .unwrap_or_else(|e| error!("{}", e));
-                        } else {
-                            start_backend = true;
-                        }
+                events::Event::KeyUp(index) => sfx::sound_for_event(sfx::SfxEvent::KeyUp, *index),
 
-                        if start_backend {
-                            try_start_audio_backend()?;
-                        }
-                    }
+                events::Event::MouseButtonDown(index) => {
+                    sfx::sound_for_event(sfx::SfxEvent::MouseButtonDown, *index)
+                }
+
+                events::Event::MouseButtonUp(index) => {
+                    sfx::sound_for_event(sfx::SfxEvent::MouseButtonUp, *index)
                 }
 
-                _ => (),
+                _ => None,
             };
 
+            if let Some((data, volume)) = sound {
+                play_sound(data, volume)?;
+            }
+
             Ok(true) // event has been processed
         });
 
@@ -284,10 +459,24 @@ impl Plugin for AudioPlugin {
             lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_raw_data()))?;
         globals.set("get_audio_raw_data", get_audio_raw_data)?;
 
+        let get_audio_band_energies =
+            lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_band_energies()))?;
+        globals.set("get_audio_band_energies", get_audio_band_energies)?;
+
+        let get_audio_bpm =
+            lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_bpm()))?;
+        globals.set("get_audio_bpm", get_audio_bpm)?;
+
         let get_audio_volume =
             lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_volume()))?;
         globals.set("get_audio_volume", get_audio_volume)?;
 
+        let play_sfx =
+            lua_ctx.create_function(move |_, (name, volume): (String, Option<f32>)| {
+                Ok(AudioPlugin::play_sfx(&name, volume.unwrap_or(1.0)))
+            })?;
+        globals.set("play_sfx", play_sfx)?;
+
         Ok(())
     }
 
@@ -304,283 +493,1894 @@ impl Plugin for AudioPlugin {
     }
 }
 
-mod util {
-    use super::AudioPluginError;
-    use super::Result;
-    use byteorder::{LittleEndian, WriteBytesExt};
-    use std::path::{Path, PathBuf};
+mod analysis {
+    use super::{
+        AudioSettings, AUDIO_SAMPLE_RATE, AUDIO_SPECTRUM, BAND_ENERGIES, CURRENT_BPM,
+        DEFAULT_FREQUENCY_BANDS, FFT_SIZE, FREQUENCY_BANDS, MAX_FFT_SIZE, MIN_FFT_SIZE,
+        PENDING_BEAT,
+    };
 
-    pub fn load_sfx<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
-        #[cfg(debug_assertions)]
-        let prefix = PathBuf::from("support/sfx");
+    use log::*;
+    use rustfft::algorithm::Radix4;
+    use rustfft::num_complex::Complex;
+    use rustfft::num_traits::Zero;
+    use rustfft::{FFTplanner, FFT};
+    use std::collections::VecDeque;
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    /// Lowest frequency of the logarithmic bin mapping, in Hz
+    const LOG_MAPPING_MIN_FREQUENCY: f32 = 20.0;
+
+    /// Window function that is applied to the samples, before computing the FFT
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum WindowFunction {
+        Rectangular,
+        Hann,
+        Hamming,
+        Blackman,
+    }
 
-        #[cfg(not(debug_assertions))]
-        let prefix = PathBuf::from("/usr/share/eruption/sfx");
+    impl WindowFunction {
+        fn parse(val: &str) -> Option<Self> {
+            match val {
+                "rectangular" => Some(WindowFunction::Rectangular),
+                "hann" => Some(WindowFunction::Hann),
+                "hamming" => Some(WindowFunction::Hamming),
+                "blackman" => Some(WindowFunction::Blackman),
 
-        let mut reader = hound::WavReader::open(prefix.join(path)).map_err(|e| {
-            AudioPluginError::PlaybackError {
-                description: format!("Could not load waveform audio file: {}", e),
+                _ => None,
             }
-        })?;
-
-        let buffer: Vec<i16> = reader
-            .samples::<i16>()
-            .map(|s| s.expect("Could not read sample data!"))
-            .collect();
+        }
 
-        let mut writer: Vec<u8> = vec![];
-        for s in buffer {
-            writer
-                .write_i16::<LittleEndian>(s)
-                .map_err(|e| AudioPluginError::IoError {
-                    description: format!("{}", e),
-                })?;
+        fn coefficients(self, size: usize) -> Vec<f32> {
+            (0..size)
+                .map(|i| {
+                    let phase = 2.0 * PI * i as f32 / (size - 1) as f32;
+
+                    match self {
+                        WindowFunction::Rectangular => 1.0,
+                        WindowFunction::Hann => 0.5 - 0.5 * phase.cos(),
+                        WindowFunction::Hamming => 0.54 - 0.46 * phase.cos(),
+                        WindowFunction::Blackman => {
+                            0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+                        }
+                    }
+                })
+                .collect()
         }
+    }
 
-        Ok(writer)
+    /// How the FFT bins are mapped to the bins of the spectrum analyzer
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum BinMapping {
+        Linear,
+        Logarithmic,
     }
-}
 
-mod backends {
-    use super::AudioPluginError;
-    use super::Result;
-    use super::ACTIVE_SFX;
-    use super::AUDIO_GRABBER_BUFFER;
-    use super::AUDIO_GRABBER_BUFFER_SIZE;
-    use super::AUDIO_GRABBER_THREAD_RUNNING;
-    use super::AUDIO_GRABBER_THREAD_SHALL_TERMINATE;
-    use super::AUDIO_SPECTRUM;
-    use super::CURRENT_RMS;
-    use super::ENABLE_SFX;
-    use super::FFT_SIZE;
+    impl BinMapping {
+        fn parse(val: &str) -> Option<Self> {
+            match val {
+                "linear" => Some(BinMapping::Linear),
+                "logarithmic" | "log" => Some(BinMapping::Logarithmic),
 
-    use log::*;
-    use std::sync::Arc;
-    use std::{sync::atomic::Ordering, thread};
+                _ => None,
+            }
+        }
+    }
 
-    use libpulse_binding as pulse;
-    use libpulse_simple_binding as psimple;
-    use psimple::Simple;
-    use pulse::sample;
-    use pulse::stream::Direction;
-    use pulsectl::controllers::DeviceControl;
-    use pulsectl::controllers::SinkController;
+    /// Settings of the spectrum analyzer and the audio grabber
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AnalysisSettings {
+        pub source: Option<String>,
+        pub fft_size: usize,
+        pub window: WindowFunction,
+        pub bin_mapping: BinMapping,
+        pub bins: usize,
+        pub attack: f32,
+        pub decay: f32,
+        pub noise_floor: f32,
+    }
 
-    use rustfft::algorithm::Radix4;
-    use rustfft::num_complex::Complex;
-    use rustfft::num_traits::Zero;
-    use rustfft::FFT;
-    use std::f32::consts::PI;
+    impl AnalysisSettings {
+        /// Read the global settings from the `[audio]` section of `eruption.conf`,
+        /// and override them with the settings of a profile, if specified
+        pub fn new(profile: Option<&AudioSettings>) -> Self {
+            Self::resolve(&super::get_global_settings(), profile)
+        }
 
-    /// Audio backend trait, defines an interface to the player and
-    /// grabber functionality
-    pub trait AudioBackend {
-        fn play_sfx(&self, data: &'static [u8]) -> Result<()>;
-        fn start_audio_grabber(&self) -> Result<()>;
+        /// Merge the settings of `profile` into the global settings `global`,
+        /// invalid values are replaced by their defaults
+        fn resolve(global: &AudioSettings, profile: Option<&AudioSettings>) -> Self {
+            // a value of the profile takes precedence over the global value
+            macro_rules! setting {
+                ($field:ident) => {
+                    profile
+                        .and_then(|p| p.$field.clone())
+                        .or_else(|| global.$field.clone())
+                };
+            }
 
-        fn get_master_volume(&self) -> Result<isize>;
-    }
+            let source = setting!(source).filter(|s| !s.is_empty() && s != "default");
 
-    /// An audio backend that does nothing
-    pub struct NullBackend {}
+            let fft_size = setting!(fft_size)
+                .filter(|size| {
+                    let valid =
+                        size.is_power_of_two() && *size >= MIN_FFT_SIZE && *size <= MAX_FFT_SIZE;
 
-    impl AudioBackend for NullBackend {
-        fn play_sfx(&self, _data: &'static [u8]) -> Result<()> {
-            Ok(())
-        }
+                    if !valid {
+                        warn!(
+                            "Invalid FFT size {}, must be a power of two between {} and {}",
+                            size, MIN_FFT_SIZE, MAX_FFT_SIZE
+                        );
+                    }
 
-        fn start_audio_grabber(&self) -> Result<()> {
-            Ok(())
+                    valid
+                })
+                .unwrap_or(FFT_SIZE);
+
+            let window = setting!(window)
+                .map(|w| {
+                    WindowFunction::parse(&w).unwrap_or_else(|| {
+                        warn!("Unknown window function '{}', using 'hamming'", w);
+                        WindowFunction::Hamming
+                    })
+                })
+                .unwrap_or(WindowFunction::Hamming);
+
+            let bin_mapping = setting!(bin_mapping)
+                .map(|m| {
+                    BinMapping::parse(&m).unwrap_or_else(|| {
+                        warn!("Unknown bin mapping '{}', using 'linear'", m);
+                        BinMapping::Linear
+                    })
+                })
+                .unwrap_or(BinMapping::Linear);
+
+            let bins = setting!(bins)
+                .unwrap_or(fft_size / 2)
+                .max(1)
+                .min(fft_size / 2);
+
+            let attack = setting!(attack).unwrap_or(0.5).max(0.0).min(1.0) as f32;
+            let decay = setting!(decay).unwrap_or(0.5).max(0.0).min(1.0) as f32;
+
+            // the default effectively disables the noise gate
+            let noise_floor = setting!(noise_floor).unwrap_or(-120.0) as f32;
+
+            Self {
+                source,
+                fft_size,
+                window,
+                bin_mapping,
+                bins,
+                attack,
+                decay,
+                noise_floor,
+            }
         }
+    }
 
-        fn get_master_volume(&self) -> Result<isize> {
-            Ok(0)
+    /// Returns the ranges of FFT bins of an FFT of `size` samples, that are mapped to each of
+    /// the `bins` bins of the spectrum analyzer
+    fn bin_ranges(bin_mapping: BinMapping, bins: usize, size: usize) -> Vec<(usize, usize)> {
+        match bin_mapping {
+            BinMapping::Linear => (0..bins)
+                .map(|i| {
+                    let low = i * (size / 2) / bins;
+                    let high = ((i + 1) * (size / 2) / bins).max(low + 1);
+
+                    (low, high)
+                })
+                .collect(),
+
+            BinMapping::Logarithmic => {
+                let resolution = AUDIO_SAMPLE_RATE as f32 / size as f32;
+                let max_frequency = AUDIO_SAMPLE_RATE as f32 / 2.0;
+                let ratio = max_frequency / LOG_MAPPING_MIN_FREQUENCY;
+
+                (0..bins)
+                    .map(|i| {
+                        let f0 = LOG_MAPPING_MIN_FREQUENCY * ratio.powf(i as f32 / bins as f32);
+                        let f1 =
+                            LOG_MAPPING_MIN_FREQUENCY * ratio.powf((i + 1) as f32 / bins as f32);
+
+                        let low = ((f0 / resolution).floor() as usize)
+                            .max(1)
+                            .min(size / 2 - 1);
+                        let high = ((f1 / resolution).ceil() as usize)
+                            .max(low + 1)
+                            .min(size / 2);
+
+                        (low, high)
+                    })
+                    .collect()
+            }
         }
     }
 
-    /// PulseAudio backend
-    pub struct PulseAudioBackend {
-        handle: Arc<psimple::Simple>,
-    }
+    /// Computes the data of the spectrum analyzer from the recorded samples
+    pub struct SpectrumAnalyzer {
+        settings: AnalysisSettings,
+        fft: Arc<dyn FFT<f32>>,
+        window: Vec<f32>,
 
-    #[allow(unused)]
-    impl PulseAudioBackend {
-        pub fn new() -> Result<Self> {
-            let handle = Arc::new(Self::init_playback()?);
-            let result = PulseAudioBackend { handle };
+        /// Scale factor of the magnitudes, so that the values do not depend on the FFT size
+        scale: f32,
 
-            Ok(result)
-        }
+        /// The magnitude of a full scale sine wave, after scaling
+        full_scale: f32,
 
-        pub fn init_playback() -> Result<psimple::Simple> {
-            let spec = sample::Spec {
-                format: sample::SAMPLE_S16NE,
-                channels: 2,
-                rate: 44100,
-            };
+        /// Ranges of FFT bins, one for each bin of the spectrum analyzer
+        ranges: Vec<(usize, usize)>,
 
-            assert!(spec.is_valid());
+        /// Most recent mono samples, an FFT may span multiple buffers of the audio grabber
+        history: VecDeque<f32>,
+    }
 
-            let result = Simple::new(
-                None,
-                "eruption",
-                Direction::Playback,
-                None,
-                "Keyboard Effects",
-                &spec,
-                None,
-                None,
-            )
-            .map_err(|e| AudioPluginError::PulseError {
-                description: format!("Could not open Pulse Audio: {}", e),
-            })?;
+    impl SpectrumAnalyzer {
+        pub fn new(settings: AnalysisSettings) -> Self {
+            let size = settings.fft_size;
 
-            Ok(result)
-        }
+            let mut planner = FFTplanner::new(false);
+            let fft = planner.plan_fft(size);
 
-        pub fn init_grabber() -> Result<psimple::Simple> {
-            let spec = sample::Spec {
-                format: sample::SAMPLE_S16NE,
-                channels: 2,
-                rate: 44100,
-            };
+            let window = settings.window.coefficients(size);
+            let gain = window.iter().sum::<f32>() / size as f32;
 
-            assert!(spec.is_valid());
+            // keep the scale of previous versions, that used a fixed FFT size and no time domain window
+            let scale = FFT_SIZE as f32 / ((FFT_SIZE / 2) as f32).sqrt() / (gain * size as f32);
+            let full_scale = gain * size as f32 / 2.0 * std::i16::MAX as f32 * scale;
 
-            let result = Simple::new(
-                None,
-                "eruption",
-                Direction::Record,
-                None,
-                "Audio Grabber",
-                &spec,
-                None,
-                None,
-            )
-            .map_err(|e| AudioPluginError::PulseError {
-                description: format!("Could not open Pulse Audio: {}", e),
-            })?;
+            let ranges = bin_ranges(settings.bin_mapping, settings.bins, size);
 
-            Ok(result)
-        }
-    }
+            let mut spectrum = AUDIO_SPECTRUM.write();
+            if spectrum.len() != settings.bins {
+                *spectrum = vec![0.0; settings.bins];
+            }
 
-    impl AudioBackend for PulseAudioBackend {
-        fn play_sfx(&self, data: &'static [u8]) -> Result<()> {
-            if !ENABLE_SFX.load(Ordering::SeqCst) {
-                return Ok(());
+            Self {
+                history: VecDeque::with_capacity(size),
+                settings,
+                fft,
+                window,
+                scale,
+                full_scale,
+                ranges,
             }
+        }
 
-            let pa = self.handle.clone();
+        /// Analyze a buffer of interleaved stereo samples
+        pub fn process(&mut self, samples: &[i16]) {
+            let size = self.settings.fft_size;
 
-            let builder = thread::Builder::new().name("audio/playback".into());
-            builder
-                .spawn(move || {
-                    ACTIVE_SFX.fetch_add(1, Ordering::SeqCst);
-
-                    pa.write(&data)
-                        .map_err(|e| AudioPluginError::PlaybackError {
-                            description: format!("Error during writing of playback buffer: {}", e),
-                        })
-                        .unwrap();
-                    pa.drain()
-                        .map_err(|e| AudioPluginError::PlaybackError {
-                            description: format!("Error during playback: {}", e),
-                        })
-                        .ok();
-
-                    ACTIVE_SFX.fetch_sub(1, Ordering::SeqCst);
-                })
-                .unwrap_or_else(|e| {
-                    error!("Could not spawn a thread: {}", e);
-                    panic!()
-                });
+            // mix down to mono, and keep only the samples of the last FFT
+            self.history.extend(
+                samples
+                    .chunks_exact(2)
+                    .map(|c| (c[0] as f32 + c[1] as f32) / 2.0),
+            );
 
-            Ok(())
-        }
+            while self.history.len() > size {
+                self.history.pop_front();
+            }
 
-        fn start_audio_grabber(&self) -> Result<()> {
-            if AUDIO_GRABBER_THREAD_RUNNING.load(Ordering::SeqCst) {
-                return Err(AudioPluginError::GrabberError {
-                    description: "Thread already running".into(),
-                }
-                .into());
+            if self.history.len() < size {
+                return;
             }
 
-            AUDIO_GRABBER_THREAD_RUNNING.store(true, Ordering::SeqCst);
+            let mut data: Vec<Complex<f32>> = self
+                .history
+                .iter()
+                .zip(self.window.iter())
+                .map(|(s, w)| Complex::from(s * w))
+                .collect();
+            let mut output = vec![Complex::zero(); size];
+
+            self.fft.process(&mut data, &mut output);
+
+            let magnitudes: Vec<f32> = output[..size / 2]
+                .iter()
+                .map(|e| {
+                    let magnitude = e.norm() * self.scale;
+
+                    // noise gate
+                    let dbfs = 20.0
+                        * (magnitude / self.full_scale)
+                            .max(std::f32::MIN_POSITIVE)
+                            .log10();
+
+                    if dbfs < self.settings.noise_floor {
+                        0.0
+                    } else {
+                        magnitude
+                    }
+                })
+                .collect();
 
-            let builder = thread::Builder::new().name("audio/grabber".into());
-            builder
-                .spawn(move || -> Result<()> {
-                    let grabber = Self::init_grabber().unwrap();
+            let mut spectrum = AUDIO_SPECTRUM.write();
 
-                    'RECORDER_LOOP: loop {
-                        let mut tmp: Vec<u8> = vec![0; AUDIO_GRABBER_BUFFER_SIZE];
+            for (e, (low, high)) in spectrum.iter_mut().zip(self.ranges.iter()) {
+                let value = magnitudes[*low..*high]
+                    .iter()
+                    .fold(0.0_f32, |max, m| max.max(*m));
 
-                        grabber
-                            .read(&mut tmp)
-                            .map_err(|e| AudioPluginError::GrabberError {
-                                description: format!("Error during recording: {}", e),
-                            })?;
+                let factor = if value > *e {
+                    self.settings.attack
+                } else {
+                    self.settings.decay
+                };
+
+                *e += (value - *e) * factor;
+            }
+        }
+    }
+
+    /// Number of past analysis frames that are used to compute the adaptive onset threshold
+    const ONSET_HISTORY_SIZE: usize = 48;
+
+    /// Sensitivity of the onset detector, in standard deviations above the mean
+    const ONSET_THRESHOLD_FACTOR: f32 = 1.5;
+
+    /// Onsets below this spectral flux are ignored, so silence does not produce beats
+    const MIN_ONSET_FLUX: f32 = 0.01;
+
+    /// Shortest and longest interval between two beats, limits the tempo to 40..240 BPM
+    const MIN_BEAT_INTERVAL_MILLIS: u128 = 250;
+    const MAX_BEAT_INTERVAL_MILLIS: u128 = 1500;
+
+    /// Number of beat intervals that are used to estimate the tempo
+    const BPM_HISTORY_SIZE: usize = 16;
+
+    /// Smoothing factors of the band energies, for rising and falling values
+    const ENERGY_ATTACK: f32 = 0.6;
+    const ENERGY_DECAY: f32 = 0.15;
+
+    /// Parse the frequency bands from the configuration, e.g.: "20-250, 250-4000"
+    pub fn get_frequency_bands() -> Vec<(f32, f32)> {
+        let bands = crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("audio.frequency_bands").ok())
+            .unwrap_or_else(|| DEFAULT_FREQUENCY_BANDS.to_string());
+
+        parse_frequency_bands(&bands).unwrap_or_else(|| {
+            warn!("Invalid frequency bands specified, using the defaults");
+
+            parse_frequency_bands(DEFAULT_FREQUENCY_BANDS).unwrap()
+        })
+    }
+
+    fn parse_frequency_bands(val: &str) -> Option<Vec<(f32, f32)>> {
+        let mut result = vec![];
+
+        for band in val.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let mut parts = band.splitn(2, '-');
+
+            let low = parts.next()?.trim().parse::<f32>().ok()?;
+            let high = parts.next()?.trim().parse::<f32>().ok()?;
+
+            if low < 0.0 || high <= low {
+                return None;
+            }
+
+            result.push((low, high));
+        }
+
+        if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    /// Map a frequency band to a range of FFT bins, each band spans at least one bin
+    fn band_to_bins(band: (f32, f32)) -> (usize, usize) {
+        let resolution = AUDIO_SAMPLE_RATE as f32 / FFT_SIZE as f32;
+
+        let low = ((band.0 / resolution).floor() as usize)
+            .max(1)
+            .min(FFT_SIZE / 2 - 1);
+        let high = ((band.1 / resolution).ceil() as usize)
+            .max(low + 1)
+            .min(FFT_SIZE / 2);
+
+        (low, high)
+    }
+
+    /// Computes band energies, and detects onsets and beats in the lowest frequency band
+    pub struct BeatDetector {
+        fft: Radix4<f32>,
+        window: Vec<f32>,
+        bins: Vec<(usize, usize)>,
+        prev_spectrum: Vec<f32>,
+        flux_history: VecDeque<f32>,
+        last_beat: Option<Instant>,
+        intervals: VecDeque<u128>,
+    }
+
+    impl BeatDetector {
+        pub fn new() -> Self {
+            // Hann window
+            let window = (0..FFT_SIZE)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+                .collect();
+
+            Self {
+                fft: Radix4::new(FFT_SIZE, false),
+                window,
+                bins: FREQUENCY_BANDS.iter().map(|b| band_to_bins(*b)).collect(),
+                prev_spectrum: vec![0.0; FFT_SIZE / 2],
+                flux_history: VecDeque::with_capacity(ONSET_HISTORY_SIZE),
+                last_beat: None,
+                intervals: VecDeque::with_capacity(BPM_HISTORY_SIZE),
+            }
+        }
+
+        /// Analyze a buffer of interleaved stereo samples
+        pub fn process(&mut self, samples: &[i16]) {
+            if samples.len() < FFT_SIZE * 2 {
+                return;
+            }
+
+            // mix down to mono and apply the window function
+            let mut data: Vec<Complex<f32>> = samples
+                .chunks_exact(2)
+                .take(FFT_SIZE)
+                .zip(self.window.iter())
+                .map(|(c, w)| Complex::from((c[0] as f32 + c[1] as f32) / 2.0 * w))
+                .collect();
+            let mut output = vec![Complex::zero(); FFT_SIZE];
+
+            self.fft.process(&mut data, &mut output);
+
+            // normalize, so that a full scale sine wave has a magnitude of 1.0
+            let scale = 4.0 / (FFT_SIZE as f32 * std::i16::MAX as f32);
+            let spectrum: Vec<f32> = output[..FFT_SIZE / 2]
+                .iter()
+                .map(|e| e.norm() * scale)
+                .collect();
+
+            // band energies
+            for (energy, (low, high)) in BAND_ENERGIES.write().iter_mut().zip(self.bins.iter()) {
+                let bins = &spectrum[*low..*high];
+                let value = (bins.iter().map(|e| e * e).sum::<f32>() / bins.len() as f32).sqrt();
+
+                let factor = if value > *energy {
+                    ENERGY_ATTACK
+                } else {
+                    ENERGY_DECAY
+                };
+
+                *energy += (value - *energy) * factor;
+            }
+
+            // spectral flux of the lowest band, e.g. the kick drum
+            let (low, high) = self.bins[0];
+            let flux: f32 = (low..high)
+                .map(|i| (spectrum[i] - self.prev_spectrum[i]).max(0.0))
+                .sum();
+
+            self.prev_spectrum = spectrum;
+
+            let threshold = onset_threshold(&self.flux_history);
+
+            if self.flux_history.len() >= ONSET_HISTORY_SIZE {
+                self.flux_history.pop_front();
+            }
+            self.flux_history.push_back(flux);
+
+            if flux > threshold {
+                self.on_onset(flux / threshold);
+            }
+        }
+
+        fn on_onset(&mut self, strength: f32) {
+            if !self.register_beat(Instant::now()) {
+                return;
+            }
+
+            if let Some(bpm) = self.estimate_bpm() {
+                *CURRENT_BPM.write() = bpm;
+            }
+
+            PENDING_BEAT.lock().replace(strength);
+        }
+
+        /// Register an onset at `now` as a beat, returns false if it follows the previous
+        /// beat too closely. Intervals that are too long are not used to estimate the tempo
+        fn register_beat(&mut self, now: Instant) -> bool {
+            if let Some(last_beat) = self.last_beat {
+                let interval = now.duration_since(last_beat).as_millis();
+
+                if interval < MIN_BEAT_INTERVAL_MILLIS {
+                    return false;
+                }
+
+                if interval <= MAX_BEAT_INTERVAL_MILLIS {
+                    if self.intervals.len() >= BPM_HISTORY_SIZE {
+                        self.intervals.pop_front();
+                    }
+                    self.intervals.push_back(interval);
+                }
+            }
+
+            self.last_beat = Some(now);
+
+            true
+        }
+
+        /// The tempo in beats per minute, estimated from the recent beat intervals
+        fn estimate_bpm(&self) -> Option<f32> {
+            // use the median, so that missed or spurious beats do not skew the estimate
+            let mut intervals: Vec<u128> = self.intervals.iter().copied().collect();
+            intervals.sort_unstable();
+
+            let median = intervals.get(intervals.len() / 2)?;
+
+            Some(60000.0 / *median as f32)
+        }
+    }
+
+    /// The spectral flux that an onset has to exceed, adapted to the recent flux `history`
+    fn onset_threshold(history: &VecDeque<f32>) -> f32 {
+        let len = history.len() as f32;
+        let mean = history.iter().sum::<f32>() / len.max(1.0);
+        let variance = history.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / len.max(1.0);
+
+        (mean + ONSET_THRESHOLD_FACTOR * variance.sqrt()).max(MIN_ONSET_FLUX)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::time::Duration;
+
+        /// Interleaved stereo samples of a sine wave of `frequency` Hz and `amplitude`
+        fn sine(frequency: f32, amplitude: f32) -> Vec<i16> {
+            (0..FFT_SIZE)
+                .flat_map(|i| {
+                    let t = i as f32 / AUDIO_SAMPLE_RATE as f32;
+                    let value = (amplitude * (2.0 * PI * frequency * t).sin()) as i16;
+
+                    vec![value, value]
+                })
+                .collect()
+        }
+
+        #[test]
+        fn test_window_function() {
+            assert_eq!(WindowFunction::parse("hann"), Some(WindowFunction::Hann));
+            assert_eq!(
+                WindowFunction::parse("blackman"),
+                Some(WindowFunction::Blackman)
+            );
+            assert_eq!(WindowFunction::parse("Hann"), None);
+            assert_eq!(WindowFunction::parse(""), None);
+
+            assert_eq!(WindowFunction::Rectangular.coefficients(4), vec![1.0; 4]);
+
+            for window in [
+                WindowFunction::Hann,
+                WindowFunction::Hamming,
+                WindowFunction::Blackman,
+            ]
+            .iter()
+            {
+                let coefficients = window.coefficients(65);
+
+                // symmetric, with the peak in the center
+                for i in 0..coefficients.len() {
+                    assert!((coefficients[i] - coefficients[64 - i]).abs() < 1e-5);
+                    assert!(coefficients[i] <= coefficients[32] + 1e-5);
+                }
+
+                assert!((coefficients[32] - 1.0).abs() < 1e-5);
+            }
+
+            assert!(WindowFunction::Hann.coefficients(65)[0].abs() < 1e-5);
+            assert!((WindowFunction::Hamming.coefficients(65)[0] - 0.08).abs() < 1e-5);
+        }
+
+        #[test]
+        fn test_bin_mapping() {
+            assert_eq!(BinMapping::parse("linear"), Some(BinMapping::Linear));
+            assert_eq!(BinMapping::parse("log"), Some(BinMapping::Logarithmic));
+            assert_eq!(
+                BinMapping::parse("logarithmic"),
+                Some(BinMapping::Logarithmic)
+            );
+            assert_eq!(BinMapping::parse("exponential"), None);
+
+            // linear bins cover all FFT bins, without gaps
+            let ranges = bin_ranges(BinMapping::Linear, 3, 16);
+            assert_eq!(ranges, vec![(0, 2), (2, 5), (5, 8)]);
+
+            for size in [MIN_FFT_SIZE, FFT_SIZE, MAX_FFT_SIZE].iter() {
+                let ranges = bin_ranges(BinMapping::Logarithmic, 64, *size);
+
+                assert_eq!(ranges.len(), 64);
+
+                for (low, high) in ranges.iter() {
+                    assert!(*low >= 1 && low < high && *high <= size / 2);
+                }
+
+                for pair in ranges.windows(2) {
+                    assert!(pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1);
+                }
+            }
+        }
+
+        #[test]
+        fn test_analysis_settings() {
+            let defaults = AnalysisSettings::resolve(&AudioSettings::default(), None);
+
+            assert_eq!(defaults.source, None);
+            assert_eq!(defaults.fft_size, FFT_SIZE);
+            assert_eq!(defaults.window, WindowFunction::Hamming);
+            assert_eq!(defaults.bin_mapping, BinMapping::Linear);
+            assert_eq!(defaults.bins, FFT_SIZE / 2);
+
+            let global = AudioSettings {
+                source: Some("default".into()),
+                fft_size: Some(MIN_FFT_SIZE),
+                window: Some("hann".into()),
+                bins: Some(8),
+                attack: Some(2.0),
+                decay: Some(-1.0),
+                ..Default::default()
+            };
+
+            let settings = AnalysisSettings::resolve(&global, None);
+
+            assert_eq!(settings.source, None);
+            assert_eq!(settings.fft_size, MIN_FFT_SIZE);
+            assert_eq!(settings.window, WindowFunction::Hann);
+            assert_eq!(settings.bins, 8);
+            assert_eq!(settings.attack, 1.0);
+            assert_eq!(settings.decay, 0.0);
+
+            // the settings of a profile take precedence
+            let profile = AudioSettings {
+                source: Some("monitor".into()),
+                window: Some("blackman".into()),
+                bin_mapping: Some("log".into()),
+                bins: Some(MAX_FFT_SIZE),
+                ..Default::default()
+            };
+
+            let settings = AnalysisSettings::resolve(&global, Some(&profile));
+
+            assert_eq!(settings.source, Some("monitor".into()));
+            assert_eq!(settings.fft_size, MIN_FFT_SIZE);
+            assert_eq!(settings.window, WindowFunction::Blackman);
+            assert_eq!(settings.bin_mapping, BinMapping::Logarithmic);
+            assert_eq!(settings.bins, MIN_FFT_SIZE / 2);
+
+            // invalid values fall back to the defaults
+            let profile = AudioSettings {
+                fft_size: Some(1000),
+                window: Some("triangle".into()),
+                bin_mapping: Some("exponential".into()),
+                bins: Some(0),
+                ..Default::default()
+            };
+
+            let settings = AnalysisSettings::resolve(&AudioSettings::default(), Some(&profile));
+
+            assert_eq!(settings.fft_size, FFT_SIZE);
+            assert_eq!(settings.window, WindowFunction::Hamming);
+            assert_eq!(settings.bin_mapping, BinMapping::Linear);
+            assert_eq!(settings.bins, 1);
+        }
+
+        #[test]
+        fn test_parse_frequency_bands() {
+            assert_eq!(
+                parse_frequency_bands(DEFAULT_FREQUENCY_BANDS),
+                Some(vec![(20.0, 250.0), (250.0, 4000.0), (4000.0, 16000.0)])
+            );
+            assert_eq!(
+                parse_frequency_bands(" 0-100.5 ,"),
+                Some(vec![(0.0, 100.5)])
+            );
+
+            assert_eq!(parse_frequency_bands(""), None);
+            assert_eq!(parse_frequency_bands("100"), None);
+            assert_eq!(parse_frequency_bands("250-20"), None);
+            assert_eq!(parse_frequency_bands("-20-250"), None);
+            assert_eq!(parse_frequency_bands("20-250, low-high"), None);
+        }
+
+        #[test]
+        fn test_band_to_bins() {
+            let resolution = AUDIO_SAMPLE_RATE as f32 / FFT_SIZE as f32;
+
+            // the DC bin is skipped
+            assert_eq!(band_to_bins((0.0, 250.0)), (1, 3));
+            assert_eq!(band_to_bins((20.0, 250.0)), (1, 3));
+
+            // each band spans at least one bin
+            assert_eq!(
+                band_to_bins((resolution * 4.0, resolution * 4.0 + 1.0)),
+                (4, 5)
+            );
+            assert_eq!(
+                band_to_bins((30000.0, 40000.0)),
+                (FFT_SIZE / 2 - 1, FFT_SIZE / 2)
+            );
+        }
+
+        #[test]
+        fn test_onset_threshold() {
+            assert_eq!(onset_threshold(&VecDeque::new()), MIN_ONSET_FLUX);
+            assert_eq!(onset_threshold(&vec![0.001; 8].into()), MIN_ONSET_FLUX);
+            assert_eq!(onset_threshold(&vec![1.0; 8].into()), 1.0);
+
+            // mean of 1.0, standard deviation of 1.0
+            assert_eq!(
+                onset_threshold(&vec![0.0, 2.0, 0.0, 2.0].into()),
+                1.0 + ONSET_THRESHOLD_FACTOR
+            );
+        }
+
+        #[test]
+        fn test_register_beat() {
+            let mut detector = BeatDetector::new();
+            let start = Instant::now();
+            let at = |millis| start + Duration::from_millis(millis);
+
+            assert!(detector.register_beat(at(0)));
+            assert_eq!(detector.estimate_bpm(), None);
+
+            // too close to the previous beat
+            assert!(!detector.register_beat(at(100)));
+
+            assert!(detector.register_beat(at(500)));
+            assert_eq!(detector.estimate_bpm(), Some(120.0));
+
+            // a beat after a pause does not affect the tempo
+            assert!(detector.register_beat(at(2500)));
+            assert_eq!(detector.estimate_bpm(), Some(120.0));
+
+            // a single spurious interval does not skew the estimate
+            assert!(detector.register_beat(at(3000)));
+            assert!(detector.register_beat(at(3300)));
+            assert_eq!(detector.estimate_bpm(), Some(120.0));
+
+            // the tempo follows the most recent intervals
+            for i in 1..=BPM_HISTORY_SIZE as u64 {
+                assert!(detector.register_beat(at(3300 + i * 400)));
+            }
+
+            assert_eq!(detector.estimate_bpm(), Some(150.0));
+        }
+
+        #[test]
+        fn test_detect_onsets() {
+            let mut detector = BeatDetector::new();
+
+            // silence does not produce beats
+            for _ in 0..ONSET_HISTORY_SIZE {
+                detector.process(&sine(60.0, 0.0));
+            }
+
+            assert!(detector.last_beat.is_none());
+
+            // neither do quiet sounds
+            detector.process(&sine(60.0, 1.0));
+            assert!(detector.last_beat.is_none());
+
+            // nor sounds above the lowest frequency band
+            detector.process(&sine(1000.0, 16000.0));
+            assert!(detector.last_beat.is_none());
+
+            detector.process(&sine(60.0, 16000.0));
+            assert!(detector.last_beat.is_some());
+
+            // too short buffers are ignored
+            let last_beat = detector.last_beat;
+            detector.process(&sine(60.0, 32000.0)[..FFT_SIZE]);
+            assert_eq!(detector.last_beat, last_beat);
+        }
+    }
+}
+
+mod sfx {
+    use super::{util, AudioPluginError, AudioSettings, Result, SFX_FILE_CACHE, SFX_PACK};
+
+    use log::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// The sound effects pack that is used if none has been configured
+    pub const DEFAULT_SFX_PACK: &str = "default";
+
+    /// File name extension of sound effects packs
+    const SFX_PACK_EXTENSION: &str = "sfxpack";
+
+    /// Classes of keys, as canvas indices of the ISO layout (see `scripting/native.rs`)
+    #[rustfmt::skip]
+    const KEY_CLASSES: [(&str, &[u8]); 11] = [
+        ("escape", &[0x00]),
+        ("function", &[0x0b, 0x11, 0x17, 0x1c, 0x30, 0x35, 0x3b, 0x41, 0x4e, 0x54, 0x55, 0x56, 0x63, 0x67, 0x6c]),
+        ("digits", &[0x06, 0x0c, 0x12, 0x18, 0x1d, 0x21, 0x31, 0x36, 0x3c, 0x42]),
+        ("letters", &[0x07, 0x0d, 0x13, 0x19, 0x1e, 0x22, 0x32, 0x37, 0x3d, 0x43, 0x49,
+                      0x08, 0x0e, 0x14, 0x1a, 0x1f, 0x23, 0x33, 0x38, 0x3e, 0x44, 0x4a,
+                      0x0f, 0x15, 0x1b, 0x20, 0x24, 0x34, 0x39]),
+        ("modifiers", &[0x03, 0x04, 0x05, 0x0a, 0x10, 0x46, 0x4c, 0x52, 0x53, 0x59]),
+        ("navigation", &[0x64, 0x68, 0x6d, 0x65, 0x69, 0x6e, 0x6a, 0x66, 0x6b, 0x6f]),
+        ("numpad", &[0x71, 0x77, 0x7c, 0x81, 0x72, 0x78, 0x7d, 0x82, 0x73, 0x79, 0x7e, 0x74, 0x7a, 0x7f, 0x83, 0x75, 0x80]),
+        ("space", &[0x25]),
+        ("enter", &[0x58]),
+        ("backspace", &[0x57]),
+        ("tab", &[0x02]),
+    ];
+
+    /// Returns the class of the key with the (1-based) index `key_index`
+    fn key_class(key_index: u8) -> Option<&'static str> {
+        let canvas_index = key_index.checked_sub(1)?;
+
+        KEY_CLASSES
+            .iter()
+            .find(|(_, keys)| keys.contains(&canvas_index))
+            .map(|(class, _)| *class)
+    }
+
+    /// Events that may trigger a sound effect
+    #[derive(Debug, Clone, Copy)]
+    pub enum SfxEvent {
+        KeyDown,
+        KeyUp,
+        MouseButtonDown,
+        MouseButtonUp,
+    }
+
+    fn default_volume() -> f32 {
+        1.0
+    }
+
+    /// The file format of a sound effects pack
+    #[derive(Debug, Deserialize)]
+    struct SoundPackFile {
+        #[allow(unused)]
+        name: String,
+
+        #[serde(default = "default_volume")]
+        volume: f32,
+
+        /// Maps names of sounds to WAV files
+        #[serde(default)]
+        sounds: HashMap<String, String>,
+
+        /// Map key indices, key classes or `default` to names of sounds
+        #[serde(default)]
+        key_down: HashMap<String, String>,
+
+        #[serde(default)]
+        key_up: HashMap<String, String>,
+
+        /// Map mouse button indices or `default` to names of sounds
+        #[serde(default)]
+        mouse_button_down: HashMap<String, String>,
+
+        #[serde(default)]
+        mouse_button_up: HashMap<String, String>,
+    }
+
+    /// Returns the pack file and the WAV files of the sound effects pack `id`,
+    /// relative to the sfx directory
+    pub fn get_pack_files(id: &str) -> Result<Vec<PathBuf>> {
+        let pack_file = PathBuf::from(format!("{}.{}", id, SFX_PACK_EXTENSION));
+        let path = util::sfx_dir().join(&pack_file);
+
+        let toml = fs::read_to_string(&path).map_err(|e| AudioPluginError::IoError {
+            description: format!("{}: {}", path.display(), e),
+        })?;
+
+        let file = toml::de::from_str::<SoundPackFile>(&toml).map_err(|e| {
+            AudioPluginError::PlaybackError {
+                description: format!("Invalid sound effects pack {}: {}", path.display(), e),
+            }
+        })?;
+
+        let mut result = vec![pack_file];
+        result.extend(file.sounds.values().map(PathBuf::from));
+
+        Ok(result)
+    }
+
+    /// A loaded sound effects pack
+    pub struct SoundPack {
+        /// File name of the pack, without the extension
+        id: String,
+
+        /// Volume of the pack, as specified in the pack file
+        pack_volume: f32,
+
+        /// Volume of the sound effects, as configured by the user
+        volume: f32,
+
+        sounds: HashMap<String, Arc<Vec<i16>>>,
+        file: SoundPackFile,
+    }
+
+    impl SoundPack {
+        pub fn load(id: &str, volume: f32) -> Result<Self> {
+            let path = util::sfx_dir().join(format!("{}.{}", id, SFX_PACK_EXTENSION));
+
+            let toml = fs::read_to_string(&path).map_err(|e| AudioPluginError::IoError {
+                description: format!("{}: {}", path.display(), e),
+            })?;
+
+            let file = toml::de::from_str::<SoundPackFile>(&toml).map_err(|e| {
+                AudioPluginError::PlaybackError {
+                    description: format!("Invalid sound effects pack {}: {}", path.display(), e),
+                }
+            })?;
+
+            let mut sounds = HashMap::new();
+
+            for (name, file_name) in file.sounds.iter() {
+                match util::load_wav(&util::sfx_dir().join(file_name)) {
+                    Ok(data) => {
+                        sounds.insert(name.clone(), Arc::new(data));
+                    }
+
+                    Err(e) => warn!("Could not load sound '{}' of pack '{}': {}", name, id, e),
+                }
+            }
+
+            info!("Loaded sound effects pack: {}", id);
+
+            Ok(Self {
+                id: id.to_string(),
+                pack_volume: file.volume.max(0.0),
+                volume,
+                sounds,
+                file,
+            })
+        }
+
+        /// Load the pack that is selected by the global settings, or by `profile`
+        pub fn load_from_settings(profile: Option<&AudioSettings>) -> Option<Self> {
+            let (id, volume) = get_selection(profile);
+
+            Self::load(&id, volume)
+                .map_err(|e| error!("Could not load the sound effects pack '{}': {}", id, e))
+                .ok()
+        }
+
+        fn volume(&self) -> f32 {
+            self.pack_volume * self.volume
+        }
+
+        /// Find the sound of an event, by the index of the key or button,
+        /// then by the class of the key, and finally by the `default` entry
+        fn sound_for_event(&self, event: SfxEvent, index: u8) -> Option<Arc<Vec<i16>>> {
+            let (mapping, class) = match event {
+                SfxEvent::KeyDown => (&self.file.key_down, key_class(index)),
+                SfxEvent::KeyUp => (&self.file.key_up, key_class(index)),
+                SfxEvent::MouseButtonDown => (&self.file.mouse_button_down, None),
+                SfxEvent::MouseButtonUp => (&self.file.mouse_button_up, None),
+            };
+
+            let name = mapping
+                .get(&index.to_string())
+                .or_else(|| class.and_then(|c| mapping.get(c)))
+                .or_else(|| mapping.get("default"))?;
+
+            self.sounds.get(name).cloned()
+        }
+    }
+
+    /// Returns the name of the selected pack and the volume, the settings
+    /// of a profile take precedence over the global settings
+    fn get_selection(profile: Option<&AudioSettings>) -> (String, f32) {
+        let global = super::get_global_settings();
+
+        let id = profile
+            .and_then(|p| p.sfx_pack.clone())
+            .or(global.sfx_pack)
+            .unwrap_or_else(|| DEFAULT_SFX_PACK.to_string());
+
+        let volume = profile
+            .and_then(|p| p.sfx_volume)
+            .or(global.sfx_volume)
+            .unwrap_or(1.0)
+            .max(0.0) as f32;
+
+        (id, volume)
+    }
+
+    /// Switch to the sound effects pack of a profile, if it is not the active pack already
+    pub fn select_pack(profile: Option<&AudioSettings>) {
+        let (id, volume) = get_selection(profile);
+
+        let mut pack = SFX_PACK.write();
+
+        match pack.as_mut() {
+            Some(pack) if pack.id == id => pack.volume = volume,
+
+            _ => {
+                *pack = SoundPack::load(&id, volume)
+                    .map_err(|e| error!("Could not load the sound effects pack '{}': {}", id, e))
+                    .ok()
+            }
+        }
+    }
+
+    /// Returns the sound of an event and its volume, if the active pack defines one
+    pub fn sound_for_event(event: SfxEvent, index: u8) -> Option<(Arc<Vec<i16>>, f32)> {
+        let pack = SFX_PACK.read();
+        let pack = pack.as_ref()?;
+
+        pack.sound_for_event(event, index)
+            .map(|data| (data, pack.volume()))
+    }
+
+    /// Find a sound by its name in the active pack, or by the name of a WAV file in the sfx directory
+    pub fn find_sound(name: &str) -> Option<(Arc<Vec<i16>>, f32)> {
+        let pack = SFX_PACK.read();
+        let volume = pack.as_ref().map(|p| p.volume).unwrap_or(1.0);
+
+        if let Some(data) = pack.as_ref().and_then(|p| p.sounds.get(name)) {
+            return Some((data.clone(), pack.as_ref().unwrap().volume()));
+        }
+
+        // only allow plain file names, so scripts can not read arbitrary files
+        if !name.ends_with(".wav") || name.contains('/') || name.starts_with('.') {
+            return None;
+        }
+
+        let mut cache = SFX_FILE_CACHE.lock();
+
+        if let Some(data) = cache.get(name) {
+            return Some((data.clone(), volume));
+        }
+
+        match util::load_wav(&util::sfx_dir().join(name)) {
+            Ok(data) => {
+                let data = Arc::new(data);
+                cache.insert(name.to_string(), data.clone());
+
+                Some((data, volume))
+            }
+
+            Err(e) => {
+                warn!("Could not load sound effect '{}': {}", name, e);
+
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Parse the sound effects pack `toml`, each sound consists of a single sample,
+        /// the first character of its name
+        fn pack(toml: &str, volume: f32) -> SoundPack {
+            let file = toml::de::from_str::<SoundPackFile>(toml).unwrap();
+
+            let sounds = file
+                .sounds
+                .keys()
+                .map(|name| (name.clone(), Arc::new(vec![name.as_bytes()[0] as i16])))
+                .collect();
+
+            SoundPack {
+                id: "test".into(),
+                pack_volume: file.volume,
+                volume,
+                sounds,
+                file,
+            }
+        }
+
+        #[test]
+        fn test_key_class() {
+            assert_eq!(key_class(1), Some("escape"));
+            assert_eq!(key_class(0x07 + 1), Some("letters"));
+            assert_eq!(key_class(0x25 + 1), Some("space"));
+            assert_eq!(key_class(0x58 + 1), Some("enter"));
+
+            assert_eq!(key_class(0), None);
+            assert_eq!(key_class(0x01 + 1), None);
+            assert_eq!(key_class(u8::MAX), None);
+
+            // each key belongs to one class at most
+            for (i, (_, keys)) in KEY_CLASSES.iter().enumerate() {
+                for (_, other) in KEY_CLASSES[i + 1..].iter() {
+                    assert!(keys.iter().all(|k| !other.contains(k)));
+                }
+            }
+        }
+
+        #[test]
+        fn test_sound_for_event() {
+            let pack = pack(
+                r#"
+                name = 'Test'
+                volume = 0.5
+
+                [sounds]
+                a = 'a.wav'
+                b = 'b.wav'
+                c = 'c.wav'
+                d = 'd.wav'
+
+                [key_down]
+                1 = 'a'
+                escape = 'd'
+                letters = 'b'
+                default = 'c'
+
+                [mouse_button_down]
+                default = 'd'
+                "#,
+                0.8,
+            );
+
+            let sound = |event, index| {
+                pack.sound_for_event(event, index)
+                    .map(|data| data[0] as u8 as char)
+            };
+
+            // the index of a key takes precedence over its class, and the class over the default
+            assert_eq!(sound(SfxEvent::KeyDown, 1), Some('a'));
+            assert_eq!(sound(SfxEvent::KeyDown, 0x07 + 1), Some('b'));
+            assert_eq!(sound(SfxEvent::KeyDown, 0x06 + 1), Some('c'));
+            assert_eq!(sound(SfxEvent::KeyUp, 1), None);
+
+            assert_eq!(sound(SfxEvent::MouseButtonDown, 1), Some('d'));
+            assert_eq!(sound(SfxEvent::MouseButtonUp, 1), None);
+
+            assert!((pack.volume() - 0.4).abs() < 1e-6);
+        }
+
+        #[test]
+        fn test_sound_pack_file() {
+            let file = toml::de::from_str::<SoundPackFile>("name = 'Empty'").unwrap();
+
+            assert_eq!(file.volume, 1.0);
+            assert!(file.sounds.is_empty() && file.key_down.is_empty());
+
+            // sounds that are not defined by the pack are ignored
+            let pack = pack("name = 'Test'\n[key_down]\ndefault = 'missing'", 1.0);
+            assert!(pack.sound_for_event(SfxEvent::KeyDown, 1).is_none());
+
+            assert!(toml::de::from_str::<SoundPackFile>("volume = 1.0").is_err());
+        }
+    }
+}
+
+mod util {
+    use super::AudioPluginError;
+    use super::Result;
+    use super::AUDIO_SAMPLE_RATE;
+    use std::path::{Path, PathBuf};
+
+    /// The directory that holds the sound effects and sound effect packs
+    pub fn sfx_dir() -> PathBuf {
+        #[cfg(debug_assertions)]
+        let prefix = PathBuf::from("support/sfx");
+
+        #[cfg(not(debug_assertions))]
+        let prefix = PathBuf::from("/usr/share/eruption/sfx");
+
+        prefix
+    }
+
+    /// Decode a WAV file to interleaved 16 bit stereo samples,
+    /// at the sample rate of the audio grabber
+    pub fn load_wav(path: &Path) -> Result<Vec<i16>> {
+        let wav_error = |e: hound::Error| AudioPluginError::IoError {
+            description: format!("Could not load waveform audio file: {}", e),
+        };
+
+        let mut reader = hound::WavReader::open(path).map_err(wav_error)?;
+        let spec = reader.spec();
+
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader
+                .samples::<f32>()
+                .map(|s| s.map(|s| s * std::i16::MAX as f32))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(wav_error)?,
+
+            hound::SampleFormat::Int => {
+                let scale = 2.0_f32.powi(16 - spec.bits_per_sample as i32);
+
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(wav_error)?
+            }
+        };
+
+        // mix to stereo: duplicate mono signals, drop additional channels
+        let channels = (spec.channels as usize).max(1);
+        let frames: Vec<(f32, f32)> = samples
+            .chunks_exact(channels)
+            .map(|c| (c[0], *c.get(1).unwrap_or(&c[0])))
+            .collect();
+
+        if frames.is_empty() {
+            return Err(AudioPluginError::IoError {
+                description: format!("{}: The file does not contain samples", path.display()),
+            }
+            .into());
+        }
+
+        // linear interpolation, if the sample rates differ
+        let ratio = spec.sample_rate as f64 / AUDIO_SAMPLE_RATE as f64;
+        let len = ((frames.len() as f64 / ratio) as usize).max(1);
+
+        let mut result = Vec::with_capacity(len * 2);
+
+        for i in 0..len {
+            let pos = i as f64 * ratio;
+            let index = (pos as usize).min(frames.len() - 1);
+            let next = (index + 1).min(frames.len() - 1);
+            let t = (pos - index as f64) as f32;
+
+            let left = frames[index].0 + (frames[next].0 - frames[index].0) * t;
+            let right = frames[index].1 + (frames[next].1 - frames[index].1) * t;
+
+            result.push(
+                left.round()
+                    .max(std::i16::MIN as f32)
+                    .min(std::i16::MAX as f32) as i16,
+            );
+            result.push(
+                right
+                    .round()
+                    .max(std::i16::MIN as f32)
+                    .min(std::i16::MAX as f32) as i16,
+            );
+        }
+
+        Ok(result)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::fs;
+
+        /// Create an empty directory for the files of the test `name`
+        fn test_dir(name: &str) -> PathBuf {
+            let dir =
+                std::env::temp_dir().join(format!("eruption-test-{}-{}", std::process::id(), name));
+
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            dir
+        }
+
+        /// Write the WAV file `path` with the format `spec` and the samples `samples`
+        fn write_wav<S: hound::Sample + Copy>(path: &Path, spec: hound::WavSpec, samples: &[S]) {
+            let mut writer = hound::WavWriter::create(path, spec).unwrap();
+
+            for sample in samples {
+                writer.write_sample(*sample).unwrap();
+            }
+
+            writer.finalize().unwrap();
+        }
+
+        fn spec(
+            channels: u16,
+            sample_rate: u32,
+            bits_per_sample: u16,
+            sample_format: hound::SampleFormat,
+        ) -> hound::WavSpec {
+            hound::WavSpec {
+                channels,
+                sample_rate,
+                bits_per_sample,
+                sample_format,
+            }
+        }
+
+        #[test]
+        fn test_load_wav() {
+            let dir = test_dir("load-wav");
+            let path = dir.join("sound.wav");
+
+            let rate = AUDIO_SAMPLE_RATE as u32;
+            let int = hound::SampleFormat::Int;
+
+            write_wav(&path, spec(2, rate, 16, int), &[100_i16, -100, 200, -200]);
+            assert_eq!(load_wav(&path).unwrap(), vec![100, -100, 200, -200]);
+
+            // mono is duplicated to both channels, and resampled to the rate of the audio grabber
+            write_wav(&path, spec(1, rate / 2, 16, int), &[0_i16, 1000]);
+            assert_eq!(
+                load_wav(&path).unwrap(),
+                vec![0, 0, 500, 500, 1000, 1000, 1000, 1000]
+            );
+
+            // additional channels are dropped
+            write_wav(&path, spec(3, rate, 16, int), &[1_i16, 2, 3]);
+            assert_eq!(load_wav(&path).unwrap(), vec![1, 2]);
+
+            // other sample formats are scaled to 16 bits
+            write_wav(&path, spec(2, rate, 8, int), &[64_i8, -64]);
+            assert_eq!(load_wav(&path).unwrap(), vec![16384, -16384]);
+
+            let float = hound::SampleFormat::Float;
+
+            write_wav(&path, spec(2, rate, 32, float), &[0.5_f32, -1.0]);
+            assert_eq!(load_wav(&path).unwrap(), vec![16384, -32767]);
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn test_load_invalid_wav() {
+            let dir = test_dir("load-invalid-wav");
+            let path = dir.join("sound.wav");
+
+            // a file without samples
+            let spec = spec(2, AUDIO_SAMPLE_RATE as u32, 16, hound::SampleFormat::Int);
+            write_wav(&path, spec, &[] as &[i16]);
+
+            assert!(load_wav(&path).is_err());
+
+            // not a WAV file
+            fs::write(&path, "RIFF").unwrap();
+            assert!(load_wav(&path).is_err());
+
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(load_wav(&path).is_err());
+        }
+    }
+}
+
+mod backends {
+    use super::AudioPluginError;
+    use super::Result;
+    use super::ACTIVE_SFX;
+    use super::ANALYSIS_SETTINGS;
+    use super::ANALYSIS_SETTINGS_GENERATION;
+    use super::AUDIO_GRABBER_BUFFER;
+    use super::AUDIO_GRABBER_BUFFER_SIZE;
+    use super::AUDIO_GRABBER_THREAD_RUNNING;
+    use super::AUDIO_GRABBER_THREAD_SHALL_TERMINATE;
+    use super::AUDIO_SAMPLE_RATE;
+    use super::CURRENT_RMS;
+    use super::ENABLE_SFX;
+
+    use super::analysis::{AnalysisSettings, BeatDetector, SpectrumAnalyzer};
+    use super::util;
+    use super::MAX_SFX_VOICES;
+
+    use crossbeam::channel::{unbounded, Receiver, Sender};
+    use log::*;
+    use std::fs::File;
+    use std::io::{self, Read, Seek, SeekFrom};
+    use std::os::unix::fs::FileTypeExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use std::{sync::atomic::Ordering, thread};
+
+    use libpulse_binding as pulse;
+    use libpulse_simple_binding as psimple;
+    use psimple::Simple;
+    use pulse::sample;
+    use pulse::stream::Direction;
+    use pulsectl::controllers::DeviceControl;
+    use pulsectl::controllers::SinkController;
+
+    /// Audio backend trait, defines an interface to the player and
+    /// grabber functionality
+    pub trait AudioBackend {
+        fn play_sfx(&self, data: Arc<Vec<i16>>, volume: f32) -> Result<()>;
+        fn start_audio_grabber(&self) -> Result<()>;
+
+        fn get_master_volume(&self) -> Result<isize>;
+    }
+
+    /// An audio backend that does nothing
+    pub struct NullBackend {}
+
+    impl AudioBackend for NullBackend {
+        fn play_sfx(&self, _data: Arc<Vec<i16>>, _volume: f32) -> Result<()> {
+            Ok(())
+        }
+
+        fn start_audio_grabber(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_master_volume(&self) -> Result<isize> {
+            Ok(0)
+        }
+    }
+
+    /// Performs the analysis steps on each buffer of recorded samples,
+    /// shared by all backends that are able to capture audio
+    struct SampleProcessor {
+        generation: usize,
+        spectrum_analyzer: SpectrumAnalyzer,
+        beat_detector: BeatDetector,
+    }
+
+    impl SampleProcessor {
+        fn new(settings: AnalysisSettings) -> Self {
+            Self {
+                generation: ANALYSIS_SETTINGS_GENERATION.load(Ordering::SeqCst),
+                spectrum_analyzer: SpectrumAnalyzer::new(settings),
+                beat_detector: BeatDetector::new(),
+            }
+        }
+
+        /// Store the recorded samples in the audio grabber buffer, and analyze them
+        fn process(&mut self, data: &[u8]) {
+            let mut buffer = AUDIO_GRABBER_BUFFER.write();
+            buffer.clear();
+            buffer.reserve(AUDIO_GRABBER_BUFFER_SIZE);
+            buffer.extend(
+                data.chunks_exact(2)
+                    .map(|c| i16::from_ne_bytes([c[0], c[1]])),
+            );
+
+            // compute root mean square (RMS) of the recorded samples
+            if super::AUDIO_GRABBER_PERFORM_RMS_COMPUTATION.load(Ordering::Relaxed) {
+                let sqr_sum = buffer
+                    .iter()
+                    .map(|s| *s as f32)
+                    .fold(0.0, |sqr_sum, s| sqr_sum + s * s);
+
+                let sqr_sum = (sqr_sum / buffer.len() as f32).sqrt();
+
+                CURRENT_RMS.store(sqr_sum.round() as isize, Ordering::SeqCst);
+            }
+
+            // compute spectrum analyzer
+            if super::AUDIO_GRABBER_PERFORM_FFT_COMPUTATION.load(Ordering::Relaxed) {
+                let current_generation = ANALYSIS_SETTINGS_GENERATION.load(Ordering::SeqCst);
+
+                if current_generation != self.generation {
+                    self.generation = current_generation;
+
+                    self.spectrum_analyzer =
+                        SpectrumAnalyzer::new(ANALYSIS_SETTINGS.read().clone());
+                }
+
+                self.spectrum_analyzer.process(&buffer);
+            }
 
-                        let mut buffer = AUDIO_GRABBER_BUFFER.write();
-                        buffer.clear();
-                        buffer.reserve(AUDIO_GRABBER_BUFFER_SIZE);
-                        buffer.extend(
-                            tmp.chunks_exact(2)
-                                .map(|c| i16::from_ne_bytes([c[0], c[1]])),
-                        );
+            // compute band energies and detect beats
+            if super::AUDIO_GRABBER_PERFORM_BEAT_DETECTION.load(Ordering::Relaxed) {
+                self.beat_detector.process(&buffer);
+            }
+        }
+    }
+
+    /// Returns true if the grabber thread has been requested to terminate,
+    /// and marks the thread as stopped
+    fn grabber_shall_terminate() -> bool {
+        if AUDIO_GRABBER_THREAD_SHALL_TERMINATE.load(Ordering::SeqCst) {
+            AUDIO_GRABBER_THREAD_SHALL_TERMINATE.store(false, Ordering::SeqCst);
+            AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reset the playback clock, if the playback position lags behind by more than this amount,
+    /// e.g. because the writer of a FIFO is slower than real-time
+    const MAX_PLAYBACK_LAG_MILLIS: u64 = 250;
+
+    /// Source of the samples of the file backend
+    enum SampleSource {
+        /// A decoded WAV file, the playback position is in samples
+        Samples { data: Vec<i16>, pos: usize },
+
+        /// Raw PCM data, read from a FIFO or from a regular file
+        Stream { file: File, path: PathBuf },
+    }
+
+    impl SampleSource {
+        fn open(path: &Path) -> Result<Self> {
+            let is_wav = path
+                .extension()
+                .map_or(false, |ext| ext.to_string_lossy().to_lowercase() == "wav");
+
+            if is_wav {
+                Ok(SampleSource::Samples {
+                    data: util::load_wav(path)?,
+                    pos: 0,
+                })
+            } else {
+                let file = File::open(path).map_err(|e| AudioPluginError::IoError {
+                    description: format!("{}: {}", path.display(), e),
+                })?;
+
+                Ok(SampleSource::Stream {
+                    file,
+                    path: path.to_path_buf(),
+                })
+            }
+        }
+
+        /// Fill `buf` with the next samples, WAV files and regular files are looped,
+        /// a FIFO is re-opened as soon as the writer closes it
+        fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+            match self {
+                SampleSource::Samples { data, pos } => {
+                    for c in buf.chunks_exact_mut(2) {
+                        c.copy_from_slice(&data[*pos].to_ne_bytes());
 
-                        // compute root mean square (RMS) of the recorded samples
-                        if super::AUDIO_GRABBER_PERFORM_RMS_COMPUTATION.load(Ordering::Relaxed) {
-                            let sqr_sum = buffer
-                                .iter()
-                                .map(|s| *s as f32)
-                                .fold(0.0, |sqr_sum, s| sqr_sum + s * s);
+                        *pos = (*pos + 1) % data.len();
+                    }
 
-                            let sqr_sum = (sqr_sum / buffer.len() as f32).sqrt();
+                    Ok(())
+                }
 
-                            CURRENT_RMS.store(sqr_sum.round() as isize, Ordering::SeqCst);
+                SampleSource::Stream { file, path } => loop {
+                    match file.read_exact(buf) {
+                        Ok(()) => return Ok(()),
+
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                            if FileBackend::is_fifo(path) {
+                                // blocks until the next writer opens the FIFO
+                                *file =
+                                    File::open(&path).map_err(|e| AudioPluginError::IoError {
+                                        description: format!("{}: {}", path.display(), e),
+                                    })?;
+                            } else {
+                                let len = file.seek(SeekFrom::End(0)).map_err(|e| {
+                                    AudioPluginError::IoError {
+                                        description: format!("{}", e),
+                                    }
+                                })?;
+
+                                if len < buf.len() as u64 {
+                                    return Err(AudioPluginError::IoError {
+                                        description: format!(
+                                            "{}: File is too short",
+                                            path.display()
+                                        ),
+                                    }
+                                    .into());
+                                }
+
+                                file.seek(SeekFrom::Start(0)).map_err(|e| {
+                                    AudioPluginError::IoError {
+                                        description: format!("{}", e),
+                                    }
+                                })?;
+                            }
                         }
 
-                        // compute spectrum analyzer
-                        if super::AUDIO_GRABBER_PERFORM_FFT_COMPUTATION.load(Ordering::Relaxed) {
-                            let mut data: Vec<Complex<f32>> = buffer
-                                .iter()
-                                .take(FFT_SIZE)
-                                .map(|e| Complex::from(*e as f32))
-                                .collect();
-                            let mut output = vec![Complex::zero(); FFT_SIZE];
-
-                            let fft = Radix4::new(FFT_SIZE, false);
-                            fft.process(&mut data, &mut output);
-
-                            // apply post processing steps: normalization, window function and smoothing
-                            let one_over_fft_len_sqrt = 1.0 / ((FFT_SIZE / 2) as f32).sqrt();
-
-                            let mut phase = 0.0;
-                            const DELTA: f32 = (2.0 * PI) / (FFT_SIZE / 2) as f32;
-
-                            let result: Vec<f32> = output[(FFT_SIZE / 2)..]
-                                .iter()
-                                // normalize
-                                .map(|e| ((e.re as f32) * one_over_fft_len_sqrt).abs())
-                                // apply Hamming window
-                                .map(|e| {
-                                    phase += DELTA;
-                                    e * (0.54 - 0.46 * phase.cos())
-                                })
-                                .collect();
-
-                            for (i, e) in AUDIO_SPECTRUM.write().iter_mut().enumerate() {
-                                *e = (*e + result[i]) / 2.0;
+                        Err(e) => {
+                            return Err(AudioPluginError::IoError {
+                                description: format!("{}: {}", path.display(), e),
                             }
+                            .into())
                         }
+                    }
+                },
+            }
+        }
+    }
+
+    /// An audio backend that replays a WAV file, or raw PCM data (signed 16 bit, native endian,
+    /// stereo, 44.1kHz) from a FIFO, at real-time pace. Sound effects are not played back
+    pub struct FileBackend {
+        path: PathBuf,
+    }
+
+    impl FileBackend {
+        pub fn new(path: PathBuf) -> Result<Self> {
+            if !path.exists() {
+                return Err(AudioPluginError::IoError {
+                    description: format!("File not found: {}", path.display()),
+                }
+                .into());
+            }
+
+            Ok(FileBackend { path })
+        }
+
+        fn is_fifo(path: &Path) -> bool {
+            path.metadata()
+                .map(|m| m.file_type().is_fifo())
+                .unwrap_or(false)
+        }
+    }
+
+    impl AudioBackend for FileBackend {
+        fn play_sfx(&self, _data: Arc<Vec<i16>>, _volume: f32) -> Result<()> {
+            Ok(())
+        }
+
+        fn start_audio_grabber(&self) -> Result<()> {
+            if AUDIO_GRABBER_THREAD_RUNNING.load(Ordering::SeqCst) {
+                return Err(AudioPluginError::GrabberError {
+                    description: "Thread already running".into(),
+                }
+                .into());
+            }
+
+            AUDIO_GRABBER_THREAD_RUNNING.store(true, Ordering::SeqCst);
+
+            let path = self.path.clone();
+
+            let builder = thread::Builder::new().name("audio/grabber".into());
+            builder
+                .spawn(move || -> Result<()> {
+                    // opening a FIFO blocks until a writer connects
+                    let mut source = SampleSource::open(&path).map_err(|e| {
+                        AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
+                        error!("Could not open the audio file: {}", e);
+
+                        e
+                    })?;
+
+                    let mut processor = SampleProcessor::new(ANALYSIS_SETTINGS.read().clone());
+
+                    // duration of a single buffer of samples
+                    let frames_per_buffer = AUDIO_GRABBER_BUFFER_SIZE / 4;
+
+                    let mut start = Instant::now();
+                    let mut frames_processed = 0;
+
+                    'RECORDER_LOOP: loop {
+                        let mut tmp: Vec<u8> = vec![0; AUDIO_GRABBER_BUFFER_SIZE];
+
+                        if let Err(e) = source.read(&mut tmp) {
+                            error!("Error during playback of the audio file: {}", e);
 
-                        if AUDIO_GRABBER_THREAD_SHALL_TERMINATE.load(Ordering::SeqCst) {
-                            AUDIO_GRABBER_THREAD_SHALL_TERMINATE.store(false, Ordering::SeqCst);
                             AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
+                            return Err(e);
+                        }
+
+                        processor.process(&tmp);
+
+                        if grabber_shall_terminate() {
+                            break 'RECORDER_LOOP;
+                        }
+
+                        // keep the pace of a real-time source
+                        frames_processed += frames_per_buffer;
+
+                        let deadline = start
+                            + Duration::from_micros(
+                                frames_processed as u64 * 1_000_000 / AUDIO_SAMPLE_RATE as u64,
+                            );
+                        let now = Instant::now();
+
+                        if deadline > now {
+                            thread::sleep(deadline - now);
+                        } else if now - deadline > Duration::from_millis(MAX_PLAYBACK_LAG_MILLIS) {
+                            // do not try to catch up, just continue from here
+                            start = now;
+                            frames_processed = 0;
+                        }
+                    }
+
+                    Ok(())
+                })
+                .unwrap_or_else(|e| {
+                    error!("Could not spawn a thread: {}", e);
+                    panic!()
+                });
+
+            Ok(())
+        }
+
+        fn get_master_volume(&self) -> Result<isize> {
+            Ok(0)
+        }
+    }
+
+    /// Number of samples that are mixed at once, 10 milliseconds of stereo audio
+    const MIXER_CHUNK_SIZE: usize = AUDIO_SAMPLE_RATE / 100 * 2;
+
+    /// A sound effect that is currently being played back
+    struct Voice {
+        data: Arc<Vec<i16>>,
+        volume: f32,
+        pos: usize,
+    }
+
+    /// Mixes all sound effects that are played back simultaneously into a single stream
+    fn run_mixer(handle: psimple::Simple, rx: Receiver<Voice>) {
+        let mut voices: Vec<Voice> = Vec::new();
+
+        loop {
+            if voices.is_empty() {
+                // wait for the next sound effect, without spinning
+                match rx.recv() {
+                    Ok(voice) => voices.push(voice),
+                    Err(_) => break,
+                }
+            }
+
+            voices.extend(rx.try_iter());
+
+            if voices.len() > MAX_SFX_VOICES {
+                voices.drain(..voices.len() - MAX_SFX_VOICES);
+            }
+
+            let mut mix = [0.0_f32; MIXER_CHUNK_SIZE];
+
+            for voice in voices.iter_mut() {
+                for (m, s) in mix.iter_mut().zip(voice.data[voice.pos..].iter()) {
+                    *m += *s as f32 * voice.volume;
+                }
+
+                voice.pos = (voice.pos + MIXER_CHUNK_SIZE).min(voice.data.len());
+            }
+
+            voices.retain(|v| v.pos < v.data.len());
+            ACTIVE_SFX.store(voices.len(), Ordering::SeqCst);
+
+            let buffer: Vec<u8> = mix
+                .iter()
+                .flat_map(|s| {
+                    let s = s
+                        .round()
+                        .max(std::i16::MIN as f32)
+                        .min(std::i16::MAX as f32);
+                    (s as i16).to_ne_bytes().to_vec()
+                })
+                .collect();
+
+            // blocks until PulseAudio accepts more data, this paces the mixer
+            handle.write(&buffer).unwrap_or_else(|e| {
+                error!("Error during writing of playback buffer: {}", e);
+            });
+        }
+    }
+
+    /// PulseAudio backend
+    pub struct PulseAudioBackend {
+        mixer_tx: Sender<Voice>,
+    }
+
+    #[allow(unused)]
+    impl PulseAudioBackend {
+        pub fn new() -> Result<Self> {
+            let handle = Self::init_playback()?;
+            let (mixer_tx, mixer_rx) = unbounded();
+
+            let builder = thread::Builder::new().name("audio/mixer".into());
+            builder
+                .spawn(move || run_mixer(handle, mixer_rx))
+                .map_err(|e| AudioPluginError::PlaybackError {
+                    description: format!("Could not spawn a thread: {}", e),
+                })?;
+
+            let result = PulseAudioBackend { mixer_tx };
+
+            Ok(result)
+        }
+
+        pub fn init_playback() -> Result<psimple::Simple> {
+            let spec = sample::Spec {
+                format: sample::SAMPLE_S16NE,
+                channels: 2,
+                rate: 44100,
+            };
+
+            assert!(spec.is_valid());
+
+            let result = Simple::new(
+                None,
+                "eruption",
+                Direction::Playback,
+                None,
+                "Keyboard Effects",
+                &spec,
+                None,
+                None,
+            )
+            .map_err(|e| AudioPluginError::PulseError {
+                description: format!("Could not open Pulse Audio: {}", e),
+            })?;
+
+            Ok(result)
+        }
+
+        /// Open the capture source `source`, or the default source if `None`
+        pub fn init_grabber(source: Option<&str>) -> Result<psimple::Simple> {
+            let spec = sample::Spec {
+                format: sample::SAMPLE_S16NE,
+                channels: 2,
+                rate: 44100,
+            };
+
+            assert!(spec.is_valid());
+
+            let result = Simple::new(
+                None,
+                "eruption",
+                Direction::Record,
+                source,
+                "Audio Grabber",
+                &spec,
+                None,
+                None,
+            )
+            .map_err(|e| AudioPluginError::PulseError {
+                description: format!("Could not open Pulse Audio: {}", e),
+            })?;
+
+            Ok(result)
+        }
+    }
+
+    impl AudioBackend for PulseAudioBackend {
+        fn play_sfx(&self, data: Arc<Vec<i16>>, volume: f32) -> Result<()> {
+            if !ENABLE_SFX.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            self.mixer_tx
+                .send(Voice {
+                    data,
+                    volume,
+                    pos: 0,
+                })
+                .map_err(|e| AudioPluginError::PlaybackError {
+                    description: format!("Could not send to the mixer: {}", e),
+                })?;
+
+            Ok(())
+        }
+
+        fn start_audio_grabber(&self) -> Result<()> {
+            if AUDIO_GRABBER_THREAD_RUNNING.load(Ordering::SeqCst) {
+                return Err(AudioPluginError::GrabberError {
+                    description: "Thread already running".into(),
+                }
+                .into());
+            }
+
+            AUDIO_GRABBER_THREAD_RUNNING.store(true, Ordering::SeqCst);
+
+            let builder = thread::Builder::new().name("audio/grabber".into());
+            builder
+                .spawn(move || -> Result<()> {
+                    let settings = ANALYSIS_SETTINGS.read().clone();
+
+                    let grabber = Self::init_grabber(settings.source.as_deref()).map_err(|e| {
+                        AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
+                        error!("Could not open the capture source: {}", e);
+
+                        e
+                    })?;
+
+                    let mut processor = SampleProcessor::new(settings);
+
+                    'RECORDER_LOOP: loop {
+                        let mut tmp: Vec<u8> = vec![0; AUDIO_GRABBER_BUFFER_SIZE];
+
+                        grabber
+                            .read(&mut tmp)
+                            .map_err(|e| AudioPluginError::GrabberError {
+                                description: format!("Error during recording: {}", e),
+                            })?;
+
+                        processor.process(&tmp);
 
+                        if grabber_shall_terminate() {
                             break 'RECORDER_LOOP;
                         }
                     }
@@ -609,4 +2409,128 @@ mod backends {
             Ok(result as isize)
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::fs;
+        use std::io::Write;
+
+        /// Returns the path of the file `name`, in a temporary directory of the test `test`
+        fn test_file(test: &str, name: &str) -> PathBuf {
+            let dir =
+                std::env::temp_dir().join(format!("eruption-test-{}-{}", std::process::id(), test));
+
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            dir.join(name)
+        }
+
+        fn to_bytes(samples: &[i16]) -> Vec<u8> {
+            samples
+                .iter()
+                .flat_map(|s| s.to_ne_bytes().to_vec())
+                .collect()
+        }
+
+        /// Read the next `count` samples from `source`
+        fn read_samples(source: &mut SampleSource, count: usize) -> Vec<i16> {
+            let mut buf = vec![0; count * 2];
+            source.read(&mut buf).unwrap();
+
+            buf.chunks_exact(2)
+                .map(|c| i16::from_ne_bytes([c[0], c[1]]))
+                .collect()
+        }
+
+        #[test]
+        fn test_file_backend() {
+            let path = test_file("file-backend", "audio.pcm");
+
+            assert!(FileBackend::new(path.clone()).is_err());
+
+            fs::write(&path, to_bytes(&[1, 2])).unwrap();
+
+            assert!(FileBackend::new(path.clone()).is_ok());
+            assert!(!FileBackend::is_fifo(&path));
+
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_read_samples() {
+            let mut source = SampleSource::Samples {
+                data: vec![1, 2, 3],
+                pos: 0,
+            };
+
+            // decoded samples are looped
+            assert_eq!(read_samples(&mut source, 5), vec![1, 2, 3, 1, 2]);
+            assert_eq!(read_samples(&mut source, 5), vec![3, 1, 2, 3, 1]);
+        }
+
+        #[test]
+        fn test_read_stream() {
+            let path = test_file("read-stream", "audio.pcm");
+
+            fs::write(&path, to_bytes(&[1, 2, 3, 4])).unwrap();
+
+            // regular files are looped
+            let mut source = SampleSource::open(&path).unwrap();
+
+            assert_eq!(read_samples(&mut source, 2), vec![1, 2]);
+            assert_eq!(read_samples(&mut source, 2), vec![3, 4]);
+            assert_eq!(read_samples(&mut source, 2), vec![1, 2]);
+
+            // a file that is shorter than a single buffer
+            let mut source = SampleSource::open(&path).unwrap();
+            let mut buf = vec![0; 10];
+
+            assert!(source.read(&mut buf).is_err());
+
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_read_fifo() {
+            let path = test_file("read-fifo", "audio.fifo");
+
+            let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+            assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+            assert!(FileBackend::is_fifo(&path));
+
+            // the FIFO is opened by two writers, one after the other, the second one
+            // keeps it open until the samples have been read
+            let (tx, rx) = std::sync::mpsc::channel();
+
+            let writer_path = path.clone();
+            let writer = thread::spawn(move || {
+                let open = || {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .open(&writer_path)
+                        .unwrap()
+                };
+
+                open().write_all(&to_bytes(&[1, 2])).unwrap();
+
+                let mut file = open();
+                file.write_all(&to_bytes(&[3, 4])).unwrap();
+
+                rx.recv().unwrap();
+            });
+
+            let mut source = SampleSource::open(&path).unwrap();
+
+            assert_eq!(read_samples(&mut source, 2), vec![1, 2]);
+            assert_eq!(read_samples(&mut source, 2), vec![3, 4]);
+
+            tx.send(()).unwrap();
+            writer.join().unwrap();
+
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
 }
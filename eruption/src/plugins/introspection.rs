@@ -15,25 +15,139 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use crossbeam::channel::Sender;
+use lazy_static::lazy_static;
 use mlua::prelude::*;
+use parking_lot::Mutex;
 use std::any::Any;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::plugins::{self, Plugin};
+use crate::scripting::manifest::Manifest;
+use crate::scripting::script;
 
-// pub type Result<T> = std::result::Result<T, IntrospectionPluginError>;
+/// Names of the event handlers that a Lua script may define
+pub const EVENT_HANDLERS: &[&str] = &[
+    "on_startup",
+    "on_quit",
+    "on_tick",
+    "on_beat",
+    "on_key_down",
+    "on_key_up",
+    "on_gesture",
+    "on_hid_event",
+    "on_mouse_button_down",
+    "on_mouse_button_up",
+    "on_mouse_hid_event",
+    "on_mouse_move",
+    "on_mouse_wheel",
+    "on_process_exit",
+];
 
-// #[derive(Debug, Fail)]
-// pub enum IntrospectionPluginError {
-//     #[error("Unknown error: {}", description)]
-//     UnknownError { description: String },
-// }
+/// The runtime status of a Lua VM (or a native effect) of the active profile
+#[derive(Debug, Clone)]
+pub struct VmStatus {
+    pub index: usize,
+    pub script_file: PathBuf,
 
+    /// The manifest of the script, native effects don't have one
+    pub manifest: Option<Manifest>,
+
+    /// The event handlers that are defined by the script
+    pub handlers: Vec<String>,
+
+    /// Memory used by the Lua VM in bytes
+    pub memory_used: usize,
+
+    /// Number of messages that have not yet been processed by the VM
+    pub queue_depth: usize,
+
+    /// Set if sending a message to the VM failed, the VM won't receive any further messages
+    pub failed: bool,
+}
+
+struct VmEntry {
+    status: VmStatus,
+    tx: Sender<script::Message>,
+}
+
+lazy_static! {
+    /// The VMs of the active profile
+    static ref VMS: Arc<Mutex<Vec<VmEntry>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Register a newly spawned VM, `tx` is used to query the depth of its message queue
+pub fn register_vm(
+    index: usize,
+    script_file: PathBuf,
+    manifest: Option<Manifest>,
+    tx: Sender<script::Message>,
+) {
+    VMS.lock().push(VmEntry {
+        status: VmStatus {
+            index,
+            script_file,
+            manifest,
+            handlers: Vec::new(),
+            memory_used: 0,
+            queue_depth: 0,
+            failed: false,
+        },
+        tx,
+    });
+}
+
+/// Remove the VM `index`, e.g. when its thread could not be started
+pub fn unregister_vm(index: usize) {
+    VMS.lock().retain(|e| e.status.index != index);
+}
+
+/// Remove all registered VMs, e.g. when switching profiles
+pub fn clear_vms() {
+    VMS.lock().clear();
+}
+
+/// Record the event handlers that are defined by the script of the VM `index`
+pub fn set_vm_handlers(index: usize, handlers: Vec<String>) {
+    if let Some(entry) = VMS.lock().iter_mut().find(|e| e.status.index == index) {
+        entry.status.handlers = handlers;
+    }
+}
+
+/// Record the amount of memory that is used by the VM `index`
+pub fn set_vm_memory_used(index: usize, bytes: usize) {
+    if let Some(entry) = VMS.lock().iter_mut().find(|e| e.status.index == index) {
+        entry.status.memory_used = bytes;
+    }
+}
+
+/// Mark the VMs in `failed_txs` as failed
+pub fn set_failed_vms(failed_txs: &HashSet<usize>) {
+    for entry in VMS.lock().iter_mut() {
+        entry.status.failed = failed_txs.contains(&entry.status.index);
+    }
+}
+
+/// A plugin that provides the runtime status of the Eruption daemon
 pub struct IntrospectionPlugin {}
 
 impl IntrospectionPlugin {
     pub fn new() -> Self {
         IntrospectionPlugin {}
     }
+
+    /// Returns the status of all VMs of the active profile
+    pub fn get_vm_status() -> Vec<VmStatus> {
+        VMS.lock()
+            .iter()
+            .map(|entry| VmStatus {
+                queue_depth: entry.tx.len(),
+                ..entry.status.clone()
+            })
+            .collect()
+    }
 }
 
 #[async_trait::async_trait]
@@ -50,8 +164,33 @@ impl Plugin for IntrospectionPlugin {
         Ok(())
     }
 
-    fn register_lua_funcs(&self, _lua_ctx: &Lua) -> mlua::Result<()> {
-        // let globals = lua_ctx.globals();
+    fn register_lua_funcs(&self, lua_ctx: &Lua) -> mlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_vm_status = lua_ctx.create_function(|lua_ctx, ()| {
+            let result = lua_ctx.create_table()?;
+
+            for (i, status) in Self::get_vm_status().into_iter().enumerate() {
+                let vm = lua_ctx.create_table()?;
+
+                vm.set("index", status.index)?;
+                vm.set("script", status.script_file.to_string_lossy().to_string())?;
+                if let Some(manifest) = status.manifest {
+                    vm.set("name", manifest.name)?;
+                    vm.set("version", manifest.version)?;
+                }
+
+                vm.set("handlers", status.handlers)?;
+                vm.set("memory_used", status.memory_used)?;
+                vm.set("queue_depth", status.queue_depth)?;
+                vm.set("failed", status.failed)?;
+
+                result.set(i + 1, vm)?;
+            }
+
+            Ok(result)
+        })?;
+        globals.set("get_vm_status", get_vm_status)?;
 
         Ok(())
     }
@@ -68,3 +207,75 @@ impl Plugin for IntrospectionPlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::unbounded;
+
+    #[test]
+    fn test_vm_status() {
+        clear_vms();
+
+        let (tx, _rx) = unbounded();
+        register_vm(0, PathBuf::from("test/a.lua"), None, tx.clone());
+
+        let (tx_b, _rx_b) = unbounded();
+        register_vm(1, PathBuf::from("test/b.lua"), None, tx_b);
+
+        set_vm_handlers(0, vec!["on_tick".to_string(), "on_key_down".to_string()]);
+        set_vm_memory_used(0, 4096);
+
+        // unknown VMs are ignored
+        set_vm_handlers(7, vec!["on_quit".to_string()]);
+        set_vm_memory_used(7, 1);
+
+        tx.send(script::Message::Tick(1)).unwrap();
+        tx.send(script::Message::Tick(2)).unwrap();
+
+        let failed: HashSet<usize> = [1].iter().cloned().collect();
+        set_failed_vms(&failed);
+
+        let status = IntrospectionPlugin::get_vm_status();
+
+        assert_eq!(status.len(), 2);
+
+        assert_eq!(status[0].index, 0);
+        assert_eq!(status[0].script_file, PathBuf::from("test/a.lua"));
+        assert_eq!(status[0].handlers, vec!["on_tick", "on_key_down"]);
+        assert_eq!(status[0].memory_used, 4096);
+        assert_eq!(status[0].queue_depth, 2);
+        assert!(!status[0].failed);
+
+        assert_eq!(status[1].index, 1);
+        assert!(status[1].handlers.is_empty());
+        assert_eq!(status[1].queue_depth, 0);
+        assert!(status[1].failed);
+
+        let lua = Lua::new();
+        IntrospectionPlugin::new().register_lua_funcs(&lua).unwrap();
+
+        let (count, script, queue_depth, handler): (usize, String, usize, String) = lua
+            .load(
+                "local status = get_vm_status()
+                 return #status, status[1].script, status[1].queue_depth, status[1].handlers[2]",
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(script, "test/a.lua");
+        assert_eq!(queue_depth, 2);
+        assert_eq!(handler, "on_key_down");
+
+        unregister_vm(0);
+
+        let status = IntrospectionPlugin::get_vm_status();
+
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].index, 1);
+
+        clear_vms();
+        assert!(IntrospectionPlugin::get_vm_status().is_empty());
+    }
+}
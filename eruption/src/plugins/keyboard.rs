@@ -15,13 +15,60 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-// use log::*;
+use lazy_static::lazy_static;
+use log::*;
 use mlua::prelude::*;
+use parking_lot::Mutex;
 use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::events;
 use crate::plugins::{self, Plugin};
 
-// pub type Result<T> = std::result::Result<T, eyre::Error>;
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Name of the Lua registry value that holds the index of the Lua VM, gestures
+/// are only delivered to the VM of the script that registered them
+pub const VM_INDEX_REGISTRY_KEY: &str = "eruption.vm_index";
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyboardPluginError {
+    #[error("Invalid gesture: {description}")]
+    InvalidGesture { description: String },
+}
+
+lazy_static! {
+    /// Recognizes the key gestures that have been registered by the Lua scripts
+    static ref GESTURE_RECOGNIZER: Arc<Mutex<gestures::Recognizer>> = Arc::new(Mutex::new(gestures::Recognizer::default()));
+
+    /// The recognized gestures that have not yet been dispatched to the Lua VMs,
+    /// as pairs of the index of the VM that registered the gesture and its name
+    static ref PENDING_GESTURES: Arc<Mutex<Vec<(usize, String)>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Returns all gestures that have been recognized since the last call, including gestures
+/// that depend on timing only, like holds. Gestures are returned along with the index
+/// of the Lua VM that registered them
+pub fn take_pending_gestures() -> Vec<(usize, String)> {
+    let mut recognized = GESTURE_RECOGNIZER.lock().poll(Instant::now());
+
+    let mut pending = PENDING_GESTURES.lock();
+    pending.append(&mut recognized);
+
+    pending.drain(..).collect()
+}
+
+/// Returns the point in time at which a gesture may be recognized without further key events
+pub fn next_gesture_deadline() -> Option<Instant> {
+    GESTURE_RECOGNIZER.lock().next_deadline()
+}
+
+/// Remove all registered gestures, e.g. when switching profiles
+pub fn clear_gestures() {
+    GESTURE_RECOGNIZER.lock().clear();
+    PENDING_GESTURES.lock().clear();
+}
 
 /// A plugin that listens for key events
 /// Registered events can be subsequently processed by Lua scripts
@@ -35,6 +82,726 @@ impl KeyboardPlugin {
     pub(crate) fn get_key_state(key_index: usize) -> bool {
         crate::KEY_STATES.lock()[key_index]
     }
+
+    /// Register the gesture `name` of the Lua VM `vm_index`, replacing a previously
+    /// registered gesture of the same name. Each VM has its own namespace of gestures
+    pub(crate) fn register_gesture(
+        vm_index: usize,
+        name: &str,
+        kind: gestures::GestureKind,
+    ) -> Result<()> {
+        kind.validate()?;
+
+        GESTURE_RECOGNIZER.lock().register(vm_index, name, kind);
+
+        Ok(())
+    }
+
+    /// Remove the gesture `name` of the Lua VM `vm_index`, returns true if it existed
+    pub(crate) fn unregister_gesture(vm_index: usize, name: &str) -> bool {
+        GESTURE_RECOGNIZER.lock().unregister(vm_index, name)
+    }
+
+    /// Returns the index of the Lua VM `lua_ctx`
+    fn vm_index(lua_ctx: &Lua) -> mlua::Result<usize> {
+        lua_ctx.named_registry_value::<_, usize>(VM_INDEX_REGISTRY_KEY)
+    }
+}
+
+mod gestures {
+    use super::*;
+
+    /// Default max. duration of a tap
+    pub const DEFAULT_TAP_MILLIS: u64 = 200;
+
+    /// Default min. duration of a hold
+    pub const DEFAULT_HOLD_MILLIS: u64 = 500;
+
+    /// Default max. time between the two taps of a double-tap
+    pub const DEFAULT_DOUBLE_TAP_MILLIS: u64 = 300;
+
+    /// Default max. time between two consecutive keys of a sequence
+    pub const DEFAULT_SEQUENCE_TIMEOUT_MILLIS: u64 = 500;
+
+    /// Max. number of keys of a chord or sequence
+    const MAX_GESTURE_KEYS: usize = 16;
+
+    #[derive(Debug, Clone)]
+    pub enum GestureKind {
+        /// All keys are held down at the same time, optionally the keys have to be
+        /// pressed within `window` of each other
+        Chord {
+            keys: Vec<u8>,
+            window: Option<Duration>,
+        },
+
+        /// The keys are pressed in order, each within `timeout` of the previous one
+        Sequence { keys: Vec<u8>, timeout: Duration },
+
+        /// The key is pressed and released within `max`
+        Tap { key: u8, max: Duration },
+
+        /// The key is held down for at least `min`
+        Hold { key: u8, min: Duration },
+
+        /// The key is tapped twice, the second tap ends within `window` of the first one.
+        /// Each tap has to end within `max`
+        DoubleTap {
+            key: u8,
+            window: Duration,
+            max: Duration,
+        },
+    }
+
+    impl GestureKind {
+        pub fn validate(&self) -> Result<()> {
+            match self {
+                GestureKind::Chord { keys, .. } | GestureKind::Sequence { keys, .. } => {
+                    if keys.is_empty() || keys.len() > MAX_GESTURE_KEYS {
+                        return Err(KeyboardPluginError::InvalidGesture {
+                            description: format!(
+                                "Gestures require between 1 and {} keys",
+                                MAX_GESTURE_KEYS
+                            ),
+                        }
+                        .into());
+                    }
+
+                    if keys.iter().any(|k| *k == 0) {
+                        return Err(KeyboardPluginError::InvalidGesture {
+                            description: "Invalid key index".to_owned(),
+                        }
+                        .into());
+                    }
+                }
+
+                GestureKind::Tap { key, .. }
+                | GestureKind::Hold { key, .. }
+                | GestureKind::DoubleTap { key, .. } => {
+                    if *key == 0 {
+                        return Err(KeyboardPluginError::InvalidGesture {
+                            description: "Invalid key index".to_owned(),
+                        }
+                        .into());
+                    }
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Recognition state of a single gesture
+    #[derive(Debug, Default, Clone)]
+    struct GestureState {
+        /// A chord or hold has been recognized, and will not be recognized
+        /// again, before one of its keys has been released
+        fired: bool,
+
+        /// Number of keys of a sequence that have been pressed in order
+        progress: usize,
+
+        /// Point in time of the last key press, that advanced a sequence
+        last_progress: Option<Instant>,
+
+        /// Point in time at which the key of a tap, hold or double-tap has been pressed
+        pressed_at: Option<Instant>,
+
+        /// Point in time at which the first tap of a double-tap has ended
+        first_tap_at: Option<Instant>,
+
+        /// A tap that will be recognized at this point in time, unless it becomes a double-tap
+        deferred_until: Option<Instant>,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Gesture {
+        /// Index of the Lua VM that registered the gesture
+        vm_index: usize,
+        name: String,
+        kind: GestureKind,
+        state: GestureState,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Recognizer {
+        gestures: Vec<Gesture>,
+
+        /// Keys that are currently held down, and the point in time at which they have been pressed
+        keys_down: Vec<(u8, Instant)>,
+    }
+
+    /// Returns the number of keys of the sequence `keys` that have been pressed in order, after
+    /// `key` has been pressed following `progress` matching keys. On a mismatch, the longest
+    /// suffix of the pressed keys that is a prefix of `keys` is kept, so 1, 1, 2 matches 1, 2
+    fn advance_sequence(keys: &[u8], progress: usize, key: u8) -> usize {
+        if keys[progress] == key {
+            return progress + 1;
+        }
+
+        (1..=progress)
+            .rev()
+            .find(|len| {
+                keys[len - 1] == key && keys[..len - 1] == keys[progress + 1 - len..progress]
+            })
+            .unwrap_or(0)
+    }
+
+    impl Recognizer {
+        pub fn register(&mut self, vm_index: usize, name: &str, kind: GestureKind) {
+            self.unregister(vm_index, name);
+
+            self.gestures.push(Gesture {
+                vm_index,
+                name: name.to_owned(),
+                kind,
+                state: GestureState::default(),
+            });
+        }
+
+        pub fn unregister(&mut self, vm_index: usize, name: &str) -> bool {
+            let len = self.gestures.len();
+            self.gestures
+                .retain(|g| g.vm_index != vm_index || g.name != name);
+
+            self.gestures.len() != len
+        }
+
+        pub fn clear(&mut self) {
+            self.gestures.clear();
+        }
+
+        /// Returns the max. time between the taps of a double-tap that has been
+        /// registered for `key` by the Lua VM `vm_index`, if any
+        fn double_tap_window(&self, vm_index: usize, key: u8) -> Option<Duration> {
+            self.gestures.iter().find_map(|g| match g.kind {
+                GestureKind::DoubleTap { key: k, window, .. }
+                    if g.vm_index == vm_index && k == key =>
+                {
+                    Some(window)
+                }
+
+                _ => None,
+            })
+        }
+
+        pub fn key_down(&mut self, key: u8, now: Instant) -> Vec<(usize, String)> {
+            // ignore repeated key down events
+            if self.keys_down.iter().any(|(k, _)| *k == key) {
+                return vec![];
+            }
+
+            self.keys_down.push((key, now));
+
+            let keys_down = &self.keys_down;
+            let mut result = Vec::new();
+
+            for gesture in self.gestures.iter_mut() {
+                let state = &mut gesture.state;
+
+                match &gesture.kind {
+                    GestureKind::Chord { keys, window } => {
+                        if state.fired || !keys.contains(&key) {
+                            continue;
+                        }
+
+                        let pressed: Vec<Instant> = keys
+                            .iter()
+                            .filter_map(|k| keys_down.iter().find(|(d, _)| d == k).map(|(_, t)| *t))
+                            .collect();
+
+                        if pressed.len() == keys.len() {
+                            let first = pressed.iter().min().copied().unwrap_or(now);
+
+                            if window.map_or(true, |w| now.duration_since(first) <= w) {
+                                state.fired = true;
+                                result.push((gesture.vm_index, gesture.name.clone()));
+                            }
+                        }
+                    }
+
+                    GestureKind::Sequence { keys, timeout } => {
+                        let timed_out = state
+                            .last_progress
+                            .map_or(true, |t| now.duration_since(t) > *timeout);
+
+                        if timed_out {
+                            state.progress = 0;
+                        }
+
+                        state.progress = advance_sequence(keys, state.progress, key);
+
+                        state.last_progress = if state.progress > 0 { Some(now) } else { None };
+
+                        if state.progress == keys.len() {
+                            state.progress = 0;
+                            state.last_progress = None;
+
+                            result.push((gesture.vm_index, gesture.name.clone()));
+                        }
+                    }
+
+                    GestureKind::Tap { key: k, .. } | GestureKind::Hold { key: k, .. } => {
+                        if *k == key {
+                            state.pressed_at = Some(now);
+                            state.fired = false;
+                        }
+                    }
+
+                    GestureKind::DoubleTap { key: k, .. } => {
+                        if *k == key {
+                            state.pressed_at = Some(now);
+                        } else {
+                            state.first_tap_at = None;
+                        }
+                    }
+                }
+            }
+
+            result
+        }
+
+        pub fn key_up(&mut self, key: u8, now: Instant) -> Vec<(usize, String)> {
+            self.keys_down.retain(|(k, _)| *k != key);
+
+            let double_tap_windows: Vec<Option<Duration>> = self
+                .gestures
+                .iter()
+                .map(|g| self.double_tap_window(g.vm_index, key))
+                .collect();
+
+            let mut result = Vec::new();
+
+            for (gesture, double_tap_window) in self.gestures.iter_mut().zip(double_tap_windows) {
+                let state = &mut gesture.state;
+
+                match &gesture.kind {
+                    GestureKind::Chord { keys, .. } => {
+                        if keys.contains(&key) {
+                            state.fired = false;
+                        }
+                    }
+
+                    GestureKind::Sequence { .. } => {}
+
+                    GestureKind::Tap { key: k, max } => {
+                        if *k != key {
+                            continue;
+                        }
+
+                        if let Some(pressed_at) = state.pressed_at.take() {
+                            if now.duration_since(pressed_at) <= *max {
+                                match double_tap_window {
+                                    // wait until it is clear that this is not the first tap of a double-tap
+                                    Some(window) => {
+                                        if state.deferred_until.take().is_none() {
+                                            state.deferred_until = Some(now + window);
+                                        }
+                                    }
+
+                                    None => result.push((gesture.vm_index, gesture.name.clone())),
+                                }
+                            }
+                        }
+                    }
+
+                    GestureKind::Hold { key: k, .. } => {
+                        if *k == key {
+                            state.pressed_at = None;
+                            state.fired = false;
+                        }
+                    }
+
+                    GestureKind::DoubleTap {
+                        key: k,
+                        window,
+                        max,
+                    } => {
+                        if *k != key {
+                            continue;
+                        }
+
+                        let is_tap = state
+                            .pressed_at
+                            .take()
+                            .map_or(false, |t| now.duration_since(t) <= *max);
+
+                        if !is_tap {
+                            state.first_tap_at = None;
+                        } else if state
+                            .first_tap_at
+                            .map_or(false, |t| now.duration_since(t) <= *window)
+                        {
+                            state.first_tap_at = None;
+                            result.push((gesture.vm_index, gesture.name.clone()));
+                        } else {
+                            state.first_tap_at = Some(now);
+                        }
+                    }
+                }
+            }
+
+            result
+        }
+
+        /// Recognize gestures that depend on timing only
+        pub fn poll(&mut self, now: Instant) -> Vec<(usize, String)> {
+            let mut result = Vec::new();
+
+            for gesture in self.gestures.iter_mut() {
+                let state = &mut gesture.state;
+
+                match &gesture.kind {
+                    GestureKind::Hold { min, .. } => {
+                        if let Some(pressed_at) = state.pressed_at {
+                            if !state.fired && now.duration_since(pressed_at) >= *min {
+                                state.fired = true;
+                                result.push((gesture.vm_index, gesture.name.clone()));
+                            }
+                        }
+                    }
+
+                    GestureKind::Tap { .. } => {
+                        if let Some(deferred_until) = state.deferred_until {
+                            if now >= deferred_until {
+                                state.deferred_until = None;
+                                result.push((gesture.vm_index, gesture.name.clone()));
+                            }
+                        }
+                    }
+
+                    _ => {}
+                }
+            }
+
+            result
+        }
+
+        pub fn next_deadline(&self) -> Option<Instant> {
+            self.gestures
+                .iter()
+                .filter_map(|g| match g.kind {
+                    GestureKind::Hold { min, .. } if !g.state.fired => {
+                        g.state.pressed_at.map(|t| t + min)
+                    }
+
+                    GestureKind::Tap { .. } => g.state.deferred_until,
+
+                    _ => None,
+                })
+                .min()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn ms(millis: u64) -> Duration {
+            Duration::from_millis(millis)
+        }
+
+        /// Returns the names of the recognized gestures `gestures`
+        fn names(gestures: Vec<(usize, String)>) -> Vec<String> {
+            gestures.into_iter().map(|(_, name)| name).collect()
+        }
+
+        /// Press and release `key`, starting at `at` and holding it down for `duration`
+        fn tap(r: &mut Recognizer, key: u8, at: Instant, duration: Duration) -> Vec<String> {
+            let mut result = r.key_down(key, at);
+            result.append(&mut r.key_up(key, at + duration));
+
+            names(result)
+        }
+
+        #[test]
+        fn test_tap() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "tap",
+                GestureKind::Tap {
+                    key: 1,
+                    max: ms(200),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            assert_eq!(tap(&mut r, 1, t0, ms(100)), vec!["tap"]);
+            assert_eq!(tap(&mut r, 1, t0 + ms(1000), ms(200)), vec!["tap"]);
+
+            // too long for a tap
+            assert!(tap(&mut r, 1, t0 + ms(2000), ms(201)).is_empty());
+
+            // another key
+            assert!(tap(&mut r, 2, t0 + ms(3000), ms(50)).is_empty());
+        }
+
+        #[test]
+        fn test_hold() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "hold",
+                GestureKind::Hold {
+                    key: 1,
+                    min: ms(500),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            assert!(r.key_down(1, t0).is_empty());
+            assert_eq!(r.next_deadline(), Some(t0 + ms(500)));
+
+            assert!(r.poll(t0 + ms(499)).is_empty());
+            assert_eq!(names(r.poll(t0 + ms(500))), vec!["hold"]);
+
+            // recognized only once per key press
+            assert!(r.poll(t0 + ms(1000)).is_empty());
+            assert_eq!(r.next_deadline(), None);
+
+            assert!(r.key_up(1, t0 + ms(1100)).is_empty());
+
+            // released too early
+            assert!(r.key_down(1, t0 + ms(2000)).is_empty());
+            assert!(r.key_up(1, t0 + ms(2400)).is_empty());
+            assert!(r.poll(t0 + ms(3000)).is_empty());
+        }
+
+        #[test]
+        fn test_double_tap() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "tap",
+                GestureKind::Tap {
+                    key: 1,
+                    max: ms(200),
+                },
+            );
+            r.register(
+                0,
+                "double-tap",
+                GestureKind::DoubleTap {
+                    key: 1,
+                    window: ms(300),
+                    max: ms(200),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            // the tap is deferred, until it is clear that it is not part of a double-tap
+            assert!(tap(&mut r, 1, t0, ms(50)).is_empty());
+            assert_eq!(r.next_deadline(), Some(t0 + ms(350)));
+
+            assert_eq!(tap(&mut r, 1, t0 + ms(100), ms(50)), vec!["double-tap"]);
+            assert!(r.poll(t0 + ms(1000)).is_empty());
+
+            // a single tap is recognized after the double-tap window has passed
+            let t1 = t0 + ms(2000);
+
+            assert!(tap(&mut r, 1, t1, ms(50)).is_empty());
+            assert!(r.poll(t1 + ms(349)).is_empty());
+            assert_eq!(names(r.poll(t1 + ms(350))), vec!["tap"]);
+
+            // the second tap comes too late
+            let t2 = t0 + ms(4000);
+
+            assert!(tap(&mut r, 1, t2, ms(50)).is_empty());
+            assert!(tap(&mut r, 1, t2 + ms(400), ms(50)).is_empty());
+
+            // another key in between the taps
+            let t3 = t0 + ms(6000);
+
+            r.register(
+                0,
+                "double-tap",
+                GestureKind::DoubleTap {
+                    key: 1,
+                    window: ms(300),
+                    max: ms(200),
+                },
+            );
+
+            assert!(tap(&mut r, 1, t3, ms(50)).is_empty());
+            assert!(tap(&mut r, 2, t3 + ms(100), ms(20)).is_empty());
+            assert!(!tap(&mut r, 1, t3 + ms(150), ms(50)).contains(&"double-tap".to_string()));
+        }
+
+        #[test]
+        fn test_sequence() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "sequence",
+                GestureKind::Sequence {
+                    keys: vec![1, 2, 3],
+                    timeout: ms(500),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            assert!(tap(&mut r, 1, t0, ms(50)).is_empty());
+            assert!(tap(&mut r, 2, t0 + ms(400), ms(50)).is_empty());
+            assert_eq!(tap(&mut r, 3, t0 + ms(900), ms(50)), vec!["sequence"]);
+
+            // timed out
+            let t1 = t0 + ms(2000);
+
+            assert!(tap(&mut r, 1, t1, ms(50)).is_empty());
+            assert!(tap(&mut r, 2, t1 + ms(501), ms(50)).is_empty());
+            assert!(tap(&mut r, 3, t1 + ms(600), ms(50)).is_empty());
+
+            // a wrong key resets the sequence
+            let t2 = t0 + ms(4000);
+
+            assert!(tap(&mut r, 1, t2, ms(50)).is_empty());
+            assert!(tap(&mut r, 4, t2 + ms(100), ms(50)).is_empty());
+            assert!(tap(&mut r, 2, t2 + ms(200), ms(50)).is_empty());
+            assert!(tap(&mut r, 3, t2 + ms(300), ms(50)).is_empty());
+
+            // a repeated first key restarts the sequence
+            let t3 = t0 + ms(6000);
+
+            assert!(tap(&mut r, 1, t3, ms(50)).is_empty());
+            assert!(tap(&mut r, 1, t3 + ms(100), ms(50)).is_empty());
+            assert!(tap(&mut r, 2, t3 + ms(200), ms(50)).is_empty());
+            assert_eq!(tap(&mut r, 3, t3 + ms(300), ms(50)), vec!["sequence"]);
+
+            // a sequence that overlaps with itself
+            r.register(
+                0,
+                "sequence",
+                GestureKind::Sequence {
+                    keys: vec![1, 2, 1, 3],
+                    timeout: ms(500),
+                },
+            );
+
+            let t4 = t0 + ms(8000);
+
+            for (i, key) in [1, 2, 1, 2, 1].iter().enumerate() {
+                assert!(tap(&mut r, *key, t4 + ms(100) * i as u32, ms(50)).is_empty());
+            }
+
+            assert_eq!(tap(&mut r, 3, t4 + ms(500), ms(50)), vec!["sequence"]);
+        }
+
+        #[test]
+        fn test_advance_sequence() {
+            assert_eq!(advance_sequence(&[1, 2], 0, 1), 1);
+            assert_eq!(advance_sequence(&[1, 2], 0, 2), 0);
+            assert_eq!(advance_sequence(&[1, 2], 1, 2), 2);
+
+            // 1, 1 keeps the last 1, so that 1, 1, 2 matches
+            assert_eq!(advance_sequence(&[1, 2], 1, 1), 1);
+            assert_eq!(advance_sequence(&[1, 2], 1, 3), 0);
+
+            // 1, 1, 1 keeps 1, 1
+            assert_eq!(advance_sequence(&[1, 1, 2], 2, 1), 2);
+
+            // 1, 2, 1, 2 keeps 1, 2
+            assert_eq!(advance_sequence(&[1, 2, 1, 3], 3, 2), 2);
+            assert_eq!(advance_sequence(&[1, 2, 1, 3], 3, 1), 1);
+            assert_eq!(advance_sequence(&[1, 2, 1, 3], 3, 4), 0);
+        }
+
+        #[test]
+        fn test_sequence_repeated_key() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "sequence",
+                GestureKind::Sequence {
+                    keys: vec![1, 2],
+                    timeout: ms(500),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            assert!(tap(&mut r, 1, t0, ms(50)).is_empty());
+            assert!(tap(&mut r, 1, t0 + ms(100), ms(50)).is_empty());
+            assert_eq!(tap(&mut r, 2, t0 + ms(200), ms(50)), vec!["sequence"]);
+        }
+
+        #[test]
+        fn test_double_tap_max() {
+            let mut r = Recognizer::default();
+            r.register(
+                0,
+                "double-tap",
+                GestureKind::DoubleTap {
+                    key: 1,
+                    window: ms(1000),
+                    max: ms(400),
+                },
+            );
+
+            let t0 = Instant::now();
+
+            // longer than the default, but within the tap duration of the gesture
+            assert!(tap(&mut r, 1, t0, ms(300)).is_empty());
+            assert_eq!(tap(&mut r, 1, t0 + ms(500), ms(300)), vec!["double-tap"]);
+
+            // too long for a tap
+            let t1 = t0 + ms(2000);
+
+            assert!(tap(&mut r, 1, t1, ms(300)).is_empty());
+            assert!(tap(&mut r, 1, t1 + ms(500), ms(401)).is_empty());
+        }
+
+        #[test]
+        fn test_vm_namespaces() {
+            let mut r = Recognizer::default();
+
+            for vm_index in 0..2 {
+                r.register(
+                    vm_index,
+                    "gesture",
+                    GestureKind::Tap {
+                        key: vm_index as u8 + 1,
+                        max: ms(200),
+                    },
+                );
+            }
+
+            let t0 = Instant::now();
+
+            // gestures of the same name do not replace each other across VMs
+            let mut result = r.key_down(1, t0);
+            result.append(&mut r.key_up(1, t0 + ms(50)));
+            assert_eq!(result, vec![(0, "gesture".to_string())]);
+
+            let mut result = r.key_down(2, t0 + ms(100));
+            result.append(&mut r.key_up(2, t0 + ms(150)));
+            assert_eq!(result, vec![(1, "gesture".to_string())]);
+
+            // a double-tap only defers the taps of its own VM
+            r.register(
+                1,
+                "double-tap",
+                GestureKind::DoubleTap {
+                    key: 1,
+                    window: ms(300),
+                    max: ms(200),
+                },
+            );
+
+            assert_eq!(tap(&mut r, 1, t0 + ms(1000), ms(50)), vec!["gesture"]);
+
+            // unregistering only affects the gestures of the calling VM
+            assert!(r.unregister(1, "gesture"));
+            assert!(!r.unregister(1, "gesture"));
+
+            assert_eq!(tap(&mut r, 1, t0 + ms(2000), ms(50)), vec!["gesture"]);
+            assert!(tap(&mut r, 2, t0 + ms(3000), ms(50)).is_empty());
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -48,6 +815,28 @@ impl Plugin for KeyboardPlugin {
     }
 
     fn initialize(&mut self) -> plugins::Result<()> {
+        events::register_observer(|event: &events::Event| {
+            let recognized = match event {
+                events::Event::KeyDown(index) => {
+                    GESTURE_RECOGNIZER.lock().key_down(*index, Instant::now())
+                }
+
+                events::Event::KeyUp(index) => {
+                    GESTURE_RECOGNIZER.lock().key_up(*index, Instant::now())
+                }
+
+                _ => return Ok(true),
+            };
+
+            for (vm_index, name) in recognized.iter() {
+                debug!("Recognized gesture: {} (Lua VM: {})", name, vm_index);
+            }
+
+            PENDING_GESTURES.lock().extend(recognized);
+
+            Ok(true) // event has been processed
+        });
+
         Ok(())
     }
 
@@ -58,6 +847,85 @@ impl Plugin for KeyboardPlugin {
             .create_function(|_, key_index: usize| Ok(KeyboardPlugin::get_key_state(key_index)))?;
         globals.set("get_key_state", get_key_state)?;
 
+        let register_chord = lua_ctx.create_function(
+            |lua_ctx, (name, keys, window_millis): (String, Vec<u8>, Option<u64>)| {
+                let kind = gestures::GestureKind::Chord {
+                    keys,
+                    window: window_millis.map(Duration::from_millis),
+                };
+
+                KeyboardPlugin::register_gesture(KeyboardPlugin::vm_index(lua_ctx)?, &name, kind)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+            },
+        )?;
+        globals.set("register_chord", register_chord)?;
+
+        let register_sequence = lua_ctx.create_function(
+            |lua_ctx, (name, keys, timeout_millis): (String, Vec<u8>, Option<u64>)| {
+                let kind = gestures::GestureKind::Sequence {
+                    keys,
+                    timeout: Duration::from_millis(
+                        timeout_millis.unwrap_or(gestures::DEFAULT_SEQUENCE_TIMEOUT_MILLIS),
+                    ),
+                };
+
+                KeyboardPlugin::register_gesture(KeyboardPlugin::vm_index(lua_ctx)?, &name, kind)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+            },
+        )?;
+        globals.set("register_sequence", register_sequence)?;
+
+        let register_tap = lua_ctx.create_function(
+            |lua_ctx, (name, key, max_millis): (String, u8, Option<u64>)| {
+                let kind = gestures::GestureKind::Tap {
+                    key,
+                    max: Duration::from_millis(max_millis.unwrap_or(gestures::DEFAULT_TAP_MILLIS)),
+                };
+
+                KeyboardPlugin::register_gesture(KeyboardPlugin::vm_index(lua_ctx)?, &name, kind)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+            },
+        )?;
+        globals.set("register_tap", register_tap)?;
+
+        let register_hold = lua_ctx.create_function(
+            |lua_ctx, (name, key, min_millis): (String, u8, Option<u64>)| {
+                let kind = gestures::GestureKind::Hold {
+                    key,
+                    min: Duration::from_millis(min_millis.unwrap_or(gestures::DEFAULT_HOLD_MILLIS)),
+                };
+
+                KeyboardPlugin::register_gesture(KeyboardPlugin::vm_index(lua_ctx)?, &name, kind)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+            },
+        )?;
+        globals.set("register_hold", register_hold)?;
+
+        let register_double_tap = lua_ctx.create_function(
+            |lua_ctx,
+             (name, key, window_millis, max_millis): (String, u8, Option<u64>, Option<u64>)| {
+                let kind = gestures::GestureKind::DoubleTap {
+                    key,
+                    window: Duration::from_millis(
+                        window_millis.unwrap_or(gestures::DEFAULT_DOUBLE_TAP_MILLIS),
+                    ),
+                    max: Duration::from_millis(max_millis.unwrap_or(gestures::DEFAULT_TAP_MILLIS)),
+                };
+
+                KeyboardPlugin::register_gesture(KeyboardPlugin::vm_index(lua_ctx)?, &name, kind)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+            },
+        )?;
+        globals.set("register_double_tap", register_double_tap)?;
+
+        let unregister_gesture = lua_ctx.create_function(|lua_ctx, name: String| {
+            Ok(KeyboardPlugin::unregister_gesture(
+                KeyboardPlugin::vm_index(lua_ctx)?,
+                &name,
+            ))
+        })?;
+        globals.set("unregister_gesture", unregister_gesture)?;
+
         Ok(())
     }
 
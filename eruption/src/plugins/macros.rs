@@ -15,19 +15,30 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use crossbeam::channel::{unbounded, Sender};
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
 use evdev_rs::enums::*;
 use evdev_rs::{Device, InputEvent, TimeVal, UInputDevice};
 use lazy_static::lazy_static;
 use log::*;
 use mlua::prelude::*;
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::sync::atomic::AtomicBool;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{any::Any, thread};
 
+use crate::constants;
+use crate::events;
+use crate::layout::{self, KeyStroke};
 use crate::plugins::{self, Plugin};
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
@@ -35,13 +46,58 @@ pub type Result<T> = std::result::Result<T, eyre::Error>;
 pub enum Message {
     // keyboard related
     MirrorKey(evdev_rs::InputEvent),
-    InjectKey { key: u32, down: bool },
+    InjectKey {
+        key: u32,
+        down: bool,
+    },
 
     // mouse related
     MirrorMouseEvent(evdev_rs::InputEvent),
     MirrorMouseEventImmediate(evdev_rs::InputEvent),
-    InjectButtonEvent { button: u32, down: bool },
-    InjectMouseWheelEvent { direction: u32 },
+    InjectButtonEvent {
+        button: u32,
+        down: bool,
+    },
+    InjectMouseWheelEvent {
+        direction: u32,
+    },
+
+    // scheduling related
+    /// Process `message` as soon as `due` has been reached. Messages that are due
+    /// at the same time are processed in the order they have been scheduled
+    Schedule {
+        due: Instant,
+        tag: String,
+        message: Box<Message>,
+    },
+
+    /// Cancel all pending messages of `tag`, or all pending messages if `tag` is `None`
+    CancelScheduled {
+        tag: Option<String>,
+    },
+
+    /// Schedule the next iteration of a macro that is being played, starting at `start`
+    RepeatMacro {
+        recorded_macro: Arc<RecordedMacro>,
+        start: Instant,
+        speed: f64,
+        repeat: u32,
+    },
+}
+
+impl Message {
+    /// Returns true if the message releases a key or a button
+    fn is_release(&self) -> bool {
+        match self {
+            Message::InjectKey { down, .. } | Message::InjectButtonEvent { down, .. } => !down,
+
+            Message::MirrorMouseEventImmediate(event) => {
+                event.value == 0 && matches!(event.event_code, EventCode::EV_KEY(_))
+            }
+
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -51,12 +107,219 @@ pub enum MacrosPluginError {
 
     #[error("Could not map an evdev event code to a key or button")]
     MappingError {},
+
+    #[error("A macro is already being recorded")]
+    RecordingActive {},
+
+    #[error("No macro is being recorded")]
+    NotRecording {},
+
+    #[error("The recording does not contain any events")]
+    EmptyRecording {},
+
+    #[error("Invalid macro name: {name}")]
+    InvalidMacroName { name: String },
+
+    #[error("Invalid playback parameters: {description}")]
+    InvalidPlaybackParameters { description: String },
+
+    #[error("Invalid text expansion trigger: {trigger:?}")]
+    InvalidTrigger { trigger: String },
+}
+
+/// Format version of recorded macro files
+const MACRO_FILE_VERSION: u32 = 1;
+
+/// File extension of recorded macro files
+const MACRO_FILE_EXTENSION: &str = "macro";
+
+/// Max. number of events that a single recording may hold
+const MAX_RECORDED_EVENTS: usize = 20000;
+
+/// Delay between two typed characters, if not specified otherwise
+const DEFAULT_TYPING_DELAY_MILLIS: u64 = 8;
+
+/// Max. number of characters of a text expansion trigger
+const MAX_TRIGGER_LENGTH: usize = 32;
+
+/// Tag of the pending key events of typed text, see `MacrosPlugin::cancel_scheduled()`
+const TYPING_TAG: &str = "type_text";
+
+const KEY_BACKSPACE: u32 = 14;
+const KEY_LEFTCTRL: u32 = 29;
+const KEY_LEFTSHIFT: u32 = 42;
+const KEY_RIGHTSHIFT: u32 = 54;
+const KEY_LEFTALT: u32 = 56;
+const KEY_RIGHTCTRL: u32 = 97;
+const KEY_RIGHTALT: u32 = 100;
+const KEY_LEFTMETA: u32 = 125;
+const KEY_RIGHTMETA: u32 = 126;
+
+/// The input device that a recorded event originated from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedDevice {
+    Keyboard,
+    Mouse,
+}
+
+/// A single recorded evdev event of type EV_KEY or EV_REL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Delay in microseconds, relative to the previous event
+    pub delay: u64,
+    pub device: RecordedDevice,
+    pub event_type: u32,
+    pub code: u32,
+    pub value: i32,
+}
+
+/// A recorded macro, as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMacro {
+    pub version: u32,
+    pub name: String,
+    pub events: Vec<RecordedEvent>,
+}
+
+/// A message that is pending in the queue of the uinput thread
+struct ScheduledMessage {
+    due: Instant,
+
+    /// Keeps the order of messages that are due at the same time
+    seq: u64,
+
+    tag: String,
+    message: Message,
+}
+
+impl PartialEq for ScheduledMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for ScheduledMessage {}
+
+impl PartialOrd for ScheduledMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledMessage {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // reversed, so that the earliest message is at the top of the max-heap
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+/// Orders the pending messages of the uinput thread by their due time
+#[derive(Default)]
+struct Scheduler {
+    queue: BinaryHeap<ScheduledMessage>,
+    seq: u64,
+}
+
+impl Scheduler {
+    fn push(&mut self, due: Instant, tag: String, message: Message) {
+        self.seq += 1;
+
+        self.queue.push(ScheduledMessage {
+            due,
+            seq: self.seq,
+            tag,
+            message,
+        });
+
+        QUEUE_DEPTH.store(self.queue.len(), Ordering::SeqCst);
+    }
+
+    /// Remove the pending messages of `tag`, or all pending messages if `tag` is `None`.
+    /// Returns the removed messages
+    fn cancel(&mut self, tag: Option<&str>) -> Vec<Message> {
+        let (cancelled, retained): (Vec<_>, Vec<_>) = std::mem::take(&mut self.queue)
+            .into_sorted_vec()
+            .into_iter()
+            .rev()
+            .partition(|m| tag.map_or(true, |tag| m.tag == tag));
+
+        self.queue = retained.into_iter().collect();
+
+        QUEUE_DEPTH.store(self.queue.len(), Ordering::SeqCst);
+
+        cancelled.into_iter().map(|m| m.message).collect()
+    }
+
+    /// The due time of the earliest pending message
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.peek().map(|m| m.due)
+    }
+
+    /// Remove the earliest pending message, if it is due at `now`
+    fn pop_due(&mut self, now: Instant) -> Option<Message> {
+        if self.next_due()? <= now {
+            let message = self.queue.pop().map(|m| m.message);
+
+            QUEUE_DEPTH.store(self.queue.len(), Ordering::SeqCst);
+
+            message
+        } else {
+            None
+        }
+    }
+}
+
+/// Text expansions that have been registered by the Lua scripts, and the
+/// state that is required to detect their triggers
+#[derive(Debug, Default)]
+struct TextExpansions {
+    /// Maps triggers to the text that they expand to
+    expansions: HashMap<String, String>,
+
+    /// The most recently typed characters
+    typed: String,
+
+    shift: bool,
+    altgr: bool,
+}
+
+/// State of an active recording
+#[derive(Debug)]
+struct Recording {
+    name: String,
+    last_timestamp: Option<u64>,
+    pressed: HashSet<(RecordedDevice, u32)>,
+    events: Vec<RecordedEvent>,
 }
 
 lazy_static! {
     pub static ref UINPUT_TX: Arc<Mutex<Option<Sender<Message>>>> = Arc::new(Mutex::new(None));
     pub static ref DROP_CURRENT_KEY: AtomicBool = AtomicBool::new(false);
     pub static ref DROP_CURRENT_MOUSE_INPUT: AtomicBool = AtomicBool::new(false);
+
+    /// The macro that is currently being recorded, if any
+    static ref RECORDING: Arc<Mutex<Option<Recording>>> = Arc::new(Mutex::new(None));
+
+    /// Names of the macros that have been played since the last call to `stop_playback()`
+    static ref PLAYED_MACROS: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    static ref TEXT_EXPANSIONS: Arc<Mutex<TextExpansions>> = Arc::new(Mutex::new(TextExpansions::default()));
+
+    /// Number of messages that are pending in the queue of the uinput thread
+    static ref QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+    /// The time at which the text that is currently being typed ends, so that
+    /// concurrently typed texts won't get interleaved
+    static ref TYPING_DEADLINE: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
+}
+
+/// Remove all registered text expansions, e.g. when switching profiles
+pub fn clear_text_expansions() {
+    let mut state = TEXT_EXPANSIONS.lock();
+
+    state.expansions.clear();
+    state.typed.clear();
 }
 
 thread_local! {
@@ -574,84 +837,748 @@ impl MacrosPlugin {
         Ok(())
     }
 
-    fn spawn_uinput_thread() -> Result<()> {
-        let (uinput_tx, uinput_rx) = unbounded();
+    /// Returns the current time, suitable for timestamping injected events
+    pub(crate) fn current_time() -> TimeVal {
+        let mut time: libc::timeval = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
 
-        thread::Builder::new()
-            .name("uinput".into())
-            .spawn(move || {
-                Self::initialize_thread_locals().unwrap();
+        unsafe {
+            libc::gettimeofday(&mut time, std::ptr::null_mut());
+        }
 
-                loop {
-                    let message = uinput_rx.recv().unwrap();
-                    match message {
-                        Message::MirrorKey(raw_event) => {
-                            if !DROP_CURRENT_KEY.load(Ordering::SeqCst) {
-                                Self::inject_key_event(raw_event).unwrap();
-                            } else {
-                                debug!("Keyboard event has been dropped as requested");
-                            }
-                        }
+        evdev_rs::TimeVal::from_raw(&time)
+    }
+
+    /// Returns the directory that holds the recorded macro files
+    fn macro_dir() -> PathBuf {
+        PathBuf::from(
+            crate::CONFIG
+                .lock()
+                .as_ref()
+                .and_then(|c| c.get_str("global.macro_dir").ok())
+                .unwrap_or_else(|| constants::DEFAULT_MACRO_DIR.to_string()),
+        )
+    }
+
+    /// Returns the path of the file that holds the macro `name`. Only names that are
+    /// made up of alphanumeric characters, '-', '_' and '.' are allowed
+    fn macro_file_path(name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        if valid {
+            Ok(Self::macro_dir().join(format!("{}.{}", name, MACRO_FILE_EXTENSION)))
+        } else {
+            Err(MacrosPluginError::InvalidMacroName {
+                name: name.to_string(),
+            }
+            .into())
+        }
+    }
+
+    /// Start recording keyboard and mouse events into the macro `name`
+    pub fn start_recording(name: &str) -> Result<()> {
+        // validate the name now, so that the recording won't be lost later on
+        Self::macro_file_path(name)?;
+
+        let mut recording = RECORDING.lock();
+
+        if recording.is_some() {
+            Err(MacrosPluginError::RecordingActive {}.into())
+        } else {
+            info!("Recording macro: {}", name);
+
+            *recording = Some(Recording {
+                name: name.to_string(),
+                last_timestamp: None,
+                pressed: HashSet::new(),
+                events: Vec::new(),
+            });
+
+            Ok(())
+        }
+    }
+
+    /// Stop the active recording and save it to disk. Returns the name of the recorded macro
+    pub fn stop_recording() -> Result<String> {
+        let recording = RECORDING
+            .lock()
+            .take()
+            .ok_or(MacrosPluginError::NotRecording {})?;
+
+        let events = Self::strip_unreleased_keys(recording.events);
+
+        if events.is_empty() {
+            Err(MacrosPluginError::EmptyRecording {}.into())
+        } else {
+            info!("Saving macro: {} ({} events)", recording.name, events.len());
+
+            Self::save_macro(&RecordedMacro {
+                version: MACRO_FILE_VERSION,
+                name: recording.name.clone(),
+                events,
+            })?;
 
-                        Message::MirrorMouseEvent(raw_event) => {
-                            if !DROP_CURRENT_MOUSE_INPUT.load(Ordering::SeqCst) {
-                                Self::inject_mouse_event(raw_event).unwrap();
-                            } else {
-                                debug!("Mouse event has been dropped as requested");
+            Ok(recording.name)
+        }
+    }
+
+    /// Returns true if a macro is currently being recorded
+    pub fn is_recording() -> bool {
+        RECORDING.lock().is_some()
+    }
+
+    /// Record a raw evdev event, if a recording is active
+    fn record_event(device: RecordedDevice, event: &InputEvent) {
+        let mut recording = RECORDING.lock();
+
+        if let Some(recording) = recording.as_mut() {
+            let (event_type, code) = match &event.event_code {
+                // ignore key repeats
+                EventCode::EV_KEY(_) if event.value > 1 => return,
+
+                EventCode::EV_KEY(key) => (EventType::EV_KEY as u32, key.clone() as u32),
+                EventCode::EV_REL(rel) => (EventType::EV_REL as u32, rel.clone() as u32),
+
+                _ => return,
+            };
+
+            if event_type == EventType::EV_KEY as u32 {
+                if event.value > 0 {
+                    recording.pressed.insert((device, code));
+                } else if !recording.pressed.remove(&(device, code)) {
+                    // the key has been pressed before the recording started
+                    return;
+                }
+            }
+
+            if recording.events.len() >= MAX_RECORDED_EVENTS {
+                warn!("Maximum number of events reached, the recording will be truncated");
+                return;
+            }
+
+            let timestamp = event.time.tv_sec as u64 * 1_000_000 + event.time.tv_usec as u64;
+
+            let delay = recording
+                .last_timestamp
+                .map(|last| timestamp.saturating_sub(last))
+                .unwrap_or(0);
+
+            recording.last_timestamp = Some(timestamp);
+
+            recording.events.push(RecordedEvent {
+                delay,
+                device,
+                event_type,
+                code,
+                value: event.value,
+            });
+        }
+    }
+
+    /// Remove presses of keys and buttons that have not been released before the recording
+    /// has been stopped, e.g. the keys of the combination that stopped the recording
+    fn strip_unreleased_keys(events: Vec<RecordedEvent>) -> Vec<RecordedEvent> {
+        let mut pressed = std::collections::HashMap::new();
+
+        for (index, event) in events.iter().enumerate() {
+            if event.event_type == EventType::EV_KEY as u32 {
+                if event.value > 0 {
+                    pressed.insert((event.device, event.code), index);
+                } else {
+                    pressed.remove(&(event.device, event.code));
+                }
+            }
+        }
+
+        let unreleased: HashSet<usize> = pressed.values().cloned().collect();
+
+        let mut result = Vec::with_capacity(events.len());
+        let mut carry = 0;
+
+        for (index, mut event) in events.into_iter().enumerate() {
+            if unreleased.contains(&index) {
+                // keep the timing of the following events intact
+                carry += event.delay;
+            } else {
+                event.delay += carry;
+                carry = 0;
+
+                result.push(event);
+            }
+        }
+
+        result
+    }
+
+    /// Save the recorded macro to disk. The file is replaced atomically. Recordings may contain
+    /// passwords, so they are accessible by the owner only
+    fn save_macro(recorded_macro: &RecordedMacro) -> Result<()> {
+        let path = Self::macro_file_path(&recorded_macro.name)?;
+        let tmp_path = path.with_extension("macro.tmp");
+
+        let dir = Self::macro_dir();
+
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+
+        // the directory may have been created by a previous version
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+        let json_string = serde_json::to_string_pretty(recorded_macro)?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+
+        // a leftover temporary file keeps its permissions
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(json_string.as_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    /// Load the recorded macro `name` from disk
+    pub fn load_macro(name: &str) -> Result<RecordedMacro> {
+        let json_string = fs::read_to_string(&Self::macro_file_path(name)?)?;
+        let result = serde_json::from_str::<RecordedMacro>(&json_string)?;
+
+        Ok(result)
+    }
+
+    /// Returns the names of all recorded macros
+    pub fn list_macros() -> Result<Vec<String>> {
+        let mut result = Vec::new();
+
+        let dir = Self::macro_dir();
+
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+
+                if path.extension().and_then(|e| e.to_str()) == Some(MACRO_FILE_EXTENSION) {
+                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                        result.push(name.to_string());
+                    }
+                }
+            }
+        }
+
+        result.sort();
+
+        Ok(result)
+    }
+
+    /// Replay the recorded macro `name` through the virtual input devices. The delays between
+    /// the events are divided by `speed`, the macro is played `repeat` times, or until
+    /// `stop_playback()` is called if `repeat` is 0. The events are scheduled on the uinput
+    /// thread, tagged with the name of the macro
+    pub fn play_macro(name: &str, speed: f64, repeat: u32) -> Result<()> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(MacrosPluginError::InvalidPlaybackParameters {
+                description: format!("speed must be greater than zero, got {}", speed),
+            }
+            .into());
+        }
+
+        let recorded_macro = Self::load_macro(name)?;
+
+        // reject macros that can not be replayed, before any of their events have been scheduled
+        for event in recorded_macro.events.iter() {
+            Self::replay_message(event)?;
+        }
+
+        debug!(
+            "Playing macro: {} (speed: {}, repeat: {})",
+            name, speed, repeat
+        );
+
+        PLAYED_MACROS.lock().insert(recorded_macro.name.clone());
+
+        let tag = recorded_macro.name.clone();
+        let messages =
+            Self::macro_iteration(Arc::new(recorded_macro), Instant::now(), speed, repeat);
+
+        Self::schedule_messages(messages, &tag);
+
+        Ok(())
+    }
+
+    /// Returns the messages of a single iteration of `recorded_macro` that starts at `start`,
+    /// along with their due times. Unless it is the last iteration, the final message
+    /// schedules the next iteration
+    fn macro_iteration(
+        recorded_macro: Arc<RecordedMacro>,
+        start: Instant,
+        speed: f64,
+        repeat: u32,
+    ) -> Vec<(Instant, Message)> {
+        let mut result = Vec::new();
+
+        let mut pressed = HashSet::new();
+        let mut deadline = start;
+
+        for event in recorded_macro.events.iter() {
+            deadline += Duration::from_micros((event.delay as f64 / speed) as u64);
+
+            if event.event_type == EventType::EV_KEY as u32 {
+                if event.value > 0 {
+                    pressed.insert((event.device, event.code));
+                } else {
+                    pressed.remove(&(event.device, event.code));
+                }
+            }
+
+            match Self::replay_message(event) {
+                Ok(message) => result.push((deadline, message)),
+
+                Err(e) => error!("Could not replay an event of a macro: {}", e),
+            }
+        }
+
+        // release all keys and buttons that are still being held down at the end of the iteration
+        for (device, code) in pressed {
+            let event = RecordedEvent {
+                delay: 0,
+                device,
+                event_type: EventType::EV_KEY as u32,
+                code,
+                value: 0,
+            };
+
+            match Self::replay_message(&event) {
+                Ok(message) => result.push((deadline, message)),
+
+                Err(e) => error!("Could not release a key after replaying a macro: {}", e),
+            }
+        }
+
+        if repeat != 1 {
+            result.push((
+                deadline,
+                Message::RepeatMacro {
+                    recorded_macro,
+                    start: deadline,
+                    speed,
+                    repeat: repeat.saturating_sub(1),
+                },
+            ));
+        }
+
+        result
+    }
+
+    /// Stop all running macro playbacks and the text that is being typed. Keys and
+    /// buttons that are still being held down are released
+    pub fn stop_playback() {
+        let played_macros = std::mem::take(&mut *PLAYED_MACROS.lock());
+
+        for name in played_macros.iter() {
+            Self::cancel_scheduled(Some(name));
+        }
+
+        Self::cancel_scheduled(Some(TYPING_TAG));
+    }
+
+    /// Translate a recorded event to a message of the uinput thread
+    fn replay_message(event: &RecordedEvent) -> Result<Message> {
+        let is_key = event.event_type == EventType::EV_KEY as u32;
+
+        let message = match event.device {
+            RecordedDevice::Keyboard if is_key => Message::InjectKey {
+                key: event.code,
+                down: event.value > 0,
+            },
+
+            RecordedDevice::Mouse => {
+                let (event_type, event_code) = if is_key {
+                    (
+                        EventType::EV_KEY,
+                        EventCode::EV_KEY(
+                            int_to_ev_key(event.code).ok_or(MacrosPluginError::MappingError {})?,
+                        ),
+                    )
+                } else {
+                    (
+                        EventType::EV_REL,
+                        EventCode::EV_REL(
+                            int_to_ev_rel(event.code).ok_or(MacrosPluginError::MappingError {})?,
+                        ),
+                    )
+                };
+
+                Message::MirrorMouseEventImmediate(InputEvent {
+                    time: Self::current_time(),
+                    event_type,
+                    event_code,
+                    value: event.value,
+                })
+            }
+
+            _ => return Err(MacrosPluginError::MappingError {}.into()),
+        };
+
+        Ok(message)
+    }
+
+    /// Type `text` on the virtual keyboard, using the configured keyboard layout to translate
+    /// characters to keys. `delay` is the time between two typed characters
+    pub fn type_text(text: &str, delay: Duration) -> Result<()> {
+        let strokes = layout::get_layout()?.key_strokes(text)?;
+
+        Self::type_key_strokes(strokes, 0, delay);
+
+        Ok(())
+    }
+
+    /// Type `strokes` on the virtual keyboard, preceded by `backspaces` presses of the backspace key.
+    /// The key events are scheduled on the uinput thread, tagged with `TYPING_TAG`
+    fn type_key_strokes(strokes: Vec<KeyStroke>, backspaces: usize, delay: Duration) {
+        let mut typing_deadline = TYPING_DEADLINE.lock();
+
+        // give the key that completed a trigger the chance to be mirrored first,
+        // and wait for the text that is still being typed
+        let mut deadline = (Instant::now() + delay).max(*typing_deadline);
+
+        let mut messages = Vec::new();
+        let mut schedule = |key: u32, down: bool, deadline: Instant| {
+            messages.push((deadline, Message::InjectKey { key, down }))
+        };
+
+        // release modifiers that would change the typed characters
+        for key in [KEY_LEFTSHIFT, KEY_RIGHTSHIFT, KEY_RIGHTALT].iter() {
+            schedule(*key, false, deadline);
+        }
+
+        let backspace = KeyStroke {
+            key: KEY_BACKSPACE,
+            level: 0,
+        };
+
+        for stroke in std::iter::repeat(backspace)
+            .take(backspaces)
+            .chain(strokes.into_iter())
+        {
+            let modifiers = stroke.modifiers();
+
+            for key in modifiers.iter() {
+                schedule(*key, true, deadline);
+            }
+
+            schedule(stroke.key, true, deadline);
+            schedule(stroke.key, false, deadline);
+
+            for key in modifiers.iter().rev() {
+                schedule(*key, false, deadline);
+            }
+
+            deadline += delay;
+        }
+
+        Self::schedule_messages(messages, TYPING_TAG);
+
+        *typing_deadline = deadline;
+    }
+
+    /// Register a text expansion: typing `trigger` replaces it with `text`
+    pub fn register_text_expansion(trigger: &str, text: &str) -> Result<()> {
+        let layout = layout::get_layout()?;
+
+        if trigger.is_empty() || trigger.chars().count() > MAX_TRIGGER_LENGTH {
+            return Err(MacrosPluginError::InvalidTrigger {
+                trigger: trigger.to_string(),
+            }
+            .into());
+        }
+
+        // triggers are detected by translating keys back to characters,
+        // so both, the trigger and the text have to be typeable
+        layout.key_strokes(trigger)?;
+        layout.key_strokes(text)?;
+
+        TEXT_EXPANSIONS
+            .lock()
+            .expansions
+            .insert(trigger.to_string(), text.to_string());
+
+        Ok(())
+    }
+
+    /// Remove the text expansion `trigger`, returns true if it existed
+    pub fn unregister_text_expansion(trigger: &str) -> bool {
+        TEXT_EXPANSIONS.lock().expansions.remove(trigger).is_some()
+    }
+
+    /// Track the characters typed on the hardware keyboard, and expand
+    /// registered triggers as soon as they have been typed completely
+    fn expand_text(event: &InputEvent) {
+        let code = match event.event_code {
+            EventCode::EV_KEY(ref key) => key.clone() as u32,
+
+            _ => return,
+        };
+
+        let mut state = TEXT_EXPANSIONS.lock();
+
+        if state.expansions.is_empty() {
+            return;
+        }
+
+        let down = event.value > 0;
+
+        match code {
+            KEY_LEFTSHIFT | KEY_RIGHTSHIFT => state.shift = down,
+
+            KEY_RIGHTALT => state.altgr = down,
+
+            // keyboard shortcuts interrupt the typed text
+            KEY_LEFTCTRL | KEY_RIGHTCTRL | KEY_LEFTALT | KEY_LEFTMETA | KEY_RIGHTMETA => {
+                state.typed.clear()
+            }
+
+            KEY_BACKSPACE if down => {
+                state.typed.pop();
+            }
+
+            _ if down => {
+                let c = match layout::get_layout() {
+                    Ok(layout) => layout.to_char(code, state.shift, state.altgr),
+
+                    Err(_) => None,
+                };
+
+                match c {
+                    Some(c) => {
+                        state.typed.push(c);
+
+                        let trigger = state
+                            .expansions
+                            .keys()
+                            .find(|trigger| state.typed.ends_with(trigger.as_str()))
+                            .cloned();
+
+                        if let Some(trigger) = trigger {
+                            let text = &state.expansions[&trigger];
+
+                            debug!("Expanding the text expansion trigger: {}", trigger);
+
+                            match layout::get_layout().and_then(|l| l.key_strokes(text)) {
+                                Ok(strokes) => Self::type_key_strokes(
+                                    strokes,
+                                    trigger.chars().count(),
+                                    Duration::from_millis(DEFAULT_TYPING_DELAY_MILLIS),
+                                ),
+
+                                Err(e) => error!("Could not expand the text: {}", e),
                             }
-                        }
 
-                        Message::MirrorMouseEventImmediate(raw_event) => {
-                            Self::inject_mouse_event_immediate(raw_event).unwrap();
+                            state.typed.clear();
+                        } else if state.typed.chars().count() > MAX_TRIGGER_LENGTH {
+                            state.typed.remove(0);
                         }
+                    }
 
-                        Message::InjectKey { key: ev_key, down } => {
-                            let key = evdev_rs::enums::int_to_ev_key(ev_key).unwrap_or_else(|| {
-                                error!("Invalid key index");
-                                panic!()
-                            });
+                    // any other key, e.g. the cursor keys, interrupts the typed text
+                    None => state.typed.clear(),
+                }
+            }
 
-                            let value = if down { 1 } else { 0 };
+            _ => {}
+        }
+    }
 
-                            let mut time: libc::timeval = libc::timeval {
-                                tv_sec: 0,
-                                tv_usec: 0,
-                            };
+    /// Process `message` on the uinput thread
+    fn process_message(message: Message, scheduler: &mut Scheduler) {
+        match message {
+            Message::MirrorKey(raw_event) => {
+                if !DROP_CURRENT_KEY.load(Ordering::SeqCst) {
+                    Self::inject_key_event(raw_event).unwrap();
+                } else {
+                    debug!("Keyboard event has been dropped as requested");
+                }
+            }
 
-                            unsafe {
-                                libc::gettimeofday(&mut time, std::ptr::null_mut());
-                            }
+            Message::MirrorMouseEvent(raw_event) => {
+                if !DROP_CURRENT_MOUSE_INPUT.load(Ordering::SeqCst) {
+                    Self::inject_mouse_event(raw_event).unwrap();
+                } else {
+                    debug!("Mouse event has been dropped as requested");
+                }
+            }
 
-                            let time = evdev_rs::TimeVal::from_raw(&time);
+            Message::MirrorMouseEventImmediate(raw_event) => {
+                Self::inject_mouse_event_immediate(raw_event).unwrap();
+            }
 
-                            Self::inject_single_key(key, value, &time).unwrap();
-                        }
+            Message::InjectKey { key: ev_key, down } => {
+                let key = evdev_rs::enums::int_to_ev_key(ev_key).unwrap_or_else(|| {
+                    error!("Invalid key index");
+                    panic!()
+                });
+
+                let value = if down { 1 } else { 0 };
 
-                        Message::InjectButtonEvent { button, down } => {
-                            let key = Self::button_index_to_ev_key(button).unwrap_or_else(|e| {
-                                error!("Invalid button index: {}", e);
-                                panic!()
-                            });
+                let mut time: libc::timeval = libc::timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                };
 
-                            let value = if down { 1 } else { 0 };
+                unsafe {
+                    libc::gettimeofday(&mut time, std::ptr::null_mut());
+                }
 
-                            let mut time: libc::timeval = libc::timeval {
-                                tv_sec: 0,
-                                tv_usec: 0,
-                            };
+                let time = evdev_rs::TimeVal::from_raw(&time);
 
-                            unsafe {
-                                libc::gettimeofday(&mut time, std::ptr::null_mut());
-                            }
+                Self::inject_single_key(key, value, &time).unwrap();
+            }
 
-                            let time = evdev_rs::TimeVal::from_raw(&time);
+            Message::InjectButtonEvent { button, down } => {
+                let key = Self::button_index_to_ev_key(button).unwrap_or_else(|e| {
+                    error!("Invalid button index: {}", e);
+                    panic!()
+                });
 
-                            Self::inject_single_mouse_event(key, value, &time).unwrap();
-                        }
+                let value = if down { 1 } else { 0 };
+
+                let mut time: libc::timeval = libc::timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                };
+
+                unsafe {
+                    libc::gettimeofday(&mut time, std::ptr::null_mut());
+                }
+
+                let time = evdev_rs::TimeVal::from_raw(&time);
+
+                Self::inject_single_mouse_event(key, value, &time).unwrap();
+            }
+
+            Message::InjectMouseWheelEvent { direction: _ } => {
+                // REL_RESERVED
+            }
+
+            Message::Schedule { due, tag, message } => scheduler.push(due, tag, *message),
+
+            Message::CancelScheduled { tag } => {
+                for message in scheduler.cancel(tag.as_deref()) {
+                    // don't leave keys or buttons stuck in the pressed state
+                    if message.is_release() {
+                        Self::process_message(message, scheduler);
+                    }
+                }
+            }
+
+            Message::RepeatMacro {
+                recorded_macro,
+                start,
+                speed,
+                repeat,
+            } => {
+                let tag = recorded_macro.name.clone();
+
+                for (due, message) in Self::macro_iteration(recorded_macro, start, speed, repeat) {
+                    scheduler.push(due, tag.clone(), message);
+                }
+            }
+        }
+    }
+
+    /// Process `message` on the uinput thread after `delay`. Pending messages may be
+    /// cancelled by their `tag`, e.g. the name of the macro that scheduled them
+    pub(crate) fn schedule_message(message: Message, delay: Duration, tag: &str) {
+        Self::schedule_messages(vec![(Instant::now() + delay, message)], tag);
+    }
+
+    /// Process each of `messages` on the uinput thread as soon as its due time has been reached.
+    /// The messages are queued at once, so they can't be interleaved with a cancellation
+    fn schedule_messages(messages: Vec<(Instant, Message)>, tag: &str) {
+        if let Some(tx) = UINPUT_TX.lock().as_ref() {
+            for (due, message) in messages {
+                let message = Message::Schedule {
+                    due,
+                    tag: tag.to_string(),
+                    message: Box::new(message),
+                };
+
+                tx.send(message)
+                    .unwrap_or_else(|e| error!("Could not schedule a message: {}", e));
+            }
+        }
+    }
+
+    /// Cancel all pending messages of `tag`, or all pending messages if `tag` is `None`.
+    /// Pending releases of keys and buttons are processed immediately
+    pub fn cancel_scheduled(tag: Option<&str>) {
+        if tag.map_or(true, |tag| tag == TYPING_TAG) {
+            *TYPING_DEADLINE.lock() = Instant::now();
+        }
+
+        let message = Message::CancelScheduled {
+            tag: tag.map(|t| t.to_string()),
+        };
+
+        if let Some(tx) = UINPUT_TX.lock().as_ref() {
+            tx.send(message)
+                .unwrap_or_else(|e| error!("Could not cancel scheduled messages: {}", e));
+        }
+    }
+
+    /// Returns the number of messages that are pending in the queue of the uinput thread
+    pub fn get_queue_depth() -> usize {
+        QUEUE_DEPTH.load(Ordering::SeqCst)
+    }
+
+    fn spawn_uinput_thread() -> Result<()> {
+        let (uinput_tx, uinput_rx) = unbounded();
+
+        thread::Builder::new()
+            .name("uinput".into())
+            .spawn(move || {
+                Self::initialize_thread_locals().unwrap();
+
+                let mut scheduler = Scheduler::default();
+
+                loop {
+                    let message = match scheduler.next_due() {
+                        Some(due) => {
+                            match uinput_rx
+                                .recv_timeout(due.saturating_duration_since(Instant::now()))
+                            {
+                                Ok(message) => Some(message),
+
+                                Err(RecvTimeoutError::Timeout) => None,
 
-                        Message::InjectMouseWheelEvent { direction: _ } => {
-                            // REL_RESERVED
+                                Err(RecvTimeoutError::Disconnected) => break,
+                            }
                         }
+
+                        None => match uinput_rx.recv() {
+                            Ok(message) => Some(message),
+
+                            Err(_) => break,
+                        },
+                    };
+
+                    if let Some(message) = message {
+                        Self::process_message(message, &mut scheduler);
+                    }
+
+                    while let Some(message) = scheduler.pop_due(Instant::now()) {
+                        Self::process_message(message, &mut scheduler);
                     }
                 }
             })?;
@@ -675,10 +1602,125 @@ impl Plugin for MacrosPlugin {
     fn initialize(&mut self) -> plugins::Result<()> {
         Self::spawn_uinput_thread()?;
 
+        events::register_observer(|event: &events::Event| {
+            match event {
+                events::Event::RawKeyboardEvent(raw_event) => {
+                    Self::record_event(RecordedDevice::Keyboard, raw_event);
+                    Self::expand_text(raw_event);
+                }
+
+                events::Event::RawMouseEvent(raw_event) => {
+                    Self::record_event(RecordedDevice::Mouse, raw_event)
+                }
+
+                _ => return Ok(true),
+            };
+
+            Ok(true) // event has been processed
+        });
+
         Ok(())
     }
 
-    fn register_lua_funcs(&self, _lua_ctx: &Lua) -> mlua::Result<()> {
+    fn register_lua_funcs(&self, lua_ctx: &Lua) -> mlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let start_macro_recording =
+            lua_ctx.create_function(|_, name: String| match Self::start_recording(&name) {
+                Ok(()) => Ok((Some(true), None)),
+
+                Err(e) => {
+                    warn!("Could not start recording the macro '{}': {}", name, e);
+                    Ok((None, Some(format!("{}", e))))
+                }
+            })?;
+        globals.set("start_macro_recording", start_macro_recording)?;
+
+        let stop_macro_recording =
+            lua_ctx.create_function(|_, ()| match Self::stop_recording() {
+                Ok(name) => Ok((Some(name), None)),
+
+                Err(e) => {
+                    warn!("Could not stop recording the macro: {}", e);
+                    Ok((None, Some(format!("{}", e))))
+                }
+            })?;
+        globals.set("stop_macro_recording", stop_macro_recording)?;
+
+        let is_macro_recording = lua_ctx.create_function(|_, ()| Ok(Self::is_recording()))?;
+        globals.set("is_macro_recording", is_macro_recording)?;
+
+        let play_macro = lua_ctx.create_function(
+            |_, (name, speed, repeat): (String, Option<f64>, Option<u32>)| {
+                let speed = speed.unwrap_or(1.0);
+                let repeat = repeat.unwrap_or(1);
+
+                match Self::play_macro(&name, speed, repeat) {
+                    Ok(()) => Ok((Some(true), None)),
+
+                    Err(e) => {
+                        warn!("Could not play the macro '{}': {}", name, e);
+                        Ok((None, Some(format!("{}", e))))
+                    }
+                }
+            },
+        )?;
+        globals.set("play_macro", play_macro)?;
+
+        let stop_macro_playback = lua_ctx.create_function(|_, ()| {
+            Self::stop_playback();
+            Ok(())
+        })?;
+        globals.set("stop_macro_playback", stop_macro_playback)?;
+
+        let list_macros = lua_ctx.create_function(|_, ()| {
+            Self::list_macros().map_err(|e| LuaError::RuntimeError(format!("{}", e)))
+        })?;
+        globals.set("list_macros", list_macros)?;
+
+        let type_text =
+            lua_ctx.create_function(|_, (text, delay_millis): (String, Option<u64>)| {
+                let delay =
+                    Duration::from_millis(delay_millis.unwrap_or(DEFAULT_TYPING_DELAY_MILLIS));
+
+                match Self::type_text(&text, delay) {
+                    Ok(()) => Ok((Some(true), None)),
+
+                    Err(e) => {
+                        warn!("Could not type the text: {}", e);
+                        Ok((None, Some(format!("{}", e))))
+                    }
+                }
+            })?;
+        globals.set("type_text", type_text)?;
+
+        let register_text_expansion =
+            lua_ctx.create_function(|_, (trigger, text): (String, String)| {
+                match Self::register_text_expansion(&trigger, &text) {
+                    Ok(()) => Ok((Some(true), None)),
+
+                    Err(e) => {
+                        warn!("Could not register the text expansion '{}': {}", trigger, e);
+                        Ok((None, Some(format!("{}", e))))
+                    }
+                }
+            })?;
+        globals.set("register_text_expansion", register_text_expansion)?;
+
+        let unregister_text_expansion = lua_ctx
+            .create_function(|_, trigger: String| Ok(Self::unregister_text_expansion(&trigger)))?;
+        globals.set("unregister_text_expansion", unregister_text_expansion)?;
+
+        let cancel_delayed_injections = lua_ctx.create_function(|_, tag: Option<String>| {
+            Self::cancel_scheduled(tag.as_deref());
+            Ok(())
+        })?;
+        globals.set("cancel_delayed_injections", cancel_delayed_injections)?;
+
+        let get_injection_queue_depth =
+            lua_ctx.create_function(|_, ()| Ok(Self::get_queue_depth()))?;
+        globals.set("get_injection_queue_depth", get_injection_queue_depth)?;
+
         Ok(())
     }
 
@@ -694,3 +1736,389 @@ impl Plugin for MacrosPlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(message: &Message) -> Option<(u32, bool)> {
+        match message {
+            Message::InjectKey { key, down } => Some((*key, *down)),
+
+            _ => None,
+        }
+    }
+
+    fn key_event(delay: u64, code: u32, value: i32) -> RecordedEvent {
+        RecordedEvent {
+            delay,
+            device: RecordedDevice::Keyboard,
+            event_type: EventType::EV_KEY as u32,
+            code,
+            value,
+        }
+    }
+
+    fn recorded_macro(events: Vec<RecordedEvent>) -> Arc<RecordedMacro> {
+        Arc::new(RecordedMacro {
+            version: MACRO_FILE_VERSION,
+            name: "test".to_string(),
+            events,
+        })
+    }
+
+    #[test]
+    fn test_scheduler_order() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.push(
+            now + Duration::from_millis(20),
+            "".into(),
+            Message::InjectKey { key: 3, down: true },
+        );
+        scheduler.push(
+            now + Duration::from_millis(10),
+            "".into(),
+            Message::InjectKey { key: 1, down: true },
+        );
+        scheduler.push(
+            now + Duration::from_millis(20),
+            "".into(),
+            Message::InjectKey { key: 4, down: true },
+        );
+        scheduler.push(
+            now + Duration::from_millis(10),
+            "".into(),
+            Message::InjectKey { key: 2, down: true },
+        );
+
+        assert_eq!(scheduler.next_due(), Some(now + Duration::from_millis(10)));
+
+        // nothing is due yet
+        assert!(scheduler.pop_due(now).is_none());
+
+        let due = now + Duration::from_millis(10);
+        assert_eq!(
+            scheduler.pop_due(due).as_ref().and_then(key),
+            Some((1, true))
+        );
+        assert_eq!(
+            scheduler.pop_due(due).as_ref().and_then(key),
+            Some((2, true))
+        );
+        assert!(scheduler.pop_due(due).is_none());
+
+        // messages that are due at the same time keep their order
+        let due = now + Duration::from_millis(30);
+        assert_eq!(
+            scheduler.pop_due(due).as_ref().and_then(key),
+            Some((3, true))
+        );
+        assert_eq!(
+            scheduler.pop_due(due).as_ref().and_then(key),
+            Some((4, true))
+        );
+        assert!(scheduler.pop_due(due).is_none());
+        assert_eq!(scheduler.next_due(), None);
+    }
+
+    #[test]
+    fn test_scheduler_cancel() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        for (i, tag) in ["a", "b", "a", "b"].iter().enumerate() {
+            let due = now + Duration::from_millis(40 - i as u64 * 10);
+
+            scheduler.push(
+                due,
+                tag.to_string(),
+                Message::InjectKey {
+                    key: i as u32,
+                    down: false,
+                },
+            );
+        }
+
+        // cancelled messages are returned in the order they would have been processed
+        let cancelled: Vec<_> = scheduler.cancel(Some("a")).iter().filter_map(key).collect();
+        assert_eq!(cancelled, vec![(2, false), (0, false)]);
+
+        assert!(scheduler.cancel(Some("c")).is_empty());
+        assert_eq!(scheduler.next_due(), Some(now + Duration::from_millis(10)));
+
+        let cancelled: Vec<_> = scheduler.cancel(None).iter().filter_map(key).collect();
+        assert_eq!(cancelled, vec![(3, false), (1, false)]);
+
+        assert_eq!(scheduler.next_due(), None);
+    }
+
+    #[test]
+    fn test_is_release() {
+        assert!(Message::InjectKey {
+            key: 30,
+            down: false
+        }
+        .is_release());
+        assert!(!Message::InjectKey {
+            key: 30,
+            down: true
+        }
+        .is_release());
+        assert!(Message::InjectButtonEvent {
+            button: 1,
+            down: false
+        }
+        .is_release());
+        assert!(!Message::CancelScheduled { tag: None }.is_release());
+
+        let button = |value| {
+            Message::MirrorMouseEventImmediate(InputEvent {
+                time: MacrosPlugin::current_time(),
+                event_type: EventType::EV_KEY,
+                event_code: EventCode::EV_KEY(EV_KEY::BTN_LEFT),
+                value,
+            })
+        };
+
+        assert!(button(0).is_release());
+        assert!(!button(1).is_release());
+    }
+
+    #[test]
+    fn test_macro_iteration() {
+        let start = Instant::now();
+
+        // the key 31 is still being held down at the end of the macro
+        let recorded_macro = recorded_macro(vec![
+            key_event(1000, 30, 1),
+            key_event(2000, 30, 0),
+            key_event(2000, 31, 1),
+        ]);
+
+        let messages = MacrosPlugin::macro_iteration(recorded_macro.clone(), start, 2.0, 1);
+
+        let expected = vec![
+            (start + Duration::from_micros(500), (30, true)),
+            (start + Duration::from_micros(1500), (30, false)),
+            (start + Duration::from_micros(2500), (31, true)),
+            (start + Duration::from_micros(2500), (31, false)),
+        ];
+
+        assert_eq!(
+            messages
+                .iter()
+                .map(|(due, m)| (*due, key(m).unwrap()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        // all but the last iteration schedule the next one at their end
+        for (repeat, next) in [(0, Some(0)), (2, Some(1)), (1, None)].iter() {
+            let messages =
+                MacrosPlugin::macro_iteration(recorded_macro.clone(), start, 1.0, *repeat);
+
+            match messages.last() {
+                Some((
+                    due,
+                    Message::RepeatMacro {
+                        start: next_start,
+                        repeat,
+                        ..
+                    },
+                )) => {
+                    assert_eq!(*due, start + Duration::from_micros(5000));
+                    assert_eq!(*next_start, *due);
+                    assert_eq!(Some(*repeat), *next);
+                }
+
+                _ => assert_eq!(*next, None),
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_message() {
+        let mut event = key_event(0, 30, 1);
+        assert_eq!(
+            MacrosPlugin::replay_message(&event)
+                .ok()
+                .as_ref()
+                .and_then(key),
+            Some((30, true))
+        );
+
+        // keyboard events other than keys can't be replayed
+        event.event_type = EventType::EV_REL as u32;
+        assert!(MacrosPlugin::replay_message(&event).is_err());
+
+        let event = RecordedEvent {
+            delay: 0,
+            device: RecordedDevice::Mouse,
+            event_type: EventType::EV_REL as u32,
+            code: EV_REL::REL_X as u32,
+            value: -5,
+        };
+
+        match MacrosPlugin::replay_message(&event).unwrap() {
+            Message::MirrorMouseEventImmediate(e) => {
+                assert_eq!(e.event_code, EventCode::EV_REL(EV_REL::REL_X));
+                assert_eq!(e.value, -5);
+            }
+
+            _ => panic!("Unexpected message"),
+        }
+    }
+
+    #[test]
+    fn test_macro_file_path() {
+        for name in ["test", "my-macro_1", "macro.v2"].iter() {
+            let path = MacrosPlugin::macro_file_path(name).unwrap();
+
+            assert_eq!(path.file_name().unwrap(), &*format!("{}.macro", name));
+            assert_eq!(path.parent().unwrap(), MacrosPlugin::macro_dir());
+        }
+
+        for name in ["", ".hidden", "../escape", "a/b", "with space", "ümlaut"].iter() {
+            assert!(MacrosPlugin::macro_file_path(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_strip_unreleased_keys() {
+        let events = vec![
+            key_event(0, 29, 1),
+            key_event(100, 30, 1),
+            key_event(200, 30, 0),
+            key_event(300, 31, 1),
+            key_event(400, 42, 1),
+        ];
+
+        let events = MacrosPlugin::strip_unreleased_keys(events);
+
+        // the delays of the removed events are carried over to the next event
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.delay, e.code, e.value))
+                .collect::<Vec<_>>(),
+            vec![(100, 30, 1), (200, 30, 0)]
+        );
+    }
+
+    #[test]
+    fn test_recording() {
+        let event = |usec, event_code, value| InputEvent {
+            time: TimeVal {
+                tv_sec: 1,
+                tv_usec: usec,
+            },
+            event_type: EventType::EV_KEY,
+            event_code,
+            value,
+        };
+
+        assert!(MacrosPlugin::start_recording("../escape").is_err());
+        assert!(!MacrosPlugin::is_recording());
+
+        MacrosPlugin::start_recording("test-recording").unwrap();
+        assert!(MacrosPlugin::is_recording());
+        assert!(MacrosPlugin::start_recording("test-recording").is_err());
+
+        // released, but pressed before the recording has been started
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &event(0, EventCode::EV_KEY(EV_KEY::KEY_ENTER), 0),
+        );
+
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &event(1000, EventCode::EV_KEY(EV_KEY::KEY_A), 1),
+        );
+
+        // key repeats are ignored
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &event(1500, EventCode::EV_KEY(EV_KEY::KEY_A), 2),
+        );
+
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &event(3000, EventCode::EV_KEY(EV_KEY::KEY_A), 0),
+        );
+
+        MacrosPlugin::record_event(
+            RecordedDevice::Mouse,
+            &InputEvent {
+                event_type: EventType::EV_REL,
+                ..event(3500, EventCode::EV_REL(EV_REL::REL_X), -5)
+            },
+        );
+
+        // other event types are not recorded
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &InputEvent {
+                event_type: EventType::EV_SYN,
+                ..event(3500, EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)
+            },
+        );
+
+        let recording = RECORDING.lock().take().unwrap();
+
+        assert_eq!(recording.name, "test-recording");
+        assert!(recording.pressed.is_empty());
+        assert_eq!(
+            recording
+                .events
+                .iter()
+                .map(|e| (e.delay, e.device, e.code, e.value))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, RecordedDevice::Keyboard, EV_KEY::KEY_A as u32, 1),
+                (2000, RecordedDevice::Keyboard, EV_KEY::KEY_A as u32, 0),
+                (500, RecordedDevice::Mouse, EV_REL::REL_X as u32, -5),
+            ]
+        );
+
+        assert!(MacrosPlugin::stop_recording().is_err());
+
+        // a recording that only holds the keys that stopped it is not saved
+        MacrosPlugin::start_recording("test-recording").unwrap();
+        MacrosPlugin::record_event(
+            RecordedDevice::Keyboard,
+            &event(0, EventCode::EV_KEY(EV_KEY::KEY_LEFTCTRL), 1),
+        );
+
+        assert!(MacrosPlugin::stop_recording().is_err());
+        assert!(!MacrosPlugin::is_recording());
+    }
+
+    #[test]
+    fn test_recorded_macro_format() {
+        let recorded_macro = RecordedMacro {
+            version: MACRO_FILE_VERSION,
+            name: "test".to_string(),
+            events: vec![RecordedEvent {
+                delay: 100,
+                device: RecordedDevice::Mouse,
+                event_type: EventType::EV_REL as u32,
+                code: EV_REL::REL_WHEEL as u32,
+                value: 1,
+            }],
+        };
+
+        let json_string = serde_json::to_string(&recorded_macro).unwrap();
+        assert!(json_string.contains("\"device\":\"mouse\""));
+
+        let result = serde_json::from_str::<RecordedMacro>(&json_string).unwrap();
+
+        assert_eq!(result.version, MACRO_FILE_VERSION);
+        assert_eq!(result.name, "test");
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].device, RecordedDevice::Mouse);
+        assert_eq!(result.events[0].code, EV_REL::REL_WHEEL as u32);
+        assert_eq!(result.events[0].value, 1);
+    }
+}
@@ -59,6 +59,14 @@ impl Plugin for MousePlugin {
         })?;
         globals.set("get_button_state", get_button_state)?;
 
+        let is_mouse_shift_active =
+            lua_ctx.create_function(|_, ()| Ok(crate::mousemap::is_shift_active()))?;
+        globals.set("is_mouse_shift_active", is_mouse_shift_active)?;
+
+        let is_sniper_mode_active =
+            lua_ctx.create_function(|_, ()| Ok(crate::mousemap::is_sniper_active()))?;
+        globals.set("is_sniper_mode_active", is_sniper_mode_active)?;
+
         Ok(())
     }
 
@@ -18,29 +18,102 @@
 use lazy_static::lazy_static;
 use log::*;
 use mlua::prelude::*;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::constants;
 use crate::plugins::{self, Plugin};
 
+/// Name of the Lua registry value that holds the file name of the running script
+pub const SCRIPT_FILE_REGISTRY_KEY: &str = "eruption.script_file";
+
+/// Name of the Lua registry value that holds the file name of the profile, that was active when the script was started
+pub const PROFILE_FILE_REGISTRY_KEY: &str = "eruption.profile_file";
+
+/// Keys with this prefix are shared by all scripts of all profiles
+pub const GLOBAL_KEY_PREFIX: &str = "global.";
+
+/// Keys with this prefix are shared by all scripts of the active profile
+pub const PROFILE_KEY_PREFIX: &str = "profile.";
+
+/// Name of the namespace of keys that are shared by all scripts of all profiles
+pub const GLOBAL_NAMESPACE: &str = "global";
+
+/// Version of the on-disk format of the persistent store
+const STORE_FILE_VERSION: u32 = 2;
+
+/// Write the store to disk, after no values have been changed for this amount of time
+const WRITE_DEBOUNCE_MILLIS: u64 = 2000;
+
+/// Write the store to disk at the latest after this amount of time, even if values are changed constantly
+const MAX_WRITE_DELAY_MILLIS: u64 = 30000;
+
+/// Max. nesting depth of tables
+const MAX_TABLE_DEPTH: usize = 32;
+
 lazy_static! {
     /// A persistent key/value store that may be used by Lua scripts to store data across script reloads
-    /// Will be stored to disk, and will survive a restart of the daemon
-    pub static ref GLOBAL_STORE: Arc<RwLock<HashMap<String, StoreValue>>> = Arc::new(RwLock::new(HashMap::new()));
+    /// Will be stored to disk, and will survive a restart of the daemon. Maps namespaces to keys
+    pub static ref GLOBAL_STORE: Arc<RwLock<HashMap<String, HashMap<String, StoreValue>>>> = Arc::new(RwLock::new(HashMap::new()));
 
     /// An ephemeral key/value store that may be used by Lua scripts to store data across script reloads
     /// This is suitable only for transient data, since it will not survive a restart of the daemon
-    pub static ref GLOBAL_EPHEMERAL_STORE: Arc<RwLock<HashMap<String, StoreValue>>> = Arc::new(RwLock::new(HashMap::new()));
+    pub static ref GLOBAL_EPHEMERAL_STORE: Arc<RwLock<HashMap<String, HashMap<String, StoreValue>>>> = Arc::new(RwLock::new(HashMap::new()));
+
+    /// Point in time of the last modification of the persistent store
+    static ref LAST_MODIFICATION: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
+
+    /// Namespaces and keys of the persistent store that have been modified since the last call to `take_changed_keys()`
+    static ref CHANGED_KEYS: Arc<Mutex<HashSet<(String, String)>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    /// Keys that have been migrated from a store without namespaces to the `global` namespace
+    static ref LEGACY_KEYS: Arc<RwLock<BTreeSet<String>>> = Arc::new(RwLock::new(BTreeSet::new()));
+}
+
+/// Incremented on each modification of the persistent store
+static STORE_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Set as soon as the persistent store has been loaded, nothing will be written to disk before that
+static PERSISTENCE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub enum TableKey {
+    Int(i64),
+    String(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum StoreValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Color(u32),
+    Table(Vec<(TableKey, StoreValue)>),
+}
+
+/// The on-disk format of the persistent store
+#[derive(Debug, Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    namespaces: HashMap<String, HashMap<String, StoreValue>>,
+
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    legacy_keys: BTreeSet<String>,
+}
+
+/// Values of the unversioned on-disk format used before namespaces were introduced
+#[derive(Debug, Deserialize)]
+enum LegacyStoreValue {
     Int(i64),
     Float(f64),
     Bool(bool),
@@ -50,6 +123,36 @@ pub enum StoreValue {
     Hash(HashMap<String, String>),
 }
 
+impl From<LegacyStoreValue> for StoreValue {
+    fn from(value: LegacyStoreValue) -> Self {
+        match value {
+            LegacyStoreValue::Int(v) => StoreValue::Int(v),
+            LegacyStoreValue::Float(v) => StoreValue::Float(v),
+            LegacyStoreValue::Bool(v) => StoreValue::Bool(v),
+            LegacyStoreValue::String(v) => StoreValue::String(v),
+            LegacyStoreValue::Color(v) => StoreValue::Color(v),
+
+            LegacyStoreValue::Array(v) => StoreValue::Table(sorted(
+                v.into_iter()
+                    .map(|(k, v)| (TableKey::Int(k as i64), StoreValue::String(v)))
+                    .collect(),
+            )),
+
+            LegacyStoreValue::Hash(v) => StoreValue::Table(sorted(
+                v.into_iter()
+                    .map(|(k, v)| (TableKey::String(k), StoreValue::String(v)))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Sort the entries of a table by key, so that the on-disk representation is stable
+fn sorted(mut entries: Vec<(TableKey, StoreValue)>) -> Vec<(TableKey, StoreValue)> {
+    entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    entries
+}
+
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -61,15 +164,247 @@ pub enum PersistencePluginError {
     KeyError { description: String },
 }
 
+/// Returns the namespace and the key that `key` refers to, when used by the Lua script
+/// running in `lua`. Keys are private to a script and profile, unless they start with
+/// `global.` (shared by all scripts) or `profile.` (shared by the scripts of the active profile)
+pub fn namespaced_key(lua: &Lua, key: &str) -> (String, String) {
+    let profile = lua
+        .named_registry_value::<_, String>(PROFILE_FILE_REGISTRY_KEY)
+        .unwrap_or_else(|_| "none".to_string());
+
+    if key.starts_with(GLOBAL_KEY_PREFIX) {
+        (
+            GLOBAL_NAMESPACE.to_string(),
+            key[GLOBAL_KEY_PREFIX.len()..].to_string(),
+        )
+    } else if key.starts_with(PROFILE_KEY_PREFIX) {
+        (profile, key[PROFILE_KEY_PREFIX.len()..].to_string())
+    } else {
+        let script = lua
+            .named_registry_value::<_, String>(SCRIPT_FILE_REGISTRY_KEY)
+            .unwrap_or_else(|_| "unknown".to_string());
+
+        (format!("{}/{}", profile, script), key.to_string())
+    }
+}
+
+/// Returns the namespace and the key that a load of `key` refers to. Stores of previous versions
+/// had no namespaces, their keys have been migrated to the `global` namespace. So loading a key
+/// without prefix falls back to the migrated value, until the script stores a value of its own
+pub fn namespaced_key_for_load(lua: &Lua, key: &str) -> (String, String) {
+    let is_private = !key.starts_with(GLOBAL_KEY_PREFIX) && !key.starts_with(PROFILE_KEY_PREFIX);
+    let (namespace, key) = namespaced_key(lua, key);
+
+    if is_private
+        && LEGACY_KEYS.read().contains(&key)
+        && !GLOBAL_STORE
+            .read()
+            .get(&namespace)
+            .map_or(false, |n| n.contains_key(&key))
+    {
+        (GLOBAL_NAMESPACE.to_string(), key)
+    } else {
+        (namespace, key)
+    }
+}
+
+/// Convert a Lua value to a value that may be stored, tables may be nested
+pub fn from_lua_value(value: LuaValue, depth: usize) -> Result<StoreValue> {
+    match value {
+        LuaValue::Boolean(v) => Ok(StoreValue::Bool(v)),
+        LuaValue::Integer(v) => Ok(StoreValue::Int(v as i64)),
+        LuaValue::Number(v) => Ok(StoreValue::Float(v)),
+        LuaValue::String(v) => Ok(StoreValue::String(v.to_str()?.to_string())),
+
+        LuaValue::Table(table) => {
+            if depth >= MAX_TABLE_DEPTH {
+                return Err(PersistencePluginError::TypeError {
+                    description: "Tables are nested too deeply".to_owned(),
+                }
+                .into());
+            }
+
+            let mut entries = Vec::new();
+
+            for pair in table.pairs::<LuaValue, LuaValue>() {
+                let (key, value) = pair?;
+
+                let key = match key {
+                    LuaValue::Integer(k) => TableKey::Int(k as i64),
+                    LuaValue::Number(k) if k.fract() == 0.0 => TableKey::Int(k as i64),
+                    LuaValue::String(k) => TableKey::String(k.to_str()?.to_string()),
+
+                    _ => {
+                        return Err(PersistencePluginError::TypeError {
+                            description: "Table keys must be integers or strings".to_owned(),
+                        }
+                        .into())
+                    }
+                };
+
+                entries.push((key, from_lua_value(value, depth + 1)?));
+            }
+
+            Ok(StoreValue::Table(sorted(entries)))
+        }
+
+        _ => Err(PersistencePluginError::TypeError {
+            description: format!("Values of type '{}' can not be stored", value.type_name()),
+        }
+        .into()),
+    }
+}
+
+/// Convert a stored value to a Lua value
+pub fn to_lua_value<'lua>(lua: &'lua Lua, value: &StoreValue) -> mlua::Result<LuaValue<'lua>> {
+    Ok(match value {
+        StoreValue::Int(v) => LuaValue::Integer(*v as mlua::Integer),
+        StoreValue::Float(v) => LuaValue::Number(*v),
+        StoreValue::Bool(v) => LuaValue::Boolean(*v),
+        StoreValue::String(v) => LuaValue::String(lua.create_string(v)?),
+        StoreValue::Color(v) => LuaValue::Integer(*v as mlua::Integer),
+
+        StoreValue::Table(entries) => {
+            let table = lua.create_table()?;
+
+            for (key, value) in entries.iter() {
+                match key {
+                    TableKey::Int(k) => {
+                        table.raw_set(*k as mlua::Integer, to_lua_value(lua, value)?)?
+                    }
+                    TableKey::String(k) => table.raw_set(k.as_str(), to_lua_value(lua, value)?)?,
+                }
+            }
+
+            LuaValue::Table(table)
+        }
+    })
+}
+
+/// Record a modification of the persistent store, it will be written to disk by the writer thread
+fn mark_modified(namespace: &str, key: &str) {
+    *LAST_MODIFICATION.lock() = Instant::now();
+    STORE_GENERATION.fetch_add(1, Ordering::SeqCst);
+
+    CHANGED_KEYS
+        .lock()
+        .insert((namespace.to_owned(), key.to_owned()));
+}
+
+/// Returns the namespaces and keys that have been modified since the last call, and clears the set
+pub fn take_changed_keys() -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = CHANGED_KEYS.lock().drain().collect();
+    result.sort();
+
+    result
+}
+
+/// Convert a stored value to JSON, tables with consecutive integer keys starting at 1 are
+/// converted to arrays, all other tables are converted to objects
+pub fn to_json_value(value: &StoreValue) -> serde_json::Value {
+    match value {
+        StoreValue::Int(v) => serde_json::Value::from(*v),
+        StoreValue::Float(v) => serde_json::Value::from(*v),
+        StoreValue::Bool(v) => serde_json::Value::from(*v),
+        StoreValue::String(v) => serde_json::Value::from(v.as_str()),
+        StoreValue::Color(v) => serde_json::Value::from(*v),
+
+        StoreValue::Table(entries) => {
+            let is_sequence = entries
+                .iter()
+                .enumerate()
+                .all(|(i, (k, _))| *k == TableKey::Int(i as i64 + 1));
+
+            if is_sequence {
+                serde_json::Value::Array(entries.iter().map(|(_, v)| to_json_value(v)).collect())
+            } else {
+                serde_json::Value::Object(
+                    entries
+                        .iter()
+                        .map(|(k, v)| {
+                            let key = match k {
+                                TableKey::Int(k) => format!("{}", k),
+                                TableKey::String(k) => k.clone(),
+                            };
+
+                            (key, to_json_value(v))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Convert a JSON value to a value that may be stored, arrays are converted to tables with
+/// integer keys starting at 1, objects are converted to tables with string keys
+pub fn from_json_value(value: &serde_json::Value, depth: usize) -> Result<StoreValue> {
+    if depth >= MAX_TABLE_DEPTH {
+        return Err(PersistencePluginError::TypeError {
+            description: "Tables are nested too deeply".to_owned(),
+        }
+        .into());
+    }
+
+    match value {
+        serde_json::Value::Bool(v) => Ok(StoreValue::Bool(*v)),
+        serde_json::Value::String(v) => Ok(StoreValue::String(v.clone())),
+
+        serde_json::Value::Number(v) => {
+            if let Some(v) = v.as_i64() {
+                Ok(StoreValue::Int(v))
+            } else {
+                Ok(StoreValue::Float(v.as_f64().unwrap_or_default()))
+            }
+        }
+
+        serde_json::Value::Array(v) => {
+            let mut entries = Vec::new();
+
+            for (i, value) in v.iter().enumerate() {
+                entries.push((
+                    TableKey::Int(i as i64 + 1),
+                    from_json_value(value, depth + 1)?,
+                ));
+            }
+
+            Ok(StoreValue::Table(entries))
+        }
+
+        serde_json::Value::Object(v) => {
+            let mut entries = Vec::new();
+
+            for (key, value) in v.iter() {
+                entries.push((
+                    TableKey::String(key.clone()),
+                    from_json_value(value, depth + 1)?,
+                ));
+            }
+
+            Ok(StoreValue::Table(sorted(entries)))
+        }
+
+        serde_json::Value::Null => Err(PersistencePluginError::TypeError {
+            description: "Null values can not be stored".to_owned(),
+        }
+        .into()),
+    }
+}
+
 pub struct PersistencePlugin {}
 
 macro_rules! store_operation {
     ($t:ident, $tval:ty, $sval:ty) => {
         paste::item! {
-            pub(crate) fn [<store_ $t>](key: String, value: $tval) -> Result<()> {
+            pub(crate) fn [<store_ $t>](namespace: &str, key: String, value: $tval) -> Result<()> {
                 GLOBAL_STORE
                     .write()
-                    .insert(key, $sval(value));
+                    .entry(namespace.to_owned())
+                    .or_insert_with(HashMap::new)
+                    .insert(key.clone(), $sval(value));
+
+                mark_modified(namespace, &key);
+
                 Ok(())
             }
         }
@@ -79,8 +414,8 @@ macro_rules! store_operation {
 macro_rules! load_operation {
     ($t:ident, $tval:ty, $sval:ty) => {
         paste::item! {
-            pub(crate) fn [<load_ $t>](key: &str) -> Result<$tval> {
-                match GLOBAL_STORE.read().get(key) {
+            pub(crate) fn [<load_ $t>](namespace: &str, key: &str) -> Result<$tval> {
+                match GLOBAL_STORE.read().get(namespace).and_then(|n| n.get(key)) {
                     Some(value) => {
                         if let $sval(val) = value {
                             Ok(val.clone())
@@ -103,10 +438,13 @@ macro_rules! load_operation {
 macro_rules! store_transient_operation {
     ($t:ident, $tval:ty, $sval:ty) => {
         paste::item! {
-            pub(crate) fn [<store_ $t _transient>](key: String, value: $tval) -> Result<()> {
+            pub(crate) fn [<store_ $t _transient>](namespace: &str, key: String, value: $tval) -> Result<()> {
                 GLOBAL_EPHEMERAL_STORE
                     .write()
+                    .entry(namespace.to_owned())
+                    .or_insert_with(HashMap::new)
                     .insert(key, $sval(value));
+
                 Ok(())
             }
         }
@@ -116,8 +454,8 @@ macro_rules! store_transient_operation {
 macro_rules! load_transient_operation {
     ($t:ident, $tval:ty, $sval:ty) => {
         paste::item! {
-            pub(crate) fn [<load_ $t _transient>](key: &str) -> Result<$tval> {
-                match GLOBAL_EPHEMERAL_STORE.read().get(key) {
+            pub(crate) fn [<load_ $t _transient>](namespace: &str, key: &str) -> Result<$tval> {
+                match GLOBAL_EPHEMERAL_STORE.read().get(namespace).and_then(|n| n.get(key)) {
                     Some(value) => {
                         if let $sval(val) = value {
                             Ok(val.clone())
@@ -142,36 +480,260 @@ impl PersistencePlugin {
         PersistencePlugin {}
     }
 
-    /// Stores the state of the persistence layer to disk
+    fn store_file_path() -> PathBuf {
+        PathBuf::from(constants::STATE_DIR).join(&PathBuf::from("persistent.store"))
+    }
+
+    /// Stores the state of the persistence layer to disk. The file is replaced
+    /// atomically, so a crash during the write does not corrupt the store
     pub fn store_persistent_data() -> Result<()> {
+        if !PERSISTENCE_ENABLED.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
         info!("Storing persistent state data to disk...");
 
-        let json_string = serde_json::to_string_pretty(&*GLOBAL_STORE.read())?;
+        let json_string = {
+            let store = GLOBAL_STORE.read();
+
+            serde_json::to_string_pretty(&StoreFile {
+                version: STORE_FILE_VERSION,
+                namespaces: store.clone(),
+                legacy_keys: LEGACY_KEYS.read().clone(),
+            })?
+        };
+
+        let path = Self::store_file_path();
+        let tmp_path = path.with_extension("store.tmp");
 
-        let path = PathBuf::from(constants::STATE_DIR).join(&PathBuf::from("persistent.store"));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(json_string.as_bytes())?;
+        file.sync_all()?;
 
-        fs::write(&path, json_string)?;
+        fs::rename(&tmp_path, &path)?;
 
         Ok(())
     }
 
-    /// Loads the state of the persistence layer from disk
+    /// Parse the contents of the store file, returns the namespaces and the keys that have
+    /// been migrated from a store of a previous version
+    fn parse_store(
+        json_string: &str,
+    ) -> Result<(
+        HashMap<String, HashMap<String, StoreValue>>,
+        BTreeSet<String>,
+    )> {
+        match serde_json::from_str::<StoreFile>(json_string) {
+            Ok(store) => Ok((store.namespaces, store.legacy_keys)),
+
+            Err(_) => {
+                // stores written by previous versions have no namespaces, so make their values global
+                let legacy: HashMap<String, LegacyStoreValue> = serde_json::from_str(json_string)?;
+
+                info!("Migrating the persistent store to the current format");
+
+                let legacy_keys = legacy.keys().cloned().collect();
+
+                let mut namespaces = HashMap::new();
+                namespaces.insert(
+                    GLOBAL_NAMESPACE.to_string(),
+                    legacy.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                );
+
+                Ok((namespaces, legacy_keys))
+            }
+        }
+    }
+
+    /// Loads the state of the persistence layer from disk, and starts writing modifications
+    /// back to disk. Nothing is written if the store exists but could not be read, and a store
+    /// that could not be parsed is moved aside, so that it won't be replaced by an empty store
     pub fn load_persistent_data() -> Result<()> {
         info!("Loading persistent state data from disk...");
 
-        let path = PathBuf::from(constants::STATE_DIR).join(&PathBuf::from("persistent.store"));
+        let path = Self::store_file_path();
 
-        let json_string = fs::read_to_string(&path)?;
+        let json_string = match fs::read_to_string(&path) {
+            Ok(json_string) => json_string,
 
-        let map: HashMap<String, StoreValue> = serde_json::from_str(&json_string)?;
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("The persistent store does not exist yet");
+
+                return Self::start_writer_thread();
+            }
+
+            Err(e) => return Err(e.into()),
+        };
+
+        let (namespaces, legacy_keys) = match Self::parse_store(&json_string) {
+            Ok(result) => result,
+
+            Err(e) => {
+                let bad_path = path.with_extension("store.bad");
+                fs::rename(&path, &bad_path)?;
+
+                error!(
+                    "Could not parse the persistent store, it has been moved to: {}",
+                    bad_path.display()
+                );
+
+                Self::start_writer_thread()?;
+
+                return Err(e);
+            }
+        };
 
         {
-            *GLOBAL_STORE.write() = map;
+            *GLOBAL_STORE.write() = namespaces;
+            *LEGACY_KEYS.write() = legacy_keys;
         }
 
+        Self::start_writer_thread()
+    }
+
+    /// Write modifications of the store to disk, after they settled down for a while
+    fn start_writer_thread() -> Result<()> {
+        if PERSISTENCE_ENABLED.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let builder = thread::Builder::new().name("persistence".into());
+        builder.spawn(move || {
+            let mut written_generation = STORE_GENERATION.load(Ordering::SeqCst);
+            let mut pending_since: Option<Instant> = None;
+
+            loop {
+                thread::sleep(Duration::from_millis(250));
+
+                let generation = STORE_GENERATION.load(Ordering::SeqCst);
+
+                if generation == written_generation {
+                    continue;
+                }
+
+                let pending = *pending_since.get_or_insert_with(Instant::now);
+
+                if LAST_MODIFICATION.lock().elapsed()
+                    >= Duration::from_millis(WRITE_DEBOUNCE_MILLIS)
+                    || pending.elapsed() >= Duration::from_millis(MAX_WRITE_DELAY_MILLIS)
+                {
+                    written_generation = generation;
+                    pending_since = None;
+
+                    Self::store_persistent_data()
+                        .unwrap_or_else(|e| error!("Could not write persisted state: {}", e));
+                }
+            }
+        })?;
+
         Ok(())
     }
 
+    /// Stores a value of any type, e.g. a nested table
+    pub(crate) fn store_value(namespace: &str, key: String, value: StoreValue) -> Result<()> {
+        GLOBAL_STORE
+            .write()
+            .entry(namespace.to_owned())
+            .or_insert_with(HashMap::new)
+            .insert(key.clone(), value);
+
+        mark_modified(namespace, &key);
+
+        Ok(())
+    }
+
+    pub(crate) fn load_value(namespace: &str, key: &str) -> Option<StoreValue> {
+        GLOBAL_STORE
+            .read()
+            .get(namespace)
+            .and_then(|n| n.get(key))
+            .cloned()
+    }
+
+    /// Returns the namespaces and keys of the persistent store, optionally
+    /// restricted to the namespace `namespace`
+    pub(crate) fn list_keys(namespace: Option<&str>) -> Vec<(String, String)> {
+        let mut result: Vec<(String, String)> = GLOBAL_STORE
+            .read()
+            .iter()
+            .filter(|(ns, _)| namespace.map_or(true, |n| n == ns.as_str()))
+            .flat_map(|(ns, keys)| keys.keys().map(move |k| (ns.clone(), k.clone())))
+            .collect();
+
+        result.sort();
+
+        result
+    }
+
+    /// Removes a single key from the persistent store, returns true if the key existed
+    pub(crate) fn delete_key(namespace: &str, key: &str) -> bool {
+        let removed = GLOBAL_STORE
+            .write()
+            .get_mut(namespace)
+            .and_then(|n| n.remove(key))
+            .is_some();
+
+        if removed {
+            mark_modified(namespace, key);
+        }
+
+        removed
+    }
+
+    /// Removes all keys starting with `prefix` from the namespace `namespace`, or
+    /// from all namespaces if `namespace` is `None`. Returns the number of removed keys
+    pub(crate) fn reset(namespace: Option<&str>, prefix: &str) -> usize {
+        let mut removed = Vec::new();
+
+        {
+            let mut store = GLOBAL_STORE.write();
+
+            for (ns, keys) in store
+                .iter_mut()
+                .filter(|(ns, _)| namespace.map_or(true, |n| n == ns.as_str()))
+            {
+                keys.retain(|k, _| {
+                    if k.starts_with(prefix) {
+                        removed.push((ns.clone(), k.clone()));
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+
+            store.retain(|_, keys| !keys.is_empty());
+        }
+
+        for (ns, key) in removed.iter() {
+            mark_modified(ns, key);
+        }
+
+        removed.len()
+    }
+
+    pub(crate) fn store_value_transient(
+        namespace: &str,
+        key: String,
+        value: StoreValue,
+    ) -> Result<()> {
+        GLOBAL_EPHEMERAL_STORE
+            .write()
+            .entry(namespace.to_owned())
+            .or_insert_with(HashMap::new)
+            .insert(key, value);
+
+        Ok(())
+    }
+
+    pub(crate) fn load_value_transient(namespace: &str, key: &str) -> Option<StoreValue> {
+        GLOBAL_EPHEMERAL_STORE
+            .read()
+            .get(namespace)
+            .and_then(|n| n.get(key))
+            .cloned()
+    }
+
     // persistent data
     store_operation!(int, i64, StoreValue::Int);
     load_operation!(int, i64, StoreValue::Int);
@@ -188,13 +750,6 @@ impl PersistencePlugin {
     store_operation!(color, u32, StoreValue::Color);
     load_operation!(color, u32, StoreValue::Color);
 
-    // experimental
-    store_operation!(string_array, HashMap<i32, String>, StoreValue::Array);
-    load_operation!(string_array, HashMap<i32, String>, StoreValue::Array);
-
-    store_operation!(string_hash, HashMap<String, String>, StoreValue::Hash);
-    load_operation!(string_hash, HashMap<String, String>, StoreValue::Hash);
-
     // transient data
     store_transient_operation!(int, i64, StoreValue::Int);
     load_transient_operation!(int, i64, StoreValue::Int);
@@ -210,13 +765,6 @@ impl PersistencePlugin {
 
     store_transient_operation!(color, u32, StoreValue::Color);
     load_transient_operation!(color, u32, StoreValue::Color);
-
-    // experimental
-    store_transient_operation!(string_array, HashMap<i32, String>, StoreValue::Array);
-    load_transient_operation!(string_array, HashMap<i32, String>, StoreValue::Array);
-
-    store_transient_operation!(string_hash, HashMap<String, String>, StoreValue::Hash);
-    load_transient_operation!(string_hash, HashMap<String, String>, StoreValue::Hash);
 }
 
 #[async_trait::async_trait]
@@ -237,147 +785,180 @@ impl Plugin for PersistencePlugin {
         let globals = lua_ctx.globals();
 
         // persistent data
-        let store_int = lua_ctx.create_function(|_, (key, value): (String, i64)| {
-            PersistencePlugin::store_int(key, value).unwrap();
+        let store_int = lua_ctx.create_function(|lua, (key, value): (String, i64)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_int(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_int", store_int)?;
 
-        let load_int = lua_ctx.create_function(|_, (key, default): (String, i64)| {
-            match PersistencePlugin::load_int(&key) {
+        let load_int = lua_ctx.create_function(|lua, (key, default): (String, i64)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_int(&namespace, &key) {
                 Ok(result) => Ok(result),
                 Err(_e) => Ok(default),
             }
         })?;
         globals.set("load_int", load_int)?;
 
-        let store_float = lua_ctx.create_function(|_, (key, value): (String, f64)| {
-            PersistencePlugin::store_float(key, value).unwrap();
+        let store_float = lua_ctx.create_function(|lua, (key, value): (String, f64)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_float(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_float", store_float)?;
 
-        let load_float = lua_ctx.create_function(|_, (key, default): (String, f64)| {
-            match PersistencePlugin::load_float(&key) {
+        let load_float = lua_ctx.create_function(|lua, (key, default): (String, f64)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_float(&namespace, &key) {
                 Ok(result) => Ok(result),
                 Err(_e) => Ok(default),
             }
         })?;
         globals.set("load_float", load_float)?;
 
-        let store_bool = lua_ctx.create_function(|_, (key, value): (String, bool)| {
-            PersistencePlugin::store_bool(key, value).unwrap();
+        let store_bool = lua_ctx.create_function(|lua, (key, value): (String, bool)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_bool(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_bool", store_bool)?;
 
-        let load_bool = lua_ctx.create_function(|_, (key, default): (String, bool)| {
-            match PersistencePlugin::load_bool(&key) {
+        let load_bool = lua_ctx.create_function(|lua, (key, default): (String, bool)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_bool(&namespace, &key) {
                 Ok(result) => Ok(result),
                 Err(_e) => Ok(default),
             }
         })?;
         globals.set("load_bool", load_bool)?;
 
-        let store_string = lua_ctx.create_function(|_, (key, value): (String, String)| {
-            PersistencePlugin::store_string(key, value).unwrap();
+        let store_string = lua_ctx.create_function(|lua, (key, value): (String, String)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_string(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_string", store_string)?;
 
-        let load_string = lua_ctx.create_function(|_, (key, default): (String, String)| {
-            match PersistencePlugin::load_string(&key) {
+        let load_string = lua_ctx.create_function(|lua, (key, default): (String, String)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_string(&namespace, &key) {
                 Ok(result) => Ok(result),
                 Err(_e) => Ok(default),
             }
         })?;
         globals.set("load_string", load_string)?;
 
-        let store_color = lua_ctx.create_function(|_, (key, value): (String, u32)| {
-            PersistencePlugin::store_color(key, value).unwrap();
+        let store_color = lua_ctx.create_function(|lua, (key, value): (String, u32)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_color(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_color", store_color)?;
 
-        let load_color = lua_ctx.create_function(|_, (key, default): (String, u32)| {
-            match PersistencePlugin::load_color(&key) {
+        let load_color = lua_ctx.create_function(|lua, (key, default): (String, u32)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_color(&namespace, &key) {
                 Ok(result) => Ok(result),
                 Err(_e) => Ok(default),
             }
         })?;
         globals.set("load_color", load_color)?;
 
-        let store_string_array =
-            lua_ctx.create_function(|_, (key, value): (String, HashMap<i32, String>)| {
-                PersistencePlugin::store_string_array(key, value).unwrap();
-                Ok(())
-            })?;
-        globals.set("store_string_array", store_string_array)?;
+        let store_table = lua_ctx.create_function(|lua, (key, value): (String, LuaValue)| {
+            let (namespace, key) = namespaced_key(lua, &key);
 
-        let load_string_array =
-            lua_ctx.create_function(|_, (key, default): (String, HashMap<i32, String>)| {
-                match PersistencePlugin::load_string_array(&key) {
-                    Ok(result) => Ok(result),
-                    Err(_e) => Ok(default),
-                }
-            })?;
-        globals.set("load_string_array", load_string_array)?;
+            let value =
+                from_lua_value(value, 0).map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
 
-        let store_string_hash =
-            lua_ctx.create_function(|_, (key, value): (String, HashMap<String, String>)| {
-                PersistencePlugin::store_string_hash(key, value).unwrap();
-                Ok(())
-            })?;
-        globals.set("store_string_hash", store_string_hash)?;
+            PersistencePlugin::store_value(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
+            Ok(())
+        })?;
+        globals.set("store_table", store_table)?;
 
-        let load_string_hash =
-            lua_ctx.create_function(|_, (key, default): (String, HashMap<String, String>)| {
-                match PersistencePlugin::load_string_hash(&key) {
-                    Ok(result) => Ok(result),
-                    Err(_e) => Ok(default),
-                }
-            })?;
-        globals.set("load_string_hash", load_string_hash)?;
+        let load_table = lua_ctx.create_function(|lua, (key, default): (String, LuaValue)| {
+            let (namespace, key) = namespaced_key_for_load(lua, &key);
+
+            match PersistencePlugin::load_value(&namespace, &key) {
+                Some(value) => to_lua_value(lua, &value),
+                None => Ok(default),
+            }
+        })?;
+        globals.set("load_table", load_table)?;
 
         // transient data
-        let store_int_transient = lua_ctx.create_function(|_, (key, value): (String, i64)| {
-            PersistencePlugin::store_int_transient(key, value).unwrap();
+        let store_int_transient = lua_ctx.create_function(|lua, (key, value): (String, i64)| {
+            let (namespace, key) = namespaced_key(lua, &key);
+
+            PersistencePlugin::store_int_transient(&namespace, key, value)
+                .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
             Ok(())
         })?;
         globals.set("store_int_transient", store_int_transient)?;
 
-        let load_int_transient = lua_ctx.create_function(|_, (key, default): (String, i64)| {
-            match PersistencePlugin::load_int_transient(&key) {
-                Ok(result) => Ok(result),
-                Err(_e) => Ok(default),
-            }
-        })?;
+        let load_int_transient =
+            lua_ctx.create_function(|lua, (key, default): (String, i64)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                match PersistencePlugin::load_int_transient(&namespace, &key) {
+                    Ok(result) => Ok(result),
+                    Err(_e) => Ok(default),
+                }
+            })?;
         globals.set("load_int_transient", load_int_transient)?;
 
-        let store_float_transient = lua_ctx.create_function(|_, (key, value): (String, f64)| {
-            PersistencePlugin::store_float_transient(key, value).unwrap();
-            Ok(())
-        })?;
+        let store_float_transient =
+            lua_ctx.create_function(|lua, (key, value): (String, f64)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                PersistencePlugin::store_float_transient(&namespace, key, value)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
+                Ok(())
+            })?;
         globals.set("store_float_transient", store_float_transient)?;
 
         let load_float_transient =
-            lua_ctx.create_function(|_, (key, default): (String, f64)| {
-                match PersistencePlugin::load_float_transient(&key) {
+            lua_ctx.create_function(|lua, (key, default): (String, f64)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                match PersistencePlugin::load_float_transient(&namespace, &key) {
                     Ok(result) => Ok(result),
                     Err(_e) => Ok(default),
                 }
             })?;
         globals.set("load_float_transient", load_float_transient)?;
 
-        let store_bool_transient = lua_ctx.create_function(|_, (key, value): (String, bool)| {
-            PersistencePlugin::store_bool_transient(key, value).unwrap();
-            Ok(())
-        })?;
+        let store_bool_transient =
+            lua_ctx.create_function(|lua, (key, value): (String, bool)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                PersistencePlugin::store_bool_transient(&namespace, key, value)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
+                Ok(())
+            })?;
         globals.set("store_bool_transient", store_bool_transient)?;
 
         let load_bool_transient =
-            lua_ctx.create_function(|_, (key, default): (String, bool)| {
-                match PersistencePlugin::load_bool_transient(&key) {
+            lua_ctx.create_function(|lua, (key, default): (String, bool)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                match PersistencePlugin::load_bool_transient(&namespace, &key) {
                     Ok(result) => Ok(result),
                     Err(_e) => Ok(default),
                 }
@@ -385,83 +966,70 @@ impl Plugin for PersistencePlugin {
         globals.set("load_bool_transient", load_bool_transient)?;
 
         let store_string_transient =
-            lua_ctx.create_function(|_, (key, value): (String, String)| {
-                PersistencePlugin::store_string_transient(key, value).unwrap();
+            lua_ctx.create_function(|lua, (key, value): (String, String)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                PersistencePlugin::store_string_transient(&namespace, key, value)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
                 Ok(())
             })?;
         globals.set("store_string_transient", store_string_transient)?;
 
         let load_string_transient =
-            lua_ctx.create_function(|_, (key, default): (String, String)| {
-                match PersistencePlugin::load_string_transient(&key) {
+            lua_ctx.create_function(|lua, (key, default): (String, String)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                match PersistencePlugin::load_string_transient(&namespace, &key) {
                     Ok(result) => Ok(result),
                     Err(_e) => Ok(default),
                 }
             })?;
         globals.set("load_string_transient", load_string_transient)?;
 
-        let store_color_transient = lua_ctx.create_function(|_, (key, value): (String, u32)| {
-            PersistencePlugin::store_color_transient(key, value).unwrap();
-            Ok(())
-        })?;
+        let store_color_transient =
+            lua_ctx.create_function(|lua, (key, value): (String, u32)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                PersistencePlugin::store_color_transient(&namespace, key, value)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
+                Ok(())
+            })?;
         globals.set("store_color_transient", store_color_transient)?;
 
         let load_color_transient =
-            lua_ctx.create_function(|_, (key, default): (String, u32)| {
-                match PersistencePlugin::load_color_transient(&key) {
+            lua_ctx.create_function(|lua, (key, default): (String, u32)| {
+                let (namespace, key) = namespaced_key(lua, &key);
+
+                match PersistencePlugin::load_color_transient(&namespace, &key) {
                     Ok(result) => Ok(result),
                     Err(_e) => Ok(default),
                 }
             })?;
         globals.set("load_color_transient", load_color_transient)?;
 
-        let store_string_array_transient =
-            lua_ctx.create_function(|_, (key, value): (String, HashMap<i32, String>)| {
-                PersistencePlugin::store_string_array_transient(key, value).unwrap();
-                Ok(())
-            })?;
-        globals.set("store_string_array_transient", store_string_array_transient)?;
+        let store_table_transient =
+            lua_ctx.create_function(|lua, (key, value): (String, LuaValue)| {
+                let (namespace, key) = namespaced_key(lua, &key);
 
-        let load_string_array_transient =
-            lua_ctx.create_function(|_, (key, default): (String, HashMap<i32, String>)| {
-                match PersistencePlugin::load_string_array_transient(&key) {
-                    Ok(result) => Ok(result),
-                    Err(_e) => Ok(default),
-                }
-            })?;
-        globals.set("load_string_array_transient", load_string_array_transient)?;
+                let value = from_lua_value(value, 0)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
 
-        let store_string_array_transient =
-            lua_ctx.create_function(|_, (key, value): (String, HashMap<i32, String>)| {
-                PersistencePlugin::store_string_array_transient(key, value).unwrap();
+                PersistencePlugin::store_value_transient(&namespace, key, value)
+                    .map_err(|e| LuaError::RuntimeError(format!("{}", e)))?;
                 Ok(())
             })?;
-        globals.set("store_string_array_transient", store_string_array_transient)?;
-
-        let load_string_array_transient =
-            lua_ctx.create_function(|_, (key, default): (String, HashMap<i32, String>)| {
-                match PersistencePlugin::load_string_array_transient(&key) {
-                    Ok(result) => Ok(result),
-                    Err(_e) => Ok(default),
-                }
-            })?;
-        globals.set("load_string_array_transient", load_string_array_transient)?;
+        globals.set("store_table_transient", store_table_transient)?;
 
-        let store_string_hash_transient =
-            lua_ctx.create_function(|_, (key, value): (String, HashMap<String, String>)| {
-                PersistencePlugin::store_string_hash_transient(key, value).unwrap();
-                Ok(())
-            })?;
-        globals.set("store_string_hash_transient", store_string_hash_transient)?;
+        let load_table_transient =
+            lua_ctx.create_function(|lua, (key, default): (String, LuaValue)| {
+                let (namespace, key) = namespaced_key(lua, &key);
 
-        let load_string_hash_transient =
-            lua_ctx.create_function(|_, (key, default): (String, HashMap<String, String>)| {
-                match PersistencePlugin::load_string_hash_transient(&key) {
-                    Ok(result) => Ok(result),
-                    Err(_e) => Ok(default),
+                match PersistencePlugin::load_value_transient(&namespace, &key) {
+                    Some(value) => to_lua_value(lua, &value),
+                    None => Ok(default),
                 }
             })?;
-        globals.set("load_string_hash_transient", load_string_hash_transient)?;
+        globals.set("load_table_transient", load_table_transient)?;
 
         Ok(())
     }
@@ -478,3 +1046,248 @@ impl Plugin for PersistencePlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: Vec<(TableKey, StoreValue)>) -> StoreValue {
+        StoreValue::Table(entries)
+    }
+
+    /// Returns a table that is nested `depth` times
+    fn nested(depth: usize) -> serde_json::Value {
+        (0..depth).fold(serde_json::json!(1), |value, _| serde_json::json!([value]))
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = table(vec![
+            (TableKey::Int(1), StoreValue::Int(-3)),
+            (TableKey::Int(2), StoreValue::Float(0.5)),
+            (TableKey::Int(3), StoreValue::Bool(true)),
+            (
+                TableKey::Int(4),
+                table(vec![
+                    (TableKey::String("a".into()), StoreValue::String("x".into())),
+                    (TableKey::String("b".into()), table(vec![])),
+                ]),
+            ),
+        ]);
+
+        let json = to_json_value(&value);
+        assert_eq!(
+            json,
+            serde_json::json!([-3, 0.5, true, { "a": "x", "b": [] }])
+        );
+
+        assert_eq!(from_json_value(&json, 0).unwrap(), value);
+    }
+
+    #[test]
+    fn test_to_json_value() {
+        // colors are plain integers
+        assert_eq!(
+            to_json_value(&StoreValue::Color(0xff00ff00)),
+            serde_json::json!(0xff00ff00u32)
+        );
+
+        // tables that are not sequences starting at 1 become objects
+        let value = table(vec![
+            (TableKey::Int(2), StoreValue::Int(2)),
+            (TableKey::Int(3), StoreValue::Int(3)),
+        ]);
+
+        assert_eq!(to_json_value(&value), serde_json::json!({ "2": 2, "3": 3 }));
+    }
+
+    #[test]
+    fn test_from_json_value() {
+        // object keys are sorted, so that the representation is stable
+        assert_eq!(
+            from_json_value(&serde_json::json!({ "b": 1, "a": 2 }), 0).unwrap(),
+            table(vec![
+                (TableKey::String("a".into()), StoreValue::Int(2)),
+                (TableKey::String("b".into()), StoreValue::Int(1)),
+            ])
+        );
+
+        assert!(from_json_value(&serde_json::Value::Null, 0).is_err());
+        assert!(from_json_value(&serde_json::json!([1, null]), 0).is_err());
+
+        assert!(from_json_value(&nested(MAX_TABLE_DEPTH - 1), 0).is_ok());
+        assert!(from_json_value(&nested(MAX_TABLE_DEPTH), 0).is_err());
+    }
+
+    #[test]
+    fn test_lua_round_trip() {
+        let lua = Lua::new();
+
+        let value: LuaValue = lua
+            .load("return { 1, 2.5, 'three', { four = true }, [10] = 10, [2.0^3] = 8 }")
+            .eval()
+            .unwrap();
+
+        let value = from_lua_value(value, 0).unwrap();
+
+        assert_eq!(
+            value,
+            table(vec![
+                (TableKey::Int(1), StoreValue::Int(1)),
+                (TableKey::Int(2), StoreValue::Float(2.5)),
+                (TableKey::Int(3), StoreValue::String("three".into())),
+                (
+                    TableKey::Int(4),
+                    table(vec![(
+                        TableKey::String("four".into()),
+                        StoreValue::Bool(true)
+                    )])
+                ),
+                (TableKey::Int(8), StoreValue::Int(8)),
+                (TableKey::Int(10), StoreValue::Int(10)),
+            ])
+        );
+
+        let lua_value = to_lua_value(&lua, &value).unwrap();
+        assert_eq!(from_lua_value(lua_value, 0).unwrap(), value);
+    }
+
+    #[test]
+    fn test_from_lua_value_rejects_invalid_values() {
+        let lua = Lua::new();
+
+        for chunk in [
+            "return function() end",
+            "return { f = function() end }",
+            "return { [true] = 1 }",
+            "return { [1.5] = 1 }",
+        ]
+        .iter()
+        {
+            let value: LuaValue = lua.load(*chunk).eval().unwrap();
+
+            assert!(from_lua_value(value, 0).is_err(), "{}", chunk);
+        }
+
+        let nested = |depth: usize| -> LuaValue {
+            lua.load(&format!(
+                "return {}1{}",
+                "{".repeat(depth),
+                "}".repeat(depth)
+            ))
+            .eval()
+            .unwrap()
+        };
+
+        assert!(from_lua_value(nested(MAX_TABLE_DEPTH), 0).is_ok());
+        assert!(from_lua_value(nested(MAX_TABLE_DEPTH + 1), 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_store() {
+        let json = r#"{
+            "version": 2,
+            "namespaces": { "global": { "a": { "Int": 1 } } },
+            "legacy_keys": ["a"]
+        }"#;
+
+        let (namespaces, legacy_keys) = PersistencePlugin::parse_store(json).unwrap();
+
+        assert_eq!(namespaces["global"]["a"], StoreValue::Int(1));
+        assert!(legacy_keys.contains("a"));
+
+        assert!(PersistencePlugin::parse_store("{ \"version\": 2").is_err());
+        assert!(PersistencePlugin::parse_store("[]").is_err());
+    }
+
+    #[test]
+    fn test_migrate_legacy_store() {
+        let json = r#"{
+            "count": { "Int": 3 },
+            "color": { "Color": 4278190335 },
+            "list": { "Array": { "2": "b", "1": "a" } },
+            "map": { "Hash": { "y": "2", "x": "1" } }
+        }"#;
+
+        let (namespaces, legacy_keys) = PersistencePlugin::parse_store(json).unwrap();
+
+        // values of previous versions are made global, and their keys are remembered
+        assert_eq!(namespaces.len(), 1);
+        assert_eq!(
+            legacy_keys.into_iter().collect::<Vec<_>>(),
+            vec!["color", "count", "list", "map"]
+        );
+
+        let global = &namespaces[GLOBAL_NAMESPACE];
+
+        assert_eq!(global["count"], StoreValue::Int(3));
+        assert_eq!(global["color"], StoreValue::Color(0xff0000ff));
+
+        assert_eq!(
+            global["list"],
+            table(vec![
+                (TableKey::Int(1), StoreValue::String("a".into())),
+                (TableKey::Int(2), StoreValue::String("b".into())),
+            ])
+        );
+
+        assert_eq!(
+            global["map"],
+            table(vec![
+                (TableKey::String("x".into()), StoreValue::String("1".into())),
+                (TableKey::String("y".into()), StoreValue::String("2".into())),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_store_management() {
+        // the store is shared by all tests, so use namespaces of this test only
+        let a = "test-store-management/a.lua";
+        let b = "test-store-management/b.lua";
+
+        for (namespace, key) in [(a, "x"), (a, "prefix.y"), (a, "prefix.z"), (b, "prefix.y")].iter()
+        {
+            PersistencePlugin::store_value(namespace, key.to_string(), StoreValue::Int(1)).unwrap();
+        }
+
+        let changed = take_changed_keys();
+        assert!(changed.contains(&(a.to_string(), "prefix.z".to_string())));
+        assert!(changed.contains(&(b.to_string(), "prefix.y".to_string())));
+
+        assert_eq!(
+            PersistencePlugin::list_keys(Some(a)),
+            vec![
+                (a.to_string(), "prefix.y".to_string()),
+                (a.to_string(), "prefix.z".to_string()),
+                (a.to_string(), "x".to_string()),
+            ]
+        );
+
+        assert!(PersistencePlugin::delete_key(a, "x"));
+        assert!(!PersistencePlugin::delete_key(a, "x"));
+        assert!(!PersistencePlugin::delete_key(
+            "test-store-management/c.lua",
+            "x"
+        ));
+
+        assert_eq!(PersistencePlugin::load_value(a, "x"), None);
+        assert!(take_changed_keys().contains(&(a.to_string(), "x".to_string())));
+
+        // reset only the keys with the prefix, in a single namespace
+        assert_eq!(PersistencePlugin::reset(Some(a), "prefix."), 2);
+        assert!(PersistencePlugin::list_keys(Some(a)).is_empty());
+
+        assert_eq!(
+            PersistencePlugin::load_value(b, "prefix.y"),
+            Some(StoreValue::Int(1))
+        );
+
+        assert_eq!(PersistencePlugin::reset(Some(b), "other."), 0);
+        assert_eq!(PersistencePlugin::reset(Some(b), ""), 1);
+        assert!(PersistencePlugin::list_keys(Some(b)).is_empty());
+
+        // empty namespaces are removed
+        assert!(!GLOBAL_STORE.read().contains_key(a));
+    }
+}
@@ -16,18 +16,22 @@
 */
 
 use lazy_static::lazy_static;
-// use log::*;
+use log::*;
 use mlua::prelude::*;
 use parking_lot::Mutex;
 use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use sysinfo::{ComponentExt, RefreshKind, SystemExt};
 
 use crate::plugins;
 use crate::plugins::Plugin;
 
-// pub type Result<T> = std::result::Result<T, eyre::Error>;
+pub type Result<T> = std::result::Result<T, eyre::Error>;
 
 // #[derive(Debug, Fail)]
 // pub enum SensorsPluginError {
@@ -41,6 +45,347 @@ lazy_static! {
 
     /// System state and sensor information
     static ref SYSTEM: Arc<Mutex<sysinfo::System>> = Arc::new(Mutex::new(sysinfo::System::new_with_specifics(RefreshKind::default().with_components().with_memory())));
+
+    /// Sensor information gathered from /proc and /sys
+    static ref EXTENDED_SENSORS: Arc<Mutex<ExtendedSensors>> = Arc::new(Mutex::new(ExtendedSensors::default()));
+}
+
+/// Size of a sector in /proc/diskstats, independent of the actual sector size of the device
+const DISKSTATS_SECTOR_SIZE: u64 = 512;
+
+/// Directory of the power supplies, e.g. batteries
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Directory of the hardware monitoring chips
+const HWMON_DIR: &str = "/sys/class/hwmon";
+
+/// Cumulative time counters of a CPU core, in USER_HZ
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// Cumulative counters of a network interface or a block device, in bytes
+#[derive(Debug, Default, Clone, Copy)]
+struct IoCounters {
+    read: u64,
+    written: u64,
+}
+
+/// Throughput of a network interface or a block device, in bytes per second
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoRates {
+    pub read: f64,
+    pub written: f64,
+}
+
+/// State of the (first) battery of the system
+#[derive(Debug, Default, Clone)]
+pub struct BatteryState {
+    pub level: u8,
+    pub status: String,
+}
+
+/// Sensor values that are not provided by `sysinfo`, rates are computed from
+/// the difference of the counters of two consecutive refreshes
+#[derive(Debug, Default)]
+struct ExtendedSensors {
+    last_refresh: Option<Instant>,
+
+    cpu_times: Vec<CpuTimes>,
+    cpu_usage: Vec<f32>,
+    cpu_usage_total: f32,
+
+    net_counters: HashMap<String, IoCounters>,
+    net_rates: HashMap<String, IoRates>,
+
+    disk_counters: HashMap<String, IoCounters>,
+    disk_rates: HashMap<String, IoRates>,
+
+    battery: Option<BatteryState>,
+
+    hwmon: HashMap<(String, String), f64>,
+}
+
+impl ExtendedSensors {
+    fn refresh(&mut self) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_refresh
+            .map(|t| now.duration_since(t).as_secs_f64())
+            .unwrap_or(0.0);
+
+        self.last_refresh = Some(now);
+
+        match read_cpu_times() {
+            Ok((total, cores)) => {
+                self.cpu_usage_total = cpu_usage(self.cpu_times.first(), &total);
+                self.cpu_usage = cores
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cur)| cpu_usage(self.cpu_times.get(i + 1), cur))
+                    .collect();
+
+                self.cpu_times = std::iter::once(total).chain(cores.into_iter()).collect();
+            }
+
+            Err(e) => debug!("Could not read CPU statistics: {}", e),
+        }
+
+        match read_net_counters() {
+            Ok(counters) => {
+                self.net_rates = compute_rates(&self.net_counters, &counters, elapsed);
+                self.net_counters = counters;
+            }
+
+            Err(e) => debug!("Could not read network statistics: {}", e),
+        }
+
+        match read_disk_counters() {
+            Ok(counters) => {
+                self.disk_rates = compute_rates(&self.disk_counters, &counters, elapsed);
+                self.disk_counters = counters;
+            }
+
+            Err(e) => debug!("Could not read disk statistics: {}", e),
+        }
+
+        self.battery = read_battery_state(Path::new(POWER_SUPPLY_DIR));
+        self.hwmon = read_hwmon_values(Path::new(HWMON_DIR));
+    }
+}
+
+/// Compute the CPU usage in percent from two consecutive samples of time counters
+fn cpu_usage(prev: Option<&CpuTimes>, cur: &CpuTimes) -> f32 {
+    match prev {
+        Some(prev) if cur.total > prev.total => {
+            (cur.busy.saturating_sub(prev.busy) as f32 / (cur.total - prev.total) as f32) * 100.0
+        }
+
+        _ => 0.0,
+    }
+}
+
+/// Compute throughput from two consecutive samples of counters, taken `elapsed` seconds apart
+fn compute_rates(
+    prev: &HashMap<String, IoCounters>,
+    cur: &HashMap<String, IoCounters>,
+    elapsed: f64,
+) -> HashMap<String, IoRates> {
+    cur.iter()
+        .filter_map(|(name, cur)| {
+            prev.get(name).filter(|_| elapsed > 0.0).map(|prev| {
+                (
+                    name.clone(),
+                    IoRates {
+                        read: cur.read.saturating_sub(prev.read) as f64 / elapsed,
+                        written: cur.written.saturating_sub(prev.written) as f64 / elapsed,
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+/// Read the cumulative time counters of all CPUs, and of each core from /proc/stat
+fn read_cpu_times() -> Result<(CpuTimes, Vec<CpuTimes>)> {
+    parse_cpu_times(&fs::read_to_string("/proc/stat")?)
+}
+
+/// Parse the cumulative time counters of all CPUs, and of each core, in the format of /proc/stat
+fn parse_cpu_times(stat: &str) -> Result<(CpuTimes, Vec<CpuTimes>)> {
+    let mut total = CpuTimes::default();
+    let mut cores = Vec::new();
+
+    for line in stat.lines().filter(|l| l.starts_with("cpu")) {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+
+        // user nice system idle iowait irq softirq steal (guest time is included in user time)
+        let values = fields
+            .take(8)
+            .map(|v| v.parse::<u64>())
+            .collect::<std::result::Result<Vec<u64>, _>>()?;
+
+        let idle = values.get(3).copied().unwrap_or(0) + values.get(4).copied().unwrap_or(0);
+        let sum: u64 = values.iter().sum();
+
+        let times = CpuTimes {
+            busy: sum - idle,
+            total: sum,
+        };
+
+        if name == "cpu" {
+            total = times;
+        } else {
+            cores.push(times);
+        }
+    }
+
+    Ok((total, cores))
+}
+
+/// Read the received and transmitted bytes of all network interfaces from /proc/net/dev
+fn read_net_counters() -> Result<HashMap<String, IoCounters>> {
+    parse_net_counters(&fs::read_to_string("/proc/net/dev")?)
+}
+
+/// Parse the received and transmitted bytes of all network interfaces, in the format of /proc/net/dev
+fn parse_net_counters(dev: &str) -> Result<HashMap<String, IoCounters>> {
+    let mut result = HashMap::new();
+
+    // skip the two header lines
+    for line in dev.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+
+        let name = parts.next().unwrap_or_default().trim();
+        let fields: Vec<&str> = parts
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+
+        if fields.len() >= 9 {
+            result.insert(
+                name.to_string(),
+                IoCounters {
+                    read: fields[0].parse()?,
+                    written: fields[8].parse()?,
+                },
+            );
+        }
+    }
+
+    Ok(result)
+}
+
+/// Read the bytes read from and written to all block devices from /proc/diskstats
+fn read_disk_counters() -> Result<HashMap<String, IoCounters>> {
+    parse_disk_counters(&fs::read_to_string("/proc/diskstats")?)
+}
+
+/// Parse the bytes read from and written to all block devices, in the format of /proc/diskstats
+fn parse_disk_counters(stats: &str) -> Result<HashMap<String, IoCounters>> {
+    let mut result = HashMap::new();
+
+    for line in stats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() >= 10 {
+            result.insert(
+                fields[2].to_string(),
+                IoCounters {
+                    read: fields[5].parse::<u64>()? * DISKSTATS_SECTOR_SIZE,
+                    written: fields[9].parse::<u64>()? * DISKSTATS_SECTOR_SIZE,
+                },
+            );
+        }
+    }
+
+    Ok(result)
+}
+
+/// Returns true if `name` is a whole disk, as opposed to a partition
+fn is_whole_disk(name: &str) -> bool {
+    Path::new("/sys/block").join(name).exists()
+}
+
+/// Returns true if `name` is the loopback network interface
+fn is_loopback(name: &str) -> bool {
+    name == "lo"
+}
+
+/// Read the charge level and status of the first battery from the power supply directory `dir`
+fn read_battery_state(dir: &Path) -> Option<BatteryState> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+
+    entries.sort();
+
+    entries.iter().find_map(|path| {
+        let kind = fs::read_to_string(path.join("type")).ok()?;
+
+        if kind.trim() == "Battery" {
+            let level = fs::read_to_string(path.join("capacity"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+
+            let status = fs::read_to_string(path.join("status"))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|_| "Unknown".to_string());
+
+            Some(BatteryState { level, status })
+        } else {
+            None
+        }
+    })
+}
+
+/// Read all `*_input` values of all hwmon chips from the hwmon directory `dir`. The values are keyed
+/// by the chip name and the name of the sensor (e.g. `fan1`), as well as by the label of the sensor,
+/// if any. Values are converted to degrees Celsius, RPM, Volts, Amperes, Watts or Joules
+fn read_hwmon_values(dir: &Path) -> HashMap<(String, String), f64> {
+    let mut result = HashMap::new();
+
+    let chips = match fs::read_dir(dir) {
+        Ok(chips) => chips,
+        Err(_) => return result,
+    };
+
+    for chip in chips.filter_map(|e| e.ok().map(|e| e.path())) {
+        let chip_name = match fs::read_to_string(chip.join("name")) {
+            Ok(name) => name.trim().to_string(),
+            Err(_) => continue,
+        };
+
+        let files = match fs::read_dir(&chip) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+
+        for file in files.filter_map(|e| e.ok().map(|e| e.path())) {
+            let file_name = file
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            if !file_name.ends_with("_input") {
+                continue;
+            }
+
+            let sensor = file_name.trim_end_matches("_input").to_string();
+
+            let raw: f64 = match fs::read_to_string(&file)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+            {
+                Some(raw) => raw,
+                None => continue,
+            };
+
+            let value = if sensor.starts_with("fan") {
+                raw
+            } else if sensor.starts_with("power") || sensor.starts_with("energy") {
+                raw / 1_000_000.0
+            } else {
+                // temp, in and curr
+                raw / 1000.0
+            };
+
+            if let Ok(label) = fs::read_to_string(chip.join(format!("{}_label", sensor))) {
+                result.insert((chip_name.clone(), label.trim().to_string()), value);
+            }
+
+            result.insert((chip_name.clone(), sensor), value);
+        }
+    }
+
+    result
 }
 
 /// A plugin that gives Lua scripts access to the systems sensor data
@@ -54,6 +399,9 @@ impl SensorsPlugin {
         system.refresh_components_list();
         system.refresh_components();
 
+        // take an initial sample, rates are available after the next refresh
+        EXTENDED_SENSORS.lock().refresh();
+
         SensorsPlugin {}
     }
 
@@ -63,6 +411,8 @@ impl SensorsPlugin {
 
         system.refresh_memory();
         system.refresh_components();
+
+        EXTENDED_SENSORS.lock().refresh();
     }
 
     /// Get the temperature of the CPU package
@@ -132,6 +482,142 @@ impl SensorsPlugin {
         let system = SYSTEM.lock();
         system.get_used_swap()
     }
+
+    /// Get the number of CPU cores
+    pub fn get_num_cores() -> usize {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        EXTENDED_SENSORS.lock().cpu_usage.len()
+    }
+
+    /// Get the utilization of all CPU cores in percent
+    pub fn get_cpu_usage() -> f32 {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        EXTENDED_SENSORS.lock().cpu_usage_total
+    }
+
+    /// Get the utilization of the CPU core `core` in percent
+    pub fn get_cpu_core_usage(core: usize) -> f32 {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        EXTENDED_SENSORS
+            .lock()
+            .cpu_usage
+            .get(core)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Get the names of all network interfaces
+    pub fn get_network_interfaces() -> Vec<String> {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let mut result: Vec<String> = EXTENDED_SENSORS
+            .lock()
+            .net_counters
+            .keys()
+            .cloned()
+            .collect();
+        result.sort();
+
+        result
+    }
+
+    /// Get the throughput of the network interface `interface` in bytes per second,
+    /// or the sum of all interfaces except the loopback interface
+    pub fn get_network_rates(interface: Option<&str>) -> IoRates {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let sensors = EXTENDED_SENSORS.lock();
+
+        match interface {
+            Some(interface) => sensors
+                .net_rates
+                .get(interface)
+                .copied()
+                .unwrap_or_default(),
+
+            None => sensors
+                .net_rates
+                .iter()
+                .filter(|(name, _)| !is_loopback(name))
+                .fold(IoRates::default(), |acc, (_, rates)| IoRates {
+                    read: acc.read + rates.read,
+                    written: acc.written + rates.written,
+                }),
+        }
+    }
+
+    /// Get the names of all block devices that are whole disks
+    pub fn get_disks() -> Vec<String> {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let mut result: Vec<String> = EXTENDED_SENSORS
+            .lock()
+            .disk_counters
+            .keys()
+            .filter(|name| is_whole_disk(name))
+            .cloned()
+            .collect();
+        result.sort();
+
+        result
+    }
+
+    /// Get the throughput of the block device `disk` in bytes per second,
+    /// or the sum of all whole disks
+    pub fn get_disk_rates(disk: Option<&str>) -> IoRates {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let sensors = EXTENDED_SENSORS.lock();
+
+        match disk {
+            Some(disk) => sensors.disk_rates.get(disk).copied().unwrap_or_default(),
+
+            None => sensors
+                .disk_rates
+                .iter()
+                .filter(|(name, _)| is_whole_disk(name))
+                .fold(IoRates::default(), |acc, (_, rates)| IoRates {
+                    read: acc.read + rates.read,
+                    written: acc.written + rates.written,
+                }),
+        }
+    }
+
+    /// Get the charge level and status of the battery, if the system has a battery
+    pub fn get_battery_state() -> Option<BatteryState> {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        EXTENDED_SENSORS.lock().battery.clone()
+    }
+
+    /// Get the value of the hwmon sensor `sensor` (name or label) of the chip `chip`
+    pub fn get_hwmon_value(chip: &str, sensor: &str) -> Option<f64> {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        EXTENDED_SENSORS
+            .lock()
+            .hwmon
+            .get(&(chip.to_string(), sensor.to_string()))
+            .copied()
+    }
+
+    /// Get the names of all hwmon sensors, in the form `chip/sensor`
+    pub fn get_hwmon_sensors() -> Vec<String> {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let mut result: Vec<String> = EXTENDED_SENSORS
+            .lock()
+            .hwmon
+            .keys()
+            .map(|(chip, sensor)| format!("{}/{}", chip, sensor))
+            .collect();
+        result.sort();
+
+        result
+    }
 }
 
 #[async_trait::async_trait]
@@ -175,6 +661,74 @@ impl Plugin for SensorsPlugin {
             lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_swap_used_kb()))?;
         globals.set("get_swap_used_kb", get_swap_used_kb)?;
 
+        let get_num_cores =
+            lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_num_cores()))?;
+        globals.set("get_num_cores", get_num_cores)?;
+
+        let get_cpu_usage =
+            lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_cpu_usage()))?;
+        globals.set("get_cpu_usage", get_cpu_usage)?;
+
+        let get_cpu_core_usage = lua_ctx
+            .create_function(move |_, core: usize| Ok(SensorsPlugin::get_cpu_core_usage(core)))?;
+        globals.set("get_cpu_core_usage", get_cpu_core_usage)?;
+
+        let get_network_interfaces =
+            lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_network_interfaces()))?;
+        globals.set("get_network_interfaces", get_network_interfaces)?;
+
+        let get_network_rx_rate =
+            lua_ctx.create_function(move |_, interface: Option<String>| {
+                Ok(SensorsPlugin::get_network_rates(interface.as_deref()).read)
+            })?;
+        globals.set("get_network_rx_rate", get_network_rx_rate)?;
+
+        let get_network_tx_rate =
+            lua_ctx.create_function(move |_, interface: Option<String>| {
+                Ok(SensorsPlugin::get_network_rates(interface.as_deref()).written)
+            })?;
+        globals.set("get_network_tx_rate", get_network_tx_rate)?;
+
+        let get_disks = lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_disks()))?;
+        globals.set("get_disks", get_disks)?;
+
+        let get_disk_read_rate = lua_ctx.create_function(move |_, disk: Option<String>| {
+            Ok(SensorsPlugin::get_disk_rates(disk.as_deref()).read)
+        })?;
+        globals.set("get_disk_read_rate", get_disk_read_rate)?;
+
+        let get_disk_write_rate = lua_ctx.create_function(move |_, disk: Option<String>| {
+            Ok(SensorsPlugin::get_disk_rates(disk.as_deref()).written)
+        })?;
+        globals.set("get_disk_write_rate", get_disk_write_rate)?;
+
+        let get_battery_level = lua_ctx.create_function(move |_, ()| {
+            Ok(SensorsPlugin::get_battery_state().map(|b| b.level))
+        })?;
+        globals.set("get_battery_level", get_battery_level)?;
+
+        let get_battery_status = lua_ctx.create_function(move |_, ()| {
+            Ok(SensorsPlugin::get_battery_state().map(|b| b.status))
+        })?;
+        globals.set("get_battery_status", get_battery_status)?;
+
+        let is_battery_charging = lua_ctx.create_function(move |_, ()| {
+            Ok(SensorsPlugin::get_battery_state()
+                .map(|b| b.status == "Charging")
+                .unwrap_or(false))
+        })?;
+        globals.set("is_battery_charging", is_battery_charging)?;
+
+        let get_hwmon_value =
+            lua_ctx.create_function(move |_, (chip, sensor): (String, String)| {
+                Ok(SensorsPlugin::get_hwmon_value(&chip, &sensor))
+            })?;
+        globals.set("get_hwmon_value", get_hwmon_value)?;
+
+        let get_hwmon_sensors =
+            lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_hwmon_sensors()))?;
+        globals.set("get_hwmon_sensors", get_hwmon_sensors)?;
+
         Ok(())
     }
 
@@ -196,3 +750,194 @@ impl Plugin for SensorsPlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-{}-{}", std::process::id(), name));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_parse_cpu_times() {
+        let stat = "cpu  100 10 50 800 40 5 5 0 20 0\n\
+                    cpu0 60 5 25 400 20 3 2 0 10 0\n\
+                    cpu1 40 5 25 400 20 2 3 0 10 0\n\
+                    intr 12345 0 0\n\
+                    ctxt 6789\n";
+
+        let (total, cores) = parse_cpu_times(stat).unwrap();
+
+        // idle and iowait are not busy, guest time is not counted twice
+        assert_eq!(total.total, 1010);
+        assert_eq!(total.busy, 170);
+
+        assert_eq!(cores.len(), 2);
+        assert_eq!(cores[0].total, 515);
+        assert_eq!(cores[0].busy, 95);
+        assert_eq!(cores[1].total, 495);
+        assert_eq!(cores[1].busy, 75);
+
+        assert!(parse_cpu_times("cpu  100 x 50 800\n").is_err());
+    }
+
+    #[test]
+    fn test_cpu_usage() {
+        let prev = CpuTimes {
+            busy: 100,
+            total: 1000,
+        };
+
+        let cur = CpuTimes {
+            busy: 150,
+            total: 1200,
+        };
+
+        assert!((cpu_usage(Some(&prev), &cur) - 25.0).abs() < f32::EPSILON);
+
+        // no previous sample, or the counters did not advance
+        assert_eq!(cpu_usage(None, &cur), 0.0);
+        assert_eq!(cpu_usage(Some(&cur), &cur), 0.0);
+    }
+
+    #[test]
+    fn test_parse_net_counters() {
+        let dev = "Inter-|   Receive                                                |  Transmit\n \
+                   face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    \
+                   lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0\n  \
+                   eth0: 5000000    4000    0    0    0     0          0         0   200000    1500    0    0    0     0       0          0\n";
+
+        let counters = parse_net_counters(dev).unwrap();
+
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters["lo"].read, 1000);
+        assert_eq!(counters["eth0"].read, 5_000_000);
+        assert_eq!(counters["eth0"].written, 200_000);
+
+        assert!(is_loopback("lo"));
+        assert!(!is_loopback("eth0"));
+    }
+
+    #[test]
+    fn test_parse_disk_counters() {
+        let stats = "   8       0 sda 1000 10 2048 500 400 20 4096 800 0 900 1300 0 0 0 0\n   \
+                        8       1 sda1 900 10 1024 450 300 20 512 700 0 800 1150 0 0 0 0\n";
+
+        let counters = parse_disk_counters(stats).unwrap();
+
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters["sda"].read, 2048 * DISKSTATS_SECTOR_SIZE);
+        assert_eq!(counters["sda"].written, 4096 * DISKSTATS_SECTOR_SIZE);
+        assert_eq!(counters["sda1"].read, 1024 * DISKSTATS_SECTOR_SIZE);
+        assert_eq!(counters["sda1"].written, 512 * DISKSTATS_SECTOR_SIZE);
+    }
+
+    #[test]
+    fn test_compute_rates() {
+        let mut prev = HashMap::new();
+        prev.insert(
+            "eth0".to_string(),
+            IoCounters {
+                read: 1000,
+                written: 500,
+            },
+        );
+
+        let mut cur = HashMap::new();
+        cur.insert(
+            "eth0".to_string(),
+            IoCounters {
+                read: 3000,
+                written: 1500,
+            },
+        );
+
+        // a device that appeared between the two samples
+        cur.insert(
+            "eth1".to_string(),
+            IoCounters {
+                read: 100,
+                written: 100,
+            },
+        );
+
+        let rates = compute_rates(&prev, &cur, 2.0);
+
+        assert_eq!(rates.len(), 1);
+        assert!((rates["eth0"].read - 1000.0).abs() < f64::EPSILON);
+        assert!((rates["eth0"].written - 500.0).abs() < f64::EPSILON);
+
+        // counters that were reset do not yield negative rates
+        let rates = compute_rates(&cur, &prev, 2.0);
+        assert_eq!(rates["eth0"].read, 0.0);
+
+        assert!(compute_rates(&prev, &cur, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_read_battery_state() {
+        let dir = test_dir("power_supply");
+
+        write(&dir.join("AC/type"), "Mains\n");
+        write(&dir.join("AC/online"), "1\n");
+
+        assert!(read_battery_state(&dir).is_none());
+
+        write(&dir.join("BAT0/type"), "Battery\n");
+        write(&dir.join("BAT0/capacity"), "87\n");
+        write(&dir.join("BAT0/status"), "Discharging\n");
+
+        let battery = read_battery_state(&dir).unwrap();
+
+        assert_eq!(battery.level, 87);
+        assert_eq!(battery.status, "Discharging");
+
+        assert!(read_battery_state(&dir.join("missing")).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_hwmon_values() {
+        let dir = test_dir("hwmon");
+
+        write(&dir.join("hwmon0/name"), "k10temp\n");
+        write(&dir.join("hwmon0/temp1_input"), "45500\n");
+        write(&dir.join("hwmon0/temp1_label"), "Tctl\n");
+
+        write(&dir.join("hwmon1/name"), "nct6775\n");
+        write(&dir.join("hwmon1/fan1_input"), "1200\n");
+        write(&dir.join("hwmon1/in0_input"), "1032\n");
+        write(&dir.join("hwmon1/power1_input"), "15000000\n");
+        write(&dir.join("hwmon1/fan1_min"), "300\n");
+        write(&dir.join("hwmon1/fan2_input"), "invalid\n");
+
+        // a chip without a name is skipped
+        write(&dir.join("hwmon2/temp1_input"), "30000\n");
+
+        let values = read_hwmon_values(&dir);
+        let value = |chip: &str, sensor: &str| values[&(chip.to_string(), sensor.to_string())];
+
+        assert_eq!(values.len(), 5);
+        assert!((value("k10temp", "temp1") - 45.5).abs() < f64::EPSILON);
+        assert!((value("k10temp", "Tctl") - 45.5).abs() < f64::EPSILON);
+        assert!((value("nct6775", "fan1") - 1200.0).abs() < f64::EPSILON);
+        assert!((value("nct6775", "in0") - 1.032).abs() < f64::EPSILON);
+        assert!((value("nct6775", "power1") - 15.0).abs() < f64::EPSILON);
+
+        assert!(read_hwmon_values(&dir.join("missing")).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
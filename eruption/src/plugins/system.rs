@@ -17,20 +17,166 @@
 
 use log::*;
 use mlua::prelude::*;
+use parking_lot::Mutex;
 use std::any::Any;
-
-use std::process::Command;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::plugins;
 use crate::plugins::Plugin;
 
-// pub type Result<T> = std::result::Result<T, eyre::Error>;
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SystemPluginError {
+    #[error("The command '{command}' is not on the allow-list")]
+    CommandNotAllowed { command: String },
+
+    #[error("Too many running processes")]
+    TooManyProcesses {},
+}
+
+/// Kill spawned processes after this amount of time, if no timeout has been specified
+const DEFAULT_PROCESS_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Max. number of bytes of stdout and stderr that will be captured, each
+const MAX_PROCESS_OUTPUT_BYTES: u64 = 64 * 1024;
+
+/// Max. number of concurrently running processes, spawned by all Lua scripts
+const MAX_RUNNING_PROCESSES: usize = 16;
+
+/// Poll spawned processes for termination every n milliseconds
+const PROCESS_POLL_MILLIS: u64 = 10;
+
+/// Time to wait for the output of killed processes, processes that left the process group may keep it open
+const PROCESS_KILL_GRACE_MILLIS: u64 = 100;
+
+/// Id of the next spawned process, ids are never reused
+static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Number of currently running processes
+static RUNNING_PROCESSES: AtomicUsize = AtomicUsize::new(0);
+
+/// The result of a process that has been spawned by a Lua script
+#[derive(Debug, Clone)]
+pub struct ProcessResult {
+    pub id: u64,
 
-// #[derive(Debug, Fail)]
-// pub enum SystemPluginError {
-//     #[error("Unknown error: {}", description)]
-//     UnknownError { description: String },
-// }
+    /// The exit code, or `None` if the process has been terminated by a signal or the timeout
+    pub code: Option<i32>,
+
+    pub stdout: String,
+    pub stderr: String,
+}
+
+thread_local! {
+    /// Results of processes spawned by the Lua VM of this thread, that have not been delivered yet
+    static PROCESS_RESULTS: Arc<Mutex<Vec<ProcessResult>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Returns the results of all processes spawned by the Lua VM of the calling thread,
+/// that terminated since the last call
+pub fn take_process_results() -> Vec<ProcessResult> {
+    PROCESS_RESULTS.with(|results| results.lock().drain(..).collect())
+}
+
+/// Returns true if `command` may be spawned by Lua scripts
+fn is_command_allowed(command: &str) -> bool {
+    let config = crate::CONFIG.lock();
+
+    let allow_list = config
+        .as_ref()
+        .and_then(|c| c.get_array("processes.allow_list").ok())
+        .unwrap_or_else(Vec::new);
+
+    let allow_list: Vec<String> = allow_list
+        .into_iter()
+        .filter_map(|v| v.into_str().ok())
+        .collect();
+
+    is_on_allow_list(&allow_list, command)
+}
+
+/// Returns true if `command` is on `allow_list`. Entries of the allow-list that contain a slash
+/// must match the command exactly, all other entries match commands that are looked up in PATH
+fn is_on_allow_list(allow_list: &[String], command: &str) -> bool {
+    allow_list.iter().any(|entry| {
+        if entry.contains('/') {
+            Path::new(entry) == Path::new(command)
+        } else {
+            !command.contains('/') && entry == command
+        }
+    })
+}
+
+/// Read up to `MAX_PROCESS_OUTPUT_BYTES` from `reader`, discarding the rest
+fn capture_output<R: Read>(mut reader: R) -> String {
+    let mut buffer = Vec::new();
+
+    (&mut reader)
+        .take(MAX_PROCESS_OUTPUT_BYTES)
+        .read_to_end(&mut buffer)
+        .unwrap_or_else(|e| {
+            warn!("Could not read the output of a process: {}", e);
+            0
+        });
+
+    // drain the pipe, so that the process does not block on a full pipe
+    io::copy(&mut reader, &mut io::sink()).ok();
+
+    String::from_utf8_lossy(&buffer).to_string()
+}
+
+/// Kill all processes of the process group `pgid`
+fn kill_process_group(pgid: u32) {
+    unsafe {
+        libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Returns true if the child process `pid` has terminated, without reaping it. The id of the
+/// process group of the child can not be reused, as long as the child has not been reaped
+fn has_terminated(pid: u32) -> io::Result<bool> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+
+    let result = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+
+    if result == 0 {
+        // the siginfo is left zeroed, if the child has not terminated yet
+        Ok(info.si_signo != 0)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Wait for the output captured by a reader thread until `deadline`. Children of the spawned
+/// process may keep the output open after the process has exited, so all processes of the
+/// process group `pgid` are killed when the deadline has passed
+fn collect_output(rx: &mpsc::Receiver<String>, deadline: Instant, pgid: u32) -> String {
+    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output,
+
+        Err(_) => {
+            kill_process_group(pgid);
+
+            rx.recv_timeout(Duration::from_millis(PROCESS_KILL_GRACE_MILLIS))
+                .unwrap_or_default()
+        }
+    }
+}
 
 /// A plugin that gives Lua scripts access to the systems state like e.g.
 /// the number of runnable processes or the load average
@@ -91,6 +237,135 @@ impl SystemPlugin {
             .tasks_total
     }
 
+    /// Spawn a process asynchronously, it is killed after `timeout_millis` milliseconds.
+    /// Returns the id of the process, the result will be delivered to the Lua VM of the
+    /// calling thread via `take_process_results()`
+    pub(crate) fn spawn_process(
+        command: &str,
+        args: &[String],
+        timeout_millis: Option<u64>,
+    ) -> Result<u64> {
+        if !is_command_allowed(command) {
+            return Err(SystemPluginError::CommandNotAllowed {
+                command: command.to_owned(),
+            }
+            .into());
+        }
+
+        if RUNNING_PROCESSES.fetch_add(1, Ordering::SeqCst) >= MAX_RUNNING_PROCESSES {
+            RUNNING_PROCESSES.fetch_sub(1, Ordering::SeqCst);
+
+            return Err(SystemPluginError::TooManyProcesses {}.into());
+        }
+
+        let mut process = Command::new(command);
+
+        process
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // run the process in a process group of its own, so that it may be killed along with its children
+        unsafe {
+            process.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+
+        let child = process.spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+
+            Err(e) => {
+                RUNNING_PROCESSES.fetch_sub(1, Ordering::SeqCst);
+
+                return Err(e.into());
+            }
+        };
+
+        let id = NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst);
+        let results = PROCESS_RESULTS.with(|results| results.clone());
+        let timeout =
+            Duration::from_millis(timeout_millis.unwrap_or(DEFAULT_PROCESS_TIMEOUT_MILLIS));
+
+        let pgid = child.id();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let builder = thread::Builder::new().name(format!("process:{}", id));
+        let result = builder.spawn(move || {
+            let (stdout_tx, stdout_rx) = mpsc::channel();
+            let (stderr_tx, stderr_rx) = mpsc::channel();
+
+            thread::spawn(move || stdout_tx.send(capture_output(stdout)).ok());
+            thread::spawn(move || stderr_tx.send(capture_output(stderr)).ok());
+
+            let deadline = Instant::now() + timeout;
+
+            loop {
+                match has_terminated(pgid) {
+                    Ok(true) => break,
+
+                    Ok(false) => {
+                        if Instant::now() >= deadline {
+                            warn!(
+                                "Process {} timed out after {} milliseconds, killing it",
+                                id,
+                                timeout.as_millis()
+                            );
+
+                            kill_process_group(pgid);
+                            break;
+                        }
+
+                        thread::sleep(Duration::from_millis(PROCESS_POLL_MILLIS));
+                    }
+
+                    Err(e) => {
+                        error!("Could not wait for process {}: {}", id, e);
+
+                        kill_process_group(pgid);
+                        break;
+                    }
+                }
+            }
+
+            let stdout = collect_output(&stdout_rx, deadline, pgid);
+            let stderr = collect_output(&stderr_rx, deadline, pgid);
+
+            // reap the process only after the output has been collected, collecting
+            // the output may have to kill the process group, whose id is reserved
+            // until the process has been reaped
+            let status = child.wait().ok();
+
+            let result = ProcessResult {
+                id,
+                code: status.and_then(|s| s.code()),
+                stdout,
+                stderr,
+            };
+
+            RUNNING_PROCESSES.fetch_sub(1, Ordering::SeqCst);
+
+            results.lock().push(result);
+        });
+
+        if let Err(e) = result {
+            kill_process_group(pgid);
+            RUNNING_PROCESSES.fetch_sub(1, Ordering::SeqCst);
+
+            return Err(e.into());
+        }
+
+        Ok(id)
+    }
+
     /// Execute a shell command
     pub(crate) fn system(command: &str, args: &[String]) -> i32 {
         Command::new(command)
@@ -144,6 +419,20 @@ impl Plugin for SystemPlugin {
         })?;
         globals.set("system", system)?;
 
+        let spawn_process = lua_ctx.create_function(
+            |_, (command, args, timeout_millis): (String, Vec<String>, Option<u64>)| {
+                match SystemPlugin::spawn_process(&command, &args, timeout_millis) {
+                    Ok(id) => Ok((Some(id), None)),
+
+                    Err(e) => {
+                        warn!("Could not spawn the process '{}': {}", command, e);
+                        Ok((None, Some(format!("{}", e))))
+                    }
+                }
+            },
+        )?;
+        globals.set("spawn_process", spawn_process)?;
+
         Ok(())
     }
 
@@ -159,3 +448,62 @@ impl Plugin for SystemPlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_on_allow_list() {
+        let allow_list = vec!["git".to_string(), "/usr/bin/systemctl".to_string()];
+
+        assert!(is_on_allow_list(&allow_list, "git"));
+        assert!(is_on_allow_list(&allow_list, "/usr/bin/systemctl"));
+        assert!(is_on_allow_list(&allow_list, "/usr/bin//systemctl"));
+
+        // entries without a slash only match commands looked up in PATH
+        assert!(!is_on_allow_list(&allow_list, "/usr/bin/git"));
+        assert!(!is_on_allow_list(&allow_list, "./git"));
+
+        // entries with a slash only match the exact path
+        assert!(!is_on_allow_list(&allow_list, "systemctl"));
+        assert!(!is_on_allow_list(&allow_list, "/bin/systemctl"));
+
+        assert!(!is_on_allow_list(&allow_list, "gitk"));
+        assert!(!is_on_allow_list(&allow_list, ""));
+        assert!(!is_on_allow_list(&[], "git"));
+    }
+
+    #[test]
+    fn test_capture_output() {
+        assert_eq!(capture_output(&b"output"[..]), "output");
+
+        let output = vec![b'x'; MAX_PROCESS_OUTPUT_BYTES as usize + 1];
+        assert_eq!(
+            capture_output(&output[..]).len(),
+            MAX_PROCESS_OUTPUT_BYTES as usize
+        );
+    }
+
+    #[test]
+    fn test_has_terminated() {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        assert!(!has_terminated(child.id()).unwrap());
+
+        // closing stdin terminates the process
+        drop(child.stdin.take());
+
+        while !has_terminated(child.id()).unwrap() {
+            thread::sleep(Duration::from_millis(PROCESS_POLL_MILLIS));
+        }
+
+        // the process has not been reaped
+        assert!(has_terminated(child.id()).unwrap());
+        assert_eq!(child.wait().unwrap().code(), Some(0));
+    }
+}
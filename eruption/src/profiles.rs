@@ -20,10 +20,13 @@
 use crate::constants;
 use log::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::default::Default;
 use std::fs;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
 use uuid::Uuid;
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
@@ -44,16 +47,76 @@ pub enum ProfileError {
 
     #[error("Could not set a config value in a profile: {msg}")]
     SetValueError { msg: String },
+
+    #[error("Could not load the base profile '{base}': {msg}")]
+    BaseError { base: String, msg: String },
+
+    #[error("Cyclic inheritance of profiles: {chain}")]
+    InheritanceCycleError { chain: String },
+}
+
+/// Key of the scripts that are appended to the active scripts of the base profile, instead of replacing them
+const APPEND_SCRIPTS_KEY: &str = "append_scripts";
+
+/// A stop of a color gradient, `position` is in the range [0.0..1.0]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub struct GradientStop {
+    pub position: f64,
+    pub color: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ConfigParam {
-    Int { name: String, value: i64 },
-    Float { name: String, value: f64 },
-    Bool { name: String, value: bool },
-    String { name: String, value: String },
-    Color { name: String, value: u32 },
+    Int {
+        name: String,
+        value: i64,
+    },
+    Float {
+        name: String,
+        value: f64,
+    },
+    Bool {
+        name: String,
+        value: bool,
+    },
+    String {
+        name: String,
+        value: String,
+    },
+    Color {
+        name: String,
+        value: u32,
+    },
+    Choice {
+        name: String,
+        value: String,
+    },
+    Gradient {
+        name: String,
+        value: Vec<GradientStop>,
+    },
+    KeySet {
+        name: String,
+        value: Vec<u8>,
+    },
+}
+
+/// Format a list of gradient stops, e.g.: "0:#ff0000,1:#0000ff"
+pub fn format_gradient(stops: &[GradientStop]) -> String {
+    stops
+        .iter()
+        .map(|s| format!("{}:#{:06x}", s.position, s.color))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Format a set of key indices, e.g.: "1,2,3"
+pub fn format_key_set(keys: &[u8]) -> String {
+    keys.iter()
+        .map(|k| format!("{}", k))
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
 pub trait GetAttr {
@@ -73,6 +136,12 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref name, .. } => name,
 
             ConfigParam::Color { ref name, .. } => name,
+
+            ConfigParam::Choice { ref name, .. } => name,
+
+            ConfigParam::Gradient { ref name, .. } => name,
+
+            ConfigParam::KeySet { ref name, .. } => name,
         }
     }
 
@@ -87,6 +156,12 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref value, .. } => value.to_owned(),
 
             ConfigParam::Color { ref value, .. } => format!("#{:06x}", value),
+
+            ConfigParam::Choice { ref value, .. } => value.to_owned(),
+
+            ConfigParam::Gradient { ref value, .. } => format_gradient(value),
+
+            ConfigParam::KeySet { ref value, .. } => format_key_set(value),
         }
     }
 }
@@ -95,6 +170,21 @@ fn default_id() -> Uuid {
     Uuid::new_v4()
 }
 
+/// Returns the id of the profile `profile_file` that does not specify an id, derived from the
+/// canonical path of the profile, so that the id is the same each time the profile is loaded
+fn id_from_path(profile_file: &Path) -> Uuid {
+    let path = fs::canonicalize(profile_file).unwrap_or_else(|_| profile_file.to_path_buf());
+
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&Sha256::digest(path.as_os_str().as_bytes())[..16]);
+
+    // a name based UUID
+    uuid::Builder::from_bytes(bytes)
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Sha1)
+        .build()
+}
+
 fn default_profile_file() -> PathBuf {
     "".into()
 }
@@ -115,10 +205,186 @@ pub struct Profile {
     pub name: String,
     pub description: String,
 
+    /// The profile that this profile is derived from, relative to the directory of this profile.
+    /// Settings that are not specified by this profile are inherited from its base profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<PathBuf>,
+
     #[serde(default = "default_script_file")]
     pub active_scripts: Vec<PathBuf>,
 
     pub config: Option<HashMap<String, Vec<ConfigParam>>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioSettings>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<Keymap>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse: Option<MouseSettings>,
+}
+
+/// Per-profile key remapping, that is applied natively in the input path. Keys are specified by
+/// their evdev names, e.g. "KEY_CAPSLOCK", remap targets may be a key, a key combination like
+/// "KEY_LEFTCTRL+KEY_C", a recorded macro like "macro:build-order", or "none" to disable a key
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Keymap {
+    /// Remaps of the base layer
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub remap: HashMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<KeymapLayer>,
+}
+
+/// A layer of a keymap, that is activated by a modifier key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeymapLayer {
+    pub name: String,
+
+    /// The key that activates the layer
+    pub key: String,
+
+    #[serde(default)]
+    pub mode: LayerMode,
+
+    /// Remaps of this layer, keys without a remap fall through to the layers below
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub remap: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerMode {
+    /// The layer is active while its key is held down
+    Momentary,
+
+    /// Each press of the key switches the layer on or off
+    Toggle,
+}
+
+impl Default for LayerMode {
+    fn default() -> Self {
+        LayerMode::Momentary
+    }
+}
+
+/// Per-profile mouse configuration, that is applied natively in the input path. Buttons are
+/// specified by their evdev names, e.g. "BTN_SIDE", remap targets use the syntax of keymaps
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MouseSettings {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub remap: HashMap<String, String>,
+
+    /// While this button is held down, pointer motion is scaled by `sniper_factor`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sniper_button: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sniper_factor: Option<f64>,
+
+    /// While this button is held down, the Easy Shift+ layer of the keyboard
+    /// is active, as well as the keymap layer `shift_layer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shift_button: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shift_layer: Option<String>,
+
+    /// Multiplier of the pointer motion on both axes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensitivity: Option<f64>,
+
+    /// Additional multiplier of the pointer motion on the X axis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensitivity_x: Option<f64>,
+
+    /// Additional multiplier of the pointer motion on the Y axis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensitivity_y: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acceleration: Option<AccelerationCurve>,
+}
+
+/// Maps the speed of the pointer, in counts per millisecond, to a gain
+/// that the pointer motion is multiplied with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "curve", rename_all = "lowercase")]
+pub enum AccelerationCurve {
+    /// No acceleration, the gain is always 1.0
+    Flat,
+
+    /// gain = 1 + acceleration * (speed - offset), capped at `cap`
+    Linear {
+        acceleration: f64,
+
+        #[serde(default)]
+        offset: f64,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cap: Option<f64>,
+    },
+
+    /// gain = 1 + (scale * (speed - offset)) ^ exponent, capped at `cap`
+    Power {
+        #[serde(default = "default_power_scale")]
+        scale: f64,
+
+        exponent: f64,
+
+        #[serde(default)]
+        offset: f64,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cap: Option<f64>,
+    },
+
+    /// Linear interpolation between `[speed, gain]` points
+    Custom { points: Vec<(f64, f64)> },
+}
+
+fn default_power_scale() -> f64 {
+    1.0
+}
+
+/// Per-profile settings of the audio analysis pipeline, these override
+/// the settings of the `[audio]` section of `eruption.conf`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AudioSettings {
+    /// Name of the PulseAudio source, e.g. a sink monitor or a microphone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fft_size: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bin_mapping: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bins: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attack: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decay: Option<f64>,
+
+    /// Noise floor in dBFS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise_floor: Option<f64>,
+
+    /// Name of the sound effects pack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sfx_pack: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sfx_volume: Option<f64>,
 }
 
 pub trait FindConfig {
@@ -159,6 +425,24 @@ impl FindConfig for Vec<ConfigParam> {
                         return Some(p);
                     }
                 }
+
+                ConfigParam::Choice { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::Gradient { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::KeySet { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
             }
         }
 
@@ -197,6 +481,24 @@ impl FindConfig for Vec<ConfigParam> {
                         return Some(p);
                     }
                 }
+
+                ConfigParam::Choice { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::Gradient { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::KeySet { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
             }
         }
 
@@ -277,51 +579,40 @@ macro_rules! set_config_value {
 
 impl Profile {
     pub fn new(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.id = Uuid::new_v4();
-                        result.profile_file = profile_file.to_path_buf();
-
-                        result.config = Some(HashMap::new());
-
-                        Ok(result)
-                    }
+        let mut result = Self::from(profile_file)?;
 
-                    Err(_e) => Err(ProfileError::ParseError {}.into()),
-                }
-            }
+        // fill in required fields, after parsing
+        result.id = Uuid::new_v4();
+        result.config = Some(HashMap::new());
 
-            Err(_e) => Err(ProfileError::OpenError {}.into()),
-        }
+        Ok(result)
     }
 
+    /// Load a profile, with the settings of its chain of base profiles merged in
     pub fn from(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.profile_file = profile_file.to_path_buf();
-
-                        if result.config.is_none() {
-                            result.config = Some(HashMap::new());
-                        }
+        let table = load_resolved(profile_file, &mut Vec::new())?;
 
-                        Ok(result)
-                    }
+        // the id of a profile is never inherited from its base profile
+        let has_id = table.contains_key("id");
+
+        // parse profile
+        match Value::Table(table).try_into::<Self>() {
+            Ok(mut result) => {
+                // fill in required fields, after parsing
+                result.profile_file = profile_file.to_path_buf();
 
-                    Err(_e) => Err(ProfileError::ParseError {}.into()),
+                if !has_id {
+                    result.id = id_from_path(profile_file);
                 }
+
+                if result.config.is_none() {
+                    result.config = Some(HashMap::new());
+                }
+
+                Ok(result)
             }
 
-            Err(_e) => Err(ProfileError::OpenError {}.into()),
+            Err(_e) => Err(ProfileError::ParseError {}.into()),
         }
     }
 
@@ -352,7 +643,11 @@ impl Profile {
     }
 
     pub fn save(&self) -> Result<()> {
-        let toml = toml::ser::to_string_pretty(&self)?;
+        let toml = match &self.base {
+            Some(base) => toml::ser::to_string_pretty(&Value::Table(self.get_overrides(base)?))?,
+
+            None => toml::ser::to_string_pretty(&self)?,
+        };
 
         fs::write(&self.profile_file, &toml).map_err(|_| ProfileError::WriteError {
             msg: "Could not write file".into(),
@@ -361,6 +656,82 @@ impl Profile {
         Ok(())
     }
 
+    /// Returns the settings of this profile that differ from its base profile, so
+    /// that modifications of the base profile still propagate to this profile
+    fn get_overrides(&self, base: &Path) -> Result<Table> {
+        let base = Profile::from(&base_profile_file(&self.profile_file, base))?;
+
+        let mut base = match Value::try_from(base)? {
+            Value::Table(table) => table,
+
+            _ => return Err(ProfileError::ParseError {}.into()),
+        };
+
+        let mut result = match Value::try_from(self)? {
+            Value::Table(table) => table,
+
+            _ => return Err(ProfileError::ParseError {}.into()),
+        };
+
+        // scripts of the base profile that are kept in order, are stored as appended scripts
+        if let (Some(Value::Array(scripts)), Some(Value::Array(base_scripts))) =
+            (result.get("active_scripts"), base.get("active_scripts"))
+        {
+            if scripts.starts_with(base_scripts) {
+                let appended = scripts[base_scripts.len()..].to_vec();
+
+                result.remove("active_scripts");
+
+                if !appended.is_empty() {
+                    result.insert(APPEND_SCRIPTS_KEY.to_string(), Value::Array(appended));
+                }
+            }
+        }
+
+        if let (Some(Value::Table(config)), Some(Value::Table(base_config))) =
+            (result.get_mut("config"), base.get("config"))
+        {
+            for (script_name, params) in config.iter_mut() {
+                if let (Value::Array(params), Some(Value::Array(base_params))) =
+                    (params, base_config.get(script_name))
+                {
+                    params.retain(|p| !base_params.contains(p));
+                }
+            }
+
+            let empty = config
+                .iter()
+                .filter(|(_, params)| matches!(params, Value::Array(p) if p.is_empty()))
+                .map(|(script_name, _)| script_name.clone())
+                .collect::<Vec<String>>();
+
+            for script_name in empty.iter() {
+                config.remove(script_name);
+            }
+
+            if config.is_empty() {
+                result.remove("config");
+            }
+        }
+
+        // the identity of a profile is never inherited
+        for key in ["id", "name", "description", "base", "config"].iter() {
+            base.remove(*key);
+        }
+
+        let inherited = result
+            .iter()
+            .filter(|(key, value)| base.get(*key) == Some(value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<String>>();
+
+        for key in inherited.iter() {
+            result.remove(key);
+        }
+
+        Ok(result)
+    }
+
     get_config_value!(int, i64, ConfigParam::Int);
     set_config_value!(int, i64, ConfigParam::Int);
 
@@ -375,6 +746,15 @@ impl Profile {
 
     get_config_value!(color, u32, ConfigParam::Color);
     set_config_value!(color, u32, ConfigParam::Color);
+
+    get_config_value!(choice, str, ConfigParam::Choice);
+    set_config_value!(choice, str, ConfigParam::Choice);
+
+    get_config_value!(gradient, [GradientStop], ConfigParam::Gradient);
+    set_config_value!(gradient, [GradientStop], ConfigParam::Gradient);
+
+    get_config_value!(key_set, [u8], ConfigParam::KeySet);
+    set_config_value!(key_set, [u8], ConfigParam::KeySet);
 }
 
 impl Default for Profile {
@@ -389,8 +769,134 @@ impl Default for Profile {
             profile_file,
             name: "Default".into(),
             description: "Auto-generated profile".into(),
+            base: None,
             active_scripts: vec![PathBuf::from(constants::DEFAULT_EFFECT_SCRIPT)],
             config,
+            audio: None,
+            keymap: None,
+            mouse: None,
+        }
+    }
+}
+
+/// Returns the path of the base profile `base` of the profile `profile_file`
+fn base_profile_file(profile_file: &Path, base: &Path) -> PathBuf {
+    profile_file
+        .parent()
+        .map(|dir| dir.join(base))
+        .unwrap_or_else(|| base.to_path_buf())
+}
+
+/// Read the profile `profile_file`, with the settings of its chain of base profiles
+/// merged in. `chain` holds the profiles that are currently being resolved
+fn load_resolved(profile_file: &Path, chain: &mut Vec<PathBuf>) -> Result<Table> {
+    let path = fs::canonicalize(profile_file).map_err(|_e| ProfileError::OpenError {})?;
+
+    if chain.contains(&path) {
+        chain.push(path);
+
+        return Err(ProfileError::InheritanceCycleError {
+            chain: chain
+                .iter()
+                .map(|p| format!("{}", p.display()))
+                .collect::<Vec<String>>()
+                .join(" -> "),
+        }
+        .into());
+    }
+
+    let toml = fs::read_to_string(&path).map_err(|_e| ProfileError::OpenError {})?;
+    let mut table = toml::de::from_str::<Table>(&toml).map_err(|_e| ProfileError::ParseError {})?;
+
+    let scripts = table.remove(APPEND_SCRIPTS_KEY);
+
+    match table.get("base") {
+        Some(Value::String(base)) => {
+            let base_file = base_profile_file(&path, Path::new(base));
+
+            chain.push(path);
+
+            let base = load_resolved(&base_file, chain).map_err(|e| {
+                match e.downcast_ref::<ProfileError>() {
+                    Some(ProfileError::BaseError { .. })
+                    | Some(ProfileError::InheritanceCycleError { .. }) => e,
+
+                    _ => ProfileError::BaseError {
+                        base: format!("{}", base_file.display()),
+                        msg: format!("{}", e),
+                    }
+                    .into(),
+                }
+            })?;
+
+            chain.pop();
+
+            Ok(merge_profiles(base, table, scripts))
+        }
+
+        Some(_) => Err(ProfileError::ParseError {}.into()),
+
+        None => Ok(merge_profiles(Table::new(), table, scripts)),
+    }
+}
+
+/// Merge the settings of `profile` into the settings of its resolved base profile `base`. Script
+/// parameters are merged by their names, `append_scripts` are appended to the active scripts,
+/// all other settings of `profile` replace the ones of `base`
+fn merge_profiles(mut base: Table, profile: Table, append_scripts: Option<Value>) -> Table {
+    // the identity of a profile is never inherited
+    base.remove("id");
+
+    for (key, value) in profile {
+        match (key.as_str(), base.get_mut(&key), value) {
+            ("config", Some(Value::Table(base_config)), Value::Table(config)) => {
+                merge_config(base_config, config)
+            }
+
+            (_, _, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+
+    if let Some(Value::Array(scripts)) = append_scripts {
+        let mut active_scripts = match base.remove("active_scripts") {
+            Some(Value::Array(active_scripts)) => active_scripts,
+
+            _ => default_script_file()
+                .iter()
+                .map(|p| Value::String(format!("{}", p.display())))
+                .collect(),
+        };
+
+        active_scripts.extend(scripts);
+
+        base.insert("active_scripts".to_string(), Value::Array(active_scripts));
+    }
+
+    base
+}
+
+/// Merge the script parameters of `config` into `base`, parameters are matched by their names
+fn merge_config(base: &mut Table, config: Table) {
+    for (script_name, params) in config {
+        match (base.get_mut(&script_name), params) {
+            (Some(Value::Array(base_params)), Value::Array(params)) => {
+                for param in params {
+                    match base_params
+                        .iter_mut()
+                        .find(|p| p.get("name").is_some() && p.get("name") == param.get("name"))
+                    {
+                        Some(base_param) => *base_param = param,
+
+                        None => base_params.push(param),
+                    }
+                }
+            }
+
+            (_, params) => {
+                base.insert(script_name, params);
+            }
         }
     }
 }
@@ -473,3 +979,250 @@ pub fn find_path_by_uuid(uuid: Uuid, profile_path: &Path) -> Option<PathBuf> {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for the profile files of the test `name`
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn parse(toml: &str) -> Table {
+        toml::de::from_str::<Table>(toml).unwrap()
+    }
+
+    #[test]
+    fn test_merge_profiles() {
+        let base = parse(
+            r#"
+            id = '5dc59fa6-e965-25cb-a0da-e87d28713094'
+            name = 'Base'
+            description = 'The base profile'
+            active_scripts = ['organic.lua', 'macros.lua']
+
+            [[config.Organic]]
+            type = 'float'
+            name = 'opacity'
+            value = 1.0
+
+            [[config.Organic]]
+            type = 'int'
+            name = 'speed'
+            value = 10
+            "#,
+        );
+
+        let mut profile = parse(
+            r#"
+            name = 'Derived'
+            append_scripts = ['stats.lua']
+
+            [[config.Organic]]
+            type = 'int'
+            name = 'speed'
+            value = 20
+
+            [[config.Macros]]
+            type = 'bool'
+            name = 'enabled'
+            value = false
+            "#,
+        );
+
+        let scripts = profile.remove(APPEND_SCRIPTS_KEY);
+        let result = merge_profiles(base, profile, scripts);
+
+        assert!(result.get("id").is_none());
+        assert_eq!(result["name"].as_str(), Some("Derived"));
+        assert_eq!(result["description"].as_str(), Some("The base profile"));
+
+        assert_eq!(
+            result["active_scripts"],
+            Value::Array(vec![
+                Value::String("organic.lua".into()),
+                Value::String("macros.lua".into()),
+                Value::String("stats.lua".into()),
+            ])
+        );
+
+        let organic = result["config"]["Organic"].as_array().unwrap();
+        assert_eq!(organic.len(), 2);
+        assert_eq!(organic[0]["value"].as_float(), Some(1.0));
+        assert_eq!(organic[1]["value"].as_integer(), Some(20));
+
+        let macros = result["config"]["Macros"].as_array().unwrap();
+        assert_eq!(macros[0]["value"].as_bool(), Some(false));
+    }
+
+    #[test]
+    fn test_base_profile() {
+        let dir = test_dir("base-profile");
+
+        fs::write(
+            dir.join("base.profile"),
+            r#"
+            id = '5dc59fa6-e965-25cb-a0da-e87d28713094'
+            name = 'Base'
+            description = 'The base profile'
+            active_scripts = ['organic.lua']
+
+            [[config.Organic]]
+            type = 'int'
+            name = 'speed'
+            value = 10
+            "#,
+        )
+        .unwrap();
+
+        fs::write(
+            dir.join("derived.profile"),
+            r#"
+            id = '6dc59fa6-e965-25cb-a0da-e87d28713094'
+            name = 'Derived'
+            description = 'A derived profile'
+            base = 'base.profile'
+            append_scripts = ['stats.lua']
+            "#,
+        )
+        .unwrap();
+
+        let mut profile = Profile::from(&dir.join("derived.profile")).unwrap();
+
+        assert_eq!(profile.name, "Derived");
+        assert_eq!(
+            profile.active_scripts,
+            vec![PathBuf::from("organic.lua"), PathBuf::from("stats.lua")]
+        );
+        assert_eq!(profile.get_int_value("Organic", "speed"), Some(&10));
+
+        // only the overrides are saved to a derived profile
+        profile.set_int_value("Organic", "speed", &20).unwrap();
+        profile.save().unwrap();
+
+        let saved = parse(&fs::read_to_string(dir.join("derived.profile")).unwrap());
+
+        assert!(saved.get("active_scripts").is_none());
+        assert_eq!(saved["base"].as_str(), Some("base.profile"));
+        assert_eq!(
+            saved["config"]["Organic"][0]["value"].as_integer(),
+            Some(20)
+        );
+
+        // changes of the base profile propagate to the derived profile
+        let mut base = Profile::from(&dir.join("base.profile")).unwrap();
+        base.active_scripts.push(PathBuf::from("macros.lua"));
+        base.save().unwrap();
+
+        let profile = Profile::from(&dir.join("derived.profile")).unwrap();
+
+        assert_eq!(profile.name, "Derived");
+        assert_eq!(
+            profile.active_scripts,
+            vec![
+                PathBuf::from("organic.lua"),
+                PathBuf::from("macros.lua"),
+                PathBuf::from("stats.lua")
+            ]
+        );
+        assert_eq!(profile.get_int_value("Organic", "speed"), Some(&20));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_id_from_path() {
+        let dir = test_dir("id-from-path");
+
+        fs::write(
+            dir.join("base.profile"),
+            r#"
+            id = '5dc59fa6-e965-25cb-a0da-e87d28713094'
+            name = 'Base'
+            description = 'The base profile'
+            "#,
+        )
+        .unwrap();
+
+        for name in ["a", "b"].iter() {
+            fs::write(
+                dir.join(format!("{}.profile", name)),
+                r#"
+                name = 'Derived'
+                description = 'A derived profile without an id'
+                base = 'base.profile'
+                "#,
+            )
+            .unwrap();
+        }
+
+        let a = Profile::from(&dir.join("a.profile")).unwrap();
+        let b = Profile::from(&dir.join("b.profile")).unwrap();
+
+        // the id is stable across loads, but unique per profile file
+        assert_eq!(a.id, Profile::from(&dir.join("a.profile")).unwrap().id);
+        assert_eq!(
+            a.id,
+            Profile::from(&dir.join(".").join("a.profile")).unwrap().id
+        );
+        assert_ne!(a.id, b.id);
+
+        // and is never inherited from the base profile
+        let base = Profile::from(&dir.join("base.profile")).unwrap();
+
+        assert_eq!(
+            base.id,
+            Uuid::parse_str("5dc59fa6-e965-25cb-a0da-e87d28713094").unwrap()
+        );
+        assert_ne!(a.id, base.id);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inheritance_cycle() {
+        let dir = test_dir("inheritance-cycle");
+
+        for (name, base) in [("a", "b"), ("b", "c"), ("c", "a"), ("self", "self")].iter() {
+            fs::write(
+                dir.join(format!("{}.profile", name)),
+                format!(
+                    "name = '{}'\ndescription = ''\nbase = '{}.profile'\n",
+                    name, base
+                ),
+            )
+            .unwrap();
+        }
+
+        for name in ["a", "self"].iter() {
+            let e = Profile::from(&dir.join(format!("{}.profile", name))).unwrap_err();
+
+            assert!(matches!(
+                e.downcast_ref::<ProfileError>(),
+                Some(ProfileError::InheritanceCycleError { .. })
+            ));
+        }
+
+        fs::write(
+            dir.join("orphan.profile"),
+            "name = 'orphan'\ndescription = ''\nbase = 'missing.profile'\n",
+        )
+        .unwrap();
+
+        let e = Profile::from(&dir.join("orphan.profile")).unwrap_err();
+
+        assert!(matches!(
+            e.downcast_ref::<ProfileError>(),
+            Some(ProfileError::BaseError { .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
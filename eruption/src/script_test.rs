@@ -0,0 +1,959 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use clap::{App, Arg};
+use crossbeam::channel::{unbounded, Sender};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::hwdevices::{KeyboardHidEvent, MouseHidEvent, RGBA};
+use crate::plugin_manager;
+use crate::plugins::{self, macros};
+use crate::profiles::Profile;
+use crate::scripting::manifest::Manifest;
+use crate::scripting::{native, script};
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Name of the executable that selects the headless script test runner
+pub const TEST_RUNNER_NAME: &str = "eruption-script-test";
+
+/// Maximum amount of time to wait for a Lua VM to complete an upcall
+const UPCALL_TIMEOUT_MILLIS: u64 = 5000;
+
+lazy_static! {
+    /// Set as soon as one of the Lua VMs terminated with errors
+    static ref SCRIPT_FAILED: AtomicBool = AtomicBool::new(false);
+
+    /// The number of the tick that is currently being processed
+    static ref CURRENT_TICK: AtomicU32 = AtomicU32::new(0);
+
+    /// Events that have been injected on the virtual devices by the scripts
+    static ref INJECTED_EVENTS: Arc<Mutex<Vec<InjectedEvent>>> = Arc::new(Mutex::new(vec![]));
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptTestError {
+    #[error("Could not parse the event sequence: {description}")]
+    ParseError { description: String },
+
+    #[error("Script file or manifest inaccessible: {path}")]
+    InaccessibleScript { path: String },
+
+    #[error("A Lua VM terminated with errors")]
+    ScriptExecError {},
+
+    #[error("Timeout while waiting for a Lua VM")]
+    TimeoutError {},
+
+    #[error("Output does not match the golden file: {description}")]
+    GoldenMismatch { description: String },
+}
+
+/// A sequence of events that will be fed to the Lua VMs
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventSequence {
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_beat_strength() -> f32 {
+    1.0
+}
+
+/// A single scripted event
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Tick {
+        #[serde(default = "default_count")]
+        count: u32,
+    },
+
+    KeyDown {
+        key: u8,
+    },
+
+    KeyUp {
+        key: u8,
+    },
+
+    MouseButtonDown {
+        button: u8,
+    },
+
+    MouseButtonUp {
+        button: u8,
+    },
+
+    MouseMove {
+        x: i32,
+        y: i32,
+        #[serde(default)]
+        z: i32,
+    },
+
+    MouseWheel {
+        direction: u8,
+    },
+
+    KeyboardHid {
+        event: KeyboardHidEventKind,
+        #[serde(default)]
+        value: u8,
+    },
+
+    MouseHid {
+        event: MouseHidEventKind,
+        #[serde(default)]
+        value: u8,
+    },
+
+    Brightness {
+        value: isize,
+    },
+
+    Beat {
+        #[serde(default = "default_beat_strength")]
+        strength: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardHidEventKind {
+    BrightnessUp,
+    BrightnessDown,
+    SetBrightness,
+    MuteDown,
+    MuteUp,
+    VolumeDown,
+    VolumeUp,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseHidEventKind {
+    ButtonDown,
+    ButtonUp,
+    DpiChange,
+}
+
+/// An event that a script injected on the virtual keyboard or mouse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InjectedEvent {
+    Key { tick: u32, key: u32, down: bool },
+    Button { tick: u32, button: u32, down: bool },
+    MouseWheel { tick: u32, direction: u32 },
+}
+
+/// A rendered frame of the canvas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub tick: u32,
+
+    /// LED colors in "#rrggbb" notation
+    pub leds: Vec<String>,
+}
+
+/// The result of a test run, this is also the format of the golden files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestOutput {
+    pub scripts: Vec<String>,
+    pub frames: Vec<Frame>,
+    pub injected: Vec<InjectedEvent>,
+}
+
+/// Returns true if we have been invoked as `eruption-script-test`
+pub fn is_invoked_as_test_runner() -> bool {
+    env::args()
+        .next()
+        .map(|arg| {
+            Path::new(&arg)
+                .file_name()
+                .map(|name| name == TEST_RUNNER_NAME)
+                .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+/// Process commandline options
+fn parse_commandline() -> clap::ArgMatches {
+    App::new("Eruption Script Test")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("X3n0m0rph59 <x3n0m0rph59@gmail.com>")
+        .about("Run Eruption Lua scripts headless, without hardware and without a running daemon")
+        .arg(
+            Arg::new("script")
+                .short('s')
+                .long("script")
+                .value_name("FILE")
+                .about("The Lua script to run, the manifest is loaded from the same directory")
+                .takes_value(true)
+                .required_unless_present("profile"),
+        )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("FILE")
+                .about("Run all scripts of a profile, using the parameters of the profile")
+                .takes_value(true)
+                .conflicts_with("script"),
+        )
+        .arg(
+            Arg::new("script-dir")
+                .short('d')
+                .long("script-dir")
+                .value_name("DIR")
+                .about("The directory used to look up the scripts of a profile")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("events")
+                .short('e')
+                .long("events")
+                .value_name("FILE")
+                .about(
+                    "A TOML or JSON file describing the sequence of events to feed to the scripts",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("ticks")
+                .short('t')
+                .long("ticks")
+                .value_name("COUNT")
+                .about("Number of ticks to run if no event sequence has been specified")
+                .takes_value(true)
+                .default_value("20"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .about("The output format")
+                .takes_value(true)
+                .possible_values(&["json", "png"])
+                .default_value("json"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .about("Write the output to FILE instead of stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("golden")
+                .short('g')
+                .long("golden")
+                .value_name("FILE")
+                .about("Compare the output with a golden file, exits with an error on mismatch")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("update-golden")
+                .long("update-golden")
+                .about("Write the output to the golden file instead of comparing it"),
+        )
+        .arg(
+            Arg::new("audio-file")
+                .short('a')
+                .long("audio-file")
+                .value_name("FILE")
+                .about("Feed a WAV file or a FIFO of raw PCM data to the audio plugin")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tolerance")
+                .long("tolerance")
+                .value_name("VALUE")
+                .about("Maximum allowed difference per color channel when comparing frames")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .get_matches()
+}
+
+/// Parse an event sequence from a TOML or JSON file
+fn load_event_sequence(path: &Path) -> Result<EventSequence> {
+    let s = fs::read_to_string(path)?;
+
+    let result = if path.extension().map(|e| e == "json").unwrap_or(false) {
+        serde_json::from_str::<EventSequence>(&s).map_err(|e| ScriptTestError::ParseError {
+            description: format!("{}", e),
+        })?
+    } else {
+        toml::de::from_str::<EventSequence>(&s).map_err(|e| ScriptTestError::ParseError {
+            description: format!("{}", e),
+        })?
+    };
+
+    Ok(result)
+}
+
+/// Register all plugins that are usable without hardware, and install a
+/// virtual uinput sink that records all injected events
+fn register_headless_plugins() -> Result<()> {
+    let mut plugin_manager = plugin_manager::PLUGIN_MANAGER.write();
+
+    plugin_manager.register_plugin(Box::new(plugins::KeyboardPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::MousePlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::IntrospectionPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::PersistencePlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::ProfilesPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::SystemPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::SensorsPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(plugins::AudioPlugin::new()))?;
+
+    let (uinput_tx, uinput_rx) = unbounded();
+
+    thread::Builder::new()
+        .name("uinput/recorder".into())
+        .spawn(move || {
+            while let Ok(message) = uinput_rx.recv() {
+                let tick = CURRENT_TICK.load(Ordering::SeqCst);
+
+                let event = match message {
+                    macros::Message::InjectKey { key, down } => {
+                        Some(InjectedEvent::Key { tick, key, down })
+                    }
+
+                    macros::Message::InjectButtonEvent { button, down } => {
+                        Some(InjectedEvent::Button { tick, button, down })
+                    }
+
+                    macros::Message::InjectMouseWheelEvent { direction } => {
+                        Some(InjectedEvent::MouseWheel { tick, direction })
+                    }
+
+                    _ => None,
+                };
+
+                if let Some(event) = event {
+                    INJECTED_EVENTS.lock().push(event);
+                }
+            }
+        })?;
+
+    *macros::UINPUT_TX.lock() = Some(uinput_tx);
+
+    Ok(())
+}
+
+/// Drives a set of Lua VMs, without hardware and without the main loop of the daemon
+struct TestRunner {
+    lua_txs: Vec<Sender<script::Message>>,
+    frames: Vec<Frame>,
+    ticks: u32,
+}
+
+impl TestRunner {
+    fn new() -> Self {
+        TestRunner {
+            lua_txs: vec![],
+            frames: vec![],
+            ticks: 0,
+        }
+    }
+
+    fn spawn_script(&mut self, script_path: &Path) -> Result<()> {
+        let effect = if native::is_native_effect(script_path) {
+            Some(native::load_effect(
+                script_path,
+                crate::ACTIVE_PROFILE.lock().as_ref(),
+            )?)
+        } else {
+            if !util::is_script_file_accessible(script_path)
+                || !util::is_manifest_file_accessible(script_path)
+            {
+                return Err(ScriptTestError::InaccessibleScript {
+                    path: script_path.display().to_string(),
+                }
+                .into());
+            }
+
+            let manifest = Manifest::from(script_path)?;
+            script::check_requirements(&manifest, &[], &[])?;
+
+            None
+        };
+
+        let (lua_tx, lua_rx) = unbounded();
+        let script_path = script_path.to_path_buf();
+
+        thread::Builder::new()
+            .name(format!(
+                "{}:{}",
+                self.lua_txs.len(),
+                script_path.file_name().unwrap().to_string_lossy(),
+            ))
+            .spawn(move || {
+                let result = if let Some(effect) = effect {
+                    native::run_effect(effect, &lua_rx)
+                } else {
+                    script::run_script(0, script_path.clone(), &lua_rx, &[], &[])
+                };
+
+                match result {
+                    Ok(script::RunScriptResult::TerminatedGracefully) => {}
+
+                    Ok(script::RunScriptResult::TerminatedWithErrors) => {
+                        error!(
+                            "Lua script '{}' terminated with errors",
+                            script_path.display()
+                        );
+                        SCRIPT_FAILED.store(true, Ordering::SeqCst);
+                    }
+
+                    Err(e) => {
+                        error!(
+                            "Could not execute Lua script '{}': {}",
+                            script_path.display(),
+                            e
+                        );
+                        SCRIPT_FAILED.store(true, Ordering::SeqCst);
+                    }
+                }
+            })?;
+
+        self.lua_txs.push(lua_tx);
+
+        Ok(())
+    }
+
+    /// Send a message to all Lua VMs, and wait until all of them completed the upcall
+    fn send_and_wait(
+        &self,
+        message: script::Message,
+        condition: &Arc<(Mutex<usize>, Condvar)>,
+    ) -> Result<()> {
+        *condition.0.lock() = self.lua_txs.len();
+
+        for lua_tx in self.lua_txs.iter() {
+            lua_tx.send(message.clone())?;
+        }
+
+        let start = Instant::now();
+
+        loop {
+            let mut pending = condition.0.lock();
+
+            if *pending == 0 {
+                break;
+            }
+
+            condition.1.wait_for(
+                &mut pending,
+                Duration::from_millis(constants::TIMEOUT_CONDITION_MILLIS),
+            );
+
+            if *pending == 0 {
+                break;
+            }
+
+            if SCRIPT_FAILED.load(Ordering::SeqCst) {
+                return Err(ScriptTestError::ScriptExecError {}.into());
+            }
+
+            if start.elapsed() >= Duration::from_millis(UPCALL_TIMEOUT_MILLIS) {
+                return Err(ScriptTestError::TimeoutError {}.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a single tick, then render and record a frame
+    fn tick(&mut self) -> Result<()> {
+        self.ticks += 1;
+        CURRENT_TICK.store(self.ticks, Ordering::SeqCst);
+
+        for lua_tx in self.lua_txs.iter() {
+            lua_tx.send(script::Message::Tick(1))?;
+        }
+
+        self.render()?;
+
+        if SCRIPT_FAILED.load(Ordering::SeqCst) {
+            return Err(ScriptTestError::ScriptExecError {}.into());
+        }
+
+        let leds = script::LED_MAP
+            .read()
+            .iter()
+            .map(|c| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b))
+            .collect();
+
+        self.frames.push(Frame {
+            tick: self.ticks,
+            leds,
+        });
+
+        Ok(())
+    }
+
+    /// Execute the render "pipeline", the same way the main loop of the daemon does
+    fn render(&self) -> Result<()> {
+        script::LED_MAP.write().copy_from_slice(
+            &[RGBA {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            }; constants::CANVAS_SIZE],
+        );
+
+        *crate::COLOR_MAPS_READY_CONDITION.0.lock() = self.lua_txs.len();
+
+        for lua_tx in self.lua_txs.iter() {
+            // guarantee the right order of execution for the alpha blend operations
+            let mut pending = crate::COLOR_MAPS_READY_CONDITION.0.lock();
+
+            lua_tx.send(script::Message::RealizeColorMap)?;
+
+            let result = crate::COLOR_MAPS_READY_CONDITION
+                .1
+                .wait_for(&mut pending, Duration::from_millis(UPCALL_TIMEOUT_MILLIS));
+
+            if result.timed_out() {
+                if SCRIPT_FAILED.load(Ordering::SeqCst) {
+                    return Err(ScriptTestError::ScriptExecError {}.into());
+                } else {
+                    return Err(ScriptTestError::TimeoutError {}.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_event(&mut self, event: &Event) -> Result<()> {
+        match *event {
+            Event::Tick { count } => {
+                for _ in 0..count {
+                    self.tick()?;
+                }
+            }
+
+            Event::KeyDown { key } => {
+                if let Some(state) = crate::KEY_STATES.lock().get_mut(key as usize) {
+                    *state = true;
+                }
+
+                self.send_and_wait(
+                    script::Message::KeyDown(key),
+                    &*crate::UPCALL_COMPLETED_ON_KEY_DOWN,
+                )?;
+            }
+
+            Event::KeyUp { key } => {
+                if let Some(state) = crate::KEY_STATES.lock().get_mut(key as usize) {
+                    *state = false;
+                }
+
+                self.send_and_wait(
+                    script::Message::KeyUp(key),
+                    &*crate::UPCALL_COMPLETED_ON_KEY_UP,
+                )?;
+            }
+
+            Event::MouseButtonDown { button } => {
+                if let Some(state) = crate::BUTTON_STATES.lock().get_mut(button as usize) {
+                    *state = true;
+                }
+
+                self.send_and_wait(
+                    script::Message::MouseButtonDown(button),
+                    &*crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_DOWN,
+                )?;
+            }
+
+            Event::MouseButtonUp { button } => {
+                if let Some(state) = crate::BUTTON_STATES.lock().get_mut(button as usize) {
+                    *state = false;
+                }
+
+                self.send_and_wait(
+                    script::Message::MouseButtonUp(button),
+                    &*crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_UP,
+                )?;
+            }
+
+            Event::MouseMove { x, y, z } => {
+                self.send_and_wait(
+                    script::Message::MouseMove(x, y, z),
+                    &*crate::UPCALL_COMPLETED_ON_MOUSE_MOVE,
+                )?;
+            }
+
+            Event::MouseWheel { direction } => {
+                self.send_and_wait(
+                    script::Message::MouseWheelEvent(direction),
+                    &*crate::UPCALL_COMPLETED_ON_MOUSE_EVENT,
+                )?;
+            }
+
+            Event::KeyboardHid { event, value } => {
+                let event = match event {
+                    KeyboardHidEventKind::BrightnessUp => KeyboardHidEvent::BrightnessUp,
+                    KeyboardHidEventKind::BrightnessDown => KeyboardHidEvent::BrightnessDown,
+                    KeyboardHidEventKind::SetBrightness => KeyboardHidEvent::SetBrightness(value),
+                    KeyboardHidEventKind::MuteDown => KeyboardHidEvent::MuteDown,
+                    KeyboardHidEventKind::MuteUp => KeyboardHidEvent::MuteUp,
+                    KeyboardHidEventKind::VolumeDown => KeyboardHidEvent::VolumeDown,
+                    KeyboardHidEventKind::VolumeUp => KeyboardHidEvent::VolumeUp,
+                };
+
+                self.send_and_wait(
+                    script::Message::KeyboardHidEvent(event),
+                    &*crate::UPCALL_COMPLETED_ON_KEYBOARD_HID_EVENT,
+                )?;
+            }
+
+            Event::MouseHid { event, value } => {
+                let event = match event {
+                    MouseHidEventKind::ButtonDown => MouseHidEvent::ButtonDown(value),
+                    MouseHidEventKind::ButtonUp => MouseHidEvent::ButtonUp(value),
+                    MouseHidEventKind::DpiChange => MouseHidEvent::DpiChange(value),
+                };
+
+                self.send_and_wait(
+                    script::Message::MouseHidEvent(event),
+                    &*crate::UPCALL_COMPLETED_ON_MOUSE_HID_EVENT,
+                )?;
+            }
+
+            Event::Brightness { value } => {
+                crate::BRIGHTNESS.store(value, Ordering::SeqCst);
+            }
+
+            Event::Beat { strength } => {
+                // beats are not acknowledged by the VMs, they are processed before the next tick
+                for lua_tx in self.lua_txs.iter() {
+                    lua_tx.send(script::Message::Beat(strength))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.send_and_wait(script::Message::Quit(0), &*crate::UPCALL_COMPLETED_ON_QUIT)
+    }
+}
+
+/// Render all frames into a PNG "strip", one row of pixels per frame
+fn write_png_strip<W: Write>(w: W, frames: &[Frame]) -> Result<()> {
+    let mut encoder = png::Encoder::new(w, constants::CANVAS_SIZE as u32, frames.len() as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(constants::CANVAS_SIZE * frames.len() * 3);
+
+    for frame in frames.iter() {
+        for led in frame.leds.iter() {
+            let (r, g, b) = parse_color(led);
+
+            data.push(r);
+            data.push(g);
+            data.push(b);
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
+
+/// Parse a color in "#rrggbb" notation
+fn parse_color(s: &str) -> (u8, u8, u8) {
+    let val = u32::from_str_radix(s.trim_start_matches('#'), 16).unwrap_or(0);
+
+    (
+        ((val >> 16) & 0xff) as u8,
+        ((val >> 8) & 0xff) as u8,
+        (val & 0xff) as u8,
+    )
+}
+
+/// Compare the output of a test run to the contents of a golden file
+fn compare_to_golden(output: &TestOutput, golden: &TestOutput, tolerance: u8) -> Result<()> {
+    if output.frames.len() != golden.frames.len() {
+        return Err(ScriptTestError::GoldenMismatch {
+            description: format!(
+                "Expected {} frames, got {}",
+                golden.frames.len(),
+                output.frames.len()
+            ),
+        }
+        .into());
+    }
+
+    for (frame, expected) in output.frames.iter().zip(golden.frames.iter()) {
+        if frame.tick != expected.tick {
+            return Err(ScriptTestError::GoldenMismatch {
+                description: format!("Expected tick {}, got tick {}", expected.tick, frame.tick),
+            }
+            .into());
+        }
+
+        if frame.leds.len() != expected.leds.len() {
+            return Err(ScriptTestError::GoldenMismatch {
+                description: format!(
+                    "Tick {}: expected {} LEDs, got {}",
+                    frame.tick,
+                    expected.leds.len(),
+                    frame.leds.len()
+                ),
+            }
+            .into());
+        }
+
+        for (idx, (led, expected_led)) in frame.leds.iter().zip(expected.leds.iter()).enumerate() {
+            let (r1, g1, b1) = parse_color(led);
+            let (r2, g2, b2) = parse_color(expected_led);
+
+            if (r1 as i16 - r2 as i16).abs() > tolerance as i16
+                || (g1 as i16 - g2 as i16).abs() > tolerance as i16
+                || (b1 as i16 - b2 as i16).abs() > tolerance as i16
+            {
+                return Err(ScriptTestError::GoldenMismatch {
+                    description: format!(
+                        "Tick {}, LED {}: expected {}, got {}",
+                        frame.tick, idx, expected_led, led
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+
+    if output.injected != golden.injected {
+        return Err(ScriptTestError::GoldenMismatch {
+            description: "The injected events differ".to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn run(matches: &clap::ArgMatches) -> Result<TestOutput> {
+    // scripts query the global configuration, so supply a default one
+    let mut config = config::Config::default();
+
+    if let Some(audio_file) = matches.value_of("audio-file") {
+        config.set("audio.backend", "file")?;
+        config.set("audio.file", audio_file)?;
+    }
+
+    *crate::CONFIG.lock() = Some(config);
+
+    register_headless_plugins()?;
+
+    let mut script_files = vec![];
+
+    if let Some(profile_file) = matches.value_of("profile") {
+        let profile = Profile::from(&PathBuf::from(profile_file))?;
+
+        let script_dir = PathBuf::from(
+            matches
+                .value_of("script-dir")
+                .unwrap_or(constants::DEFAULT_SCRIPT_DIR),
+        );
+
+        for script_file in profile.active_scripts.iter() {
+            script_files.push(script_dir.join(script_file));
+        }
+
+        // the profile needs to be active before the VMs are spawned,
+        // since they apply its parameters on startup
+        *crate::ACTIVE_PROFILE.lock() = Some(profile);
+    } else if let Some(script_file) = matches.value_of("script") {
+        script_files.push(PathBuf::from(script_file));
+    }
+
+    let sequence = if let Some(events_file) = matches.value_of("events") {
+        load_event_sequence(&PathBuf::from(events_file))?
+    } else {
+        let count = matches
+            .value_of("ticks")
+            .unwrap_or("20")
+            .parse::<u32>()
+            .map_err(|e| ScriptTestError::ParseError {
+                description: format!("{}", e),
+            })?;
+
+        EventSequence {
+            events: vec![Event::Tick { count }],
+        }
+    };
+
+    let mut runner = TestRunner::new();
+
+    for script_file in script_files.iter() {
+        runner.spawn_script(script_file)?;
+    }
+
+    for event in sequence.events.iter() {
+        runner.process_event(event)?;
+    }
+
+    runner.shutdown()?;
+
+    Ok(TestOutput {
+        scripts: script_files
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect(),
+        frames: runner.frames,
+        injected: INJECTED_EVENTS.lock().clone(),
+    })
+}
+
+/// Entrypoint of the `eruption-script-test` utility
+pub fn main() -> Result<()> {
+    let matches = parse_commandline();
+
+    // reject an invalid tolerance with a usage error, before running the scripts
+    let tolerance = matches
+        .value_of_t::<u8>("tolerance")
+        .unwrap_or_else(|e| e.exit());
+
+    // initialize logging
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG_OVERRIDE", "warn");
+        pretty_env_logger::init_custom_env("RUST_LOG_OVERRIDE");
+    } else {
+        pretty_env_logger::init();
+    }
+
+    let output = run(&matches).unwrap_or_else(|e| {
+        error!("Test run failed: {}", e);
+        process::exit(2);
+    });
+
+    if let Some(golden_file) = matches.value_of("golden") {
+        if matches.is_present("update-golden") {
+            fs::write(golden_file, serde_json::to_string_pretty(&output)?)?;
+        } else {
+            let golden = serde_json::from_str::<TestOutput>(&fs::read_to_string(golden_file)?)?;
+
+            compare_to_golden(&output, &golden, tolerance).unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+        }
+    }
+
+    // when comparing against a golden file, only write output if requested explicitly
+    if matches.is_present("golden") && !matches.is_present("output") {
+        return Ok(());
+    }
+
+    let format = matches.value_of("format").unwrap_or("json");
+
+    match matches.value_of("output") {
+        Some(output_file) => {
+            let file = fs::File::create(output_file)?;
+
+            if format == "png" {
+                write_png_strip(file, &output.frames)?;
+            } else {
+                serde_json::to_writer_pretty(file, &output)?;
+            }
+        }
+
+        None => {
+            if format == "png" {
+                write_png_strip(io::stdout(), &output.frames)?;
+            } else {
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_to_golden() {
+        let output = |leds: &[&str]| TestOutput {
+            scripts: vec![],
+            frames: vec![Frame {
+                tick: 1,
+                leds: leds.iter().map(|l| l.to_string()).collect(),
+            }],
+            injected: vec![],
+        };
+
+        let golden = output(&["#ff1010", "#000000"]);
+
+        assert!(compare_to_golden(&output(&["#ff1010", "#000000"]), &golden, 0).is_ok());
+        assert!(compare_to_golden(&output(&["#fe0f10", "#010101"]), &golden, 0).is_err());
+        assert!(compare_to_golden(&output(&["#fe0f10", "#010101"]), &golden, 1).is_ok());
+        assert!(compare_to_golden(&output(&["#fd1010", "#000000"]), &golden, 1).is_err());
+
+        let mut injected = output(&["#ff1010", "#000000"]);
+        injected.injected.push(InjectedEvent::Key {
+            tick: 1,
+            key: 30,
+            down: true,
+        });
+
+        assert!(compare_to_golden(&injected, &golden, 0).is_err());
+
+        // truncated or extended frames
+        assert!(compare_to_golden(&output(&["#ff1010"]), &golden, 0).is_err());
+        assert!(
+            compare_to_golden(&output(&["#ff1010", "#000000", "#000000"]), &golden, 0).is_err()
+        );
+
+        let mut shifted = output(&["#ff1010", "#000000"]);
+        shifted.frames[0].tick = 2;
+
+        assert!(compare_to_golden(&shifted, &golden, 0).is_err());
+    }
+}
@@ -0,0 +1,658 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::constants;
+use crate::hwdevices::RGBA;
+use crate::scripting::native::{self, NativeEffect};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// File name extension of animation files
+pub const ANIMATION_FILE_EXTENSION: &str = "animation";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnimationError {
+    #[error("Could not open animation file: {description}")]
+    OpenError { description: String },
+
+    #[error("Could not parse animation file: {description}")]
+    ParseError { description: String },
+
+    #[error("Invalid animation: {description}")]
+    ValidationError { description: String },
+}
+
+/// How an animation behaves after it reached its end
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    /// Play once, then hold the last frame
+    Once,
+
+    /// Restart from the beginning
+    Loop,
+
+    /// Play forwards, then backwards
+    PingPong,
+}
+
+impl Default for RepeatMode {
+    fn default() -> Self {
+        Self::Loop
+    }
+}
+
+/// Easing function, applied to the transition from one keyframe to the next
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    Step,
+    InQuad,
+    OutQuad,
+    InOutQuad,
+    InCubic,
+    OutCubic,
+    InOutCubic,
+    InSine,
+    OutSine,
+    InOutSine,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Self::Linear
+    }
+}
+
+impl Easing {
+    /// Map the linear progress `t` [0.0..1.0] to the eased progress
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+
+            Easing::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+
+            Easing::InQuad => t * t,
+            Easing::OutQuad => t * (2.0 - t),
+            Easing::InOutQuad => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    -1.0 + (4.0 - 2.0 * t) * t
+                }
+            }
+
+            Easing::InCubic => t * t * t,
+            Easing::OutCubic => (t - 1.0).powi(3) + 1.0,
+            Easing::InOutCubic => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    (t - 1.0) * (2.0 * t - 2.0).powi(2) + 1.0
+                }
+            }
+
+            Easing::InSine => 1.0 - (t * PI / 2.0).cos(),
+            Easing::OutSine => (t * PI / 2.0).sin(),
+            Easing::InOutSine => -((PI * t).cos() - 1.0) / 2.0,
+        }
+    }
+}
+
+/// The color space that is used to interpolate between two keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Rgb,
+    Hsl,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Self::Rgb
+    }
+}
+
+/// A rectangular area on the keyboard, in key coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Zone {
+    /// Returns true if the zone lies completely within the grid of keys
+    fn is_on_keyboard(&self) -> bool {
+        self.x
+            .checked_add(self.width)
+            .map_or(false, |right| right <= native::MAX_KEYS_PER_ROW)
+            && self
+                .y
+                .checked_add(self.height)
+                .map_or(false, |bottom| bottom <= native::NUM_ROWS)
+    }
+}
+
+/// The color of a track at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Time offset in milliseconds, relative to the start of the animation
+    pub time: u64,
+
+    /// The color, in the format 0xAARRGGBB
+    pub color: u32,
+
+    /// Easing of the transition to the next keyframe, overrides the easing of the track
+    #[serde(default)]
+    pub easing: Option<Easing>,
+}
+
+/// A sequence of keyframes that is applied to a set of keys and/or zones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    /// Key indices, as passed to the `on_key_down` event handler of Lua scripts
+    #[serde(default)]
+    pub keys: Vec<usize>,
+
+    /// Rectangular areas on the keyboard
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+
+    #[serde(default)]
+    pub easing: Easing,
+
+    #[serde(default)]
+    pub interpolation: Interpolation,
+
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    /// Returns the canvas indices affected by this track. A track
+    /// without keys and zones covers the whole canvas
+    fn canvas_indices(&self) -> Vec<usize> {
+        if self.keys.is_empty() && self.zones.is_empty() {
+            return (0..constants::CANVAS_SIZE).collect();
+        }
+
+        // keys are 1-based, invalid keys are rejected by `Animation::validate`
+        let mut result: Vec<usize> = self.keys.iter().filter_map(|k| k.checked_sub(1)).collect();
+
+        for zone in self.zones.iter() {
+            let rows = zone.y..zone.y.saturating_add(zone.height).min(native::NUM_ROWS);
+            let columns = zone.x
+                ..zone
+                    .x
+                    .saturating_add(zone.width)
+                    .min(native::MAX_KEYS_PER_ROW);
+
+            for y in rows {
+                for x in columns.clone() {
+                    if let Some(idx) = native::canvas_index(x, y) {
+                        result.push(idx);
+                    }
+                }
+            }
+        }
+
+        result.retain(|idx| *idx < constants::CANVAS_SIZE);
+        result.sort_unstable();
+        result.dedup();
+
+        result
+    }
+
+    /// Compute the color of the track at time `time`
+    fn color_at(&self, time: u64) -> u32 {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+
+        if time <= first.time {
+            return first.color;
+        }
+
+        if time >= last.time {
+            return last.color;
+        }
+
+        let next = self.keyframes.iter().position(|k| k.time > time).unwrap();
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+
+        let p = (time - from.time) as f64 / (to.time - from.time) as f64;
+        let p = from.easing.unwrap_or(self.easing).apply(p);
+
+        match self.interpolation {
+            Interpolation::Rgb => interpolate_rgb(from.color, to.color, p),
+            Interpolation::Hsl => interpolate_hsl(from.color, to.color, p),
+        }
+    }
+}
+
+/// A declarative animation, loaded from a TOML or JSON file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Animation {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    /// Length of a single iteration in milliseconds
+    pub duration: u64,
+
+    #[serde(default)]
+    pub repeat: RepeatMode,
+
+    pub tracks: Vec<Track>,
+
+    /// Elapsed time in milliseconds, advanced on each frame
+    #[serde(skip)]
+    elapsed: u64,
+
+    /// Cached canvas indices of each track
+    #[serde(skip)]
+    indices: Vec<Vec<usize>>,
+}
+
+impl Animation {
+    /// Returns true if `path` names an animation file
+    pub fn is_animation_file(path: &Path) -> bool {
+        path.extension()
+            .map_or(false, |ext| ext == ANIMATION_FILE_EXTENSION)
+    }
+
+    /// Load and validate an animation file. The file may either
+    /// be written in TOML or in JSON syntax
+    pub fn from(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| AnimationError::OpenError {
+            description: format!("{}: {}", path.display(), e),
+        })?;
+
+        let mut result: Self = if text.trim_start().starts_with('{') {
+            serde_json::from_str(&text).map_err(|e| AnimationError::ParseError {
+                description: format!("{}: {}", path.display(), e),
+            })?
+        } else {
+            toml::de::from_str(&text).map_err(|e| AnimationError::ParseError {
+                description: format!("{}: {}", path.display(), e),
+            })?
+        };
+
+        result.validate()?;
+
+        for track in result.tracks.iter_mut() {
+            track.keyframes.sort_by_key(|k| k.time);
+        }
+
+        result.indices = result.tracks.iter().map(|t| t.canvas_indices()).collect();
+
+        Ok(result)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.duration == 0 {
+            return Err(AnimationError::ValidationError {
+                description: "The duration must be greater than zero".to_string(),
+            }
+            .into());
+        }
+
+        for (idx, track) in self.tracks.iter().enumerate() {
+            if track.keyframes.is_empty() {
+                return Err(AnimationError::ValidationError {
+                    description: format!("Track #{} has no keyframes", idx + 1),
+                }
+                .into());
+            }
+
+            if let Some(key) = track
+                .keys
+                .iter()
+                .find(|k| **k == 0 || **k > constants::CANVAS_SIZE)
+            {
+                return Err(AnimationError::ValidationError {
+                    description: format!("Track #{}: Invalid key index {}", idx + 1, key),
+                }
+                .into());
+            }
+
+            if let Some(zone) = track.zones.iter().find(|z| !z.is_on_keyboard()) {
+                return Err(AnimationError::ValidationError {
+                    description: format!(
+                        "Track #{}: The zone at {},{} of {}x{} keys exceeds the keyboard of {}x{} keys",
+                        idx + 1,
+                        zone.x,
+                        zone.y,
+                        zone.width,
+                        zone.height,
+                        native::MAX_KEYS_PER_ROW,
+                        native::NUM_ROWS
+                    ),
+                }
+                .into());
+            }
+
+            if let Some(keyframe) = track.keyframes.iter().find(|k| k.time > self.duration) {
+                return Err(AnimationError::ValidationError {
+                    description: format!(
+                        "Track #{}: Keyframe at {} ms exceeds the duration of the animation",
+                        idx + 1,
+                        keyframe.time
+                    ),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Map the elapsed time to a point in time within a single iteration
+    fn current_time(&self) -> u64 {
+        match self.repeat {
+            RepeatMode::Once => self.elapsed.min(self.duration),
+
+            RepeatMode::Loop => self.elapsed % self.duration,
+
+            RepeatMode::PingPong => {
+                let t = self.elapsed % (self.duration * 2);
+
+                if t > self.duration {
+                    self.duration * 2 - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+}
+
+impl NativeEffect for Animation {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn on_tick(&mut self, canvas: &mut [RGBA]) -> Result<()> {
+        let time = self.current_time();
+
+        // later tracks paint over earlier ones
+        for (track, indices) in self.tracks.iter().zip(self.indices.iter()) {
+            let (r, g, b, a) = color_to_rgba(track.color_at(time));
+
+            for idx in indices.iter() {
+                canvas[*idx] = RGBA { r, g, b, a };
+            }
+        }
+
+        self.elapsed += 1000 / constants::TARGET_FPS;
+
+        Ok(())
+    }
+}
+
+fn color_to_rgba(c: u32) -> (u8, u8, u8, u8) {
+    (
+        ((c >> 16) & 0xff) as u8,
+        ((c >> 8) & 0xff) as u8,
+        (c & 0xff) as u8,
+        ((c >> 24) & 0xff) as u8,
+    )
+}
+
+fn rgba_to_color(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+fn lerp(a: f64, b: f64, p: f64) -> f64 {
+    a + (b - a) * p
+}
+
+fn interpolate_rgb(start: u32, dest: u32, p: f64) -> u32 {
+    let (sr, sg, sb, sa) = color_to_rgba(start);
+    let (dr, dg, db, da) = color_to_rgba(dest);
+
+    rgba_to_color(
+        lerp(sr as f64, dr as f64, p).round() as u8,
+        lerp(sg as f64, dg as f64, p).round() as u8,
+        lerp(sb as f64, db as f64, p).round() as u8,
+        lerp(sa as f64, da as f64, p).round() as u8,
+    )
+}
+
+/// Interpolate in the HSL color space, taking the shortest path around the hue circle
+fn interpolate_hsl(start: u32, dest: u32, p: f64) -> u32 {
+    let (sr, sg, sb, sa) = color_to_rgba(start);
+    let (dr, dg, db, da) = color_to_rgba(dest);
+
+    let (sh, ss, sl) = rgb_to_hsl(sr, sg, sb);
+    let (dh, ds, dl) = rgb_to_hsl(dr, dg, db);
+
+    let mut delta = dh - sh;
+    if delta > 180.0 {
+        delta -= 360.0;
+    } else if delta < -180.0 {
+        delta += 360.0;
+    }
+
+    let h = (sh + delta * p).rem_euclid(360.0);
+    let (r, g, b) = hsl_to_rgb(h, lerp(ss, ds, p), lerp(sl, dl, p));
+
+    rgba_to_color(r, g, b, lerp(sa as f64, da as f64, p).round() as u8)
+}
+
+#[allow(clippy::many_single_char_names)]
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+
+    if (max - min).abs() < f64::EPSILON {
+        return (0.0, 0.0, l);
+    }
+
+    let d = max - min;
+    let s = if l > 0.5 {
+        d / (2.0 - max - min)
+    } else {
+        d / (max + min)
+    };
+
+    let h = if (max - r).abs() < f64::EPSILON {
+        (g - b) / d + if g < b { 6.0 } else { 0.0 }
+    } else if (max - g).abs() < f64::EPSILON {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+
+    (h * 60.0, s, l)
+}
+
+#[allow(clippy::many_single_char_names)]
+fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (u8, u8, u8) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    (
+        ((r + m) * 255.0).round() as u8,
+        ((g + m) * 255.0).round() as u8,
+        ((b + m) * 255.0).round() as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Animation {
+        toml::de::from_str::<Animation>(toml).unwrap()
+    }
+
+    fn track(keys: &[usize], zones: &[Zone]) -> Track {
+        Track {
+            keys: keys.to_vec(),
+            zones: zones.to_vec(),
+            easing: Easing::default(),
+            interpolation: Interpolation::default(),
+            keyframes: vec![Keyframe {
+                time: 0,
+                color: 0xffffffff,
+                easing: None,
+            }],
+        }
+    }
+
+    fn zone(x: usize, y: usize, width: usize, height: usize) -> Zone {
+        Zone {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_canvas_indices() {
+        assert_eq!(
+            track(&[], &[]).canvas_indices(),
+            (0..constants::CANVAS_SIZE).collect::<Vec<usize>>()
+        );
+
+        // keys are 1-based
+        assert_eq!(track(&[3, 1, 2, 1], &[]).canvas_indices(), vec![0, 1, 2]);
+
+        // the first three keys of the top row, and the first two keys of the leftmost column
+        assert_eq!(
+            track(&[], &[zone(0, 0, 3, 1), zone(0, 0, 1, 2)]).canvas_indices(),
+            vec![0x00, 0x01, 0x0b, 0x11]
+        );
+
+        // positions without a key are skipped
+        assert!(track(&[], &[zone(16, 0, 6, 1)]).canvas_indices().is_empty());
+
+        // out of range input is ignored instead of overflowing
+        assert_eq!(track(&[0], &[]).canvas_indices(), Vec::<usize>::new());
+        assert_eq!(
+            track(&[constants::CANVAS_SIZE + 1], &[]).canvas_indices(),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            track(&[], &[zone(usize::MAX, usize::MAX, usize::MAX, usize::MAX)]).canvas_indices(),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            track(&[], &[zone(0, 0, usize::MAX, 1)])
+                .canvas_indices()
+                .len(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let animation = |tracks: Vec<Track>| {
+            let mut result = parse("name = 'Test'\nduration = 1000\ntracks = []");
+            result.tracks = tracks;
+            result
+        };
+
+        assert!(animation(vec![track(&[], &[])]).validate().is_ok());
+        assert!(animation(vec![track(&[1, constants::CANVAS_SIZE], &[])])
+            .validate()
+            .is_ok());
+        assert!(animation(vec![track(&[], &[zone(0, 0, 22, 6)])])
+            .validate()
+            .is_ok());
+
+        assert!(animation(vec![track(&[0], &[])]).validate().is_err());
+        assert!(animation(vec![track(&[constants::CANVAS_SIZE + 1], &[])])
+            .validate()
+            .is_err());
+
+        assert!(animation(vec![track(&[], &[zone(0, 0, 23, 1)])])
+            .validate()
+            .is_err());
+        assert!(animation(vec![track(&[], &[zone(21, 5, 1, 2)])])
+            .validate()
+            .is_err());
+        assert!(animation(vec![track(&[], &[zone(1, 0, usize::MAX, 1)])])
+            .validate()
+            .is_err());
+        assert!(
+            animation(vec![track(&[], &[zone(0, usize::MAX, 1, usize::MAX)])])
+                .validate()
+                .is_err()
+        );
+
+        let mut empty = track(&[], &[]);
+        empty.keyframes.clear();
+        assert!(animation(vec![empty]).validate().is_err());
+
+        let mut late = track(&[], &[]);
+        late.keyframes[0].time = 1001;
+        assert!(animation(vec![late]).validate().is_err());
+    }
+
+    #[test]
+    fn test_color_at() {
+        let animation = parse(
+            r#"
+            name = 'Test'
+            duration = 1000
+
+            [[tracks]]
+            keyframes = [
+                { time = 1000, color = 0xff0000ff },
+                { time = 0, color = 0xffff0000 },
+            ]
+            "#,
+        );
+
+        let mut track = animation.tracks[0].clone();
+        track.keyframes.sort_by_key(|k| k.time);
+
+        assert_eq!(track.color_at(0), 0xffff0000);
+        assert_eq!(track.color_at(500), 0xff800080);
+        assert_eq!(track.color_at(1000), 0xff0000ff);
+        assert_eq!(track.color_at(2000), 0xff0000ff);
+    }
+}
@@ -0,0 +1,267 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use image::gif::GifDecoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, GenericImageView};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::constants;
+use crate::hwdevices::RGBA;
+use crate::profiles::Profile;
+use crate::scripting::native::{self, NativeEffect};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Supported file name extensions of image files
+pub const IMAGE_FILE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "gif"];
+
+/// The delay of GIF frames that do not specify one, in milliseconds
+const DEFAULT_FRAME_DELAY_MILLIS: u64 = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImagePlaybackError {
+    #[error("Could not load image file: {description}")]
+    OpenError { description: String },
+
+    #[error("Invalid parameter value: {description}")]
+    ParameterError { description: String },
+}
+
+/// How an image is scaled to the size of the keyboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    /// Scale to the exact size, ignoring the aspect ratio
+    Stretch,
+
+    /// Scale to fit, preserving the aspect ratio, leaving the rest transparent
+    Contain,
+
+    /// Scale to fill, preserving the aspect ratio, cropping what is left over
+    Cover,
+}
+
+impl Fit {
+    fn parse(val: &str) -> Result<Self> {
+        match val {
+            "stretch" => Ok(Fit::Stretch),
+            "contain" => Ok(Fit::Contain),
+            "cover" => Ok(Fit::Cover),
+
+            _ => Err(ImagePlaybackError::ParameterError {
+                description: format!("Unknown fit: {}", val),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Sampling filter, used when scaling an image
+fn parse_sampling(val: &str) -> Result<FilterType> {
+    match val {
+        "nearest" => Ok(FilterType::Nearest),
+        "bilinear" => Ok(FilterType::Triangle),
+        "bicubic" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos" => Ok(FilterType::Lanczos3),
+
+        _ => Err(ImagePlaybackError::ParameterError {
+            description: format!("Unknown sampling filter: {}", val),
+        }
+        .into()),
+    }
+}
+
+/// A single pre-rendered frame
+struct Frame {
+    canvas: Vec<RGBA>,
+    delay: u64,
+}
+
+/// Plays back a still image or an animated GIF
+pub struct ImagePlayback {
+    name: String,
+    frames: Vec<Frame>,
+
+    /// Index of the currently displayed frame
+    current: usize,
+
+    /// Time in milliseconds that the current frame has been displayed
+    elapsed: u64,
+}
+
+impl ImagePlayback {
+    /// Returns true if `path` names a supported image file
+    pub fn is_image_file(path: &Path) -> bool {
+        path.extension().map_or(false, |ext| {
+            IMAGE_FILE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+        })
+    }
+
+    /// Load an image file, and pre-render all of its frames.
+    /// Parameters are read from the config section of `profile`
+    /// that is named after the file, e.g. `[[config."logo.gif"]]`
+    pub fn from(path: &Path, profile: Option<&Profile>) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let mut fit = Fit::Contain;
+        let mut sampling = FilterType::Gaussian;
+        let mut opacity = 1.0;
+
+        if let Some(profile) = profile {
+            if let Some(val) = profile
+                .get_choice_value(&name, "fit")
+                .or_else(|| profile.get_string_value(&name, "fit"))
+            {
+                fit = Fit::parse(val)?;
+            }
+
+            if let Some(val) = profile
+                .get_choice_value(&name, "sampling")
+                .or_else(|| profile.get_string_value(&name, "sampling"))
+            {
+                sampling = parse_sampling(val)?;
+            }
+
+            if let Some(val) = profile.get_float_value(&name, "opacity") {
+                opacity = val.max(0.0).min(1.0);
+            }
+        }
+
+        let open_error = |e: &dyn std::fmt::Display| ImagePlaybackError::OpenError {
+            description: format!("{}: {}", path.display(), e),
+        };
+
+        let is_gif = path
+            .extension()
+            .map_or(false, |ext| ext.to_string_lossy().to_lowercase() == "gif");
+
+        let frames = if is_gif {
+            let file = File::open(path).map_err(|e| open_error(&e))?;
+            let decoder = GifDecoder::new(BufReader::new(file)).map_err(|e| open_error(&e))?;
+
+            decoder
+                .into_frames()
+                .collect_frames()
+                .map_err(|e| open_error(&e))?
+                .into_iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    let delay = match (numer as u64).checked_div(denom as u64) {
+                        Some(0) | None => DEFAULT_FRAME_DELAY_MILLIS,
+                        Some(delay) => delay,
+                    };
+
+                    let img = DynamicImage::ImageRgba8(frame.into_buffer());
+
+                    Frame {
+                        canvas: render(&img, fit, sampling, opacity),
+                        delay,
+                    }
+                })
+                .collect()
+        } else {
+            let img = image::open(path).map_err(|e| open_error(&e))?;
+
+            vec![Frame {
+                canvas: render(&img, fit, sampling, opacity),
+                delay: u64::MAX,
+            }]
+        };
+
+        if frames.is_empty() {
+            return Err(open_error(&"The image does not contain any frames").into());
+        }
+
+        Ok(Self {
+            name,
+            frames,
+            current: 0,
+            elapsed: 0,
+        })
+    }
+}
+
+/// Scale `img` to the size of the keyboard and map its pixels to the canvas
+fn render(img: &DynamicImage, fit: Fit, sampling: FilterType, opacity: f64) -> Vec<RGBA> {
+    let width = native::MAX_KEYS_PER_ROW as u32;
+    let height = native::NUM_ROWS as u32;
+
+    let img = match fit {
+        Fit::Stretch => img.resize_exact(width, height, sampling),
+        Fit::Contain => img.resize(width, height, sampling),
+        Fit::Cover => img.resize_to_fill(width, height, sampling),
+    };
+
+    // center the image, if it is smaller than the keyboard
+    let offset_x = (width - img.width().min(width)) / 2;
+    let offset_y = (height - img.height().min(height)) / 2;
+
+    let mut canvas = vec![
+        RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0x00,
+        };
+        constants::CANVAS_SIZE
+    ];
+
+    for y in 0..img.height().min(height) {
+        for x in 0..img.width().min(width) {
+            let index = native::canvas_index((x + offset_x) as usize, (y + offset_y) as usize);
+
+            if let Some(index) = index {
+                let pixel = img.get_pixel(x, y);
+
+                canvas[index] = RGBA {
+                    r: pixel[0],
+                    g: pixel[1],
+                    b: pixel[2],
+                    a: (pixel[3] as f64 * opacity).round() as u8,
+                };
+            }
+        }
+    }
+
+    canvas
+}
+
+impl NativeEffect for ImagePlayback {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn on_tick(&mut self, canvas: &mut [RGBA]) -> Result<()> {
+        canvas.copy_from_slice(&self.frames[self.current].canvas);
+
+        self.elapsed += 1000 / constants::TARGET_FPS;
+
+        // skip frames that are shorter than a tick
+        while self.elapsed >= self.frames[self.current].delay {
+            self.elapsed -= self.frames[self.current].delay;
+            self.current = (self.current + 1) % self.frames.len();
+        }
+
+        Ok(())
+    }
+}
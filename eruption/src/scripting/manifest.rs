@@ -23,7 +23,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use crate::profiles;
+use crate::constants;
+use crate::profiles::{self, GradientStop};
 use crate::util;
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
@@ -41,6 +42,15 @@ pub enum ManifestError {
 
     #[error("Could not parse param value")]
     ParseParamError {},
+
+    #[error("Param value is out of range")]
+    ValueOutOfRange {},
+
+    #[error("Param value is not one of the available choices")]
+    InvalidChoice {},
+
+    #[error("Could not parse version string: {version}")]
+    VersionParseError { version: String },
 }
 
 fn default_id() -> usize {
@@ -51,6 +61,13 @@ fn default_script_file() -> PathBuf {
     "".into()
 }
 
+/// A labelled option of a `choice` parameter
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
+pub struct ChoiceOption {
+    pub value: String,
+    pub label: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ConfigParam {
@@ -58,11 +75,23 @@ pub enum ConfigParam {
         name: String,
         description: String,
         default: i64,
+        #[serde(default)]
+        min: Option<i64>,
+        #[serde(default)]
+        max: Option<i64>,
+        #[serde(default)]
+        step: Option<i64>,
     },
     Float {
         name: String,
         description: String,
         default: f64,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+        #[serde(default)]
+        step: Option<f64>,
     },
     Bool {
         name: String,
@@ -79,6 +108,136 @@ pub enum ConfigParam {
         description: String,
         default: u32,
     },
+    Choice {
+        name: String,
+        description: String,
+        default: String,
+        options: Vec<ChoiceOption>,
+    },
+    Gradient {
+        name: String,
+        description: String,
+        default: Vec<GradientStop>,
+    },
+    KeySet {
+        name: String,
+        description: String,
+        default: Vec<u8>,
+    },
+}
+
+/// Parse a color value in "#rrggbb" or "#aarrggbb" notation
+fn parse_color(val: &str) -> Result<u32> {
+    if !val.starts_with('#') {
+        return Err(ManifestError::ParseParamError {}.into());
+    }
+
+    let value =
+        u32::from_str_radix(&val[1..], 16).map_err(|_e| ManifestError::ParseParamError {})?;
+
+    Ok(value)
+}
+
+/// Parse a list of gradient stops, e.g.: "0.0:#ff0000,0.5:#00ff00,1.0:#0000ff"
+fn parse_gradient(val: &str) -> Result<Vec<GradientStop>> {
+    let mut result: Vec<GradientStop> = vec![];
+
+    for stop in val.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let mut parts = stop.splitn(2, ':');
+
+        let position = f64::from_str(parts.next().unwrap_or("").trim())
+            .map_err(|_e| ManifestError::ParseParamError {})?;
+        let color = parse_color(
+            parts
+                .next()
+                .ok_or(ManifestError::ParseParamError {})?
+                .trim(),
+        )?;
+
+        if !(0.0..=1.0).contains(&position) {
+            return Err(ManifestError::ValueOutOfRange {}.into());
+        }
+
+        // stops have to be sorted by their position
+        if let Some(last) = result.last() {
+            if position < last.position {
+                return Err(ManifestError::ParseParamError {}.into());
+            }
+        }
+
+        result.push(GradientStop { position, color });
+    }
+
+    if result.is_empty() {
+        return Err(ManifestError::ParseParamError {}.into());
+    }
+
+    Ok(result)
+}
+
+/// Parse a set of key indices, e.g.: "1,2,3,17"
+fn parse_key_set(val: &str) -> Result<Vec<u8>> {
+    let mut result = vec![];
+
+    for key in val.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let index = u8::from_str(key).map_err(|_e| ManifestError::ParseParamError {})?;
+
+        if index as usize >= constants::MAX_KEYS {
+            return Err(ManifestError::ValueOutOfRange {}.into());
+        }
+
+        if !result.contains(&index) {
+            result.push(index);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Check whether `value` is within the bounds `min` and `max`,
+/// and whether it is a multiple of `step`, counting from `min`
+fn check_int_constraints(
+    value: i64,
+    min: Option<i64>,
+    max: Option<i64>,
+    step: Option<i64>,
+) -> Result<()> {
+    if min.map_or(false, |min| value < min) || max.map_or(false, |max| value > max) {
+        return Err(ManifestError::ValueOutOfRange {}.into());
+    }
+
+    if let Some(step) = step {
+        if step > 0 && (value - min.unwrap_or(0)) % step != 0 {
+            return Err(ManifestError::ValueOutOfRange {}.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether `value` is within the bounds `min` and `max`,
+/// and whether it is a multiple of `step`, counting from `min`
+fn check_float_constraints(
+    value: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    step: Option<f64>,
+) -> Result<()> {
+    if min.map_or(false, |min| value < min) || max.map_or(false, |max| value > max) {
+        return Err(ManifestError::ValueOutOfRange {}.into());
+    }
+
+    if let Some(step) = step {
+        if step > 0.0 {
+            let steps = (value - min.unwrap_or(0.0)) / step;
+
+            if (steps - steps.round()).abs() > 1e-6 {
+                return Err(ManifestError::ValueOutOfRange {}.into());
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub trait ParseConfig {
@@ -89,11 +248,19 @@ impl ParseConfig for Vec<ConfigParam> {
     fn parse_config_param(&self, param: &str, val: &str) -> Result<profiles::ConfigParam> {
         for p in self.iter() {
             match &p {
-                ConfigParam::Int { name, .. } => {
+                ConfigParam::Int {
+                    name,
+                    min,
+                    max,
+                    step,
+                    ..
+                } => {
                     if name == param {
                         let value =
                             i64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?;
 
+                        check_int_constraints(value, *min, *max, *step)?;
+
                         return Ok(profiles::ConfigParam::Int {
                             name: name.to_string(),
                             value,
@@ -101,11 +268,19 @@ impl ParseConfig for Vec<ConfigParam> {
                     }
                 }
 
-                ConfigParam::Float { name, .. } => {
+                ConfigParam::Float {
+                    name,
+                    min,
+                    max,
+                    step,
+                    ..
+                } => {
                     if name == param {
                         let value =
                             f64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?;
 
+                        check_float_constraints(value, *min, *max, *step)?;
+
                         return Ok(profiles::ConfigParam::Float {
                             name: name.to_string(),
                             value,
@@ -138,8 +313,7 @@ impl ParseConfig for Vec<ConfigParam> {
 
                 ConfigParam::Color { name, .. } => {
                     if name == param {
-                        let value = u32::from_str_radix(&val[1..], 16)
-                            .map_err(|_e| ManifestError::ParseParamError {})?;
+                        let value = parse_color(&val)?;
 
                         return Ok(profiles::ConfigParam::Color {
                             name: name.to_string(),
@@ -147,6 +321,41 @@ impl ParseConfig for Vec<ConfigParam> {
                         });
                     }
                 }
+
+                ConfigParam::Choice { name, options, .. } => {
+                    if name == param {
+                        if !options.iter().any(|o| o.value == val) {
+                            return Err(ManifestError::InvalidChoice {}.into());
+                        }
+
+                        return Ok(profiles::ConfigParam::Choice {
+                            name: name.to_string(),
+                            value: val.to_owned(),
+                        });
+                    }
+                }
+
+                ConfigParam::Gradient { name, .. } => {
+                    if name == param {
+                        let value = parse_gradient(&val)?;
+
+                        return Ok(profiles::ConfigParam::Gradient {
+                            name: name.to_string(),
+                            value,
+                        });
+                    }
+                }
+
+                ConfigParam::KeySet { name, .. } => {
+                    if name == param {
+                        let value = parse_key_set(&val)?;
+
+                        return Ok(profiles::ConfigParam::KeySet {
+                            name: name.to_string(),
+                            value,
+                        });
+                    }
+                }
             }
         }
 
@@ -171,6 +380,12 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref name, .. } => name,
 
             ConfigParam::Color { ref name, .. } => name,
+
+            ConfigParam::Choice { ref name, .. } => name,
+
+            ConfigParam::Gradient { ref name, .. } => name,
+
+            ConfigParam::KeySet { ref name, .. } => name,
         }
     }
 
@@ -185,10 +400,63 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref default, .. } => default.to_owned(),
 
             ConfigParam::Color { ref default, .. } => format!("#{:06x}", default),
+
+            ConfigParam::Choice { ref default, .. } => default.to_owned(),
+
+            ConfigParam::Gradient { ref default, .. } => profiles::format_gradient(default),
+
+            ConfigParam::KeySet { ref default, .. } => profiles::format_key_set(default),
         }
     }
 }
 
+/// Classes of devices that a script may depend on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceClass {
+    Keyboard,
+    Mouse,
+}
+
+/// Requirements of a script, they have to be met before the script will be executed
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Requirements {
+    /// Lua libraries, as passed to `require`
+    #[serde(default)]
+    pub libs: Vec<String>,
+
+    /// Names of Eruption plugins, like e.g. "Audio" or "Sensors"
+    #[serde(default)]
+    pub plugins: Vec<String>,
+
+    /// Classes of devices that need to be present
+    #[serde(default)]
+    pub devices: Vec<DeviceClass>,
+}
+
+/// Parse a version string like "0.1.19" into its numeric components
+pub fn parse_version(version: &str) -> Result<Vec<u32>> {
+    let mut result = version
+        .trim()
+        .split('.')
+        .map(|c| {
+            // ignore suffixes like "-beta"
+            let digits: String = c.chars().take_while(|c| c.is_ascii_digit()).collect();
+
+            u32::from_str(&digits).map_err(|_e| ManifestError::VersionParseError {
+                version: version.to_string(),
+            })
+        })
+        .collect::<std::result::Result<Vec<u32>, ManifestError>>()?;
+
+    // normalize, so that e.g. "0.1" equals "0.1.0"
+    while result.len() < 3 {
+        result.push(0);
+    }
+
+    Ok(result)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Manifest {
     #[serde(default = "default_id")]
@@ -203,6 +471,7 @@ pub struct Manifest {
     pub min_supported_version: String,
     pub tags: Option<Vec<ScriptTag>>,
     pub config: Option<Vec<ConfigParam>>,
+    pub requires: Option<Requirements>,
 }
 
 impl std::cmp::PartialOrd for Manifest {
@@ -240,6 +509,14 @@ impl Manifest {
     pub fn from(script: &Path) -> Result<Self> {
         Self::new(default_id(), script)
     }
+
+    /// Returns true if the script supports being run by Eruption version `version`
+    pub fn is_supported_by(&self, version: &str) -> Result<bool> {
+        let required = parse_version(&self.min_supported_version)?;
+        let current = parse_version(version)?;
+
+        Ok(current >= required)
+    }
 }
 
 /// Get a `Vec` of `PathBufs` of available script files in the directory `script_path`.
@@ -312,6 +589,173 @@ pub fn get_scripts(script_path: &Path) -> Result<Vec<Manifest>> {
     Ok(result)
 }
 
+/// A problem of a profile, as found by `validate_profile`
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ValidationError {
+    #[error("The script '{script}' does not exist")]
+    MissingScript { script: String },
+
+    #[error("The manifest of the script '{script}' is invalid: {description}")]
+    InvalidManifest { script: String, description: String },
+
+    #[error("The config section \"{section}\" does not match the name of any script")]
+    UnknownSection { section: String },
+
+    #[error("The script \"{section}\" has no parameter named '{param}'")]
+    UnknownParam { section: String, param: String },
+
+    #[error("The parameter '{param}' of \"{section}\" must be of type '{expected}'")]
+    WrongType {
+        section: String,
+        param: String,
+        expected: &'static str,
+    },
+
+    #[error("The value of the parameter '{param}' of \"{section}\" is invalid: {description}")]
+    InvalidValue {
+        section: String,
+        param: String,
+        description: String,
+    },
+}
+
+impl ValidationError {
+    /// Returns true if the problem breaks the profile. Unknown sections and parameters are
+    /// ignored when the profile is loaded, the scripts use their default values instead
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            ValidationError::UnknownSection { .. } | ValidationError::UnknownParam { .. }
+        )
+    }
+}
+
+/// Returns the name of the type of a parameter, as used in manifests and profiles
+fn param_type(param: &ConfigParam) -> &'static str {
+    match param {
+        ConfigParam::Int { .. } => "int",
+        ConfigParam::Float { .. } => "float",
+        ConfigParam::Bool { .. } => "bool",
+        ConfigParam::String { .. } => "string",
+        ConfigParam::Color { .. } => "color",
+        ConfigParam::Choice { .. } => "choice",
+        ConfigParam::Gradient { .. } => "gradient",
+        ConfigParam::KeySet { .. } => "keyset",
+    }
+}
+
+/// Returns the name of the type of a parameter of a profile
+fn profile_param_type(param: &profiles::ConfigParam) -> &'static str {
+    match param {
+        profiles::ConfigParam::Int { .. } => "int",
+        profiles::ConfigParam::Float { .. } => "float",
+        profiles::ConfigParam::Bool { .. } => "bool",
+        profiles::ConfigParam::String { .. } => "string",
+        profiles::ConfigParam::Color { .. } => "color",
+        profiles::ConfigParam::Choice { .. } => "choice",
+        profiles::ConfigParam::Gradient { .. } => "gradient",
+        profiles::ConfigParam::KeySet { .. } => "keyset",
+    }
+}
+
+/// Check `profile` against the manifests of the scripts in `script_dir`: All of the active
+/// scripts have to exist, and each config section has to match the name of a script. The
+/// parameters have to be declared by the manifest of that script, with the same type, and
+/// their values have to satisfy the constraints of the manifest. Returns all problems found
+#[allow(dead_code)]
+pub fn validate_profile(profile: &profiles::Profile, script_dir: &Path) -> Vec<ValidationError> {
+    use profiles::GetAttr as _;
+
+    let mut result = Vec::new();
+
+    // native effects have no manifest, their config sections are named after their file name
+    let mut native_effects = Vec::new();
+
+    for script in profile.active_scripts.iter() {
+        let script_file = script_dir.join(script);
+
+        if !script_file.is_file() {
+            result.push(ValidationError::MissingScript {
+                script: script.display().to_string(),
+            });
+        } else if script_file.extension().unwrap_or_default() == "lua" {
+            if let Err(e) = Manifest::from(&script_file) {
+                result.push(ValidationError::InvalidManifest {
+                    script: script.display().to_string(),
+                    description: format!("{}", e),
+                });
+            }
+        } else if let Some(file_name) = script.file_name() {
+            native_effects.push(file_name.to_string_lossy().to_string());
+        }
+    }
+
+    // sections of scripts that are not active are checked as well
+    let manifests: Vec<Manifest> = get_script_files(script_dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|script_file| Manifest::from(script_file).ok())
+        .collect();
+
+    if let Some(config) = &profile.config {
+        let mut sections: Vec<&String> = config.keys().collect();
+        sections.sort();
+
+        for section in sections {
+            if native_effects.contains(section) {
+                continue;
+            }
+
+            let manifest = match manifests.iter().find(|m| &m.name == section) {
+                Some(manifest) => manifest,
+
+                None => {
+                    result.push(ValidationError::UnknownSection {
+                        section: section.clone(),
+                    });
+
+                    continue;
+                }
+            };
+
+            let empty = vec![];
+            let declared = manifest.config.as_ref().unwrap_or(&empty);
+
+            for param in config[section].iter() {
+                let name = param.get_name();
+
+                match declared.iter().find(|p| p.get_name() == name) {
+                    None => result.push(ValidationError::UnknownParam {
+                        section: section.clone(),
+                        param: name.clone(),
+                    }),
+
+                    Some(p) if param_type(p) != profile_param_type(param) => {
+                        result.push(ValidationError::WrongType {
+                            section: section.clone(),
+                            param: name.clone(),
+                            expected: param_type(p),
+                        })
+                    }
+
+                    // parse the value like a value from the UI, to check the constraints
+                    Some(_) => {
+                        if let Err(e) = declared.parse_config_param(name, &param.get_value()) {
+                            result.push(ValidationError::InvalidValue {
+                                section: section.clone(),
+                                param: name.clone(),
+                                description: format!("{}", e),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum ScriptTag {
     // Script "classes"
@@ -408,3 +852,229 @@ impl ScriptTag {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for the script files of the test `name`
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        let dir = test_dir("validate-profile");
+
+        fs::write(dir.join("test.lua"), "").unwrap();
+        fs::write(
+            dir.join("test.lua.manifest"),
+            r#"
+            name = "Test"
+            description = "A test script"
+            version = "0.0.1"
+            author = "The Eruption development team"
+            min_supported_version = "0.0.12"
+
+            [[config]]
+            type = 'int'
+            name = 'speed'
+            description = 'Speed'
+            default = 5
+            min = 0
+            max = 10
+
+            [[config]]
+            type = 'int'
+            name = 'level'
+            description = 'Level'
+            default = 1
+
+            [[config]]
+            type = 'choice'
+            name = 'mode'
+            description = 'Mode'
+            default = 'slow'
+            options = [{ value = 'slow', label = 'Slow' }, { value = 'fast', label = 'Fast' }]
+            "#,
+        )
+        .unwrap();
+
+        fs::write(dir.join("broken.lua"), "").unwrap();
+        fs::write(dir.join("broken.lua.manifest"), "name = ").unwrap();
+
+        fs::write(dir.join("effect.anim"), "").unwrap();
+
+        let profile = |toml: &str| toml::de::from_str::<profiles::Profile>(toml).unwrap();
+
+        let valid = profile(
+            r#"
+            name = 'Valid'
+            description = ''
+            active_scripts = ['test.lua', 'effect.anim']
+
+            [[config.Test]]
+            type = 'int'
+            name = 'speed'
+            value = 10
+
+            [[config.Test]]
+            type = 'choice'
+            name = 'mode'
+            value = 'fast'
+
+            [[config."effect.anim"]]
+            type = 'int'
+            name = 'frame_rate'
+            value = 24
+            "#,
+        );
+
+        let errors = validate_profile(&valid, &dir);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let invalid = profile(
+            r#"
+            name = 'Invalid'
+            description = ''
+            active_scripts = ['test.lua', 'missing.lua', 'broken.lua']
+
+            [[config.Test]]
+            type = 'int'
+            name = 'speed'
+            value = 11
+
+            [[config.Test]]
+            type = 'float'
+            name = 'level'
+            value = 1.0
+
+            [[config.Test]]
+            type = 'choice'
+            name = 'mode'
+            value = 'turbo'
+
+            [[config.Test]]
+            type = 'int'
+            name = 'unused'
+            value = 1
+
+            [[config.Unknown]]
+            type = 'int'
+            name = 'speed'
+            value = 1
+            "#,
+        );
+
+        let errors = validate_profile(&invalid, &dir);
+
+        let fatal = |e: &ValidationError| match e {
+            ValidationError::MissingScript { script } => script == "missing.lua",
+            ValidationError::InvalidManifest { script, .. } => script == "broken.lua",
+            ValidationError::WrongType {
+                param, expected, ..
+            } => param == "level" && *expected == "int",
+            ValidationError::InvalidValue { param, .. } => param == "speed" || param == "mode",
+            _ => false,
+        };
+
+        let non_fatal = |e: &ValidationError| match e {
+            ValidationError::UnknownSection { section } => section == "Unknown",
+            ValidationError::UnknownParam { section, param } => {
+                section == "Test" && param == "unused"
+            }
+            _ => false,
+        };
+
+        assert_eq!(errors.len(), 7, "{:?}", errors);
+        assert_eq!(errors.iter().filter(|e| fatal(e)).count(), 5);
+        assert_eq!(errors.iter().filter(|e| non_fatal(e)).count(), 2);
+
+        for e in errors.iter() {
+            assert_eq!(e.is_fatal(), fatal(e), "{}", e);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_gradient() {
+        assert_eq!(
+            parse_gradient("0.0:#ff0000, 0.5:#00ff00,1:#0000ff").unwrap(),
+            vec![
+                GradientStop {
+                    position: 0.0,
+                    color: 0xff0000
+                },
+                GradientStop {
+                    position: 0.5,
+                    color: 0x00ff00
+                },
+                GradientStop {
+                    position: 1.0,
+                    color: 0x0000ff
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse_gradient("0.5:#80ff0000").unwrap()[0].color,
+            0x80ff0000
+        );
+
+        // empty, unsorted, out of range, or malformed stops
+        assert!(parse_gradient("").is_err());
+        assert!(parse_gradient("0.5:#ff0000,0.25:#00ff00").is_err());
+        assert!(parse_gradient("1.5:#ff0000").is_err());
+        assert!(parse_gradient("-0.1:#ff0000").is_err());
+        assert!(parse_gradient("0.5").is_err());
+        assert!(parse_gradient("0.5:ff0000").is_err());
+        assert!(parse_gradient("x:#ff0000").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_set() {
+        assert_eq!(parse_key_set("1, 2,3,17").unwrap(), vec![1, 2, 3, 17]);
+        assert_eq!(parse_key_set("3,1,3").unwrap(), vec![3, 1]);
+        assert!(parse_key_set("").unwrap().is_empty());
+
+        assert!(parse_key_set(&format!("{}", constants::MAX_KEYS - 1)).is_ok());
+        assert!(parse_key_set(&format!("{}", constants::MAX_KEYS)).is_err());
+        assert!(parse_key_set("1,a").is_err());
+        assert!(parse_key_set("-1").is_err());
+    }
+
+    #[test]
+    fn test_check_int_constraints() {
+        assert!(check_int_constraints(5, None, None, None).is_ok());
+        assert!(check_int_constraints(0, Some(0), Some(10), None).is_ok());
+        assert!(check_int_constraints(10, Some(0), Some(10), None).is_ok());
+        assert!(check_int_constraints(-1, Some(0), Some(10), None).is_err());
+        assert!(check_int_constraints(11, Some(0), Some(10), None).is_err());
+
+        // steps are counted from min
+        assert!(check_int_constraints(7, Some(1), Some(10), Some(3)).is_ok());
+        assert!(check_int_constraints(6, Some(1), Some(10), Some(3)).is_err());
+        assert!(check_int_constraints(6, None, None, Some(3)).is_ok());
+        assert!(check_int_constraints(6, None, None, Some(0)).is_ok());
+    }
+
+    #[test]
+    fn test_check_float_constraints() {
+        assert!(check_float_constraints(0.5, Some(0.0), Some(1.0), None).is_ok());
+        assert!(check_float_constraints(1.01, Some(0.0), Some(1.0), None).is_err());
+        assert!(check_float_constraints(-0.01, Some(0.0), Some(1.0), None).is_err());
+
+        // rounding errors are tolerated
+        assert!(check_float_constraints(0.3, Some(0.0), Some(1.0), Some(0.1)).is_ok());
+        assert!(check_float_constraints(0.35, Some(0.0), Some(1.0), Some(0.1)).is_err());
+        assert!(check_float_constraints(0.75, Some(0.25), None, Some(0.5)).is_ok());
+        assert!(check_float_constraints(0.5, Some(0.25), None, Some(0.5)).is_err());
+    }
+}
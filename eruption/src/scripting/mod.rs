@@ -15,5 +15,8 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+pub mod animation;
+pub mod image_playback;
 pub mod manifest;
+pub mod native;
 pub mod script;
@@ -0,0 +1,234 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crossbeam::channel::Receiver;
+use log::*;
+use parking_lot::{Condvar, Mutex};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crate::constants;
+use crate::hwdevices::RGBA;
+use crate::profiles::Profile;
+use crate::scripting::animation::Animation;
+use crate::scripting::image_playback::ImagePlayback;
+use crate::scripting::script::{self, Message, RunScriptResult};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Number of rows of the keyboard layout
+pub const NUM_ROWS: usize = 6;
+
+/// Max. number of keys per row of the keyboard layout
+pub const MAX_KEYS_PER_ROW: usize = 22;
+
+/// Maps coordinates on the keyboard to key indices (ISO model),
+/// mirrors `rows_topology` of `lib/declarations.lua`
+#[rustfmt::skip]
+const ROWS_TOPOLOGY: [u8; NUM_ROWS * MAX_KEYS_PER_ROW] = [
+    0x00, 0x0b, 0x11, 0x17, 0x1c, 0x30, 0x35, 0x3b, 0x41, 0x4e, 0x54, 0x55, 0x56, 0x63, 0x67, 0x6c, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x01, 0x06, 0x0c, 0x12, 0x18, 0x1d, 0x21, 0x31, 0x36, 0x3c, 0x42, 0x48, 0x4f, 0x57, 0x64, 0x68, 0x6d, 0x71, 0x77, 0x7c, 0x81, 0xff,
+    0x02, 0x07, 0x0d, 0x13, 0x19, 0x1e, 0x22, 0x32, 0x37, 0x3d, 0x43, 0x49, 0x50, 0x58, 0x65, 0x69, 0x6e, 0x72, 0x78, 0x7d, 0x82, 0xff,
+    0x03, 0x08, 0x0e, 0x14, 0x1a, 0x1f, 0x23, 0x33, 0x38, 0x3e, 0x44, 0x4a, 0x60, 0x73, 0x79, 0x7e, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x04, 0x09, 0x0f, 0x15, 0x1b, 0x20, 0x24, 0x34, 0x39, 0x3f, 0x45, 0x4b, 0x52, 0x6a, 0x74, 0x7a, 0x7f, 0x83, 0xff, 0xff, 0xff, 0xff,
+    0x05, 0x0a, 0x10, 0x25, 0x46, 0x4c, 0x53, 0x59, 0x66, 0x6b, 0x6f, 0x75, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// Returns the canvas index of the key at the coordinates `x` and `y`,
+/// or `None` if there is no key at that position
+pub fn canvas_index(x: usize, y: usize) -> Option<usize> {
+    if x >= MAX_KEYS_PER_ROW || y >= NUM_ROWS {
+        return None;
+    }
+
+    match ROWS_TOPOLOGY[y * MAX_KEYS_PER_ROW + x] {
+        0xff => None,
+        index => Some(index as usize),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NativeEffectError {
+    #[error("Unsupported effect file: {path}")]
+    UnsupportedFile { path: String },
+}
+
+/// An effect that is implemented in Rust instead of Lua. Native effects are
+/// part of the script stack of a profile, and their color maps get blended
+/// exactly like the color maps of Lua VMs
+pub trait NativeEffect {
+    /// The name of the effect, used for diagnostics
+    fn name(&self) -> String;
+
+    /// Advance the effect by one frame and render it to `canvas`
+    fn on_tick(&mut self, canvas: &mut [RGBA]) -> Result<()>;
+
+    /// Called when a key has been pressed
+    fn on_key_down(&mut self, _key_index: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a key has been released
+    fn on_key_up(&mut self, _key_index: u8) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns true if `path` refers to a file that is handled by a native effect
+/// instead of the Lua interpreter
+pub fn is_native_effect(path: &Path) -> bool {
+    Animation::is_animation_file(path) || ImagePlayback::is_image_file(path)
+}
+
+/// Instantiate the native effect that handles the file `path`,
+/// using the parameters specified in `profile`
+pub fn load_effect(
+    path: &Path,
+    profile: Option<&Profile>,
+) -> Result<Box<dyn NativeEffect + Send>> {
+    if Animation::is_animation_file(path) {
+        Ok(Box::new(Animation::from(path)?))
+    } else if ImagePlayback::is_image_file(path) {
+        Ok(Box::new(ImagePlayback::from(path, profile)?))
+    } else {
+        Err(NativeEffectError::UnsupportedFile {
+            path: path.display().to_string(),
+        }
+        .into())
+    }
+}
+
+/// Decrement the counter of pending upcalls and wake up the waiting thread
+fn complete_upcall(condition: &(Mutex<usize>, Condvar)) {
+    *condition.0.lock() -= 1;
+    condition.1.notify_all();
+}
+
+/// Runs a native effect. This mirrors the event loop of `script::run_script`,
+/// so the main loop does not need to distinguish between native effects and Lua VMs
+pub fn run_effect(
+    mut effect: Box<dyn NativeEffect + Send>,
+    rx: &Receiver<Message>,
+) -> Result<RunScriptResult> {
+    let mut canvas = vec![
+        RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0x00,
+        };
+        constants::CANVAS_SIZE
+    ];
+
+    // nothing will be blended until the first frame has been rendered
+    let mut canvas_modified = false;
+
+    loop {
+        if let Ok(msg) = rx.recv() {
+            match msg {
+                Message::Quit(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_QUIT);
+                }
+
+                Message::Tick(_param) => {
+                    if let Err(e) = effect.on_tick(&mut canvas) {
+                        error!("Error in native effect '{}': {}", effect.name(), e);
+                        return Ok(RunScriptResult::TerminatedWithErrors);
+                    }
+
+                    canvas_modified = true;
+
+                    script::FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+                }
+
+                Message::RealizeColorMap => {
+                    if canvas_modified {
+                        script::blend_color_map(&canvas);
+                    }
+
+                    // signal readiness / notify the main thread that we are done
+                    let val = { *crate::COLOR_MAPS_READY_CONDITION.0.lock() };
+
+                    let val = val.checked_sub(1).unwrap_or_else(|| {
+                        warn!("Incorrect state in locking code detected");
+                        0
+                    });
+
+                    *crate::COLOR_MAPS_READY_CONDITION.0.lock() = val;
+
+                    crate::COLOR_MAPS_READY_CONDITION.1.notify_one();
+                }
+
+                Message::KeyDown(param) => {
+                    let result = effect.on_key_down(param);
+
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_KEY_DOWN);
+
+                    if let Err(e) = result {
+                        error!("Error in native effect '{}': {}", effect.name(), e);
+                        return Ok(RunScriptResult::TerminatedWithErrors);
+                    }
+                }
+
+                Message::KeyUp(param) => {
+                    let result = effect.on_key_up(param);
+
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_KEY_UP);
+
+                    if let Err(e) = result {
+                        error!("Error in native effect '{}': {}", effect.name(), e);
+                        return Ok(RunScriptResult::TerminatedWithErrors);
+                    }
+                }
+
+                Message::KeyboardHidEvent(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_KEYBOARD_HID_EVENT);
+                }
+
+                Message::MouseHidEvent(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_MOUSE_HID_EVENT);
+                }
+
+                Message::MouseButtonDown(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_DOWN);
+                }
+
+                Message::MouseButtonUp(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_UP);
+                }
+
+                Message::MouseMove(_rel_x, _rel_y, _rel_z) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_MOUSE_MOVE);
+                }
+
+                Message::MouseWheelEvent(_param) => {
+                    complete_upcall(&*crate::UPCALL_COMPLETED_ON_MOUSE_EVENT);
+                }
+
+                Message::Beat(_param) => {}
+
+                Message::Gesture(_param) => {}
+
+                Message::Unload => {
+                    debug!("Native effect '{}' terminated gracefully", effect.name());
+
+                    return Ok(RunScriptResult::TerminatedGracefully);
+                }
+            }
+        }
+    }
+}
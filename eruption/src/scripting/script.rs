@@ -23,11 +23,11 @@ use mlua::Function;
 use parking_lot::RwLock;
 use rand::Rng;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::vec::Vec;
@@ -35,7 +35,8 @@ use std::vec::Vec;
 use crate::constants;
 use crate::hwdevices::{KeyboardDevice, KeyboardHidEvent, MouseDevice, MouseHidEvent, RGBA};
 use crate::plugin_manager;
-use crate::scripting::manifest::{ConfigParam, Manifest};
+use crate::plugins::{audio, introspection, keyboard, persistence, system};
+use crate::scripting::manifest::{ConfigParam, DeviceClass, Manifest};
 
 use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
 
@@ -61,6 +62,12 @@ pub enum Message {
     MouseMove(i32, i32, i32),
     MouseWheelEvent(u8),
 
+    // Audio events
+    Beat(f32),
+
+    // Gesture events
+    Gesture(String),
+
     //LoadScript(PathBuf),
     // Abort,
     Unload,
@@ -88,6 +95,12 @@ lazy_static! {
 
     /// Frame generation counter, used to detect if we need to submit the LED_MAP to the keyboard
     pub static ref FRAME_GENERATION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Where `require` looks for Lua libraries, determined once
+    static ref LUA_SEARCH_PATHS: LuaSearchPaths = LuaSearchPaths::new();
+
+    /// Lua libraries that have been found by `is_lua_library_available`
+    static ref AVAILABLE_LUA_LIBRARIES: Arc<RwLock<HashSet<String>>> = Arc::new(RwLock::new(HashSet::new()));
 }
 
 thread_local! {
@@ -113,6 +126,15 @@ pub enum ScriptingError {
 
     #[error("Invalid value")]
     ValueError {},
+
+    #[error("Script requires Eruption version {required} or later")]
+    UnsupportedVersion { required: String },
+
+    #[error("Required Lua library is not available: {name}")]
+    MissingLibrary { name: String },
+
+    #[error("Required plugin is not available: {name}")]
+    MissingPlugin { name: String },
 }
 
 #[derive(Debug)]
@@ -299,26 +321,19 @@ mod callbacks {
             .unwrap();
     }
 
-    /// Inject a key on the eruption virtual keyboard after sleeping for `millis` milliseconds.
-    pub(crate) fn inject_key_with_delay(ev_key: u32, down: bool, millis: u64) {
+    /// Inject a key on the eruption virtual keyboard after `millis` milliseconds. Pending
+    /// injections may be cancelled by their `tag`, e.g. the name of the macro
+    pub(crate) fn inject_key_with_delay(ev_key: u32, down: bool, millis: u64, tag: Option<String>) {
         // calling inject_key(..) from Lua will drop the current input;
         // the original key event from the hardware keyboard will not be
         // mirrored on the virtual keyboard.
         macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
 
-        thread::Builder::new()
-            .name("uinput/delayed".to_owned())
-            .spawn(move || {
-                thread::sleep(Duration::from_millis(millis));
-
-                macros::UINPUT_TX
-                    .lock()
-                    .as_ref()
-                    .unwrap()
-                    .send(macros::Message::InjectKey { key: ev_key, down })
-                    .unwrap();
-            })
-            .unwrap();
+        macros::MacrosPlugin::schedule_message(
+            macros::Message::InjectKey { key: ev_key, down },
+            Duration::from_millis(millis),
+            tag.as_deref().unwrap_or_default(),
+        );
     }
 
     // pub(crate) fn set_status_led(keyboard_device: &KeyboardDevice, led_id: u8, on: bool) {
@@ -628,6 +643,162 @@ mod callbacks {
     }
 }
 
+/// The search paths of `require`, as set up for the Lua VMs of the scripts
+struct LuaSearchPaths {
+    path: String,
+    cpath: String,
+
+    /// Names of the libraries that are built into the Lua VM, like `string` or `math`
+    builtin: HashSet<String>,
+}
+
+impl LuaSearchPaths {
+    fn new() -> Self {
+        let lua_ctx = unsafe { Lua::unsafe_new_with(mlua::StdLib::ALL) };
+
+        let query = || -> mlua::Result<Self> {
+            register_support_globals(&lua_ctx)?;
+
+            let package: mlua::Table = lua_ctx.globals().get("package")?;
+
+            let mut builtin = HashSet::new();
+
+            for table in &["loaded", "preload"] {
+                for pair in package
+                    .get::<_, mlua::Table>(*table)?
+                    .pairs::<LuaValue, LuaValue>()
+                {
+                    if let (LuaValue::String(name), _) = pair? {
+                        builtin.insert(name.to_str()?.to_string());
+                    }
+                }
+            }
+
+            Ok(Self {
+                path: package.get("path")?,
+                cpath: package.get("cpath")?,
+                builtin,
+            })
+        };
+
+        query().unwrap_or_else(|e| {
+            error!(
+                "Could not determine the search paths of Lua libraries: {}",
+                e
+            );
+
+            Self {
+                path: String::new(),
+                cpath: String::new(),
+                builtin: HashSet::new(),
+            }
+        })
+    }
+
+    /// Returns true if a file for the library `name` exists in one of the templates of
+    /// `search_path`, like `package.searchpath` does
+    fn search(name: &str, search_path: &str) -> bool {
+        let name = name.replace('.', "/");
+
+        search_path
+            .split(';')
+            .filter(|template| !template.is_empty())
+            .any(|template| fs::File::open(template.replace('?', &name)).is_ok())
+    }
+}
+
+/// Returns true if the Lua library `name` may be loaded via `require`.
+/// The library is only searched for, but will not be loaded. Libraries that have been found
+/// are remembered, missing libraries are searched for again, since they may be installed later on
+pub fn is_lua_library_available(name: &str) -> bool {
+    if AVAILABLE_LUA_LIBRARIES.read().contains(name) {
+        return true;
+    }
+
+    let paths = &*LUA_SEARCH_PATHS;
+
+    let result = paths.builtin.contains(name)
+        || LuaSearchPaths::search(name, &paths.path)
+        || LuaSearchPaths::search(name, &paths.cpath);
+
+    if result {
+        AVAILABLE_LUA_LIBRARIES.write().insert(name.to_string());
+    }
+
+    result
+}
+
+/// Verify that the requirements of a script, as declared in its manifest, are met
+pub fn check_requirements(
+    manifest: &Manifest,
+    keyboard_devices: &[KeyboardDevice],
+    mouse_devices: &[MouseDevice],
+) -> Result<()> {
+    if !manifest.is_supported_by(env!("CARGO_PKG_VERSION"))? {
+        return Err(ScriptingError::UnsupportedVersion {
+            required: manifest.min_supported_version.clone(),
+        }
+        .into());
+    }
+
+    if let Some(requires) = &manifest.requires {
+        for lib in requires.libs.iter() {
+            if !is_lua_library_available(lib) {
+                return Err(ScriptingError::MissingLibrary { name: lib.clone() }.into());
+            }
+        }
+
+        let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
+
+        for plugin in requires.plugins.iter() {
+            if plugin_manager.find_plugin_by_name(plugin.clone()).is_none() {
+                return Err(ScriptingError::MissingPlugin {
+                    name: plugin.clone(),
+                }
+                .into());
+            }
+        }
+
+        // a missing device does not prevent the script from running
+        for device_class in requires.devices.iter() {
+            let present = match device_class {
+                DeviceClass::Keyboard => !keyboard_devices.is_empty(),
+                DeviceClass::Mouse => !mouse_devices.is_empty(),
+            };
+
+            if !present {
+                warn!(
+                    "Script '{}' requires a device of class {:?}, but none is connected",
+                    manifest.name, device_class
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Blend the color map `foreground` with the global LED_MAP, taking the
+/// alpha channel of `foreground` and the global brightness into account
+pub fn blend_color_map(foreground: &[RGBA]) {
+    let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst);
+
+    for (idx, background) in LED_MAP.write().iter_mut().enumerate() {
+        let bg = &background;
+        let fg = foreground[idx];
+
+        #[rustfmt::skip]
+        let color = RGBA {
+            r: ((((fg.a as f64) * fg.r as f64 + (255 - fg.a) as f64 * bg.r as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+            g: ((((fg.a as f64) * fg.g as f64 + (255 - fg.a) as f64 * bg.g as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+            b: ((((fg.a as f64) * fg.b as f64 + (255 - fg.a) as f64 * bg.b as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+            a: fg.a as u8,
+        };
+
+        *background = color;
+    }
+}
+
 /// Action requests for `run_script`
 pub enum RunScriptResult {
     /// Script terminated gracefully
@@ -642,6 +813,7 @@ pub enum RunScriptResult {
 /// Loads and runs a lua script.
 /// Initializes a lua environment, loads the script and executes it
 pub fn run_script(
+    thread_idx: usize,
     file: PathBuf,
     rx: &Receiver<Message>,
     keyboard_devices: &[KeyboardDevice],
@@ -672,6 +844,18 @@ pub fn run_script(
                 return Ok(RunScriptResult::TerminatedWithErrors);
             }
 
+            if register_persistence_namespace(&lua_ctx, &file).is_err() {
+                return Ok(RunScriptResult::TerminatedWithErrors);
+            }
+
+            // gestures are delivered to the VM that registered them
+            if lua_ctx
+                .set_named_registry_value(keyboard::VM_INDEX_REGISTRY_KEY, thread_idx)
+                .is_err()
+            {
+                return Ok(RunScriptResult::TerminatedWithErrors);
+            }
+
             if register_support_funcs(&lua_ctx).is_err() {
                 return Ok(RunScriptResult::TerminatedWithErrors);
             }
@@ -712,6 +896,21 @@ pub fn run_script(
                 return Ok(RunScriptResult::TerminatedWithErrors);
             }
 
+            // record the defined event handlers, for introspection
+            let handlers = introspection::EVENT_HANDLERS
+                .iter()
+                .filter(|name| lua_ctx.globals().get::<_, Function>(**name).is_ok())
+                .map(|name| name.to_string())
+                .collect();
+
+            introspection::set_vm_handlers(thread_idx, handlers);
+
+            // scripts that react to beats need the audio grabber, even
+            // if they do not query any of the audio analysis results
+            if lua_ctx.globals().get::<_, Function>("on_beat").is_ok() {
+                audio::AudioPlugin::enable_beat_detection();
+            }
+
             // reduce CPU load by caching the event handler status
             let mut has_tick_handler = true;
             let mut has_mouse_move_handler = true;
@@ -743,6 +942,38 @@ pub fn run_script(
                         }
 
                         Message::Tick(param) => {
+                            introspection::set_vm_memory_used(thread_idx, lua_ctx.used_memory());
+
+                            // deliver the results of processes that have been spawned by this script
+                            for result in system::take_process_results() {
+                                let mut errors_present = false;
+
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_process_exit")
+                                {
+                                    handler
+                                        .call::<_, ()>((
+                                            result.id,
+                                            result.code,
+                                            result.stdout,
+                                            result.stderr,
+                                        ))
+                                        .unwrap_or_else(|e| {
+                                            error!(
+                                                "Lua error in file {}: {}\n\t{:?}",
+                                                file.to_string_lossy(),
+                                                e,
+                                                e.source().unwrap_or(&UnknownError {})
+                                            );
+                                            errors_present = true;
+                                        });
+                                }
+
+                                if errors_present {
+                                    return Ok(RunScriptResult::TerminatedWithErrors);
+                                }
+                            }
+
                             if has_tick_handler {
                                 let mut errors_present = false;
 
@@ -770,23 +1001,8 @@ pub fn run_script(
                         Message::RealizeColorMap => {
                             if LOCAL_LED_MAP_MODIFIED.with(|f| *f.borrow()) {
                                 LOCAL_LED_MAP.with(|foreground| {
-                                        for (idx, background) in LED_MAP.write().iter_mut().enumerate() {
-                                            let bg = &background;
-                                            let fg = foreground.borrow()[idx];
-
-                                            let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst);
-
-                                            #[rustfmt::skip]
-                                            let color = RGBA {
-                                                r: ((((fg.a as f64) * fg.r as f64 + (255 - fg.a) as f64 * bg.r as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                                g: ((((fg.a as f64) * fg.g as f64 + (255 - fg.a) as f64 * bg.g as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                                b: ((((fg.a as f64) * fg.b as f64 + (255 - fg.a) as f64 * bg.b as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                                a: fg.a as u8,
-                                            };
-
-                                            *background = color;
-                                        }
-                                    });
+                                    blend_color_map(&foreground.borrow());
+                                });
                             }
 
                             // signal readiness / notify the main thread that we are done
@@ -1089,6 +1305,47 @@ pub fn run_script(
                             }
                         }
 
+                        Message::Beat(param) => {
+                            let mut errors_present = false;
+
+                            if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_beat") {
+                                handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    error!(
+                                        "Lua error in file {}: {}\n\t{:?}",
+                                        file.to_string_lossy(),
+                                        e,
+                                        e.source().unwrap_or(&UnknownError {})
+                                    );
+                                    errors_present = true;
+                                });
+                            }
+
+                            if errors_present {
+                                return Ok(RunScriptResult::TerminatedWithErrors);
+                            }
+                        }
+
+                        Message::Gesture(param) => {
+                            let mut errors_present = false;
+
+                            if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_gesture")
+                            {
+                                handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    error!(
+                                        "Lua error in file {}: {}\n\t{:?}",
+                                        file.to_string_lossy(),
+                                        e,
+                                        e.source().unwrap_or(&UnknownError {})
+                                    );
+                                    errors_present = true;
+                                });
+                            }
+
+                            if errors_present {
+                                return Ok(RunScriptResult::TerminatedWithErrors);
+                            }
+                        }
+
                         //Message::LoadScript(script_path) => {
                         //return Ok(RunScriptResult::ReExecuteOtherScript(script_path))
                         //}
@@ -1160,6 +1417,26 @@ fn register_support_globals(lua_ctx: &Lua) -> mlua::Result<()> {
     Ok(())
 }
 
+/// Remember the script and the profile, the persistence plugin uses them to namespace the keys of the script
+fn register_persistence_namespace(lua_ctx: &Lua, file: &Path) -> mlua::Result<()> {
+    let script = file
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let profile = ACTIVE_PROFILE
+        .lock()
+        .as_ref()
+        .and_then(|p| p.profile_file.file_name())
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| "none".to_string());
+
+    lua_ctx.set_named_registry_value(persistence::SCRIPT_FILE_REGISTRY_KEY, script)?;
+    lua_ctx.set_named_registry_value(persistence::PROFILE_FILE_REGISTRY_KEY, profile)?;
+
+    Ok(())
+}
+
 fn register_support_funcs(lua_ctx: &Lua) -> mlua::Result<()> {
     let globals = lua_ctx.globals();
 
@@ -1283,11 +1560,12 @@ fn register_support_funcs(lua_ctx: &Lua) -> mlua::Result<()> {
     })?;
     globals.set("inject_key", inject_key)?;
 
-    let inject_key_with_delay =
-        lua_ctx.create_function(|_, (ev_key, down, millis): (u32, bool, u64)| {
-            callbacks::inject_key_with_delay(ev_key, down, millis);
+    let inject_key_with_delay = lua_ctx.create_function(
+        |_, (ev_key, down, millis, tag): (u32, bool, u64, Option<String>)| {
+            callbacks::inject_key_with_delay(ev_key, down, millis, tag);
             Ok(())
-        })?;
+        },
+    )?;
     globals.set("inject_key_with_delay", inject_key_with_delay)?;
 
     // mouse state and macros
@@ -1518,6 +1796,47 @@ fn register_script_config(lua_ctx: &Lua, manifest: &Manifest) -> mlua::Result<()
                         globals.raw_set::<&str, u32>(name, *default)?;
                     }
                 }
+
+                ConfigParam::Choice { name, default, .. } => {
+                    if let Some(profile) = profile {
+                        if let Some(val) = profile.get_choice_value(script_name, name) {
+                            globals.raw_set::<&str, &str>(name, &*val)?;
+                        } else {
+                            globals.raw_set::<&str, &str>(name, &*default)?;
+                        }
+                    } else {
+                        globals.raw_set::<&str, &str>(name, &*default)?;
+                    }
+                }
+
+                ConfigParam::Gradient { name, default, .. } => {
+                    let stops = profile
+                        .as_ref()
+                        .and_then(|profile| profile.get_gradient_value(script_name, name))
+                        .unwrap_or(default.as_slice());
+
+                    // a gradient is represented as an array of tables: { position, color }
+                    let table = lua_ctx.create_table()?;
+
+                    for (idx, stop) in stops.iter().enumerate() {
+                        let entry = lua_ctx.create_table()?;
+                        entry.set("position", stop.position)?;
+                        entry.set("color", stop.color)?;
+
+                        table.set(idx + 1, entry)?;
+                    }
+
+                    globals.raw_set::<&str, LuaTable>(name, table)?;
+                }
+
+                ConfigParam::KeySet { name, default, .. } => {
+                    let keys = profile
+                        .as_ref()
+                        .and_then(|profile| profile.get_key_set_value(script_name, name))
+                        .unwrap_or(default.as_slice());
+
+                    globals.raw_set::<&str, Vec<u8>>(name, keys.to_vec())?;
+                }
             }
         }
     }
@@ -24,7 +24,7 @@ local function load_key_histogram(name)
   local result = {}
 
   for i = 0, num_keys do
-      local key = "statistics.histograms." .. name .. "[" .. i .. "]"
+      local key = "global.statistics.histograms." .. name .. "[" .. i .. "]"
       result[i] = load_int(key, 1)
   end
 
@@ -16,6 +16,9 @@
 ENABLE_EASY_SHIFT = true   -- set this to false if you don't want to
 						   -- use the Easy Shift+ functionality
 
+QUICK_MACRO_NAME = "quick-macro"  -- the name of the macro that is recorded
+								  -- when pressing the modifier key + PAUSE
+
 -- comment out the declarations below to change the modifier key you want to use; default is the "FN" key:
 
 MODIFIER_KEY = FN		       --
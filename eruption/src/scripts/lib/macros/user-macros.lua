@@ -300,6 +300,14 @@ EASY_SHIFT_MACRO_TABLE[1][7]		= easyshift_macro_1  --
 EASY_SHIFT_MACRO_TABLE[1][13]		= easyshift_macro_2  --
 EASY_SHIFT_MACRO_TABLE[1][19]		= easyshift_macro_3  --
 
+-- assign recorded macros to keys on the Easy Shift+ layer
+-- EASY_SHIFT_MACRO_TABLE[1][8]		= recorded_macro("quick-macro")  		-- play once at normal speed
+-- EASY_SHIFT_MACRO_TABLE[1][14]	= recorded_macro("quick-macro", 2.0, 3)	-- play three times at double speed
+
+-- type text on the Easy Shift+ layer, and expand typed triggers
+-- EASY_SHIFT_MACRO_TABLE[1][20]	= function() type_text("Hello, World!") end
+-- register_text_expansion(";sig", "Best regards,\nJane Doe")
+
 -- assign macros to mouse buttons on the Easy Shift+ layer
 EASY_SHIFT_MOUSE_DOWN_MACRO_TABLE[1][1]	= easyshift_mouse_macro_1  --
 EASY_SHIFT_MOUSE_DOWN_MACRO_TABLE[1][2]	= easyshift_mouse_macro_2  --
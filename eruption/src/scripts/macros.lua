@@ -61,6 +61,18 @@ EASY_SHIFT_MOUSE_DPI_MACRO_TABLE = {	-- macro tables for mouse DPI change events
 	{}, {}, {}, {}, {}, {}
 }
 
+-- returns a function that plays the recorded macro `name`, e.g. for use in the Easy Shift+ macro tables
+function recorded_macro(name, speed, count)
+	return function()
+		consume_key()
+
+		local result, err = play_macro(name, speed, count)
+		if result == nil then
+			error("Macros: Could not play the recorded macro '" .. name .. "': " .. err)
+		end
+	end
+end
+
 -- import default color scheme
 require "themes/default"
 
@@ -90,6 +102,7 @@ highlight_ttl = 0
 highlight_max_ttl = 255
 
 modifier_map = {} -- holds the state of modifier keys
+mouse_shift_active = false -- true while the shift button of the mouse is held down
 game_mode_enabled = load_bool_transient("global.game_mode_enabled", false) -- keyboard can be in "game mode" or in "normal mode";
 
 -- utility functions --
@@ -380,6 +393,11 @@ function on_key_down(key_index)
 		on_macro_key_down(5)
 	end
 
+	-- start or stop recording a macro (PAUSE)
+	if modifier_map[MODIFIER_KEY] and key_index == 109 then
+		do_toggle_macro_recording()
+	end
+
 	-- switch Easy Shift+ layers via Caps Lock + macro keys
 	if modifier_map[CAPS_LOCK] and key_index == 101 then
 		do_switch_easy_shift_layer(0)
@@ -450,6 +468,8 @@ end
 function on_mouse_button_down(button_index)
 	debug("Macros: Mouse down: Button: " .. button_index)
 
+	update_mouse_shift_state()
+
 	-- call complex macros on the Easy Shift+ layer (layer 4)
 	if modifier_map[CAPS_LOCK] and ENABLE_EASY_SHIFT and game_mode_enabled and
 		EASY_SHIFT_MOUSE_DOWN_MACRO_TABLE[ACTIVE_EASY_SHIFT_LAYER][button_index] ~= nil then
@@ -464,6 +484,8 @@ end
 function on_mouse_button_up(button_index)
 	debug("Macros: Mouse up: Button: " .. button_index)
 
+	update_mouse_shift_state()
+
 	-- call complex macros on the Easy Shift+ layer (layer 4)
 	if modifier_map[CAPS_LOCK] and ENABLE_EASY_SHIFT and game_mode_enabled and
 		EASY_SHIFT_MOUSE_UP_MACRO_TABLE[ACTIVE_EASY_SHIFT_LAYER][button_index] ~= nil then
@@ -489,6 +511,17 @@ function on_mouse_wheel(direction)
 	end
 end
 
+-- the shift button of the profile's mouse configuration activates the Easy Shift+ layer,
+-- just like holding down the Easy Shift+ key on the keyboard
+function update_mouse_shift_state()
+	local active = is_mouse_shift_active()
+
+	if active ~= mouse_shift_active then
+		mouse_shift_active = active
+		modifier_map[CAPS_LOCK] = active
+	end
+end
+
 -- perform a simple remapping
 function simple_remapping(key_index, down)
 	if modifier_map[CAPS_LOCK] and ENABLE_EASY_SHIFT then
@@ -538,6 +571,27 @@ function do_switch_easy_shift_layer(index)
 	ACTIVE_EASY_SHIFT_LAYER = index + 1
 end
 
+function do_toggle_macro_recording()
+	-- consume the keystroke
+	consume_key()
+
+	if is_macro_recording() then
+		local name, err = stop_macro_recording()
+		if name ~= nil then
+			info("Macros: Saved the recorded macro: " .. name)
+		else
+			error("Macros: Could not save the recorded macro: " .. err)
+		end
+	else
+		local result, err = start_macro_recording(QUICK_MACRO_NAME)
+		if result ~= nil then
+			info("Macros: Recording macro: " .. QUICK_MACRO_NAME)
+		else
+			error("Macros: Could not start recording: " .. err)
+		end
+	end
+end
+
 function update_overlay_state()
 	if overlay_state == NO_OVERLAY then
 		overlay_ttl = 0
@@ -30,7 +30,7 @@ local function load_key_histogram(name)
     local result = {}
 
     for i = 0, num_keys do
-        local key = "statistics.histograms." .. name .. "[" .. i .. "]"
+        local key = "global.statistics.histograms." .. name .. "[" .. i .. "]"
         result[i] = load_int(key, 0)
     end
 
@@ -41,7 +41,7 @@ local function store_key_histogram(key_histogram, name)
     trace("Statistics: Saving histogram '" .. name .. "' to persistent storage")
 
     for i = 0, num_keys do
-        local key = "statistics.histograms." .. name .. "[" .. i .. "]"
+        local key = "global.statistics.histograms." .. name .. "[" .. i .. "]"
         store_int(key, key_histogram[i])
     end
 end
@@ -50,7 +50,7 @@ local function dump_key_histogram(name)
     info("Statistics: Dumping: '" .. name .. "'")
 
     for i = 0, num_keys do
-        local key = "statistics.histograms." .. name .. "[" .. i .. "]"
+        local key = "global.statistics.histograms." .. name .. "[" .. i .. "]"
         local result = load_int(key, 0)
 
         info(i .. ": " .. result)
@@ -0,0 +1,82 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns the path of `path`, relative to the root of the source tree
+fn source_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(path)
+}
+
+/// Run the daemon binary as `eruption-script-test`, with the arguments `args`
+fn script_test(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_eruption"));
+
+    command
+        .arg0("eruption-script-test")
+        .args(args)
+        // in debug builds, the Lua libraries are looked up relative to the root of the source tree
+        .current_dir(source_path(""))
+        .env("RUST_LOG", "warn");
+
+    command
+}
+
+#[test]
+fn test_golden_solid() {
+    let script = source_path("eruption/src/scripts/solid.lua");
+    let golden = source_path("eruption/tests/golden/solid.golden");
+
+    let output = script_test(&[
+        "--script",
+        script.to_str().unwrap(),
+        "--ticks",
+        "5",
+        "--golden",
+        golden.to_str().unwrap(),
+    ])
+    .output()
+    .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_invalid_tolerance() {
+    let script = source_path("eruption/src/scripts/solid.lua");
+    let golden = source_path("eruption/tests/golden/solid.golden");
+
+    let output = script_test(&[
+        "--script",
+        script.to_str().unwrap(),
+        "--golden",
+        golden.to_str().unwrap(),
+        "--tolerance",
+        "256",
+    ])
+    .output()
+    .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tolerance"));
+}
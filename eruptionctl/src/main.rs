@@ -21,6 +21,8 @@ use dbus::nonblock;
 use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
 use dbus_tokio::connection;
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -35,6 +37,9 @@ type Result<T> = std::result::Result<T, eyre::Error>;
 pub enum MainError {
     #[error("Unknown error: {description}")]
     UnknownError { description: String },
+
+    #[error("Found {count} invalid profile(s)")]
+    InvalidProfiles { count: usize },
 }
 
 /// Supported command line arguments
@@ -85,6 +90,21 @@ pub enum Subcommands {
         #[clap(subcommand)]
         command: ScriptsSubcommands,
     },
+
+    /// Access the persistent store of the Lua scripts
+    Store {
+        #[clap(subcommand)]
+        command: StoreSubcommands,
+    },
+
+    /// Record and play back macros
+    Macros {
+        #[clap(subcommand)]
+        command: MacrosSubcommands,
+    },
+
+    /// Show the status of the running Lua VMs
+    Status,
 }
 
 /// Sub-commands of the "config" command
@@ -118,6 +138,34 @@ pub enum ProfilesSubcommands {
 
     /// List available profiles
     List,
+
+    /// Export a profile, along with its scripts, libraries and sound effects, into a bundle
+    Export {
+        profile_name: String,
+
+        /// The bundle file to write, defaults to the name of the profile with the extension ".tar"
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Install a profile bundle
+    Import {
+        bundle_file: PathBuf,
+
+        /// Replace installed files that differ from the files of the bundle
+        #[clap(short, long)]
+        overwrite: bool,
+    },
+
+    /// Check profiles against the manifests of their scripts
+    Check {
+        #[clap(required_unless_present = "all")]
+        profile_name: Option<String>,
+
+        /// Check all available profiles
+        #[clap(short, long)]
+        all: bool,
+    },
 }
 
 /// Subcommands of the "names" command
@@ -146,6 +194,58 @@ pub enum ScriptsSubcommands {
     List,
 }
 
+/// Subcommands of the "store" command
+#[derive(Debug, Clap)]
+pub enum StoreSubcommands {
+    /// List the keys of the persistent store, optionally restricted to a namespace
+    List { namespace: Option<String> },
+
+    /// Show the value of a key as JSON
+    Get { namespace: String, key: String },
+
+    /// Set the value of a key, values that are not valid JSON are stored as strings
+    Set {
+        namespace: String,
+        key: String,
+        value: String,
+    },
+
+    /// Remove all keys of a namespace, or only the keys starting with a prefix
+    Reset {
+        namespace: String,
+        prefix: Option<String>,
+    },
+}
+
+/// Subcommands of the "macros" command
+#[derive(Debug, Clap)]
+pub enum MacrosSubcommands {
+    /// List recorded macros
+    List,
+
+    /// Start recording keyboard and mouse events into a macro
+    Record { name: String },
+
+    /// Stop recording and save the macro
+    Stop,
+
+    /// Play a recorded macro
+    Play {
+        name: String,
+
+        /// Playback speed, values greater than 1.0 play the macro faster
+        #[clap(short, long, default_value = "1.0")]
+        speed: f64,
+
+        /// Number of times to play the macro, 0 repeats it until playback is stopped
+        #[clap(short, long, default_value = "1")]
+        repeat: u32,
+    },
+
+    /// Stop all running playbacks
+    StopPlayback,
+}
+
 /// Print license information
 #[allow(dead_code)]
 fn print_header() {
@@ -252,6 +352,26 @@ pub async fn get_profiles() -> Result<Vec<(String, String)>> {
     Ok(result)
 }
 
+/// Export the profile `name` into a bundle
+pub async fn export_bundle(name: &str) -> Result<Vec<u8>> {
+    let (result,): (Vec<u8>,) = dbus_system_bus("/org/eruption/profile")
+        .await?
+        .method_call("org.eruption.Profile", "ExportBundle", (name.to_owned(),))
+        .await?;
+
+    Ok(result)
+}
+
+/// Install the profile bundle `data`, returns the installed files
+pub async fn import_bundle(data: Vec<u8>, overwrite: bool) -> Result<Vec<String>> {
+    let (result,): (Vec<String>,) = dbus_system_bus("/org/eruption/profile")
+        .await?
+        .method_call("org.eruption.Profile", "ImportBundle", (data, overwrite))
+        .await?;
+
+    Ok(result)
+}
+
 /// Enumerate all available scripts
 pub fn get_script_list() -> Result<Vec<(String, String)>> {
     let path = constants::DEFAULT_SCRIPT_DIR;
@@ -316,6 +436,136 @@ pub async fn set_sound_fx(enabled: bool) -> Result<()> {
     Ok(())
 }
 
+// persistent store
+
+/// Enumerate the keys of the persistent store, an empty `namespace` selects all namespaces
+pub async fn get_store_keys(namespace: &str) -> Result<Vec<(String, String)>> {
+    let (result,): (Vec<(String, String)>,) = dbus_system_bus("/org/eruption/persistence")
+        .await?
+        .method_call(
+            "org.eruption.Persistence",
+            "ListKeys",
+            (namespace.to_owned(),),
+        )
+        .await?;
+
+    Ok(result)
+}
+
+/// Get the value of a key of the persistent store, encoded as JSON
+pub async fn get_store_value(namespace: &str, key: &str) -> Result<String> {
+    let (result,): (String,) = dbus_system_bus("/org/eruption/persistence")
+        .await?
+        .method_call(
+            "org.eruption.Persistence",
+            "GetValue",
+            (namespace.to_owned(), key.to_owned()),
+        )
+        .await?;
+
+    Ok(result)
+}
+
+/// Set the value of a key of the persistent store, `value` has to be encoded as JSON
+pub async fn set_store_value(namespace: &str, key: &str, value: &str) -> Result<()> {
+    let (_result,): (bool,) = dbus_system_bus("/org/eruption/persistence")
+        .await?
+        .method_call(
+            "org.eruption.Persistence",
+            "SetValue",
+            (namespace.to_owned(), key.to_owned(), value.to_owned()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Remove all keys starting with `prefix` from the persistent store
+pub async fn reset_store(namespace: &str, prefix: &str) -> Result<u64> {
+    let (result,): (u64,) = dbus_system_bus("/org/eruption/persistence")
+        .await?
+        .method_call(
+            "org.eruption.Persistence",
+            "Reset",
+            (namespace.to_owned(), prefix.to_owned()),
+        )
+        .await?;
+
+    Ok(result)
+}
+
+// macros
+
+/// Enumerate all recorded macros
+pub async fn get_macros() -> Result<Vec<String>> {
+    let (result,): (Vec<String>,) = dbus_system_bus("/org/eruption/macros")
+        .await?
+        .method_call("org.eruption.Macros", "ListMacros", ())
+        .await?;
+
+    Ok(result)
+}
+
+/// Start recording keyboard and mouse events into the macro `name`
+pub async fn start_macro_recording(name: &str) -> Result<()> {
+    let (_result,): (bool,) = dbus_system_bus("/org/eruption/macros")
+        .await?
+        .method_call("org.eruption.Macros", "StartRecording", (name.to_owned(),))
+        .await?;
+
+    Ok(())
+}
+
+/// Stop the active recording, returns the name of the recorded macro
+pub async fn stop_macro_recording() -> Result<String> {
+    let (result,): (String,) = dbus_system_bus("/org/eruption/macros")
+        .await?
+        .method_call("org.eruption.Macros", "StopRecording", ())
+        .await?;
+
+    Ok(result)
+}
+
+/// Play the recorded macro `name`
+pub async fn play_macro(name: &str, speed: f64, repeat: u32) -> Result<()> {
+    let (_result,): (bool,) = dbus_system_bus("/org/eruption/macros")
+        .await?
+        .method_call(
+            "org.eruption.Macros",
+            "PlayMacro",
+            (name.to_owned(), speed, repeat),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Stop all running macro playbacks
+pub async fn stop_macro_playback() -> Result<()> {
+    let (_result,): (bool,) = dbus_system_bus("/org/eruption/macros")
+        .await?
+        .method_call("org.eruption.Macros", "StopPlayback", ())
+        .await?;
+
+    Ok(())
+}
+
+// status
+
+/// The status of a Lua VM: index, script file, name and version of the script,
+/// event handlers, used memory, queue depth and whether the VM has failed
+type VmStatus = (u32, String, String, String, Vec<String>, u64, u64, bool);
+
+/// Get the status of all running Lua VMs
+pub async fn get_vm_status() -> Result<Vec<VmStatus>> {
+    let (result,): (Vec<VmStatus>,) = dbus_system_bus("/org/eruption/status")
+        .await?
+        .method_call("org.eruption.Status", "GetVmStatus", ())
+        .await?;
+
+    Ok(result)
+}
+
 #[tokio::main]
 pub async fn main() -> std::result::Result<(), eyre::Error> {
     color_eyre::install()?;
@@ -378,6 +628,105 @@ pub async fn main() -> std::result::Result<(), eyre::Error> {
                 }
             }
 
+            ProfilesSubcommands::Export {
+                profile_name,
+                output,
+            } => {
+                let output = output.unwrap_or_else(|| {
+                    let mut path = PathBuf::from(&profile_name);
+                    path.set_extension("tar");
+
+                    path
+                });
+
+                let data = export_bundle(&profile_name).await?;
+                fs::write(&output, data)?;
+
+                println!(
+                    "Exported profile {} to: {}",
+                    profile_name.bold(),
+                    output.display()
+                );
+            }
+
+            ProfilesSubcommands::Import {
+                bundle_file,
+                overwrite,
+            } => {
+                let data = fs::read(&bundle_file)?;
+                let files = import_bundle(data, overwrite).await?;
+
+                if files.is_empty() {
+                    println!("All files of the bundle are already installed");
+                } else {
+                    for file in files.iter() {
+                        println!("Installed: {}", file.bold());
+                    }
+                }
+            }
+
+            ProfilesSubcommands::Check { profile_name, all } => {
+                let path = Path::new(constants::DEFAULT_PROFILE_DIR);
+                let script_dir = Path::new(constants::DEFAULT_SCRIPT_DIR);
+
+                let profile_files: Vec<PathBuf> = profiles::get_profile_files(path)?
+                    .into_iter()
+                    .filter(|f| {
+                        all || profile_name.as_deref()
+                            == Some(&f.file_name().unwrap_or_default().to_string_lossy())
+                    })
+                    .collect();
+
+                if profile_files.is_empty() {
+                    eprintln!("No matches found");
+                }
+
+                let mut invalid_profiles = 0;
+
+                for profile_file in profile_files.iter() {
+                    let file_name = profile_file
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy();
+
+                    let errors = match profiles::Profile::from(profile_file) {
+                        Ok(profile) => manifest::validate_profile(&profile, script_dir),
+
+                        Err(e) => {
+                            println!("{}: {}", file_name.bold(), format!("{}", e).red());
+                            invalid_profiles += 1;
+
+                            continue;
+                        }
+                    };
+
+                    if errors.is_empty() {
+                        println!("{}: {}", file_name.bold(), "OK".green());
+                    } else {
+                        println!("{}:", file_name.bold());
+
+                        for e in errors.iter() {
+                            if e.is_fatal() {
+                                println!("  {} {}", "error:".red(), e);
+                            } else {
+                                println!("  {} {}", "warning:".yellow(), e);
+                            }
+                        }
+
+                        if errors.iter().any(|e| e.is_fatal()) {
+                            invalid_profiles += 1;
+                        }
+                    }
+                }
+
+                if invalid_profiles > 0 {
+                    return Err(MainError::InvalidProfiles {
+                        count: invalid_profiles,
+                    }
+                    .into());
+                }
+            }
+
             ProfilesSubcommands::Info { profile_name } => {
                 let path = constants::DEFAULT_PROFILE_DIR;
                 let profiles = util::enumerate_profiles(path)?;
@@ -480,12 +829,105 @@ pub async fn main() -> std::result::Result<(), eyre::Error> {
                         script.description,
                         script.tags.as_ref().unwrap_or(&empty),
                     );
+
+                    if let Some(requires) = &script.requires {
+                        println!(
+                            "Requires:\tLua libraries: {:?}, plugins: {:?}, devices: {:?}",
+                            requires.libs, requires.plugins, requires.devices,
+                        );
+                    }
                 } else {
                     eprintln!("No matches found");
                 }
             }
         },
 
+        // persistent store related sub-commands
+        Subcommands::Store { command } => match command {
+            StoreSubcommands::List { namespace } => {
+                let namespace = namespace.unwrap_or_default();
+
+                for (namespace, key) in get_store_keys(&namespace).await? {
+                    println!("{}: {}", namespace.bold(), key);
+                }
+            }
+
+            StoreSubcommands::Get { namespace, key } => {
+                let result = get_store_value(&namespace, &key).await?;
+                println!("{}", result);
+            }
+
+            StoreSubcommands::Set {
+                namespace,
+                key,
+                value,
+            } => {
+                let value = match serde_json::from_str::<serde_json::Value>(&value) {
+                    Ok(_) => value,
+                    Err(_) => serde_json::Value::String(value).to_string(),
+                };
+
+                set_store_value(&namespace, &key, &value).await?
+            }
+
+            StoreSubcommands::Reset { namespace, prefix } => {
+                let count = reset_store(&namespace, &prefix.unwrap_or_default()).await?;
+                println!("Removed {} keys", format!("{}", count).bold());
+            }
+        },
+
+        // macro recording and playback
+        Subcommands::Macros { command } => match command {
+            MacrosSubcommands::List => {
+                for name in get_macros().await? {
+                    println!("{}", name);
+                }
+            }
+
+            MacrosSubcommands::Record { name } => {
+                println!("Recording macro: {}", name.bold());
+                start_macro_recording(&name).await?
+            }
+
+            MacrosSubcommands::Stop => {
+                let name = stop_macro_recording().await?;
+                println!("Saved macro: {}", name.bold());
+            }
+
+            MacrosSubcommands::Play {
+                name,
+                speed,
+                repeat,
+            } => {
+                println!("Playing macro: {}", name.bold());
+                play_macro(&name, speed, repeat).await?
+            }
+
+            MacrosSubcommands::StopPlayback => stop_macro_playback().await?,
+        },
+
+        // status of the Lua VMs
+        Subcommands::Status => {
+            for (index, script, name, version, handlers, memory, queue_depth, failed) in
+                get_vm_status().await?
+            {
+                let state = if failed {
+                    "failed".red().bold()
+                } else {
+                    "running".green().bold()
+                };
+
+                println!("{:02}: {} ({}) [{}]", index, name.bold(), version, state);
+                println!("    Script: {}", script);
+                println!("    Handlers: {}", handlers.join(", "));
+                println!(
+                    "    Memory: {} KiB, queued messages: {}",
+                    memory / 1024,
+                    queue_depth
+                );
+            }
+        }
+
         // convenience operations: switch profile or slot
         Subcommands::Switch { command } => match command {
             SwitchSubcommands::Profile { profile_name } => {
@@ -108,6 +108,8 @@ cp -a %{_builddir}/%{name}-%{version}/support/profiles/matrix.profile %{buildroo
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/netfx.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/batique.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/batique-mouse.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
+cp -a %{_builddir}/%{name}-%{version}/support/profiles/breathing-animation.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
+cp -a %{_builddir}/%{name}-%{version}/support/profiles/logo.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/profile1.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/profile2.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/profile3.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
@@ -138,6 +140,8 @@ cp -a %{_builddir}/%{name}-%{version}/support/sfx/phaser1.wav %{buildroot}%{_dat
 cp -a %{_builddir}/%{name}-%{version}/support/sfx/phaser2.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/phaser2.wav
 ln -s phaser1.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/key-down.wav
 ln -s phaser2.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/key-up.wav
+cp -a %{_builddir}/%{name}-%{version}/support/sfx/default.sfxpack %{buildroot}%{_datarootdir}/%{ShortName}/sfx/default.sfxpack
+cp -a %{_builddir}/%{name}-%{version}/support/sfx/typewriter.sfxpack %{buildroot}%{_datarootdir}/%{ShortName}/sfx/typewriter.sfxpack
 cp -ra %{_builddir}/%{name}-%{version}/eruption/src/scripts %{buildroot}%{_datarootdir}/%{ShortName}/
 
 cp -a %{_builddir}/%{name}-%{version}/support/systemd/eruption-suspend.sh %{buildroot}/usr/lib/systemd/system-sleep/eruption
@@ -150,6 +154,7 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-netf
 install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-debug-tool %{buildroot}%{_bindir}/eruption-debug-tool
 install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-process-monitor %{buildroot}%{_bindir}/eruption-process-monitor
 install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-gui %{buildroot}%{_bindir}/eruption-gui
+ln -s eruption %{buildroot}%{_bindir}/eruption-script-test
 
 %post
 %systemd_post %{ShortName}.service
@@ -176,6 +181,7 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-gui
 /usr/lib/udev/rules.d/99-eruption.rules
 /usr/lib/systemd/system-sleep/eruption
 %{_bindir}/eruption
+%{_bindir}/eruption-script-test
 %{_bindir}/eruptionctl
 %{_bindir}/eruption-netfx
 %{_bindir}/eruption-debug-tool
@@ -202,6 +208,8 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-gui
 %{_sharedstatedir}/%{ShortName}/profiles/netfx.profile
 %{_sharedstatedir}/%{ShortName}/profiles/batique.profile
 %{_sharedstatedir}/%{ShortName}/profiles/batique-mouse.profile
+%{_sharedstatedir}/%{ShortName}/profiles/breathing-animation.profile
+%{_sharedstatedir}/%{ShortName}/profiles/logo.profile
 %{_sharedstatedir}/%{ShortName}/profiles/profile1.profile
 %{_sharedstatedir}/%{ShortName}/profiles/profile2.profile
 %{_sharedstatedir}/%{ShortName}/profiles/profile3.profile
@@ -242,6 +250,8 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-gui
 %{_datarootdir}/%{ShortName}/scripts/macros.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/stats.lua
 %{_datarootdir}/%{ShortName}/scripts/stats.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/breathing.animation
+%{_datarootdir}/%{ShortName}/scripts/logo.png
 %{_datarootdir}/%{ShortName}/scripts/afterglow.lua
 %{_datarootdir}/%{ShortName}/scripts/afterglow.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/afterhue.lua
@@ -339,5 +349,7 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption-gui
 %{_datarootdir}/%{ShortName}/sfx/phaser2.wav
 %{_datarootdir}/%{ShortName}/sfx/key-down.wav
 %{_datarootdir}/%{ShortName}/sfx/key-up.wav
+%{_datarootdir}/%{ShortName}/sfx/default.sfxpack
+%{_datarootdir}/%{ShortName}/sfx/typewriter.sfxpack
 
 %changelog
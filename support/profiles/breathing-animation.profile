@@ -0,0 +1,9 @@
+id = '8f4f6f28-6a2b-4f7e-9a0b-0d3a5c1e7b42'
+name = 'Breathing (Animation)'
+description = 'A declarative animation, combined with Lua effects'
+active_scripts = [
+    'breathing.animation',
+    'impact.lua',
+    'macros.lua',
+#   'stats.lua',
+]
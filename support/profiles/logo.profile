@@ -0,0 +1,24 @@
+id = '3c0e6d9a-1f4b-4d52-8e7a-5b2f0c9d6a13'
+name = 'Logo'
+description = 'Display an image on the keyboard'
+active_scripts = [
+    'logo.png',
+    'impact.lua',
+    'macros.lua',
+#   'stats.lua',
+]
+
+[[config."logo.png"]]
+type = 'string'
+name = 'fit'
+value = 'stretch'
+
+[[config."logo.png"]]
+type = 'string'
+name = 'sampling'
+value = 'nearest'
+
+[[config."logo.png"]]
+type = 'float'
+name = 'opacity'
+value = 1.0